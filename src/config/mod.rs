@@ -1,10 +1,16 @@
 //! Configuration types for Nginx x402 module
 
+mod networks;
 mod validation;
 
+pub use networks::{
+    define_network, network_by_chain_id, network_by_name, network_names, reset_networks,
+    NetworkInfo, NetworkVm,
+};
 pub use validation::{
     chain_id_to_network, parse_accept_priority, validate_amount, validate_ethereum_address,
-    validate_network, validate_payment_header, validate_resource_path, validate_url,
+    validate_network, validate_network_address, validate_payment_address, validate_payment_header,
+    validate_resource_path, validate_solana_address, validate_url,
 };
 
 use rust_decimal::Decimal;
@@ -66,12 +72,17 @@ impl NginxX402Config {
             rust_x402::types::networks::BASE_MAINNET
         };
 
+        let network_info =
+            network_by_name(network).ok_or_else(|| X402Error::NetworkNotSupported {
+                network: network.to_string(),
+            })?;
         let usdc_address =
-            rust_x402::types::networks::get_usdc_address(network).ok_or_else(|| {
-                X402Error::NetworkNotSupported {
+            network_info
+                .usdc
+                .clone()
+                .ok_or_else(|| X402Error::NetworkNotSupported {
                     network: network.to_string(),
-                }
-            })?;
+                })?;
 
         let resource = if let Some(ref resource_url) = self.resource {
             resource_url.clone()
@@ -83,22 +94,27 @@ impl NginxX402Config {
             .normalize()
             .to_string();
 
+        // EVM addresses are case-insensitive, base58 Solana addresses are not
+        let pay_to = match network_info.vm {
+            NetworkVm::Evm => self.pay_to.to_lowercase(),
+            NetworkVm::Svm => self.pay_to.clone(),
+        };
+
         let mut requirements = PaymentRequirements::new(
             rust_x402::types::schemes::EXACT,
             network,
             max_amount_required,
             usdc_address,
-            self.pay_to.to_lowercase(),
+            pay_to,
             resource,
             self.description.as_deref().unwrap_or("Payment required"),
         );
 
-        let network_enum = if self.testnet {
-            rust_x402::types::Network::Testnet
-        } else {
-            rust_x402::types::Network::Mainnet
-        };
-        requirements.set_usdc_info(network_enum)?;
+        if let (Some(name), Some(version)) =
+            (&network_info.eip712_name, &network_info.eip712_version)
+        {
+            requirements.extra = Some(serde_json::json!({ "name": name, "version": version }));
+        }
 
         Ok(requirements)
     }
@@ -0,0 +1,294 @@
+//! Network registry
+//!
+//! Maps network names (as used in payment requirements) to their virtual
+//! machine, chain ID and USDC contract (SPL mint on Solana). The registry starts
+//! with the networks used across the x402 ecosystem and can be extended, or
+//! corrected, from nginx configuration with `x402_network_define` (EVM networks
+//! only), without waiting for a new rust-x402 release.
+
+use rust_x402::{Result, X402Error};
+use std::sync::{OnceLock, RwLock};
+
+/// Virtual machine of a network, which decides the address and payload formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkVm {
+    /// Ethereum virtual machine: `0x` addresses, EIP-3009 authorizations
+    Evm,
+    /// Solana virtual machine: base58 addresses, signed SPL token transfers
+    Svm,
+}
+
+/// Network known to the module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkInfo {
+    /// Network name (e.g., "base-sepolia")
+    pub name: String,
+    /// Virtual machine of the network
+    pub vm: NetworkVm,
+    /// EVM chain ID (None on Solana)
+    pub chain_id: Option<u64>,
+    /// USDC contract address or SPL mint (None: payments need `x402_asset`)
+    pub usdc: Option<String>,
+    /// EIP-712 domain name of the USDC contract
+    pub eip712_name: Option<String>,
+    /// EIP-712 domain version of the USDC contract
+    pub eip712_version: Option<String>,
+}
+
+/// Built-in networks: name, chain ID, USDC address, USDC EIP-712 domain name
+///
+/// All USDC contracts use EIP-712 domain version "2".
+const BUILTIN_NETWORKS: &[(&str, u64, &str, &str)] = &[
+    (
+        "base",
+        8453,
+        "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913",
+        "USD Coin",
+    ),
+    (
+        "base-sepolia",
+        84532,
+        "0x036CbD53842c5426634e7929541eC2318f3dCF7e",
+        "USDC",
+    ),
+    (
+        "avalanche",
+        43114,
+        "0xB97EF9Ef8734C71904D8002F8b6Bc66Dd9c48a6E",
+        "USD Coin",
+    ),
+    (
+        "avalanche-fuji",
+        43113,
+        "0x5425890298aed601595a70AB815c96711a31Bc65",
+        "USD Coin",
+    ),
+    (
+        "polygon",
+        137,
+        "0x3c499c542cEF5E3811e1192ce70d8cC03d5c3359",
+        "USD Coin",
+    ),
+    (
+        "polygon-amoy",
+        80002,
+        "0x41E94Eb019C0762f9Bfcf9Fb1E58725BfB0e7582",
+        "USDC",
+    ),
+    (
+        "iotex",
+        4689,
+        "0xcdf79194c6c285077a58da47641d4dbe51f63542",
+        "Bridged USDC",
+    ),
+    (
+        "sei",
+        1329,
+        "0xe15fc38f6d8c56af07bbcbe3baf5708a2bf42392",
+        "USDC",
+    ),
+    (
+        "sei-testnet",
+        1328,
+        "0x4fcf1784b31630811181f670aea7a7bef803eaed",
+        "USDC",
+    ),
+    (
+        "peaq",
+        3338,
+        "0xbbA60da06c2c5424f03f7434542280FCAd453d10",
+        "USDC",
+    ),
+];
+
+/// EIP-712 domain version of the built-in USDC contracts
+const USDC_EIP712_VERSION: &str = "2";
+
+/// Built-in Solana networks: name, USDC mint
+const BUILTIN_SVM_NETWORKS: &[(&str, &str)] = &[
+    ("solana", "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v"),
+    (
+        "solana-devnet",
+        "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU",
+    ),
+];
+
+/// Networks of this process: the built-in ones, then those defined in nginx.conf
+static REGISTRY: OnceLock<RwLock<Vec<NetworkInfo>>> = OnceLock::new();
+
+fn builtin_networks() -> Vec<NetworkInfo> {
+    BUILTIN_NETWORKS
+        .iter()
+        .map(|&(name, chain_id, usdc, eip712_name)| NetworkInfo {
+            name: name.to_string(),
+            vm: NetworkVm::Evm,
+            chain_id: Some(chain_id),
+            usdc: Some(usdc.to_string()),
+            eip712_name: Some(eip712_name.to_string()),
+            eip712_version: Some(USDC_EIP712_VERSION.to_string()),
+        })
+        .chain(
+            BUILTIN_SVM_NETWORKS
+                .iter()
+                .map(|&(name, usdc)| NetworkInfo {
+                    name: name.to_string(),
+                    vm: NetworkVm::Svm,
+                    chain_id: None,
+                    usdc: Some(usdc.to_string()),
+                    eip712_name: None,
+                    eip712_version: None,
+                }),
+        )
+        .collect()
+}
+
+fn registry() -> &'static RwLock<Vec<NetworkInfo>> {
+    REGISTRY.get_or_init(|| RwLock::new(builtin_networks()))
+}
+
+/// Find a network by name
+#[must_use]
+pub fn network_by_name(name: &str) -> Option<NetworkInfo> {
+    let networks = registry().read().ok()?;
+    networks
+        .iter()
+        .find(|network| network.name == name)
+        .cloned()
+}
+
+/// Find a network by chain ID
+#[must_use]
+pub fn network_by_chain_id(chain_id: u64) -> Option<NetworkInfo> {
+    let networks = registry().read().ok()?;
+    networks
+        .iter()
+        .find(|network| network.chain_id == Some(chain_id))
+        .cloned()
+}
+
+/// Names of all known networks
+#[must_use]
+pub fn network_names() -> Vec<String> {
+    registry()
+        .read()
+        .map(|networks| {
+            networks
+                .iter()
+                .map(|network| network.name.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Add a network to the registry, or replace the network of the same name
+///
+/// # Errors
+/// - Returns error if another network already uses the chain ID
+pub fn define_network(network: NetworkInfo) -> Result<()> {
+    let mut networks = registry()
+        .write()
+        .map_err(|_| X402Error::config("Network registry lock poisoned"))?;
+
+    if let Some(chain_id) = network.chain_id {
+        if let Some(other) = networks
+            .iter()
+            .find(|other| other.chain_id == Some(chain_id) && other.name != network.name)
+        {
+            return Err(X402Error::config(format!(
+                "chain_id {chain_id} is already used by network '{}'",
+                other.name
+            )));
+        }
+    }
+
+    match networks.iter_mut().find(|other| other.name == network.name) {
+        Some(existing) => *existing = network,
+        None => networks.push(network),
+    }
+    Ok(())
+}
+
+/// Forget the networks defined in nginx.conf
+///
+/// Called before the configuration is (re)loaded.
+pub fn reset_networks() {
+    if let Ok(mut networks) = registry().write() {
+        *networks = builtin_networks();
+    }
+}
+
+impl NetworkInfo {
+    /// Parse `x402_network_define` arguments
+    /// (`name chain_id=... [usdc=...] [eip712_name=...] [eip712_version=...]`)
+    ///
+    /// `eip712_version` defaults to "2" when `eip712_name` is set.
+    ///
+    /// # Errors
+    /// - Returns error if the name or `chain_id` is missing
+    /// - Returns error if a parameter is unknown, duplicated or invalid
+    pub fn parse_define(args: &[&str]) -> Result<Self> {
+        let (name, params) = args
+            .split_first()
+            .ok_or_else(|| X402Error::config("network_define requires a network name"))?;
+        if name.is_empty() || name.contains('=') || name.chars().any(char::is_whitespace) {
+            return Err(X402Error::config(format!("Invalid network name '{name}'")));
+        }
+
+        let mut chain_id = None;
+        let mut usdc = None;
+        let mut eip712_name = None;
+        let mut eip712_version = None;
+
+        for param in params {
+            let (key, value) = param.split_once('=').ok_or_else(|| {
+                X402Error::config(format!(
+                    "network_define parameter '{param}' is invalid, expected key=value"
+                ))
+            })?;
+            let duplicate = match key {
+                "chain_id" => {
+                    let id = value.parse::<u64>().map_err(|e| {
+                        X402Error::config(format!("Invalid network_define chain_id: {e}"))
+                    })?;
+                    chain_id.replace(id).is_some()
+                }
+                "usdc" => {
+                    super::validate_ethereum_address(value)?;
+                    usdc.replace(value.to_string()).is_some()
+                }
+                "eip712_name" => eip712_name.replace(value.to_string()).is_some(),
+                "eip712_version" => eip712_version.replace(value.to_string()).is_some(),
+                _ => {
+                    return Err(X402Error::config(format!(
+                        "Unknown network_define parameter '{key}'. Must be 'chain_id', 'usdc', 'eip712_name' or 'eip712_version'"
+                    )));
+                }
+            };
+            if duplicate {
+                return Err(X402Error::config(format!(
+                    "Duplicate network_define parameter '{key}'"
+                )));
+            }
+        }
+
+        let chain_id =
+            chain_id.ok_or_else(|| X402Error::config("network_define requires chain_id"))?;
+        if eip712_version.is_some() && eip712_name.is_none() {
+            return Err(X402Error::config(
+                "network_define eip712_version requires eip712_name",
+            ));
+        }
+        if eip712_name.is_some() && eip712_version.is_none() {
+            eip712_version = Some(USDC_EIP712_VERSION.to_string());
+        }
+
+        Ok(Self {
+            name: (*name).to_string(),
+            vm: NetworkVm::Evm,
+            chain_id: Some(chain_id),
+            usdc,
+            eip712_name,
+            eip712_version,
+        })
+    }
+}
@@ -44,6 +44,98 @@ pub fn validate_ethereum_address(address: &str) -> Result<()> {
     Ok(())
 }
 
+/// Base58 alphabet used by Solana (Bitcoin alphabet)
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Decode a base58 string
+///
+/// # Returns
+/// - `None` if the string contains characters outside the base58 alphabet
+fn decode_base58(value: &str) -> Option<Vec<u8>> {
+    // Little-endian bytes of the number decoded so far
+    let mut bytes: Vec<u8> = Vec::with_capacity(value.len());
+    for c in value.bytes() {
+        let mut carry = BASE58_ALPHABET.iter().position(|&a| a == c)? as u32;
+        for byte in &mut bytes {
+            carry += u32::from(*byte) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    // Each leading '1' encodes a leading zero byte
+    let zeros = value.bytes().take_while(|&c| c == b'1').count();
+    bytes.extend(std::iter::repeat_n(0, zeros));
+    bytes.reverse();
+    Some(bytes)
+}
+
+/// Validate Solana address format
+///
+/// Solana addresses (wallets and SPL token mints) are base58-encoded 32-byte
+/// public keys.
+///
+/// # Arguments
+/// - `address`: Address string to validate
+///
+/// # Returns
+/// - `Ok(())` if address is valid
+/// - `Err` if address format is invalid
+pub fn validate_solana_address(address: &str) -> Result<()> {
+    if address.is_empty() {
+        return Err(X402Error::config("Solana address cannot be empty"));
+    }
+
+    // 32 bytes encode to 32-44 base58 characters
+    if address.len() < 32 || address.len() > 44 {
+        return Err(X402Error::config(format!(
+            "Invalid Solana address length: expected 32 to 44 characters, got {}",
+            address.len()
+        )));
+    }
+
+    let bytes = decode_base58(address).ok_or_else(|| {
+        X402Error::config("Solana address contains invalid characters (must be base58)")
+    })?;
+    if bytes.len() != 32 {
+        return Err(X402Error::config(format!(
+            "Invalid Solana address: expected 32 bytes, got {}",
+            bytes.len()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Validate an address of any supported network (EVM or Solana)
+///
+/// Used where the network is not known yet; errors describe the EVM format.
+///
+/// # Returns
+/// - `Ok(())` if address is a valid EVM or Solana address
+/// - `Err` if address format is invalid
+pub fn validate_payment_address(address: &str) -> Result<()> {
+    if validate_solana_address(address).is_ok() {
+        return Ok(());
+    }
+    validate_ethereum_address(address)
+}
+
+/// Validate that an address has the format of a network's virtual machine
+///
+/// # Returns
+/// - `Ok(())` if address is valid on the network
+/// - `Err` if address format is invalid
+pub fn validate_network_address(vm: super::NetworkVm, address: &str) -> Result<()> {
+    match vm {
+        super::NetworkVm::Evm => validate_ethereum_address(address),
+        super::NetworkVm::Svm => validate_solana_address(address),
+    }
+}
+
 /// Validate URL format
 ///
 /// # Arguments
@@ -83,23 +175,16 @@ pub fn validate_url(url: &str) -> Result<()> {
 /// - `chain_id`: Chain ID (e.g., 8453 for Base Mainnet, 84532 for Base Sepolia)
 ///
 /// # Returns
-/// - `Ok(String)` with network name if chainId is supported
+/// - `Ok(String)` with network name if chainId is in the network registry
 /// - `Err` if chainId is not supported
 pub fn chain_id_to_network(chain_id: u64) -> Result<String> {
-    use rust_x402::types::networks;
-
-    let network = match chain_id {
-        8453 => networks::BASE_MAINNET,  // Base Mainnet
-        84532 => networks::BASE_SEPOLIA, // Base Sepolia
-        _ => {
-            return Err(X402Error::config(format!(
-                "Unsupported chainId: {}. Supported chainIds: 8453 (base), 84532 (base-sepolia)",
-                chain_id
-            )));
-        }
-    };
-
-    Ok(network.to_string())
+    super::network_by_chain_id(chain_id)
+        .map(|network| network.name)
+        .ok_or_else(|| {
+            X402Error::config(format!(
+                "Unsupported chainId: {chain_id}. Define it with x402_network_define"
+            ))
+        })
 }
 
 /// Validate network name
@@ -108,20 +193,18 @@ pub fn chain_id_to_network(chain_id: u64) -> Result<String> {
 /// - `network`: Network name to validate
 ///
 /// # Returns
-/// - `Ok(())` if network is supported
+/// - `Ok(())` if network is in the network registry
 /// - `Err` if network is not supported
 pub fn validate_network(network: &str) -> Result<()> {
-    use rust_x402::types::networks;
-
     if network.is_empty() {
         return Err(X402Error::config("Network name cannot be empty"));
     }
 
-    if !networks::is_supported(network) {
+    if super::network_by_name(network).is_none() {
         return Err(X402Error::config(format!(
             "Unsupported network: {}. Supported networks: {}",
             network,
-            networks::all_supported().join(", ")
+            super::network_names().join(", ")
         )));
     }
 
@@ -6,7 +6,7 @@ pub mod ngx_module;
 // Re-export validation functions for testing
 pub use config::{
     parse_accept_priority, validate_amount, validate_ethereum_address, validate_network,
-    validate_payment_header, validate_resource_path, validate_url,
+    validate_payment_header, validate_resource_path, validate_solana_address, validate_url,
 };
 
 // Re-export ngx_module types and functions
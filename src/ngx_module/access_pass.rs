@@ -0,0 +1,437 @@
+//! Time-based access passes
+//!
+//! Content sites often sell access for a period rather than per request ("0.05 USDC
+//! for 24h of /articles/"). With `x402_access_pass`, a successful paid response
+//! carries a signed cookie bound to the payer and a path scope (by default, the
+//! prefix of the location). Later requests within the scope that present a valid
+//! cookie are let through without running the payment flow
+//! (`$x402_status = access_pass`).
+//!
+//! Passes are stateless: the cookie value is the base64url-encoded
+//! `<payer>|<expires>|<scope>` followed by `.` and an HMAC-SHA256 of it under the
+//! location's pass key. That key is derived from the `key=` file, the names and
+//! listen addresses of the server and the name of the location, so a pass is only
+//! accepted where it was sold, even by locations and servers sharing the key
+//! file. Without `key=`, a random key is generated when the
+//! configuration is first read; it is shared by all workers and kept across
+//! reloads, but passes become invalid when nginx is restarted.
+//!
+//! A pass is only issued once the response is known to be successful and the
+//! payment has been settled: access pass locations settle before the request is
+//! passed on, and can't use `x402_settle off` or `x402_verify_mode local`.
+
+use crate::ngx_module::ctx::{request_ctx_mut, update_request_ctx, PaymentStatus};
+use crate::ngx_module::error::{ConfigError, Result};
+use crate::ngx_module::logging::{log_debug, log_info, log_warn};
+use crate::ngx_module::metrics::X402Metrics;
+use crate::ngx_module::replay::unix_now;
+use crate::ngx_module::session::{cookie_values, is_valid_cookie_name, parse_duration};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use ngx::http::Request;
+use sha2::Sha256;
+use std::ptr::NonNull;
+use std::sync::OnceLock;
+
+/// Default name of the access pass cookie
+pub const DEFAULT_ACCESS_PASS_COOKIE: &str = "x402_pass";
+
+/// Scope of a pass configured without `scope` outside a location
+pub const DEFAULT_ACCESS_PASS_SCOPE: &str = "/";
+
+/// Length of the generated key used without `key=` (bytes)
+const GENERATED_KEY_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Key generated for passes configured without `key=`
+static GENERATED_KEY: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Key signing passes of locations configured without `key=`
+///
+/// Generated on first use, which happens while the master process reads the
+/// configuration, so all workers share it.
+///
+/// # Errors
+/// - Returns error if no random key can be generated
+pub fn generated_key() -> Result<&'static [u8]> {
+    if let Some(key) = GENERATED_KEY.get() {
+        return Ok(key);
+    }
+    let mut key = vec![0u8; GENERATED_KEY_LEN];
+    getrandom::getrandom(&mut key)
+        .map_err(|e| ConfigError::from(format!("Failed to generate access pass key: {e}")))?;
+    Ok(GENERATED_KEY.get_or_init(|| key))
+}
+
+/// Key signing the passes of one location of a server
+///
+/// # Arguments
+/// - `key`: Key of the `key=` file, or the generated key
+/// - `server`: Names and listen addresses of the server
+/// - `location`: Name of the location of the directive (empty in `http` and `server`)
+#[must_use]
+pub fn location_key(key: &[u8], server: &str, location: &str) -> Vec<u8> {
+    pass_mac(key, &format!("server {server}\nlocation {location}"))
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+/// Scope of a pass configured without `scope`
+///
+/// # Arguments
+/// - `location`: Name of the location of the directive (empty in `http` and `server`)
+///
+/// # Returns
+/// - The location's prefix, or `/` outside a location
+/// - `None` for named and regex locations, which have no prefix
+#[must_use]
+pub fn default_scope(location: &str) -> Option<&str> {
+    if location.is_empty() {
+        return Some(DEFAULT_ACCESS_PASS_SCOPE);
+    }
+    let prefix = location.starts_with('/')
+        && !location
+            .bytes()
+            .any(|b| b.is_ascii_control() || b" ;,|\"\\^$*+?()[]{}".contains(&b));
+    prefix.then_some(location)
+}
+
+/// Access pass settings of a location (`x402_access_pass`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessPassConfig {
+    /// Pass lifetime (seconds)
+    pub duration: u64,
+    /// Path prefix the pass grants access to
+    pub scope: String,
+    /// Name of the pass cookie
+    pub cookie: String,
+    /// Key signing the passes
+    pub key: Vec<u8>,
+}
+
+impl AccessPassConfig {
+    /// Parse `x402_access_pass` arguments (`duration=T [scope=/prefix/] [cookie=name]`)
+    ///
+    /// The key is read by the directive handler (`key=<file>`) and passed separately.
+    ///
+    /// # Errors
+    /// - Returns error if a parameter is unknown, duplicated or invalid, or `duration` is missing
+    pub fn parse(value: &str, key: Vec<u8>) -> Result<Self> {
+        let mut duration = None;
+        let mut scope = None;
+        let mut cookie = None;
+
+        for param in value.split_whitespace() {
+            let (name, value) = param.split_once('=').ok_or_else(|| {
+                ConfigError::from(format!(
+                    "Invalid access_pass parameter '{param}', expected key=value"
+                ))
+            })?;
+            let duplicate = match name {
+                "duration" => {
+                    let seconds = parse_duration(value)
+                        .filter(|seconds| *seconds > 0)
+                        .ok_or_else(|| {
+                            ConfigError::from(format!("Invalid access_pass duration '{value}'"))
+                        })?;
+                    duration.replace(seconds).is_some()
+                }
+                "scope" => {
+                    // The scope is the cookie Path and part of the signed value
+                    let valid = value.starts_with('/')
+                        && !value
+                            .bytes()
+                            .any(|b| b.is_ascii_control() || b" ;,|\"\\".contains(&b));
+                    if !valid {
+                        return Err(ConfigError::from(format!(
+                            "Invalid access_pass scope '{value}', must be a path starting with '/'"
+                        )));
+                    }
+                    scope.replace(value.to_string()).is_some()
+                }
+                "cookie" => {
+                    if !is_valid_cookie_name(value) {
+                        return Err(ConfigError::from(format!(
+                            "Invalid access_pass cookie name '{value}'"
+                        )));
+                    }
+                    cookie.replace(value.to_string()).is_some()
+                }
+                _ => {
+                    return Err(ConfigError::from(format!(
+                        "Unknown access_pass parameter '{name}'. Must be 'duration', 'scope', 'cookie' or 'key'"
+                    )));
+                }
+            };
+            if duplicate {
+                return Err(ConfigError::from(format!(
+                    "Duplicate access_pass parameter '{name}'"
+                )));
+            }
+        }
+
+        Ok(AccessPassConfig {
+            duration: duration.ok_or_else(|| ConfigError::from("access_pass requires duration"))?,
+            scope: scope.unwrap_or_else(|| DEFAULT_ACCESS_PASS_SCOPE.to_string()),
+            cookie: cookie.unwrap_or_else(|| DEFAULT_ACCESS_PASS_COOKIE.to_string()),
+            key,
+        })
+    }
+
+    /// Check whether a request path is within the scope of the pass
+    #[must_use]
+    pub fn covers(&self, path: &str) -> bool {
+        path.starts_with(&self.scope)
+    }
+
+    /// Pass terms shown on the paywall, e.g. "Includes 24 hours of access to /articles/"
+    #[must_use]
+    pub fn terms(&self) -> String {
+        format!(
+            "Includes {} of access to {}",
+            format_duration(self.duration),
+            self.scope
+        )
+    }
+}
+
+/// `x402_access_pass` of a configuration block
+///
+/// Allocated from the configuration pool by the directive, which reads the key
+/// once. Passes are signed with a key derived for each server, which is only
+/// known once the configuration is merged (see [`AccessPassSettings::for_server`]).
+#[derive(Debug, Clone)]
+pub struct AccessPassSettings {
+    /// Settings, with the key signing the passes of `server`
+    pub config: AccessPassConfig,
+    /// Key of the `key=` file, or the generated key
+    pub secret: Vec<u8>,
+    /// Name of the location of the directive (empty in `http` and `server`)
+    pub location: String,
+    /// Server the signing key was derived for (None: not derived yet)
+    pub server: Option<String>,
+}
+
+impl AccessPassSettings {
+    /// Settings of a directive, before they are bound to a server
+    #[must_use]
+    pub fn new(config: AccessPassConfig, secret: Vec<u8>, location: String) -> Self {
+        Self {
+            config,
+            secret,
+            location,
+            server: None,
+        }
+    }
+
+    /// Settings signing the passes of `server` (see [`location_key`])
+    #[must_use]
+    pub fn for_server(&self, server: &str) -> Self {
+        let mut settings = self.clone();
+        settings.config.key = location_key(&self.secret, server, &self.location);
+        settings.server = Some(server.to_string());
+        settings
+    }
+}
+
+/// Access pass settings parsed at configuration time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfiguredAccessPass {
+    settings: NonNull<AccessPassSettings>,
+}
+
+impl ConfiguredAccessPass {
+    /// Wrap settings allocated by the `x402_access_pass` directive
+    #[must_use]
+    pub fn new(settings: NonNull<AccessPassSettings>) -> Self {
+        Self { settings }
+    }
+
+    /// The parsed settings
+    #[must_use]
+    pub fn get(&self) -> &AccessPassConfig {
+        // Safe: the settings live as long as the configuration cycle
+        unsafe { &self.settings.as_ref().config }
+    }
+}
+
+/// Human-readable duration, in the largest unit that divides it
+#[must_use]
+pub fn format_duration(seconds: u64) -> String {
+    let (count, unit) = match seconds {
+        s if s % (24 * 60 * 60) == 0 => (s / (24 * 60 * 60), "day"),
+        s if s % (60 * 60) == 0 => (s / (60 * 60), "hour"),
+        s if s % 60 == 0 => (s / 60, "minute"),
+        s => (s, "second"),
+    };
+    if count == 1 {
+        format!("1 {unit}")
+    } else {
+        format!("{count} {unit}s")
+    }
+}
+
+/// Access pass
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessPass {
+    /// Payer address of the payment that bought the pass
+    pub payer: String,
+    /// Path prefix the pass grants access to
+    pub scope: String,
+    /// Expiry time (unix seconds)
+    pub expires: u64,
+}
+
+impl AccessPass {
+    /// Encode the pass as a cookie value, signed with `key`
+    #[must_use]
+    pub fn encode(&self, key: &[u8]) -> String {
+        let payload = format!("{}|{}|{}", self.payer, self.expires, self.scope);
+        let mac = pass_mac(key, &payload).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(payload.as_bytes()),
+            hex::encode(mac)
+        )
+    }
+
+    /// Decode a pass signed with `key`
+    ///
+    /// # Returns
+    /// - `Some(AccessPass)` if the signature is valid and the pass has not expired
+    /// - `None` otherwise
+    #[must_use]
+    pub fn decode(value: &str, key: &[u8], now: u64) -> Option<Self> {
+        let (payload, mac) = value.trim().split_once('.')?;
+        let payload = String::from_utf8(URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+        let mac = hex::decode(mac).ok()?;
+        // Constant-time comparison
+        pass_mac(key, &payload).verify_slice(&mac).ok()?;
+
+        let mut parts = payload.splitn(3, '|');
+        let (payer, expires, scope) = (parts.next()?, parts.next()?, parts.next()?);
+        let expires = expires.parse::<u64>().ok()?;
+        (expires > now).then(|| AccessPass {
+            payer: payer.to_string(),
+            scope: scope.to_string(),
+            expires,
+        })
+    }
+}
+
+/// HMAC of a pass payload
+fn pass_mac(key: &[u8], payload: &str) -> HmacSha256 {
+    // HMAC accepts keys of any length
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC key of any length");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// `Set-Cookie` header value for an access pass
+#[must_use]
+pub fn access_pass_cookie(config: &AccessPassConfig, value: &str) -> String {
+    format!(
+        "{}={value}; Path={}; Max-Age={}; HttpOnly; Secure; SameSite=Lax",
+        config.cookie, config.scope, config.duration
+    )
+}
+
+/// Valid access pass presented with the request, if any
+fn presented_pass(r: &Request, config: &AccessPassConfig, now: u64) -> Option<AccessPass> {
+    r.headers_in_iterator()
+        .filter(|(key, _)| {
+            key.to_str()
+                .is_ok_and(|key| key.eq_ignore_ascii_case("Cookie"))
+        })
+        .filter_map(|(_, value)| value.to_str().ok())
+        .flat_map(|header| cookie_values(header, &config.cookie))
+        .filter_map(|value| AccessPass::decode(value, &config.key, now))
+        .find(|pass| pass.scope == config.scope)
+}
+
+/// Accept an access pass cookie in place of a payment
+///
+/// Missing, invalid and expired passes, and passes for another scope, are ignored,
+/// so the request goes through the payment flow.
+///
+/// # Returns
+/// - `true` if the request is covered by a valid pass
+pub fn redeem_access_pass(r: &mut Request, config: &AccessPassConfig) -> bool {
+    let in_scope = r.path().to_str().is_ok_and(|path| config.covers(path));
+    if !in_scope {
+        return false;
+    }
+    let Some(pass) = presented_pass(r, config, unix_now()) else {
+        return false;
+    };
+
+    log_debug(
+        Some(r),
+        &format!(
+            "Request covered by access pass, expires at {}",
+            pass.expires
+        ),
+    );
+    X402Metrics::get().record_access_pass_request();
+    update_request_ctx(r, |ctx| {
+        ctx.status = PaymentStatus::AccessPass;
+        ctx.payer = Some(pass.payer);
+    });
+    true
+}
+
+/// Issue an access pass with the response to a verified payment
+///
+/// Called from the header filter. Payments at access pass locations are settled
+/// before the request is passed on, so the pass is only issued for funds that
+/// were actually moved.
+pub fn issue_access_pass(r: &mut Request) {
+    let Some(ctx) = request_ctx_mut(r) else {
+        return;
+    };
+    let Some(access_pass) = ctx.pending_access_pass.take() else {
+        return;
+    };
+    let config = access_pass.get();
+    let payer = ctx.payer.clone().or_else(|| {
+        ctx.settle_response
+            .as_ref()
+            .and_then(|response| response.payer.clone())
+    });
+
+    let status = r.as_ref().headers_out.status;
+    if !(200..300).contains(&status) {
+        log_debug(
+            Some(r),
+            &format!("Response status {status} is not successful, not issuing an access pass"),
+        );
+        return;
+    }
+    let Some(payer) = payer.filter(|payer| !payer.contains('|')) else {
+        log_warn(Some(r), "Payer is unknown, not issuing an access pass");
+        return;
+    };
+
+    let expires = unix_now().saturating_add(config.duration);
+    let pass = AccessPass {
+        payer,
+        scope: config.scope.clone(),
+        expires,
+    };
+    let cookie = access_pass_cookie(config, &pass.encode(&config.key));
+    if r.add_header_out("Set-Cookie", &cookie).is_none() {
+        log_warn(Some(r), "Failed to add access pass Set-Cookie header");
+        return;
+    }
+    log_info(
+        Some(r),
+        &format!(
+            "Access pass to {} issued, expires at {expires}",
+            config.scope
+        ),
+    );
+    X402Metrics::get().record_access_pass_issued();
+}
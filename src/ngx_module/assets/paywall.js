@@ -0,0 +1,27 @@
+// x402 paywall page, served by x402_paywall_assets
+//
+// Exposes the payment requirements of the page as `window.x402` for wallet
+// integrations and announces them with an `x402:ready` event.
+(function () {
+  'use strict';
+
+  var element = document.getElementById('x402-requirements');
+  if (!element) {
+    return;
+  }
+
+  var requirements;
+  try {
+    requirements = JSON.parse(element.textContent);
+  } catch (e) {
+    console.warn('x402: invalid payment requirements', e);
+    return;
+  }
+
+  window.x402 = {
+    x402Version: requirements.x402Version,
+    error: requirements.error,
+    paymentRequirements: requirements.accepts || []
+  };
+  document.dispatchEvent(new CustomEvent('x402:ready', { detail: window.x402 }));
+})();
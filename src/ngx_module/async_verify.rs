@@ -0,0 +1,554 @@
+//! Non-blocking facilitator calls
+//!
+//! Verifying or settling a payment requires an HTTP round trip to the facilitator.
+//! Running it with `block_on` would freeze the whole nginx worker (and every
+//! connection it serves) for up to `x402_timeout` seconds, so the access phase
+//! handler instead:
+//!
+//! 1. Spawns the facilitator call on the tokio runtime
+//! 2. Marks the request as blocked (`r->main->blocked++`, `r->aio = 1`), the same way
+//!    nginx's own thread pool offloading does, so it cannot be freed while pending
+//! 3. Returns `NGX_AGAIN`, which suspends the phase engine for this request
+//!
+//! When the facilitator call finishes, the tokio task queues the result and wakes
+//! the nginx event loop by writing to a socket pair owned by the module (see
+//! [`NotifyChannel`]). Its read end is registered with the worker's event module
+//! like any connection, so the read handler runs on the nginx worker thread. It
+//! stores the result in the request context, unblocks the request and re-runs its
+//! phase handlers, which then pick up the completed call.
+//!
+//! Payments settled after a successful response (`x402_settle after_success`) use
+//! the same queue, but the request is not suspended: the header filter holds the
+//! response and takes a reference on the request (`r->main->count++`). Once the
+//! settlement has been recorded, the held response is sent with its
+//! `X-PAYMENT-RESPONSE` header and the reference is released.
+//!
+//! If the socket pair can't be set up, a periodic sweep timer drains the queue
+//! instead while calls are in flight.
+
+use crate::ngx_module::config::{FacilitatorEndpoint, FacilitatorPolicy};
+use crate::ngx_module::ctx::{get_or_create_request_ctx, request_ctx_mut, PendingSettlement};
+use crate::ngx_module::error::{ConfigError, Result};
+use crate::ngx_module::filter::send_held_response;
+use crate::ngx_module::logging::{log_debug, log_warn};
+use crate::ngx_module::panic_handler::catch_panic;
+use crate::ngx_module::runtime::{get_runtime, settle_payment, verify_payment};
+use crate::ngx_module::settlement::finish_settlement;
+use ngx::ffi::{ngx_connection_t, ngx_event_t, ngx_http_request_t};
+use ngx::http::Request;
+use rust_x402::types::{PaymentRequirements, SettleResponse};
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+/// Interval of the sweep timer used without a notification channel
+const COMPLETION_SWEEP_INTERVAL_MS: ngx::ffi::ngx_msec_t = 50;
+
+/// How facilitator calls are performed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationMode {
+    /// Suspend the request (`NGX_AGAIN`) and resume it from the event loop
+    NonBlocking,
+    /// Block the worker until the facilitator responds
+    Blocking,
+}
+
+/// Result of a finished facilitator verification
+pub struct CompletedVerification {
+    /// Verification result (`Ok(is_valid)` or facilitator error)
+    pub result: Result<bool>,
+    /// Facilitator round-trip duration in seconds
+    pub duration_secs: f64,
+}
+
+/// Result of a finished facilitator settlement
+pub struct CompletedSettlement {
+    /// Facilitator settlement response (check `success`) or facilitator error
+    pub result: Result<SettleResponse>,
+    /// Facilitator round-trip duration in seconds
+    pub duration_secs: f64,
+}
+
+/// Result of a facilitator call
+enum Outcome {
+    Verification(CompletedVerification),
+    Settlement(CompletedSettlement),
+}
+
+/// What happens to the request once its call has finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// The request was suspended in the access phase, its phase handlers are re-run
+    Resume,
+    /// The response is held by the header filter, it is sent and the request's
+    /// reference is released
+    Release,
+}
+
+/// Call result waiting to be delivered on the nginx worker thread
+struct Completion {
+    /// Request pointer (kept alive by `r->main->blocked`)
+    request: usize,
+    delivery: Delivery,
+    outcome: Outcome,
+}
+
+/// Results of facilitator calls, pushed by tokio tasks and drained on the worker thread
+pub struct CompletionQueue<T> {
+    completions: Mutex<Vec<T>>,
+    in_flight: AtomicUsize,
+}
+
+impl<T> CompletionQueue<T> {
+    /// Empty queue
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            completions: Mutex::new(Vec::new()),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    /// Count a call that has been started
+    pub fn start(&self) {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Queue the result of a started call
+    pub fn push(&self, completion: T) {
+        if let Ok(mut completions) = self.completions.lock() {
+            completions.push(completion);
+        }
+    }
+
+    /// Take all queued results, which are no longer counted as in flight
+    pub fn drain(&self) -> Vec<T> {
+        let completions = match self.completions.lock() {
+            Ok(mut completions) => std::mem::take(&mut *completions),
+            Err(_) => return Vec::new(),
+        };
+        self.in_flight
+            .fetch_sub(completions.len(), Ordering::SeqCst);
+        completions
+    }
+
+    /// Number of calls started but not yet drained
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+impl<T> Default for CompletionQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Socket pair waking the nginx event loop from tokio threads
+///
+/// The read end is registered with the worker's event module; writing a byte to
+/// the other end makes it readable. Both ends are non-blocking: if the socket
+/// buffer is full, a wakeup is pending anyway.
+pub struct NotifyChannel {
+    reader: UnixStream,
+    writer: UnixStream,
+}
+
+impl NotifyChannel {
+    /// Create a channel
+    ///
+    /// # Errors
+    /// - Returns error if the socket pair can't be created
+    pub fn new() -> io::Result<Self> {
+        let (reader, writer) = UnixStream::pair()?;
+        reader.set_nonblocking(true)?;
+        writer.set_nonblocking(true)?;
+        Ok(Self { reader, writer })
+    }
+
+    /// Wake the reader (any thread)
+    pub fn notify(&self) {
+        // WouldBlock means unread wakeups are pending already
+        let _ = (&self.writer).write(&[1]);
+    }
+
+    /// Consume pending wakeups (worker thread)
+    ///
+    /// # Returns
+    /// - `true` if there was at least one wakeup
+    pub fn clear(&self) -> bool {
+        let mut woken = false;
+        let mut buf = [0u8; 64];
+        while let Ok(read) = (&self.reader).read(&mut buf) {
+            if read == 0 {
+                break;
+            }
+            woken = true;
+        }
+        woken
+    }
+
+    /// File descriptor of the read end
+    #[must_use]
+    pub fn reader_fd(&self) -> i32 {
+        self.reader.as_raw_fd()
+    }
+}
+
+/// Finished calls, filled by tokio tasks and drained by the event loop
+static COMPLETIONS: CompletionQueue<Completion> = CompletionQueue::new();
+
+/// Notification channel of this worker process (None: it couldn't be set up)
+///
+/// Created on first use from the worker thread, after the worker was forked.
+static NOTIFY: OnceLock<Option<NotifyChannel>> = OnceLock::new();
+
+/// Sweep timer event (only touched from the nginx worker thread)
+static mut SWEEP_EVENT: ngx_event_t = unsafe { std::mem::zeroed() };
+
+/// Start a non-blocking verification for this request
+///
+/// On success the caller must return `NGX_AGAIN` from the phase handler. The phase
+/// handler will be re-run once the result is available, and the result can be
+/// retrieved with [`take_completed_verification`].
+///
+/// # Errors
+/// - Returns error if the runtime or request context is unavailable
+pub fn start_verification(
+    r: &mut Request,
+    payment_b64: &str,
+    requirements: &PaymentRequirements,
+    facilitators: &[FacilitatorEndpoint],
+    policy: FacilitatorPolicy,
+) -> Result<()> {
+    let payment_b64 = payment_b64.to_string();
+    let requirements = requirements.clone();
+    let facilitators = facilitators.to_vec();
+
+    start_call(r, Delivery::Resume, async move {
+        let start = Instant::now();
+        let result = verify_payment(&payment_b64, &requirements, &facilitators, policy).await;
+        Outcome::Verification(CompletedVerification {
+            result,
+            duration_secs: start.elapsed().as_secs_f64(),
+        })
+    })
+}
+
+/// Start a non-blocking settlement for this request
+///
+/// Like [`start_verification`]: the caller must return `NGX_AGAIN`, and the
+/// result can be retrieved with [`take_completed_settlement`] once the phase
+/// handler is re-run.
+///
+/// # Errors
+/// - Returns error if the runtime or request context is unavailable
+pub fn start_settlement(r: &mut Request, pending: PendingSettlement) -> Result<()> {
+    start_call(r, Delivery::Resume, settle(pending))
+}
+
+/// Settle a payment once its response is known to be successful
+///
+/// Called from the header filter, which holds the response. The request is not
+/// suspended; it is kept alive until the settlement has been recorded with
+/// `finish_settlement`, then the held response is sent (see `send_held_response`).
+///
+/// # Errors
+/// - Returns error if the runtime or request context is unavailable
+pub fn start_response_settlement(r: &mut Request, pending: PendingSettlement) -> Result<()> {
+    start_call(r, Delivery::Release, settle(pending))
+}
+
+/// Settlement call of a pending payment
+async fn settle(pending: PendingSettlement) -> Outcome {
+    let start = Instant::now();
+    let result = settle_payment(
+        &pending.payment_b64,
+        &pending.requirements,
+        &pending.facilitators,
+        pending.policy,
+    )
+    .await;
+    Outcome::Settlement(CompletedSettlement {
+        result,
+        duration_secs: start.elapsed().as_secs_f64(),
+    })
+}
+
+/// Block the request and run a facilitator call on the tokio runtime
+fn start_call(
+    r: &mut Request,
+    delivery: Delivery,
+    call: impl Future<Output = Outcome> + Send + 'static,
+) -> Result<()> {
+    let runtime = get_runtime()?;
+    get_or_create_request_ctx(r)
+        .ok_or_else(|| ConfigError::from("Failed to allocate request context"))?;
+
+    let raw: *mut ngx_http_request_t = r.as_mut();
+    // Safe: we're on the worker thread and `raw` is the live request
+    unsafe {
+        block_request(raw, delivery);
+        if notify_channel().is_none() {
+            arm_sweep_timer();
+        }
+    }
+    COMPLETIONS.start();
+    let request = raw as usize;
+
+    runtime.spawn(async move {
+        let outcome = call.await;
+        COMPLETIONS.push(Completion {
+            request,
+            delivery,
+            outcome,
+        });
+        if let Some(Some(channel)) = NOTIFY.get() {
+            channel.notify();
+        }
+    });
+
+    Ok(())
+}
+
+/// Take the completed verification stored for this request, if any
+///
+/// Returns `Some` only on the resumed pass of the phase handler.
+pub fn take_completed_verification(r: &mut Request) -> Option<CompletedVerification> {
+    request_ctx_mut(r).and_then(|ctx| ctx.completed_verification.take())
+}
+
+/// Take the completed settlement stored for this request, if any
+///
+/// Returns `Some` only on the pass of the phase handler resumed after settlement.
+pub fn take_completed_settlement(r: &mut Request) -> Option<CompletedSettlement> {
+    request_ctx_mut(r).and_then(|ctx| ctx.completed_settlement.take())
+}
+
+/// Mark a request as waiting for a facilitator call
+///
+/// The main request's `blocked` counter keeps nginx from freeing the request
+/// while the call is in flight. A suspended request (`Resume`) also has `aio`
+/// set, like a request waiting for a thread pool task. A request whose response
+/// is held (`Release`) takes a reference instead, so it isn't logged before the
+/// call has been recorded.
+///
+/// # Safety
+///
+/// `r` must point to a live request whose `main` pointer is valid.
+pub unsafe fn block_request(r: *mut ngx_http_request_t, delivery: Delivery) {
+    let main = (*r).main;
+    (*main).set_blocked((*main).blocked() + 1);
+    match delivery {
+        Delivery::Resume => (*r).set_aio(1),
+        Delivery::Release => (*main).set_count((*main).count() + 1),
+    }
+}
+
+/// Undo [`block_request`] once the call has finished
+///
+/// The reference taken for `Release` is not dropped here: it is released by
+/// `ngx_http_finalize_request(r, NGX_DONE)`, which may free the request.
+///
+/// # Safety
+///
+/// `r` must point to a request passed to [`block_request`] with the same `delivery`.
+pub unsafe fn unblock_request(r: *mut ngx_http_request_t, delivery: Delivery) {
+    let main = (*r).main;
+    (*main).set_blocked((*main).blocked() - 1);
+    if delivery == Delivery::Resume {
+        (*r).set_aio(0);
+    }
+}
+
+/// Notification channel of this worker, registered with the event loop on first use
+///
+/// Must be called from the nginx worker thread.
+fn notify_channel() -> Option<&'static NotifyChannel> {
+    NOTIFY
+        .get_or_init(|| {
+            let channel = NotifyChannel::new()
+                .map_err(|e| e.to_string())
+                .and_then(|channel| {
+                    // Safe: on the worker thread, the cycle is initialized
+                    unsafe { register_notify_channel(&channel) }.map(|()| channel)
+                });
+            match channel {
+                Ok(channel) => Some(channel),
+                Err(e) => {
+                    log_warn(
+                        None,
+                        &format!(
+                            "Failed to set up facilitator call notifications, polling instead: {e}"
+                        ),
+                    );
+                    None
+                }
+            }
+        })
+        .as_ref()
+}
+
+/// Register the read end of the channel as a connection of the event loop
+///
+/// # Safety
+///
+/// Must be called from the nginx worker thread.
+unsafe fn register_notify_channel(channel: &NotifyChannel) -> std::result::Result<(), String> {
+    let log = (*ngx::ffi::ngx_cycle).log;
+    let c: *mut ngx_connection_t = ngx::ffi::ngx_get_connection(channel.reader_fd(), log);
+    if c.is_null() {
+        return Err("no free connection".to_string());
+    }
+    let rev = (*c).read;
+    (*rev).handler = Some(notify_read_handler);
+    (*rev).log = log;
+    // Like nginx's own master/worker channel: not reported as a leaked socket on exit
+    (*rev).set_channel(1);
+    (*(*c).write).set_channel(1);
+    if ngx::ffi::ngx_handle_read_event(rev, 0) != ngx::ffi::NGX_OK as ngx::ffi::ngx_int_t {
+        ngx::ffi::ngx_free_connection(c);
+        return Err("failed to add read event".to_string());
+    }
+    Ok(())
+}
+
+/// Read handler of the notification channel
+unsafe extern "C" fn notify_read_handler(ev: *mut ngx_event_t) {
+    if let Some(Some(channel)) = NOTIFY.get() {
+        channel.clear();
+    }
+    (*ev).set_ready(0);
+    if ngx::ffi::ngx_handle_read_event(ev, 0) != ngx::ffi::NGX_OK as ngx::ffi::ngx_int_t {
+        log_warn(None, "Failed to re-arm facilitator call notifications");
+    }
+    drain_completions();
+}
+
+/// Sweep timer handler
+unsafe extern "C" fn completion_sweep_handler(_ev: *mut ngx_event_t) {
+    drain_completions();
+    if COMPLETIONS.in_flight() > 0 {
+        arm_sweep_timer();
+    }
+}
+
+/// Arm the sweep timer if it's not already pending
+///
+/// # Safety
+///
+/// Must be called from the nginx worker thread.
+unsafe fn arm_sweep_timer() {
+    let ev = &raw mut SWEEP_EVENT;
+    if (*ev).handler.is_none() {
+        (*ev).handler = Some(completion_sweep_handler);
+        (*ev).log = (*ngx::ffi::ngx_cycle).log;
+        // Don't keep the worker alive during graceful shutdown just for this timer
+        (*ev).set_cancelable(1);
+    }
+    if (*ev).timer_set() == 0 {
+        ngx::ffi::ngx_add_timer(ev, COMPLETION_SWEEP_INTERVAL_MS);
+    }
+}
+
+/// Deliver all finished calls to their requests
+///
+/// Runs on the nginx worker thread.
+fn drain_completions() {
+    for completion in COMPLETIONS.drain() {
+        let r = completion.request as *mut ngx_http_request_t;
+        match completion.delivery {
+            Delivery::Resume => catch_panic(
+                || unsafe { resume_request(r, completion.outcome) },
+                "resume request after facilitator call",
+            ),
+            Delivery::Release => catch_panic(
+                || unsafe { release_request(r, completion.outcome) },
+                "send response after settlement",
+            ),
+        };
+    }
+}
+
+/// Unblock a suspended request and re-run its phase handlers
+///
+/// # Safety
+///
+/// `r` must be a request suspended by [`start_verification`] or
+/// [`start_settlement`] (still blocked).
+unsafe fn resume_request(r: *mut ngx_http_request_t, outcome: Outcome) {
+    let c = (*r).connection;
+    unblock_request(r, Delivery::Resume);
+
+    let req = Request::from_ngx_http_request(r);
+    let (call, duration_secs) = match outcome {
+        Outcome::Verification(ref verification) => ("verification", verification.duration_secs),
+        Outcome::Settlement(ref settlement) => ("settlement", settlement.duration_secs),
+    };
+    log_debug(
+        Some(req),
+        &format!("Facilitator {call} finished after {duration_secs:.3}s, resuming request"),
+    );
+    if let Some(ctx) = request_ctx_mut(req) {
+        match outcome {
+            Outcome::Verification(verification) => ctx.completed_verification = Some(verification),
+            Outcome::Settlement(settlement) => ctx.completed_settlement = Some(settlement),
+        }
+    }
+
+    // write_event_handler is ngx_http_core_run_phases while the request is in the
+    // phase engine, or ngx_http_request_finalizer if it was terminated meanwhile
+    if let Some(write_event_handler) = (*r).write_event_handler {
+        write_event_handler(r);
+    }
+    ngx::ffi::ngx_http_run_posted_requests(c);
+}
+
+/// Record a settlement made for a held response, send it and release the request
+///
+/// # Safety
+///
+/// `r` must be a request passed to [`start_response_settlement`] (still blocked
+/// and referenced).
+unsafe fn release_request(r: *mut ngx_http_request_t, outcome: Outcome) {
+    let c = (*r).connection;
+    unblock_request(r, Delivery::Release);
+
+    let req = Request::from_ngx_http_request(r);
+    if let Outcome::Settlement(settlement) = outcome {
+        if let Some(response) = finish_settlement(req, settlement) {
+            if let Some(ctx) = request_ctx_mut(req) {
+                ctx.settle_response = Some(response);
+            }
+        }
+    }
+
+    if (*c).error() != 0 {
+        // Terminated meanwhile: write_event_handler is ngx_http_request_finalizer,
+        // which closes the request regardless of its references
+        if let Some(write_event_handler) = (*r).write_event_handler {
+            write_event_handler(r);
+        }
+    } else {
+        // The response is sent even if the settlement failed: the upstream has
+        // already served the request
+        let rc = send_held_response(r);
+        if rc == ngx::ffi::NGX_ERROR as ngx::ffi::ngx_int_t
+            || rc > ngx::ffi::NGX_OK as ngx::ffi::ngx_int_t
+        {
+            ngx::ffi::ngx_http_finalize_request(r, rc);
+        } else if let Some(write_event_handler) = (*r).write_event_handler {
+            // ngx_http_writer if the request has been finalized meanwhile, or the
+            // upstream's handler if it is still being proxied: carries on sending
+            write_event_handler(r);
+        }
+        // Drops the reference taken by start_response_settlement; the request is
+        // logged and freed if it was the last one
+        ngx::ffi::ngx_http_finalize_request(r, ngx::ffi::NGX_DONE as ngx::ffi::ngx_int_t);
+    }
+    ngx::ffi::ngx_http_run_posted_requests(c);
+}
@@ -0,0 +1,303 @@
+//! Payment bypass rules
+//!
+//! `x402_bypass` lets trusted clients reach a protected location without paying:
+//!
+//! - `cidr`: client addresses in the given ranges (after `real_ip`)
+//! - `header`: a request header carrying a shared secret, read from a file
+//! - `api_key`: a request header carrying one of the keys listed in a file
+//!
+//! Secrets and keys are compared as SHA-256 digests in constant time, so the
+//! time taken doesn't reveal how much of a guess was right. Bypassed requests
+//! have `$x402_status = bypassed` and are counted by
+//! `x402_bypassed_requests_total`, labelled with the matching rule.
+//!
+//! # Example
+//! ```nginx
+//! x402_bypass cidr 10.0.0.0/8 fd00::/8;
+//! x402_bypass header X-Internal-Token /etc/nginx/x402/internal.secret;
+//! x402_bypass api_key X-API-Key /etc/nginx/x402/partner.keys;
+//! ```
+
+use crate::ngx_module::error::{ConfigError, Result};
+use crate::ngx_module::module::get_module_config;
+use crate::ngx_module::request::get_header_value;
+use ngx::core::NgxStr;
+use ngx::http::Request;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use std::str::FromStr;
+use subtle::ConstantTimeEq;
+
+/// Minimum length of bypass secrets and API keys, in bytes
+pub const MIN_SECRET_LEN: usize = 16;
+
+/// Why a request skipped payment verification (`reason` label of the metric)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BypassReason {
+    /// OPTIONS, HEAD or TRACE request
+    Method,
+    /// WebSocket upgrade
+    WebSocket,
+    /// Client address in an `x402_bypass cidr` range
+    Cidr,
+    /// Shared secret of an `x402_bypass header` rule
+    Header,
+    /// Key listed by an `x402_bypass api_key` rule
+    ApiKey,
+}
+
+impl BypassReason {
+    /// Metric label of this reason
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BypassReason::Method => "method",
+            BypassReason::WebSocket => "websocket",
+            BypassReason::Cidr => "cidr",
+            BypassReason::Header => "header",
+            BypassReason::ApiKey => "api_key",
+        }
+    }
+}
+
+/// Address range in CIDR notation (`10.0.0.0/8`, `fd00::/8`, or a single address)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Parse a CIDR range
+    ///
+    /// # Errors
+    /// - Returns error if the address or prefix length is invalid, or if the
+    ///   address has bits set after the prefix
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = || ConfigError::from(format!("Invalid CIDR range \"{value}\""));
+        let (address, prefix_len) = match value.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (value, None),
+        };
+        let network = IpAddr::from_str(address).map_err(|_| invalid())?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(invalid)?,
+            None => max_len,
+        };
+
+        let cidr = Cidr {
+            network,
+            prefix_len,
+        };
+        if cidr.masked(network) != address_bits(network) {
+            return Err(ConfigError::from(format!(
+                "Invalid CIDR range \"{value}\", the address has bits set after the prefix"
+            )));
+        }
+        Ok(cidr)
+    }
+
+    /// Whether the range contains an address
+    ///
+    /// IPv4-mapped IPv6 addresses (`::ffff:10.0.0.1`) match IPv4 ranges.
+    #[must_use]
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let address = match address {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+            IpAddr::V4(_) => address,
+        };
+        address.is_ipv4() == self.network.is_ipv4()
+            && self.masked(address) == address_bits(self.network)
+    }
+
+    /// Address bits after applying the prefix mask
+    fn masked(&self, address: IpAddr) -> u128 {
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        let host_bits = u32::from(bits - self.prefix_len);
+        let mask = u128::MAX.checked_shl(host_bits).unwrap_or(0);
+        address_bits(address) & mask
+    }
+}
+
+/// Address as an integer (IPv4 addresses in the low 32 bits)
+fn address_bits(address: IpAddr) -> u128 {
+    match address {
+        IpAddr::V4(v4) => u128::from(u32::from(v4)),
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+/// One `x402_bypass` rule
+#[derive(Debug, Clone)]
+pub enum BypassRule {
+    /// Client addresses in any of the ranges
+    Cidr(Vec<Cidr>),
+    /// Header carrying a shared secret (SHA-256 digest)
+    Header { name: String, secret: [u8; 32] },
+    /// Header carrying one of the keys (SHA-256 digests)
+    ApiKey { name: String, keys: Vec<[u8; 32]> },
+}
+
+impl BypassRule {
+    /// Rule of `x402_bypass cidr <range> ...`
+    ///
+    /// # Errors
+    /// - Returns error if a range is invalid
+    pub fn cidr(ranges: &[&str]) -> Result<Self> {
+        if ranges.is_empty() {
+            return Err(ConfigError::from("No CIDR range"));
+        }
+        let ranges = ranges
+            .iter()
+            .map(|range| Cidr::parse(range))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(BypassRule::Cidr(ranges))
+    }
+
+    /// Rule of `x402_bypass header <name> <file>`, from the content of the secret file
+    ///
+    /// Surrounding whitespace (such as a trailing newline) isn't part of the secret.
+    ///
+    /// # Errors
+    /// - Returns error if the header name is invalid or the secret too short
+    pub fn header(name: &str, secret: &str) -> Result<Self> {
+        let name = header_name(name)?;
+        let secret = secret.trim();
+        if secret.len() < MIN_SECRET_LEN {
+            return Err(ConfigError::from(format!(
+                "Bypass secret is too short, at least {MIN_SECRET_LEN} bytes are required"
+            )));
+        }
+        Ok(BypassRule::Header {
+            name,
+            secret: Sha256::digest(secret.as_bytes()).into(),
+        })
+    }
+
+    /// Rule of `x402_bypass api_key <name> <file>`, from the content of the key file
+    ///
+    /// The file lists one key per line; empty lines and lines starting with `#`
+    /// are ignored.
+    ///
+    /// # Errors
+    /// - Returns error if the header name is invalid, a key too short, or the
+    ///   file lists no key
+    pub fn api_keys(name: &str, keys: &str) -> Result<Self> {
+        let name = header_name(name)?;
+        let mut digests = Vec::new();
+        for (index, line) in keys.lines().enumerate() {
+            let key = line.trim();
+            if key.is_empty() || key.starts_with('#') {
+                continue;
+            }
+            if key.len() < MIN_SECRET_LEN {
+                return Err(ConfigError::from(format!(
+                    "API key on line {} is too short, at least {MIN_SECRET_LEN} bytes are required",
+                    index + 1
+                )));
+            }
+            digests.push(Sha256::digest(key.as_bytes()).into());
+        }
+        if digests.is_empty() {
+            return Err(ConfigError::from("No API key"));
+        }
+        Ok(BypassRule::ApiKey {
+            name,
+            keys: digests,
+        })
+    }
+}
+
+/// Validate a header name (an HTTP token)
+fn header_name(name: &str) -> Result<String> {
+    let valid = !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    if valid {
+        Ok(name.to_string())
+    } else {
+        Err(ConfigError::from(format!("Invalid header name \"{name}\"")))
+    }
+}
+
+/// Whether a presented value matches a digest, in constant time
+fn secret_matches(value: &str, digest: &[u8; 32]) -> bool {
+    let presented: [u8; 32] = Sha256::digest(value.as_bytes()).into();
+    bool::from(presented.ct_eq(digest))
+}
+
+/// `x402_bypass` rules of a block, allocated from the configuration pool
+#[derive(Debug, Clone, Default)]
+pub struct BypassRules {
+    rules: Vec<BypassRule>,
+}
+
+impl BypassRules {
+    /// Add a rule
+    pub fn add(&mut self, rule: BypassRule) {
+        self.rules.push(rule);
+    }
+
+    /// Rule matching a request, if any
+    ///
+    /// # Arguments
+    /// - `client`: Client address (None: not an IP connection)
+    /// - `header`: Lookup of request header values by name
+    #[must_use]
+    pub fn matches(
+        &self,
+        client: Option<IpAddr>,
+        header: impl Fn(&str) -> Option<String>,
+    ) -> Option<BypassReason> {
+        self.rules.iter().find_map(|rule| {
+            let matched = match rule {
+                BypassRule::Cidr(ranges) => {
+                    client.is_some_and(|client| ranges.iter().any(|range| range.contains(client)))
+                }
+                BypassRule::Header { name, secret } => {
+                    header(name).is_some_and(|value| secret_matches(&value, secret))
+                }
+                // Every key is compared, so the time taken doesn't reveal which one matched
+                BypassRule::ApiKey { name, keys } => header(name).is_some_and(|value| {
+                    keys.iter()
+                        .fold(false, |found, key| found | secret_matches(&value, key))
+                }),
+            };
+            matched.then_some(match rule {
+                BypassRule::Cidr(_) => BypassReason::Cidr,
+                BypassRule::Header { .. } => BypassReason::Header,
+                BypassRule::ApiKey { .. } => BypassReason::ApiKey,
+            })
+        })
+    }
+}
+
+/// Client address of a request (as rewritten by `real_ip`)
+fn client_address(r: &Request) -> Option<IpAddr> {
+    let connection = r.as_ref().connection;
+    if connection.is_null() {
+        return None;
+    }
+    // Safe: the connection outlives the request
+    let addr_text = unsafe { (*connection).addr_text };
+    if addr_text.len == 0 || addr_text.data.is_null() {
+        return None;
+    }
+    let text = unsafe { NgxStr::from_ngx_str(addr_text) }.to_str().ok()?;
+    IpAddr::from_str(text).ok()
+}
+
+/// `x402_bypass` rule matching a request, if any
+#[must_use]
+pub fn request_bypass_reason(r: &Request) -> Option<BypassReason> {
+    let rules = get_module_config(r).ok()?.bypass?;
+    // Safe: set by the x402_bypass directive, lives as long as the configuration cycle
+    let rules = unsafe { rules.as_ref() };
+    rules.matches(client_address(r), |name| get_header_value(r, name))
+}
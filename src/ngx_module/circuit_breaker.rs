@@ -0,0 +1,263 @@
+//! Circuit breaker for facilitator calls
+//!
+//! With `x402_circuit_breaker`, the outcome of each facilitator call of a
+//! location (after failover and retries) is recorded. A payment the facilitator
+//! refused (4xx) is neither a success nor a failure, and isn't recorded:
+//!
+//! - **Closed**: calls go through. Once `min_calls` calls were made within
+//!   `window` and at least `failure_rate` percent of them failed, the breaker
+//!   opens
+//! - **Open**: calls fail immediately, so requests get the
+//!   `x402_facilitator_fallback` behaviour without waiting for `x402_timeout`.
+//!   After `open_time` the breaker half-opens
+//! - **Half-open**: one probe call goes through, the others still fail
+//!   immediately. The breaker closes if the probe succeeds and opens again if
+//!   it fails
+//!
+//! Locations with the same facilitators share a breaker. Like facilitator
+//! health (see `failover`), each worker process has its own breakers. State
+//! changes are logged and exported as the `x402_circuit_breaker_state` gauge.
+
+use crate::ngx_module::config::{CircuitBreakerConfig, FacilitatorEndpoint};
+use crate::ngx_module::error::user_errors;
+use crate::ngx_module::failover::{CallError, FailureKind};
+use crate::ngx_module::logging::{log_info, log_warn};
+use crate::ngx_module::metrics::X402Metrics;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+/// Breakers of this worker process, keyed by facilitator URLs
+static BREAKERS: OnceLock<Mutex<HashMap<String, CircuitBreaker>>> = OnceLock::new();
+
+/// State of a circuit breaker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Calls go through
+    Closed,
+    /// Calls fail immediately
+    Open,
+    /// One probe call goes through
+    HalfOpen,
+}
+
+impl BreakerState {
+    /// Name used in logs
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half-open",
+        }
+    }
+
+    /// Value of the `x402_circuit_breaker_state` gauge
+    #[must_use]
+    pub fn gauge_value(self) -> i64 {
+        match self {
+            BreakerState::Closed => 0,
+            BreakerState::HalfOpen => 1,
+            BreakerState::Open => 2,
+        }
+    }
+}
+
+/// Circuit breaker of one set of facilitators
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreaker {
+    state: BreakerState,
+    /// Start of the current window (closed)
+    window_start: Instant,
+    /// Calls in the current window (closed)
+    calls: u32,
+    /// Failed calls in the current window (closed)
+    failures: u32,
+    /// When the breaker opened (open)
+    opened_at: Instant,
+    /// When the probe call started (half-open)
+    probe_started: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// Closed breaker
+    #[must_use]
+    pub fn new(now: Instant) -> Self {
+        Self {
+            state: BreakerState::Closed,
+            window_start: now,
+            calls: 0,
+            failures: 0,
+            opened_at: now,
+            probe_started: None,
+        }
+    }
+
+    /// Current state
+    #[must_use]
+    pub fn state(&self) -> BreakerState {
+        self.state
+    }
+
+    /// Whether a call may be made at `now`
+    ///
+    /// Half-opens the breaker once `open_time` has elapsed; the call allowed
+    /// then is the probe.
+    pub fn allow(&mut self, config: &CircuitBreakerConfig, now: Instant) -> bool {
+        match self.state {
+            BreakerState::Closed => true,
+            BreakerState::Open => {
+                if now.duration_since(self.opened_at) < config.open_time {
+                    return false;
+                }
+                self.state = BreakerState::HalfOpen;
+                self.probe_started = Some(now);
+                true
+            }
+            BreakerState::HalfOpen => {
+                // A probe that never reported back (e.g. a dropped request) is replaced
+                let stale = self
+                    .probe_started
+                    .is_none_or(|started| now.duration_since(started) >= config.open_time);
+                if stale {
+                    self.probe_started = Some(now);
+                }
+                stale
+            }
+        }
+    }
+
+    /// Record the outcome of a call allowed by `allow`
+    pub fn record(&mut self, config: &CircuitBreakerConfig, success: bool, now: Instant) {
+        match self.state {
+            BreakerState::Closed => {
+                if now.duration_since(self.window_start) >= config.window {
+                    self.window_start = now;
+                    self.calls = 0;
+                    self.failures = 0;
+                }
+                self.calls += 1;
+                if !success {
+                    self.failures += 1;
+                }
+                if self.calls >= config.min_calls
+                    && u64::from(self.failures) * 100
+                        >= u64::from(config.failure_rate) * u64::from(self.calls)
+                {
+                    self.open(now);
+                }
+            }
+            BreakerState::HalfOpen => {
+                if success {
+                    *self = Self::new(now);
+                } else {
+                    self.open(now);
+                }
+            }
+            // Calls that started before the breaker opened
+            BreakerState::Open => {}
+        }
+    }
+
+    /// Forget a call allowed by `allow` that says nothing about the facilitators
+    ///
+    /// A half-open breaker lets the next call probe instead.
+    pub fn release(&mut self) {
+        if self.state == BreakerState::HalfOpen {
+            self.probe_started = None;
+        }
+    }
+
+    fn open(&mut self, now: Instant) {
+        self.state = BreakerState::Open;
+        self.opened_at = now;
+        self.probe_started = None;
+    }
+}
+
+/// Run a facilitator call through the circuit breaker of `endpoints`
+///
+/// # Arguments
+/// - `endpoints`: Facilitators of the location
+/// - `config`: Breaker settings (None: no breaker, the call is always made)
+/// - `call`: The call, with failover and retries
+///
+/// # Errors
+/// - Returns error without making the call if the breaker is open
+/// - Returns the error of the call
+pub async fn call_with_breaker<T, F, Fut>(
+    endpoints: &[FacilitatorEndpoint],
+    config: Option<CircuitBreakerConfig>,
+    call: F,
+) -> Result<T, CallError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, CallError>>,
+{
+    let Some(config) = config else {
+        return call().await;
+    };
+
+    let key = endpoints
+        .iter()
+        .map(|endpoint| endpoint.url.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let allowed = update_breaker(&key, &config, |breaker, now| breaker.allow(&config, now));
+    if allowed == Some(false) {
+        X402Metrics::get().record_circuit_breaker_rejected();
+        return Err(CallError::new(
+            FailureKind::NotSent,
+            user_errors::FACILITATOR_UNAVAILABLE,
+        ));
+    }
+
+    let result = call().await;
+    update_breaker(&key, &config, |breaker, now| match &result {
+        Err(e) if e.kind == FailureKind::Rejected => breaker.release(),
+        _ => breaker.record(&config, result.is_ok(), now),
+    });
+    result
+}
+
+/// Run `f` on the breaker of `key`, logging and exporting state changes
+///
+/// # Returns
+/// - `None` if the lock is poisoned
+fn update_breaker<T>(
+    key: &str,
+    config: &CircuitBreakerConfig,
+    f: impl FnOnce(&mut CircuitBreaker, Instant) -> T,
+) -> Option<T> {
+    let now = Instant::now();
+    let breakers = BREAKERS.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut breakers = breakers.lock().ok()?;
+    let breaker = breakers
+        .entry(key.to_string())
+        .or_insert_with(|| CircuitBreaker::new(now));
+
+    let before = breaker.state();
+    let result = f(breaker, now);
+    let after = breaker.state();
+    drop(breakers);
+
+    if before != after {
+        let message = format!(
+            "Circuit breaker of facilitator(s) {key} changed from {} to {}",
+            before.as_str(),
+            after.as_str()
+        );
+        if after == BreakerState::Open {
+            log_warn(
+                None,
+                &format!("{message}, calls fail for {}s", config.open_time.as_secs()),
+            );
+        } else {
+            log_info(None, &message);
+        }
+        X402Metrics::get().record_circuit_breaker_state(key, after.gauge_value());
+    }
+    Some(result)
+}
@@ -0,0 +1,78 @@
+//! Payment option command handlers
+//!
+//! This module contains the handler for the repeatable `x402_accept` directive,
+//! which declares one accepted payment option (network, asset, price, recipient)
+//! per directive.
+
+use crate::ngx_module::commands::common::{conf_error_message, copy_string_to_pool};
+use crate::ngx_module::config::{AcceptOption, X402Config};
+use ngx::core::NgxStr;
+use ngx::ffi::{ngx_command_t, ngx_conf_t, ngx_str_t};
+use std::ffi::c_char;
+use std::ptr;
+
+/// Parse `x402_accept` directive
+///
+/// Each directive adds one payment option to the location. Options are validated
+/// here, so `nginx -t` reports mistakes, and stored as one line per directive.
+///
+/// # Example
+/// ```nginx
+/// x402_accept network=base amount=0.001;
+/// x402_accept network=base-sepolia amount=0.0001;
+/// x402_accept network=base asset=0xYourToken decimals=18 amount=0.5 pay_to=0xYourAddress;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_accept(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let mut params = Vec::with_capacity((*args).nelts - 1);
+    for i in 1..(*args).nelts {
+        match NgxStr::from_ngx_str(*elts.add(i)).to_str() {
+            Ok(param) => params.push(param),
+            Err(_) => return conf_error_message(cf, "has invalid string encoding"),
+        }
+    }
+    let line = params.join(" ");
+
+    if let Err(e) = AcceptOption::parse(&line) {
+        return conf_error_message(cf, &e.to_string());
+    }
+
+    // Append to the options declared by previous x402_accept directives
+    let existing = (*conf).accepts_str;
+    let accepts = if existing.len == 0 {
+        line
+    } else {
+        let existing = NgxStr::from_ngx_str(existing).to_str().unwrap_or_default();
+        format!("{existing}\n{line}")
+    };
+    let accepts_str = ngx_str_t {
+        len: accepts.len(),
+        data: accepts.as_ptr().cast_mut(),
+    };
+
+    match copy_string_to_pool(cf, accepts_str) {
+        Some(allocated_str) => {
+            (*conf).accepts_str = allocated_str;
+        }
+        None => {
+            return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+        }
+    }
+
+    ptr::null_mut()
+}
@@ -5,7 +5,7 @@
 //! - `x402_asset_decimals`
 //! - `x402_resource`
 
-use crate::ngx_module::commands::common::copy_string_to_pool;
+use crate::ngx_module::commands::common::{compile_complex_value, copy_string_to_pool};
 use crate::ngx_module::config::X402Config;
 use ngx::ffi::{ngx_command_t, ngx_conf_t, ngx_str_t};
 use std::ffi::c_char;
@@ -33,6 +33,11 @@ pub(crate) unsafe extern "C" fn ngx_http_x402_resource(
     match copy_string_to_pool(cf, value_str) {
         Some(allocated_str) => {
             (*conf).resource_str = allocated_str;
+            // Values with variables (e.g. `$arg_tier`) are evaluated per request
+            match compile_complex_value(cf, allocated_str) {
+                Ok(cv) => (*conf).resource_cv = cv,
+                Err(_) => return ngx::core::NGX_CONF_ERROR.cast::<c_char>(),
+            }
         }
         None => {
             return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
@@ -7,8 +7,10 @@
 //! - `x402_facilitator_url`
 //! - `x402_description`
 
-use crate::ngx_module::commands::common::copy_string_to_pool;
-use crate::ngx_module::config::X402Config;
+use crate::ngx_module::commands::common::{
+    compile_complex_value, conf_error_message, copy_string_to_pool,
+};
+use crate::ngx_module::config::{FacilitatorEndpoint, X402Config};
 use ngx::core::{NgxStr, Pool};
 use ngx::ffi::{
     ngx_command_t, ngx_conf_t, ngx_http_core_loc_conf_t, ngx_http_handler_pt, ngx_str_t,
@@ -189,6 +191,11 @@ pub(crate) unsafe extern "C" fn ngx_http_x402_amount(
     match copy_string_to_pool(cf, value_str) {
         Some(allocated_str) => {
             (*conf).amount_str = allocated_str;
+            // Values with variables (e.g. `$arg_tier`) are evaluated per request
+            match compile_complex_value(cf, allocated_str) {
+                Ok(cv) => (*conf).amount_cv = cv,
+                Err(_) => return ngx::core::NGX_CONF_ERROR.cast::<c_char>(),
+            }
         }
         None => {
             return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
@@ -221,6 +228,11 @@ pub(crate) unsafe extern "C" fn ngx_http_x402_pay_to(
     match copy_string_to_pool(cf, value_str) {
         Some(allocated_str) => {
             (*conf).pay_to_str = allocated_str;
+            // Values with variables (e.g. `$arg_tier`) are evaluated per request
+            match compile_complex_value(cf, allocated_str) {
+                Ok(cv) => (*conf).pay_to_cv = cv,
+                Err(_) => return ngx::core::NGX_CONF_ERROR.cast::<c_char>(),
+            }
         }
         None => {
             return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
@@ -231,6 +243,17 @@ pub(crate) unsafe extern "C" fn ngx_http_x402_pay_to(
 }
 
 /// Parse `x402_facilitator_url` directive
+///
+/// Each directive adds one facilitator to the location, stored as one line per
+/// directive. Further facilitators are used when the first ones fail (see
+/// `failover`).
+///
+/// # Example
+/// ```nginx
+/// x402_facilitator_url https://facilitator-a.example.com weight=2;
+/// x402_facilitator_url https://facilitator-b.example.com;
+/// x402_facilitator_url https://x402.org/facilitator priority=1 max_fails=5 fail_timeout=60s;
+/// ```
 pub(crate) unsafe extern "C" fn ngx_http_x402_facilitator_url(
     cf: *mut ngx_conf_t,
     _cmd: *mut ngx_command_t,
@@ -248,9 +271,33 @@ pub(crate) unsafe extern "C" fn ngx_http_x402_facilitator_url(
 
     // elts is a pointer to an array of ngx_str_t, not an array of pointers
     let elts = (*args).elts.cast::<ngx_str_t>();
-    let value_str = *elts.add(1);
+    let mut params = Vec::with_capacity((*args).nelts - 1);
+    for i in 1..(*args).nelts {
+        match NgxStr::from_ngx_str(*elts.add(i)).to_str() {
+            Ok(param) => params.push(param),
+            Err(_) => return conf_error_message(cf, "has invalid string encoding"),
+        }
+    }
+    let line = params.join(" ");
 
-    match copy_string_to_pool(cf, value_str) {
+    if let Err(e) = FacilitatorEndpoint::parse(&line) {
+        return conf_error_message(cf, &e.to_string());
+    }
+
+    // Append to the facilitators declared by previous x402_facilitator_url directives
+    let existing = (*conf).facilitator_url_str;
+    let facilitators = if existing.len == 0 {
+        line
+    } else {
+        let existing = NgxStr::from_ngx_str(existing).to_str().unwrap_or_default();
+        format!("{existing}\n{line}")
+    };
+    let facilitators_str = ngx_str_t {
+        len: facilitators.len(),
+        data: facilitators.as_ptr().cast_mut(),
+    };
+
+    match copy_string_to_pool(cf, facilitators_str) {
         Some(allocated_str) => {
             (*conf).facilitator_url_str = allocated_str;
         }
@@ -285,6 +332,11 @@ pub(crate) unsafe extern "C" fn ngx_http_x402_description(
     match copy_string_to_pool(cf, value_str) {
         Some(allocated_str) => {
             (*conf).description_str = allocated_str;
+            // Values with variables (e.g. `$arg_tier`) are evaluated per request
+            match compile_complex_value(cf, allocated_str) {
+                Ok(cv) => (*conf).description_cv = cv,
+                Err(_) => return ngx::core::NGX_CONF_ERROR.cast::<c_char>(),
+            }
         }
         None => {
             return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
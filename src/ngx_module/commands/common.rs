@@ -2,9 +2,18 @@
 //!
 //! This module provides shared helper functions used by all command handlers.
 
+use crate::ngx_module::error::{ConfigError, Result};
 use ngx::core::{NgxStr, Pool};
-use ngx::ffi::{ngx_conf_t, ngx_str_t};
-use std::ptr;
+use ngx::ffi::{
+    ngx_array_t, ngx_conf_t, ngx_http_compile_complex_value, ngx_http_compile_complex_value_t,
+    ngx_http_complex_value_t, ngx_http_conf_addr_t, ngx_http_conf_port_t, ngx_http_core_srv_conf_t,
+    ngx_http_server_name_t, ngx_str_t,
+};
+use ngx::http::{
+    HttpModuleLocationConf, HttpModuleMainConf, HttpModuleServerConf, NgxHttpCoreModule,
+};
+use std::ffi::c_char;
+use std::ptr::{self, NonNull};
 
 /// Helper function to copy a string from configuration args to pool-allocated memory
 ///
@@ -55,3 +64,208 @@ pub unsafe fn copy_string_to_pool(cf: *mut ngx_conf_t, src: ngx_str_t) -> Option
         Err(_) => None,
     }
 }
+
+/// Allocate a directive error message for nginx
+///
+/// Directive handlers report errors by returning a C string, which nginx logs as
+/// `"<directive>" directive <message>`. The message is copied to the configuration
+/// pool with a terminating NUL.
+///
+/// # Safety
+///
+/// The caller must ensure that `cf` is a valid pointer to a `ngx_conf_t` structure.
+pub unsafe fn conf_error_message(cf: *mut ngx_conf_t, message: &str) -> *mut c_char {
+    let pool = Pool::from_ngx_pool((*cf).pool);
+    let data = pool.alloc(message.len() + 1).cast::<u8>();
+    if data.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+    ptr::copy_nonoverlapping(message.as_ptr(), data, message.len());
+    *data.add(message.len()) = 0;
+    data.cast::<c_char>()
+}
+
+/// Compile a directive value into a complex value if it references variables
+///
+/// Values without `$` stay plain strings, so static configurations don't pay for
+/// per-request evaluation.
+///
+/// # Arguments
+///
+/// * `cf` - Nginx configuration context
+/// * `value` - Directive value, allocated from the configuration pool
+///
+/// # Returns
+///
+/// * `Ok(Some(cv))` - The value references variables and was compiled
+/// * `Ok(None)` - The value is static
+/// * `Err` - Compilation failed (e.g. unknown variable; nginx logs the reason)
+///
+/// # Safety
+///
+/// The caller must ensure that:
+/// * `cf` is a valid pointer to a `ngx_conf_t` structure
+/// * `value` lives as long as the configuration (the compiled value references it)
+pub unsafe fn compile_complex_value(
+    cf: *mut ngx_conf_t,
+    value: ngx_str_t,
+) -> Result<Option<NonNull<ngx_http_complex_value_t>>> {
+    if value.len == 0 || !NgxStr::from_ngx_str(value).as_bytes().contains(&b'$') {
+        return Ok(None);
+    }
+
+    let pool = Pool::from_ngx_pool((*cf).pool);
+    let cv = pool
+        .calloc(std::mem::size_of::<ngx_http_complex_value_t>())
+        .cast::<ngx_http_complex_value_t>();
+    if cv.is_null() {
+        return Err(ConfigError::from("Failed to allocate complex value"));
+    }
+
+    let mut value = value;
+    let mut ccv: ngx_http_compile_complex_value_t = std::mem::zeroed();
+    ccv.cf = cf;
+    ccv.value = &raw mut value;
+    ccv.complex_value = cv;
+
+    if ngx_http_compile_complex_value(&raw mut ccv) != ngx::ffi::NGX_OK as ngx::ffi::ngx_int_t {
+        return Err(ConfigError::from("Failed to compile complex value"));
+    }
+
+    Ok(NonNull::new(cv))
+}
+
+/// Resolve a file path of a directive against the configuration prefix
+///
+/// # Returns
+///
+/// * `Ok(path)` - Absolute path
+/// * `Err(message)` - Directive error message
+///
+/// # Safety
+///
+/// The caller must ensure that `cf` is a valid pointer to a `ngx_conf_t` structure.
+pub unsafe fn conf_full_path(
+    cf: *mut ngx_conf_t,
+    path: &str,
+) -> std::result::Result<String, String> {
+    let mut full_path = ngx_str_t {
+        len: path.len(),
+        data: path.as_ptr().cast_mut(),
+    };
+    if ngx::ffi::ngx_conf_full_name((*cf).cycle, &raw mut full_path, 1)
+        != ngx::ffi::NGX_OK as ngx::ffi::ngx_int_t
+    {
+        return Err(format!("can't resolve path \"{path}\""));
+    }
+    Ok(NgxStr::from_ngx_str(full_path)
+        .to_string_lossy()
+        .into_owned())
+}
+
+/// Read a key file referenced by a directive
+///
+/// Relative paths are resolved against the configuration prefix.
+///
+/// # Returns
+///
+/// * `Ok(key)` - The file content, at least `min_len` bytes
+/// * `Err(message)` - Directive error message (unreadable or too short file)
+///
+/// # Safety
+///
+/// The caller must ensure that `cf` is a valid pointer to a `ngx_conf_t` structure.
+pub unsafe fn read_key_file(
+    cf: *mut ngx_conf_t,
+    path: &str,
+    min_len: usize,
+) -> std::result::Result<Vec<u8>, String> {
+    let path = conf_full_path(cf, path)?;
+    match std::fs::read(&path) {
+        Ok(bytes) if bytes.len() >= min_len => Ok(bytes),
+        Ok(_) => Err(format!(
+            "key file \"{path}\" is too short, at least {min_len} bytes are required"
+        )),
+        Err(e) => Err(format!("can't read key file \"{path}\": {e}")),
+    }
+}
+
+/// Name of the `location` block a directive is in
+///
+/// # Returns
+///
+/// * The location name (`/articles/`, `@fallback`, or the regex of a regex location)
+/// * An empty string in `http` and `server` blocks
+///
+/// # Safety
+///
+/// The caller must ensure that `cf` is a valid pointer to a `ngx_conf_t` structure
+/// of the http module.
+pub unsafe fn conf_location_name(cf: *mut ngx_conf_t) -> String {
+    match NgxHttpCoreModule::location_conf(&*cf) {
+        Some(clcf) if clcf.name.len > 0 => NgxStr::from_ngx_str(clcf.name)
+            .to_string_lossy()
+            .into_owned(),
+        _ => String::new(),
+    }
+}
+
+/// Names and listen addresses of the `server` block being merged
+///
+/// Only complete once the whole `http` block has been read, so it is meant for
+/// `merge_loc_conf`: `server_name` may follow the locations of a server, and
+/// servers without `listen` get the default address when their `server`
+/// configuration is merged.
+///
+/// # Returns
+///
+/// * The server names followed by the listen addresses, e.g.
+///   `example.com www.example.com 0.0.0.0:443`
+///
+/// # Safety
+///
+/// The caller must ensure that `cf` is a valid pointer to a `ngx_conf_t` structure
+/// of the http module, in the merge phase.
+pub unsafe fn conf_server_identity(cf: *mut ngx_conf_t) -> String {
+    let Some(cscf) = NgxHttpCoreModule::server_conf(&*cf) else {
+        return String::new();
+    };
+    let mut identity: Vec<String> = array_elts::<ngx_http_server_name_t>(&cscf.server_names)
+        .iter()
+        .map(|name| {
+            NgxStr::from_ngx_str(name.name)
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+
+    // Listen addresses know their servers, not the other way around
+    let ports = NgxHttpCoreModule::main_conf(&*cf)
+        .and_then(|cmcf| cmcf.ports.as_ref())
+        .map_or(&[][..], |ports| array_elts::<ngx_http_conf_port_t>(ports));
+    for port in ports {
+        for addr in array_elts::<ngx_http_conf_addr_t>(&port.addrs) {
+            let servers = array_elts::<*mut ngx_http_core_srv_conf_t>(&addr.servers);
+            if servers.iter().any(|&server| ptr::eq(server, cscf)) {
+                identity.push(
+                    NgxStr::from_ngx_str(addr.opt.addr_text)
+                        .to_string_lossy()
+                        .into_owned(),
+                );
+            }
+        }
+    }
+    identity.join(" ")
+}
+
+/// Elements of an nginx array
+///
+/// # Safety
+///
+/// The caller must ensure that the array holds elements of type `T`.
+unsafe fn array_elts<T>(array: &ngx_array_t) -> &[T] {
+    if array.elts.is_null() || array.nelts == 0 {
+        return &[];
+    }
+    std::slice::from_raw_parts(array.elts.cast::<T>(), array.nelts)
+}
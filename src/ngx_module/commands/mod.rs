@@ -16,33 +16,52 @@
 //!
 //! This module is organized into submodules:
 //!
+//! - `accept`: Payment option commands (accept)
 //! - `common`: Shared utilities (string copying, etc.)
 //! - `basic`: Basic configuration commands (x402, amount, pay_to, etc.)
-//! - `network`: Network-related commands (network, network_id)
+//! - `network`: Network-related commands (network, network_id, network_define, fee_payer)
 //! - `asset`: Asset-related commands (asset, asset_decimals, resource)
-//! - `other`: Other commands (timeout, facilitator_fallback, metrics)
+//! - `other`: Other commands (timeout, facilitator_fallback, settle, session_purchase,
+//!   access_pass, paywall_template, paywall_assets_url, paywall_assets, messages, response_format,
+//!   browser_detection, bypass, forward_headers, upstream_pricing, verify_mode, facilitator_retries, circuit_breaker, metrics)
+//! - `receipt`: Signed payment receipt commands (receipt_key, receipt_ttl, receipt_jwks)
+//! - `zone`: Shared memory zone commands (replay_zone, verify_cache, session)
 
+mod accept;
 mod asset;
 mod basic;
 pub mod common;
 mod network;
 mod other;
+mod receipt;
+mod zone;
 
 use ngx::ffi::{ngx_command_t, ngx_str_t};
 use ngx::ngx_string;
 use std::ptr;
 
 // Re-export command handlers
+use accept::ngx_http_x402_accept;
 use asset::{ngx_http_x402_asset, ngx_http_x402_asset_decimals, ngx_http_x402_resource};
 use basic::{
     ngx_http_x402, ngx_http_x402_amount, ngx_http_x402_description, ngx_http_x402_facilitator_url,
     ngx_http_x402_pay_to,
 };
-use network::{ngx_http_x402_network, ngx_http_x402_network_id};
+use network::{
+    ngx_http_x402_fee_payer, ngx_http_x402_network, ngx_http_x402_network_define,
+    ngx_http_x402_network_id,
+};
 use other::{
-    ngx_http_x402_facilitator_fallback, ngx_http_x402_metrics, ngx_http_x402_timeout,
-    ngx_http_x402_ttl,
+    ngx_http_x402_access_pass, ngx_http_x402_browser_detection, ngx_http_x402_bypass,
+    ngx_http_x402_circuit_breaker, ngx_http_x402_facilitator_fallback,
+    ngx_http_x402_facilitator_retries, ngx_http_x402_forward_headers, ngx_http_x402_messages,
+    ngx_http_x402_metrics, ngx_http_x402_paywall_assets, ngx_http_x402_paywall_assets_url,
+    ngx_http_x402_paywall_template, ngx_http_x402_response_format, ngx_http_x402_session_purchase,
+    ngx_http_x402_settle, ngx_http_x402_timeout, ngx_http_x402_ttl, ngx_http_x402_upstream_pricing,
+    ngx_http_x402_verify_mode,
 };
+use receipt::{ngx_http_x402_receipt_jwks, ngx_http_x402_receipt_key, ngx_http_x402_receipt_ttl};
+use zone::{ngx_http_x402_replay_zone, ngx_http_x402_session, ngx_http_x402_verify_cache};
 
 /// Configuration commands array
 ///
@@ -55,7 +74,7 @@ use other::{
 /// - Offset: offset within the config structure
 /// - Post: post-processing function (if any)
 #[no_mangle]
-pub static mut ngx_http_x402_commands: [ngx_command_t; 15] = [
+pub static mut ngx_http_x402_commands: [ngx_command_t; 39] = [
     ngx_command_t {
         name: ngx_string!("x402"),
         type_: (ngx::ffi::NGX_HTTP_MAIN_CONF
@@ -85,7 +104,7 @@ pub static mut ngx_http_x402_commands: [ngx_command_t; 15] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_facilitator_url"),
-        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_TAKE1) as usize,
+        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_1MORE) as usize,
         set: Some(ngx_http_x402_facilitator_url),
         conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -115,6 +134,22 @@ pub static mut ngx_http_x402_commands: [ngx_command_t; 15] = [
         offset: 0,
         post: ptr::null_mut(),
     },
+    ngx_command_t {
+        name: ngx_string!("x402_network_define"),
+        type_: (ngx::ffi::NGX_HTTP_MAIN_CONF | ngx::ffi::NGX_CONF_2MORE) as usize,
+        set: Some(ngx_http_x402_network_define),
+        conf: ngx::ffi::NGX_HTTP_MAIN_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_fee_payer"),
+        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_TAKE1) as usize,
+        set: Some(ngx_http_x402_fee_payer),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
     ngx_command_t {
         name: ngx_string!("x402_resource"),
         type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_TAKE1) as usize,
@@ -149,12 +184,28 @@ pub static mut ngx_http_x402_commands: [ngx_command_t; 15] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_facilitator_fallback"),
-        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_TAKE1) as usize,
+        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_TAKE123) as usize,
         set: Some(ngx_http_x402_facilitator_fallback),
         conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
         post: ptr::null_mut(),
     },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_retries"),
+        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_TAKE12) as usize,
+        set: Some(ngx_http_x402_facilitator_retries),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_circuit_breaker"),
+        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_1MORE) as usize,
+        set: Some(ngx_http_x402_circuit_breaker),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
     ngx_command_t {
         name: ngx_string!("x402_ttl"),
         type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_TAKE1) as usize,
@@ -163,6 +214,202 @@ pub static mut ngx_http_x402_commands: [ngx_command_t; 15] = [
         offset: 0,
         post: ptr::null_mut(),
     },
+    ngx_command_t {
+        name: ngx_string!("x402_settle"),
+        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_TAKE1) as usize,
+        set: Some(ngx_http_x402_settle),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_forward_headers"),
+        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_TAKE1) as usize,
+        set: Some(ngx_http_x402_forward_headers),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_accept"),
+        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_1MORE) as usize,
+        set: Some(ngx_http_x402_accept),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_upstream_pricing"),
+        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_TAKE1) as usize,
+        set: Some(ngx_http_x402_upstream_pricing),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_verify_mode"),
+        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_TAKE1) as usize,
+        set: Some(ngx_http_x402_verify_mode),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_replay_zone"),
+        type_: (ngx::ffi::NGX_HTTP_MAIN_CONF
+            | ngx::ffi::NGX_HTTP_SRV_CONF
+            | ngx::ffi::NGX_HTTP_LOC_CONF
+            | ngx::ffi::NGX_CONF_TAKE2) as usize,
+        set: Some(ngx_http_x402_replay_zone),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_verify_cache"),
+        type_: (ngx::ffi::NGX_HTTP_MAIN_CONF
+            | ngx::ffi::NGX_HTTP_SRV_CONF
+            | ngx::ffi::NGX_HTTP_LOC_CONF
+            | ngx::ffi::NGX_CONF_TAKE12) as usize,
+        set: Some(ngx_http_x402_verify_cache),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_session"),
+        type_: (ngx::ffi::NGX_HTTP_MAIN_CONF
+            | ngx::ffi::NGX_HTTP_SRV_CONF
+            | ngx::ffi::NGX_HTTP_LOC_CONF
+            | ngx::ffi::NGX_CONF_2MORE) as usize,
+        set: Some(ngx_http_x402_session),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_session_purchase"),
+        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_1MORE) as usize,
+        set: Some(ngx_http_x402_session_purchase),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_access_pass"),
+        type_: (ngx::ffi::NGX_HTTP_MAIN_CONF
+            | ngx::ffi::NGX_HTTP_SRV_CONF
+            | ngx::ffi::NGX_HTTP_LOC_CONF
+            | ngx::ffi::NGX_CONF_1MORE) as usize,
+        set: Some(ngx_http_x402_access_pass),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_paywall_template"),
+        type_: (ngx::ffi::NGX_HTTP_MAIN_CONF
+            | ngx::ffi::NGX_HTTP_SRV_CONF
+            | ngx::ffi::NGX_HTTP_LOC_CONF
+            | ngx::ffi::NGX_CONF_TAKE1) as usize,
+        set: Some(ngx_http_x402_paywall_template),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_paywall_assets_url"),
+        type_: (ngx::ffi::NGX_HTTP_MAIN_CONF
+            | ngx::ffi::NGX_HTTP_SRV_CONF
+            | ngx::ffi::NGX_HTTP_LOC_CONF
+            | ngx::ffi::NGX_CONF_TAKE1) as usize,
+        set: Some(ngx_http_x402_paywall_assets_url),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_paywall_assets"),
+        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_FLAG) as usize,
+        set: Some(ngx_http_x402_paywall_assets),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_messages"),
+        type_: (ngx::ffi::NGX_HTTP_MAIN_CONF
+            | ngx::ffi::NGX_HTTP_SRV_CONF
+            | ngx::ffi::NGX_HTTP_LOC_CONF
+            | ngx::ffi::NGX_CONF_TAKE1) as usize,
+        set: Some(ngx_http_x402_messages),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_response_format"),
+        type_: (ngx::ffi::NGX_HTTP_MAIN_CONF
+            | ngx::ffi::NGX_HTTP_SRV_CONF
+            | ngx::ffi::NGX_HTTP_LOC_CONF
+            | ngx::ffi::NGX_CONF_TAKE1) as usize,
+        set: Some(ngx_http_x402_response_format),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_browser_detection"),
+        type_: (ngx::ffi::NGX_HTTP_MAIN_CONF
+            | ngx::ffi::NGX_HTTP_SRV_CONF
+            | ngx::ffi::NGX_HTTP_LOC_CONF
+            | ngx::ffi::NGX_CONF_1MORE) as usize,
+        set: Some(ngx_http_x402_browser_detection),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_bypass"),
+        type_: (ngx::ffi::NGX_HTTP_MAIN_CONF
+            | ngx::ffi::NGX_HTTP_SRV_CONF
+            | ngx::ffi::NGX_HTTP_LOC_CONF
+            | ngx::ffi::NGX_CONF_2MORE) as usize,
+        set: Some(ngx_http_x402_bypass),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_receipt_key"),
+        type_: (ngx::ffi::NGX_HTTP_MAIN_CONF
+            | ngx::ffi::NGX_HTTP_SRV_CONF
+            | ngx::ffi::NGX_HTTP_LOC_CONF
+            | ngx::ffi::NGX_CONF_TAKE12) as usize,
+        set: Some(ngx_http_x402_receipt_key),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_receipt_ttl"),
+        type_: (ngx::ffi::NGX_HTTP_MAIN_CONF
+            | ngx::ffi::NGX_HTTP_SRV_CONF
+            | ngx::ffi::NGX_HTTP_LOC_CONF
+            | ngx::ffi::NGX_CONF_TAKE1) as usize,
+        set: Some(ngx_http_x402_receipt_ttl),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_receipt_jwks"),
+        type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_NOARGS) as usize,
+        set: Some(ngx_http_x402_receipt_jwks),
+        conf: ngx::ffi::NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: ptr::null_mut(),
+    },
     ngx_command_t {
         name: ngx_string!("x402_metrics"),
         type_: (ngx::ffi::NGX_HTTP_LOC_CONF | ngx::ffi::NGX_CONF_FLAG) as usize,
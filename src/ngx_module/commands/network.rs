@@ -3,9 +3,13 @@
 //! This module contains handlers for network-related configuration directives:
 //! - `x402_network`
 //! - `x402_network_id`
+//! - `x402_network_define`
+//! - `x402_fee_payer`
 
-use crate::ngx_module::commands::common::copy_string_to_pool;
+use crate::config::NetworkInfo;
+use crate::ngx_module::commands::common::{conf_error_message, copy_string_to_pool};
 use crate::ngx_module::config::X402Config;
+use ngx::core::NgxStr;
 use ngx::ffi::{ngx_command_t, ngx_conf_t, ngx_str_t};
 use std::ffi::c_char;
 use std::ptr;
@@ -82,3 +86,89 @@ pub(crate) unsafe extern "C" fn ngx_http_x402_network_id(
 
     ptr::null_mut()
 }
+
+/// Parse `x402_network_define` directive
+///
+/// Adds a network to the network registry, or replaces the built-in network of
+/// the same name. The network can be used by `x402_network`, `x402_network_id`
+/// and `x402_accept` directives that follow it.
+///
+/// # Example
+/// ```nginx
+/// x402_network_define unichain chain_id=130 usdc=0x078D782b760474a361dDA0AF3839290b0EF57AD6 "eip712_name=USD Coin";
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_network_define(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    _conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let mut params = Vec::with_capacity((*args).nelts - 1);
+    for i in 1..(*args).nelts {
+        match NgxStr::from_ngx_str(*elts.add(i)).to_str() {
+            Ok(param) => params.push(param),
+            Err(_) => return conf_error_message(cf, "has invalid string encoding"),
+        }
+    }
+
+    let result = NetworkInfo::parse_define(&params).and_then(crate::config::define_network);
+    if let Err(e) = result {
+        return conf_error_message(cf, &e.to_string());
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_fee_payer` directive
+///
+/// Sets the Solana address that pays the transaction fees, advertised to clients
+/// as `extra.feePayer`. It belongs to the facilitator, which co-signs the payment
+/// transaction at settlement. Required on Solana networks.
+///
+/// # Example
+/// ```nginx
+/// x402_fee_payer 2wKupLR9q6wXYppw8Gr2NvWxKBUqm4PPJKkQfoxHDBg4;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_fee_payer(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let value_str = *elts.add(1);
+
+    let Ok(fee_payer) = NgxStr::from_ngx_str(value_str).to_str() else {
+        return conf_error_message(cf, "has invalid string encoding");
+    };
+    if let Err(e) = crate::config::validate_solana_address(fee_payer) {
+        return conf_error_message(cf, &e.to_string());
+    }
+
+    match copy_string_to_pool(cf, value_str) {
+        Some(allocated_str) => {
+            (*conf).fee_payer_str = allocated_str;
+        }
+        None => {
+            return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+        }
+    }
+
+    ptr::null_mut()
+}
@@ -4,11 +4,39 @@
 //! - `x402_timeout`
 //! - `x402_facilitator_fallback`
 //! - `x402_ttl`
+//! - `x402_settle`
+//! - `x402_session_purchase`
+//! - `x402_access_pass`
+//! - `x402_paywall_template`
+//! - `x402_paywall_assets_url`
+//! - `x402_paywall_assets`
+//! - `x402_messages` (repeatable)
+//! - `x402_response_format`
+//! - `x402_browser_detection`
+//! - `x402_bypass` (repeatable)
+//! - `x402_forward_headers`
+//! - `x402_upstream_pricing`
+//! - `x402_verify_mode`
+//! - `x402_facilitator_retries`
+//! - `x402_circuit_breaker`
 //! - `x402_metrics`
 
-use crate::ngx_module::commands::common::copy_string_to_pool;
-use crate::ngx_module::config::X402Config;
-use ngx::core::Pool;
+use crate::ngx_module::access_pass::{
+    default_scope, generated_key, AccessPassConfig, AccessPassSettings,
+};
+use crate::ngx_module::bypass::{BypassRule, BypassRules};
+use crate::ngx_module::commands::common::{
+    conf_error_message, conf_full_path, conf_location_name, copy_string_to_pool, read_key_file,
+};
+use crate::ngx_module::config::{
+    CircuitBreakerConfig, FacilitatorFallback, RetryPolicy, X402Config,
+};
+use crate::ngx_module::error::ConfigError;
+use crate::ngx_module::messages::{CustomCatalog, MessageCatalogs};
+use crate::ngx_module::negotiation::{BrowserDetection, ResponseFormat};
+use crate::ngx_module::paywall::{is_valid_assets_url, PaywallTemplate};
+use crate::ngx_module::session::{SessionGrant, MIN_SESSION_KEY_LEN};
+use ngx::core::{NgxStr, Pool};
 use ngx::ffi::{
     ngx_command_t, ngx_conf_t, ngx_http_core_loc_conf_t, ngx_http_handler_pt, ngx_str_t,
 };
@@ -18,6 +46,8 @@ use std::ptr;
 // Import handler functions for setting in location configuration
 extern "C" {
     pub fn x402_metrics_handler(r: *mut ngx::ffi::ngx_http_request_t) -> ngx::ffi::ngx_int_t;
+    pub fn x402_paywall_assets_handler(r: *mut ngx::ffi::ngx_http_request_t)
+        -> ngx::ffi::ngx_int_t;
 }
 
 /// Parse `x402_timeout` directive
@@ -97,6 +127,16 @@ pub(crate) unsafe extern "C" fn ngx_http_x402_ttl(
 }
 
 /// Parse `x402_facilitator_fallback` directive
+///
+/// Sets the response when the facilitator fails: `error [status [body]]`
+/// (default: 500), `pass`, `unavailable [retry_after]` or `repay`. The arguments
+/// are stored one per line, since the body may contain spaces.
+///
+/// # Example
+/// ```nginx
+/// x402_facilitator_fallback error 502 "Payment service unavailable";
+/// x402_facilitator_fallback unavailable 60;
+/// ```
 pub(crate) unsafe extern "C" fn ngx_http_x402_facilitator_fallback(
     cf: *mut ngx_conf_t,
     _cmd: *mut ngx_command_t,
@@ -114,8 +154,23 @@ pub(crate) unsafe extern "C" fn ngx_http_x402_facilitator_fallback(
 
     // elts is a pointer to an array of ngx_str_t, not an array of pointers
     let elts = (*args).elts.cast::<ngx_str_t>();
-    let value_str = *elts.add(1);
+    let mut params = Vec::with_capacity((*args).nelts - 1);
+    for i in 1..(*args).nelts {
+        match NgxStr::from_ngx_str(*elts.add(i)).to_str() {
+            Ok(param) => params.push(param),
+            Err(_) => return conf_error_message(cf, "has invalid string encoding"),
+        }
+    }
 
+    if let Err(e) = FacilitatorFallback::parse(&params) {
+        return conf_error_message(cf, &e.to_string());
+    }
+
+    let line = params.join("\n");
+    let value_str = ngx_str_t {
+        len: line.len(),
+        data: line.as_ptr().cast_mut(),
+    };
     match copy_string_to_pool(cf, value_str) {
         Some(allocated_str) => {
             (*conf).facilitator_fallback_str = allocated_str;
@@ -128,6 +183,811 @@ pub(crate) unsafe extern "C" fn ngx_http_x402_facilitator_fallback(
     ptr::null_mut()
 }
 
+/// Parse `x402_settle` directive
+///
+/// Sets when verified payments are settled with the facilitator:
+/// `before_upstream`, `after_success` (default) or `off`.
+pub(crate) unsafe extern "C" fn ngx_http_x402_settle(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let value_str = *elts.add(1);
+
+    match copy_string_to_pool(cf, value_str) {
+        Some(allocated_str) => {
+            (*conf).settle_str = allocated_str;
+        }
+        None => {
+            return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+        }
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_session_purchase` directive
+///
+/// Makes the location sell prepaid sessions (see `x402_session`): a verified
+/// payment is settled and answered with a session token.
+///
+/// # Example
+/// ```nginx
+/// x402_session_purchase requests=1000 duration=24h;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_session_purchase(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let mut params = Vec::with_capacity((*args).nelts - 1);
+    for i in 1..(*args).nelts {
+        match NgxStr::from_ngx_str(*elts.add(i)).to_str() {
+            Ok(param) => params.push(param),
+            Err(_) => return conf_error_message(cf, "has invalid string encoding"),
+        }
+    }
+    let value = params.join(" ");
+
+    // Report invalid parameters at configuration time (nginx -t)
+    if let Err(e) = SessionGrant::parse(&value) {
+        return conf_error_message(cf, &e.to_string());
+    }
+
+    let value_str = ngx_str_t {
+        len: value.len(),
+        data: value.as_ptr().cast_mut(),
+    };
+    match copy_string_to_pool(cf, value_str) {
+        Some(allocated_str) => {
+            (*conf).session_purchase_str = allocated_str;
+        }
+        None => {
+            return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+        }
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_access_pass` directive
+///
+/// Issues a signed cookie with each successful paid response, granting access to
+/// the `scope` path prefix (default: the location's prefix) for `duration`. Passes
+/// are signed with a key of the server and location, derived from the key in the
+/// `key=` file (at least 32 bytes) or from a key generated at startup. The server
+/// is only known once the configuration is merged, which derives the key.
+///
+/// # Example
+/// ```nginx
+/// x402_access_pass duration=24h scope=/articles/ key=/etc/nginx/x402_pass.key;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_access_pass(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let mut params = Vec::with_capacity((*args).nelts - 1);
+    let mut key = None;
+    for i in 1..(*args).nelts {
+        let Ok(param) = NgxStr::from_ngx_str(*elts.add(i)).to_str() else {
+            return conf_error_message(cf, "has invalid string encoding");
+        };
+        if let Some(path) = param.strip_prefix("key=") {
+            if key.is_some() {
+                return conf_error_message(cf, "has duplicate parameter \"key\"");
+            }
+            match read_key_file(cf, path, MIN_SESSION_KEY_LEN) {
+                Ok(bytes) => key = Some(bytes),
+                Err(message) => return conf_error_message(cf, &message),
+            }
+        } else {
+            params.push(param);
+        }
+    }
+    let mut value = params.join(" ");
+
+    let location = conf_location_name(cf);
+    if !params.iter().any(|param| param.starts_with("scope=")) {
+        let Some(scope) = default_scope(&location) else {
+            return conf_error_message(cf, "requires \"scope\" in named and regex locations");
+        };
+        value = format!("{value} scope={scope}");
+    }
+
+    let config = match AccessPassConfig::parse(&value, Vec::new()) {
+        Ok(config) => config,
+        Err(e) => return conf_error_message(cf, &e.to_string()),
+    };
+    let key = match key {
+        Some(key) => key,
+        None => match generated_key() {
+            Ok(key) => key.to_vec(),
+            Err(e) => return conf_error_message(cf, &e.to_string()),
+        },
+    };
+    let pool = Pool::from_ngx_pool((*cf).pool);
+    match ptr::NonNull::new(pool.allocate(AccessPassSettings::new(config, key, location))) {
+        Some(access_pass) => (*conf).access_pass = Some(access_pass),
+        None => return ngx::core::NGX_CONF_ERROR.cast::<c_char>(),
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_paywall_template` directive
+///
+/// Reads and parses the HTML paywall template, so `nginx -t` reports unreadable
+/// files and invalid placeholders. The parsed template is kept in the
+/// configuration pool.
+///
+/// # Example
+/// ```nginx
+/// x402_paywall_template /etc/nginx/x402/paywall.html;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_paywall_template(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    if (*conf).paywall_template.is_some() {
+        return conf_error_message(cf, "is duplicate");
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let Ok(path) = NgxStr::from_ngx_str(*elts.add(1)).to_str() else {
+        return conf_error_message(cf, "has invalid string encoding");
+    };
+    let path = match conf_full_path(cf, path) {
+        Ok(path) => path,
+        Err(message) => return conf_error_message(cf, &message),
+    };
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) => return conf_error_message(cf, &format!("can't read \"{path}\": {e}")),
+    };
+    let template = match PaywallTemplate::parse(&source) {
+        Ok(template) => template,
+        Err(e) => return conf_error_message(cf, &format!("\"{path}\": {e}")),
+    };
+
+    // Dropped by the pool cleanup when the configuration cycle is destroyed
+    let pool = Pool::from_ngx_pool((*cf).pool);
+    match ptr::NonNull::new(pool.allocate(template)) {
+        Some(template) => (*conf).paywall_template = Some(template),
+        None => return ngx::core::NGX_CONF_ERROR.cast::<c_char>(),
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_paywall_assets_url` directive
+///
+/// URL prefix of a location with `x402_paywall_assets on`. When set, browsers
+/// get the self-hosted paywall page, which loads its script and stylesheet from
+/// this prefix and is sent with a nonce-based `Content-Security-Policy`.
+///
+/// # Example
+/// ```nginx
+/// x402_paywall_assets_url /x402/assets/;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_paywall_assets_url(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let value_str = *elts.add(1);
+
+    let valid = NgxStr::from_ngx_str(value_str)
+        .to_str()
+        .is_ok_and(is_valid_assets_url);
+    if !valid {
+        return conf_error_message(
+            cf,
+            "has invalid value, expected a path or \"https://\" URL ending with \"/\"",
+        );
+    }
+
+    match copy_string_to_pool(cf, value_str) {
+        Some(allocated_str) => {
+            (*conf).paywall_assets_url_str = allocated_str;
+        }
+        None => {
+            return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+        }
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_paywall_assets` directive
+///
+/// When `on`, the location serves the paywall script and stylesheet bundled
+/// into the module, like `x402_metrics` serves metrics.
+pub(crate) unsafe extern "C" fn ngx_http_x402_paywall_assets(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    _conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    match NgxStr::from_ngx_str(*elts.add(1)).to_str() {
+        Ok("on") => {}
+        Ok("off") => return ptr::null_mut(),
+        _ => return conf_error_message(cf, "has invalid value, expected \"on\" or \"off\""),
+    }
+
+    let ctx = (*cf).ctx.cast::<ngx::ffi::ngx_http_conf_ctx_t>();
+    if ctx.is_null() {
+        return ptr::null_mut();
+    }
+
+    let loc_conf = (*ctx).loc_conf;
+    if loc_conf.is_null() {
+        return conf_error_message(cf, "is not allowed here");
+    }
+
+    let core_ctx_index = ngx::ffi::ngx_http_core_module.ctx_index;
+    let ptr_to_ptr = loc_conf.add(core_ctx_index);
+    if !ptr_to_ptr.is_null() {
+        let clcf = ptr::read(ptr_to_ptr.cast_const()).cast::<ngx_http_core_loc_conf_t>();
+        if !clcf.is_null() {
+            let handler_ptr: ngx_http_handler_pt = Some(x402_paywall_assets_handler);
+            (*clcf).handler = handler_ptr;
+        }
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_messages` directive
+///
+/// Loads a JSON message catalog; the language is the file name (`fr.json`).
+/// Repeatable, once per language. The catalogs are kept in the configuration pool.
+///
+/// # Example
+/// ```nginx
+/// x402_messages /etc/nginx/x402/messages/fr.json;
+/// x402_messages /etc/nginx/x402/messages/pt-BR.json;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_messages(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let Ok(path) = NgxStr::from_ngx_str(*elts.add(1)).to_str() else {
+        return conf_error_message(cf, "has invalid string encoding");
+    };
+    let path = match conf_full_path(cf, path) {
+        Ok(path) => path,
+        Err(message) => return conf_error_message(cf, &message),
+    };
+    let language = std::path::Path::new(&path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) => return conf_error_message(cf, &format!("can't read \"{path}\": {e}")),
+    };
+    let catalog = match CustomCatalog::parse(language, &source) {
+        Ok(catalog) => catalog,
+        Err(e) => return conf_error_message(cf, &format!("\"{path}\": {e}")),
+    };
+
+    // The first x402_messages of a block allocates the catalogs, dropped by the
+    // pool cleanup when the configuration cycle is destroyed
+    let mut catalogs = match (*conf).messages {
+        Some(catalogs) => catalogs,
+        None => {
+            let pool = Pool::from_ngx_pool((*cf).pool);
+            match ptr::NonNull::new(pool.allocate(MessageCatalogs::default())) {
+                Some(catalogs) => {
+                    (*conf).messages = Some(catalogs);
+                    catalogs
+                }
+                None => return ngx::core::NGX_CONF_ERROR.cast::<c_char>(),
+            }
+        }
+    };
+    if let Err(e) = catalogs.as_mut().add(catalog) {
+        return conf_error_message(cf, &e.to_string());
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_response_format` directive
+///
+/// Format of 402 responses: `auto` (detected from the request, default), `json`
+/// or `html`.
+///
+/// # Example
+/// ```nginx
+/// x402_response_format json;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_response_format(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let value_str = *elts.add(1);
+
+    let valid = NgxStr::from_ngx_str(value_str)
+        .to_str()
+        .is_ok_and(|value| ResponseFormat::parse(value).is_ok());
+    if !valid {
+        return conf_error_message(
+            cf,
+            "has invalid value, expected \"auto\", \"json\" or \"html\"",
+        );
+    }
+
+    match copy_string_to_pool(cf, value_str) {
+        Some(allocated_str) => {
+            (*conf).response_format_str = allocated_str;
+        }
+        None => {
+            return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+        }
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_browser_detection` directive
+///
+/// Rules of `x402_response_format auto`: User-Agent regexes of browsers and API
+/// clients, `Accept` quality thresholds and a query argument overriding the
+/// format. The regexes are compiled once, into the configuration pool.
+///
+/// # Example
+/// ```nginx
+/// x402_browser_detection api_ua=Electron api_ua=HeadlessChrome html_q=0.8 format_arg=format;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_browser_detection(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    if (*conf).browser_detection.is_some() {
+        return conf_error_message(cf, "is duplicate");
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let mut params = Vec::with_capacity((*args).nelts - 1);
+    for i in 1..(*args).nelts {
+        let Ok(param) = NgxStr::from_ngx_str(*elts.add(i)).to_str() else {
+            return conf_error_message(cf, "has invalid string encoding");
+        };
+        params.push(param);
+    }
+    let rules = match BrowserDetection::parse(&params) {
+        Ok(rules) => rules,
+        Err(e) => return conf_error_message(cf, &e.to_string()),
+    };
+
+    // Dropped by the pool cleanup when the configuration cycle is destroyed
+    let pool = Pool::from_ngx_pool((*cf).pool);
+    match ptr::NonNull::new(pool.allocate(rules)) {
+        Some(rules) => (*conf).browser_detection = Some(rules),
+        None => return ngx::core::NGX_CONF_ERROR.cast::<c_char>(),
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_bypass` directive
+///
+/// Lets trusted clients skip payment verification. Repeatable; a request
+/// matching any rule of the block is bypassed. Secret and key files are read at
+/// configuration time, so changes take effect on reload.
+///
+/// # Example
+/// ```nginx
+/// x402_bypass cidr 10.0.0.0/8 192.168.0.0/16;
+/// x402_bypass header X-Internal-Token /etc/nginx/x402/internal.secret;
+/// x402_bypass api_key X-API-Key /etc/nginx/x402/partner.keys;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_bypass(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 3 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let mut params = Vec::with_capacity((*args).nelts - 1);
+    for i in 1..(*args).nelts {
+        let Ok(param) = NgxStr::from_ngx_str(*elts.add(i)).to_str() else {
+            return conf_error_message(cf, "has invalid string encoding");
+        };
+        params.push(param);
+    }
+
+    let rule = match params.as_slice() {
+        ["cidr", ranges @ ..] => BypassRule::cidr(ranges),
+        [kind @ ("header" | "api_key"), name, path] => {
+            let path = match conf_full_path(cf, path) {
+                Ok(path) => path,
+                Err(message) => return conf_error_message(cf, &message),
+            };
+            let content = match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) => return conf_error_message(cf, &format!("can't read \"{path}\": {e}")),
+            };
+            let rule = if *kind == "header" {
+                BypassRule::header(name, &content)
+            } else {
+                BypassRule::api_keys(name, &content)
+            };
+            rule.map_err(|e| ConfigError::from(format!("\"{path}\": {e}")))
+        }
+        ["header" | "api_key", ..] => {
+            return conf_error_message(cf, "expects a header name and a file");
+        }
+        _ => {
+            return conf_error_message(
+                cf,
+                "has invalid rule, expected \"cidr\", \"header\" or \"api_key\"",
+            );
+        }
+    };
+    let rule = match rule {
+        Ok(rule) => rule,
+        Err(e) => return conf_error_message(cf, &e.to_string()),
+    };
+
+    // The first x402_bypass of a block allocates the rules, dropped by the pool
+    // cleanup when the configuration cycle is destroyed
+    let mut rules = match (*conf).bypass {
+        Some(rules) => rules,
+        None => {
+            let pool = Pool::from_ngx_pool((*cf).pool);
+            match ptr::NonNull::new(pool.allocate(BypassRules::default())) {
+                Some(rules) => {
+                    (*conf).bypass = Some(rules);
+                    rules
+                }
+                None => return ngx::core::NGX_CONF_ERROR.cast::<c_char>(),
+            }
+        }
+    };
+    rules.as_mut().add(rule);
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_forward_headers` directive
+///
+/// When `on`, client-supplied `X-X402-*` request headers are removed and trusted
+/// payment headers are added after successful verification.
+pub(crate) unsafe extern "C" fn ngx_http_x402_forward_headers(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let value_str = *elts.add(1);
+
+    match copy_string_to_pool(cf, value_str) {
+        Some(allocated_str) => {
+            (*conf).forward_headers_str = allocated_str;
+        }
+        None => {
+            return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+        }
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_upstream_pricing` directive
+///
+/// When `on`, requests without payment are passed to the upstream, and a 402
+/// response from the upstream is rewritten into x402 payment requirements.
+pub(crate) unsafe extern "C" fn ngx_http_x402_upstream_pricing(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let value_str = *elts.add(1);
+
+    match copy_string_to_pool(cf, value_str) {
+        Some(allocated_str) => {
+            (*conf).upstream_pricing_str = allocated_str;
+        }
+        None => {
+            return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+        }
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_verify_mode` directive
+///
+/// Sets who verifies payments: `facilitator` (default), `local` or `hybrid`.
+pub(crate) unsafe extern "C" fn ngx_http_x402_verify_mode(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let value_str = *elts.add(1);
+
+    match copy_string_to_pool(cf, value_str) {
+        Some(allocated_str) => {
+            (*conf).verify_mode_str = allocated_str;
+        }
+        None => {
+            return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+        }
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_facilitator_retries` directive
+///
+/// Sets how many times a failed facilitator call is retried, and the base delay
+/// between retries (`backoff`, default 100ms).
+///
+/// # Example
+/// ```nginx
+/// x402_facilitator_retries 2 backoff=200ms;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_facilitator_retries(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    if (*conf).retries_str.len != 0 {
+        return conf_error_message(cf, "is duplicate");
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let mut params = Vec::with_capacity((*args).nelts - 1);
+    for i in 1..(*args).nelts {
+        match NgxStr::from_ngx_str(*elts.add(i)).to_str() {
+            Ok(param) => params.push(param),
+            Err(_) => return conf_error_message(cf, "has invalid string encoding"),
+        }
+    }
+    let line = params.join(" ");
+
+    if let Err(e) = RetryPolicy::parse(&line) {
+        return conf_error_message(cf, &e.to_string());
+    }
+
+    let value_str = ngx_str_t {
+        len: line.len(),
+        data: line.as_ptr().cast_mut(),
+    };
+    match copy_string_to_pool(cf, value_str) {
+        Some(allocated_str) => {
+            (*conf).retries_str = allocated_str;
+        }
+        None => {
+            return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+        }
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_circuit_breaker` directive
+///
+/// Sets the failure rate at which facilitator calls are short-circuited to the
+/// `x402_facilitator_fallback` behaviour, or `off` (default).
+///
+/// # Example
+/// ```nginx
+/// x402_circuit_breaker failure_rate=50% min_calls=20 window=60s open_time=30s;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_circuit_breaker(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    if (*conf).circuit_breaker_str.len != 0 {
+        return conf_error_message(cf, "is duplicate");
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let mut params = Vec::with_capacity((*args).nelts - 1);
+    for i in 1..(*args).nelts {
+        match NgxStr::from_ngx_str(*elts.add(i)).to_str() {
+            Ok(param) => params.push(param),
+            Err(_) => return conf_error_message(cf, "has invalid string encoding"),
+        }
+    }
+    let line = params.join(" ");
+
+    if let Err(e) = CircuitBreakerConfig::parse(&line) {
+        return conf_error_message(cf, &e.to_string());
+    }
+
+    let value_str = ngx_str_t {
+        len: line.len(),
+        data: line.as_ptr().cast_mut(),
+    };
+    match copy_string_to_pool(cf, value_str) {
+        Some(allocated_str) => {
+            (*conf).circuit_breaker_str = allocated_str;
+        }
+        None => {
+            return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+        }
+    }
+
+    ptr::null_mut()
+}
+
 /// Parse `x402_metrics` directive
 pub(crate) unsafe extern "C" fn ngx_http_x402_metrics(
     cf: *mut ngx_conf_t,
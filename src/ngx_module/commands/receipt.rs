@@ -0,0 +1,201 @@
+//! Receipt command handlers
+//!
+//! This module contains handlers for the signed payment receipt directives:
+//! - `x402_receipt_key` (repeatable)
+//! - `x402_receipt_ttl`
+//! - `x402_receipt_jwks`
+
+use crate::ngx_module::commands::common::{conf_error_message, copy_string_to_pool, read_key_file};
+use crate::ngx_module::config::X402Config;
+use crate::ngx_module::receipt::{
+    ReceiptConfig, ReceiptKey, ReceiptSigningKey, DEFAULT_RECEIPT_TTL,
+};
+use crate::ngx_module::session::parse_duration;
+use ngx::core::{NgxStr, Pool};
+use ngx::ffi::{
+    ngx_command_t, ngx_conf_t, ngx_http_core_loc_conf_t, ngx_http_handler_pt, ngx_str_t,
+};
+use std::ffi::c_char;
+use std::ptr;
+
+// Import handler functions for setting in location configuration
+extern "C" {
+    pub fn x402_jwks_handler(r: *mut ngx::ffi::ngx_http_request_t) -> ngx::ffi::ngx_int_t;
+}
+
+/// Parse `x402_receipt_key` directive
+///
+/// Loads an Ed25519 or P-256 private key (PKCS#8 PEM). The first key of a
+/// location signs receipts, further keys are only published by
+/// `x402_receipt_jwks`. Without `kid=`, the key ID is derived from the public key.
+///
+/// # Example
+/// ```nginx
+/// x402_receipt_key /etc/nginx/x402/receipt-2025.pem kid=2025;
+/// x402_receipt_key /etc/nginx/x402/receipt-2024.pem kid=2024;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_receipt_key(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let Ok(path) = NgxStr::from_ngx_str(*elts.add(1)).to_str() else {
+        return conf_error_message(cf, "has invalid string encoding");
+    };
+    let mut kid = None;
+    for i in 2..(*args).nelts {
+        let Ok(param) = NgxStr::from_ngx_str(*elts.add(i)).to_str() else {
+            return conf_error_message(cf, "has invalid string encoding");
+        };
+        match param.strip_prefix("kid=") {
+            Some(_) if kid.is_some() => {
+                return conf_error_message(cf, "has duplicate parameter \"kid\"");
+            }
+            Some(value) if is_valid_kid(value) => kid = Some(value.to_string()),
+            Some(value) => {
+                return conf_error_message(cf, &format!("has invalid kid \"{value}\""));
+            }
+            None => {
+                return conf_error_message(cf, &format!("has unknown parameter \"{param}\""));
+            }
+        }
+    }
+
+    let pem = match read_key_file(cf, path, 1) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(message) => return conf_error_message(cf, &message),
+    };
+    let key = match ReceiptSigningKey::from_pem(&pem) {
+        Ok(key) => key,
+        Err(e) => return conf_error_message(cf, &format!("\"{path}\": {e}")),
+    };
+    let key = ReceiptKey {
+        kid: kid.unwrap_or_else(|| key.default_kid()),
+        key,
+    };
+
+    // Keys of previous x402_receipt_key directives of this block, decoded once
+    // and dropped by the pool cleanup when the configuration cycle is destroyed
+    let receipt = match (*conf).receipt {
+        Some(receipt) => receipt,
+        None => {
+            let pool = Pool::from_ngx_pool((*cf).pool);
+            let receipt = ReceiptConfig {
+                keys: Vec::new(),
+                ttl: DEFAULT_RECEIPT_TTL,
+            };
+            match ptr::NonNull::new(pool.allocate(receipt)) {
+                Some(receipt) => {
+                    (*conf).receipt = Some(receipt);
+                    receipt
+                }
+                None => return ngx::core::NGX_CONF_ERROR.cast::<c_char>(),
+            }
+        }
+    };
+    let receipt = &mut *receipt.as_ptr();
+    if receipt.keys.iter().any(|existing| existing.kid == key.kid) {
+        return conf_error_message(cf, &format!("has duplicate kid \"{}\"", key.kid));
+    }
+    receipt.keys.push(key);
+
+    ptr::null_mut()
+}
+
+/// Key IDs end up in JWT headers
+fn is_valid_kid(kid: &str) -> bool {
+    !kid.is_empty()
+        && kid
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+}
+
+/// Parse `x402_receipt_ttl` directive
+///
+/// Lifetime of the receipts (`exp` claim), e.g. `5m` (default: 300 seconds).
+pub(crate) unsafe extern "C" fn ngx_http_x402_receipt_ttl(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let value_str = *elts.add(1);
+
+    let valid = NgxStr::from_ngx_str(value_str)
+        .to_str()
+        .ok()
+        .and_then(parse_duration)
+        .is_some_and(|ttl| ttl > 0);
+    if !valid {
+        return conf_error_message(
+            cf,
+            "has invalid value, expected a time like \"300\" or \"5m\"",
+        );
+    }
+
+    match copy_string_to_pool(cf, value_str) {
+        Some(allocated_str) => {
+            (*conf).receipt_ttl_str = allocated_str;
+        }
+        None => {
+            return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+        }
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_receipt_jwks` directive
+///
+/// Makes the location serve the public receipt keys as a JWK Set, like
+/// `x402_metrics` serves metrics.
+pub(crate) unsafe extern "C" fn ngx_http_x402_receipt_jwks(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    _conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let ctx = (*cf).ctx.cast::<ngx::ffi::ngx_http_conf_ctx_t>();
+    if ctx.is_null() {
+        return ptr::null_mut();
+    }
+
+    let loc_conf = (*ctx).loc_conf;
+    if loc_conf.is_null() {
+        return conf_error_message(cf, "is not allowed here");
+    }
+
+    let core_ctx_index = ngx::ffi::ngx_http_core_module.ctx_index;
+    let ptr_to_ptr = loc_conf.add(core_ctx_index);
+    if !ptr_to_ptr.is_null() {
+        let clcf = ptr::read(ptr_to_ptr.cast_const()).cast::<ngx_http_core_loc_conf_t>();
+        if !clcf.is_null() {
+            let handler_ptr: ngx_http_handler_pt = Some(x402_jwks_handler);
+            (*clcf).handler = handler_ptr;
+        }
+    }
+
+    ptr::null_mut()
+}
@@ -0,0 +1,264 @@
+//! Shared memory zone command handlers
+//!
+//! This module contains handlers for directives that declare shared memory
+//! zones (state shared by all worker processes):
+//! - `x402_replay_zone`
+//! - `x402_verify_cache`
+//! - `x402_session`
+
+use crate::ngx_module::commands::common::{conf_error_message, copy_string_to_pool, read_key_file};
+use crate::ngx_module::config::X402Config;
+use crate::ngx_module::module::ngx_http_x402_module;
+use crate::ngx_module::session::{
+    is_valid_cookie_name, SessionConfig, DEFAULT_SESSION_COOKIE, MIN_SESSION_KEY_LEN,
+};
+use crate::ngx_module::shm::{add_table_zone, SharedTable};
+use crate::ngx_module::verify_cache::DEFAULT_NEGATIVE_TTL;
+use ngx::core::{NgxStr, Pool};
+use ngx::ffi::{ngx_command_t, ngx_conf_t, ngx_str_t};
+use std::ffi::c_char;
+use std::ptr;
+
+/// Parse `x402_replay_zone` directive
+///
+/// Declares the zone recording used payment authorizations. Locations declaring
+/// the same zone name share it.
+///
+/// # Example
+/// ```nginx
+/// x402_replay_zone x402_replay 10m;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_replay_zone(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    if (*conf).replay_zone.is_some() {
+        return conf_error_message(cf, "is duplicate");
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 3 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let Some(name) = copy_string_to_pool(cf, *elts.add(1)) else {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    };
+    if name.len == 0 {
+        return conf_error_message(cf, "requires a zone name");
+    }
+    let size = *elts.add(2);
+
+    let tag = (&raw const ngx_http_x402_module).cast_mut().cast();
+    match add_table_zone(cf, name, size, tag) {
+        Ok(zone) => {
+            (*conf).replay_zone = Some(zone);
+        }
+        Err(e) => {
+            return conf_error_message(cf, &e.to_string());
+        }
+    }
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_verify_cache` directive
+///
+/// Declares the zone caching facilitator verification results, and how long
+/// invalid results are kept (`negative_ttl`, nginx time syntax, default 10s).
+///
+/// # Example
+/// ```nginx
+/// x402_verify_cache zone=x402_verify:10m negative_ttl=30s;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_verify_cache(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    if (*conf).verify_cache_zone.is_some() {
+        return conf_error_message(cf, "is duplicate");
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 2 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let mut zone = None;
+    let mut negative_ttl = DEFAULT_NEGATIVE_TTL;
+    for i in 1..(*args).nelts {
+        let arg = *elts.add(i);
+        let Ok(param) = NgxStr::from_ngx_str(arg).to_str() else {
+            return conf_error_message(cf, "has invalid string encoding");
+        };
+
+        if let Some(value) = param.strip_prefix("zone=") {
+            // zone=name:size, the size is after the last ':'
+            let Some((name, size)) = value.rsplit_once(':') else {
+                return conf_error_message(cf, "requires zone=name:size");
+            };
+            if name.is_empty() {
+                return conf_error_message(cf, "requires a zone name");
+            }
+            let name_str = ngx_str_t {
+                len: name.len(),
+                data: name.as_ptr().cast_mut(),
+            };
+            let Some(name) = copy_string_to_pool(cf, name_str) else {
+                return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+            };
+            let size = ngx_str_t {
+                len: size.len(),
+                data: size.as_ptr().cast_mut(),
+            };
+            let tag = (&raw const ngx_http_x402_module).cast_mut().cast();
+            match add_table_zone(cf, name, size, tag) {
+                Ok(added) => zone = Some(added),
+                Err(e) => return conf_error_message(cf, &e.to_string()),
+            }
+        } else if let Some(value) = param.strip_prefix("negative_ttl=") {
+            let mut value_str = ngx_str_t {
+                len: value.len(),
+                data: value.as_ptr().cast_mut(),
+            };
+            let seconds = ngx::ffi::ngx_parse_time(&raw mut value_str, 1);
+            if seconds < 0 {
+                return conf_error_message(cf, &format!("has invalid negative_ttl \"{value}\""));
+            }
+            negative_ttl = seconds as u64;
+        } else {
+            return conf_error_message(
+                cf,
+                &format!("has unknown parameter \"{param}\", must be 'zone' or 'negative_ttl'"),
+            );
+        }
+    }
+
+    let Some(zone) = zone else {
+        return conf_error_message(cf, "requires zone=name:size");
+    };
+    (*conf).verify_cache_zone = Some(zone);
+    (*conf).verify_cache_negative_ttl = negative_ttl;
+
+    ptr::null_mut()
+}
+
+/// Parse `x402_session` directive
+///
+/// Declares the zone holding prepaid session balances, the file with the key
+/// signing session tokens (at least 32 bytes, read at configuration time; relative
+/// paths are resolved against the configuration prefix), and the session cookie
+/// name (default `x402_session`). The settings are kept in the configuration pool.
+///
+/// # Example
+/// ```nginx
+/// x402_session zone=x402_sessions:10m key=/etc/nginx/x402_session.key cookie=x402_session;
+/// ```
+pub(crate) unsafe extern "C" fn ngx_http_x402_session(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut core::ffi::c_void,
+) -> *mut c_char {
+    let conf = conf.cast::<X402Config>();
+    if conf.is_null() {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    if (*conf).session.is_some() {
+        return conf_error_message(cf, "is duplicate");
+    }
+
+    let args = (*cf).args;
+    if args.is_null() || (*args).nelts < 3 {
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
+
+    // elts is a pointer to an array of ngx_str_t, not an array of pointers
+    let elts = (*args).elts.cast::<ngx_str_t>();
+    let mut zone = None;
+    let mut key = None;
+    let mut cookie = DEFAULT_SESSION_COOKIE.to_string();
+    for i in 1..(*args).nelts {
+        let arg = *elts.add(i);
+        let Ok(param) = NgxStr::from_ngx_str(arg).to_str() else {
+            return conf_error_message(cf, "has invalid string encoding");
+        };
+
+        if let Some(value) = param.strip_prefix("zone=") {
+            // zone=name:size, the size is after the last ':'
+            let Some((name, size)) = value.rsplit_once(':') else {
+                return conf_error_message(cf, "requires zone=name:size");
+            };
+            if name.is_empty() {
+                return conf_error_message(cf, "requires a zone name");
+            }
+            let name_str = ngx_str_t {
+                len: name.len(),
+                data: name.as_ptr().cast_mut(),
+            };
+            let Some(name) = copy_string_to_pool(cf, name_str) else {
+                return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+            };
+            let size = ngx_str_t {
+                len: size.len(),
+                data: size.as_ptr().cast_mut(),
+            };
+            let tag = (&raw const ngx_http_x402_module).cast_mut().cast();
+            match add_table_zone(cf, name, size, tag) {
+                Ok(added) => zone = Some(added),
+                Err(e) => return conf_error_message(cf, &e.to_string()),
+            }
+        } else if let Some(value) = param.strip_prefix("key=") {
+            match read_key_file(cf, value, MIN_SESSION_KEY_LEN) {
+                Ok(bytes) => key = Some(bytes),
+                Err(message) => return conf_error_message(cf, &message),
+            }
+        } else if let Some(value) = param.strip_prefix("cookie=") {
+            if !is_valid_cookie_name(value) {
+                return conf_error_message(cf, &format!("has invalid cookie name \"{value}\""));
+            }
+            cookie = value.to_string();
+        } else {
+            return conf_error_message(
+                cf,
+                &format!("has unknown parameter \"{param}\", must be 'zone', 'key' or 'cookie'"),
+            );
+        }
+    }
+
+    let Some(zone) = zone else {
+        return conf_error_message(cf, "requires zone=name:size");
+    };
+    let Some(key) = key else {
+        return conf_error_message(cf, "requires key=<file>");
+    };
+
+    let session = SessionConfig {
+        table: SharedTable::new(zone),
+        key,
+        cookie,
+    };
+    let pool = Pool::from_ngx_pool((*cf).pool);
+    match ptr::NonNull::new(pool.allocate(session)) {
+        Some(session) => (*conf).session = Some(session),
+        None => return ngx::core::NGX_CONF_ERROR.cast::<c_char>(),
+    }
+
+    ptr::null_mut()
+}
@@ -1,9 +1,21 @@
 //! Configuration types for the Nginx module
 
+use crate::ngx_module::access_pass::{AccessPassSettings, ConfiguredAccessPass};
+use crate::ngx_module::bypass::BypassRules;
 use crate::ngx_module::error::{ConfigError, Result};
+use crate::ngx_module::messages::MessageCatalogs;
+use crate::ngx_module::negotiation::{
+    BrowserDetection, ConfiguredBrowserDetection, ResponseFormat,
+};
+use crate::ngx_module::paywall::{ConfiguredPaywallTemplate, PaywallTemplate};
+use crate::ngx_module::receipt::{ConfiguredReceipt, ReceiptConfig, DEFAULT_RECEIPT_TTL};
+use crate::ngx_module::session::{ConfiguredSession, SessionConfig, SessionGrant};
+use crate::ngx_module::shm::SharedTable;
+use crate::ngx_module::verify_cache::VerifyCache;
 use ngx::core::NgxStr;
-use ngx::ffi::ngx_str_t;
+use ngx::ffi::{ngx_http_complex_value_t, ngx_shm_zone_t, ngx_str_t};
 use rust_decimal::Decimal;
+use std::ptr::NonNull;
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -22,7 +34,7 @@ pub struct X402Config {
     pub enabled: ngx::ffi::ngx_flag_t,
     pub amount_str: ngx_str_t,
     pub pay_to_str: ngx_str_t,
-    pub facilitator_url_str: ngx_str_t,
+    pub facilitator_url_str: ngx_str_t, // x402_facilitator_url entries, one "url key=value ..." line per directive
     pub description_str: ngx_str_t,
     pub network_str: ngx_str_t,
     pub network_id_str: ngx_str_t, // Chain ID (e.g., "8453", "84532")
@@ -30,25 +42,569 @@ pub struct X402Config {
     pub asset_str: ngx_str_t, // Custom token/contract address (e.g., "0x...")
     pub asset_decimals_str: ngx_str_t, // Token decimals (e.g., "6" for USDC, "18" for most ERC-20)
     pub timeout_str: ngx_str_t, // Timeout in seconds (e.g., "10")
-    pub facilitator_fallback_str: ngx_str_t, // Fallback mode: "error" or "pass"
-    pub ttl_str: ngx_str_t,   // TTL for payment authorization validity in seconds (e.g., "60")
+    pub facilitator_fallback_str: ngx_str_t, // Fallback mode and its arguments, one per line (e.g. "error\n503")
+    pub ttl_str: ngx_str_t, // TTL for payment authorization validity in seconds (e.g., "60")
+    pub settle_str: ngx_str_t, // Settlement mode: "before_upstream", "after_success" or "off"
+    pub forward_headers_str: ngx_str_t, // Forward verified payment info upstream: "on" or "off"
+    pub accepts_str: ngx_str_t, // x402_accept options, one "key=value ..." line per directive
+    pub upstream_pricing_str: ngx_str_t, // Price taken from the upstream's 402 response: "on" or "off"
+    pub verify_mode_str: ngx_str_t,      // Verification mode: "facilitator", "local" or "hybrid"
+    pub retries_str: ngx_str_t,          // Facilitator call retries: "<count> [backoff=<time>]"
+    pub circuit_breaker_str: ngx_str_t, // Circuit breaker: "off" or "failure_rate=<percent> key=value ..."
+    pub fee_payer_str: ngx_str_t,       // Solana fee payer of the facilitator (base58 address)
+    pub session_purchase_str: ngx_str_t, // What a session purchase buys: "requests=N duration=T"
+    pub receipt_ttl_str: ngx_str_t,     // Receipt lifetime (e.g., "5m")
+    pub paywall_assets_url_str: ngx_str_t, // URL prefix of the self-hosted paywall assets
+    pub response_format_str: ngx_str_t, // Format of 402 responses: "auto", "json" or "html"
+    // Compiled complex values for directives that reference variables (None if static).
+    // They are evaluated into the matching `*_str` field for each request.
+    pub amount_cv: Option<NonNull<ngx_http_complex_value_t>>,
+    pub pay_to_cv: Option<NonNull<ngx_http_complex_value_t>>,
+    pub description_cv: Option<NonNull<ngx_http_complex_value_t>>,
+    pub resource_cv: Option<NonNull<ngx_http_complex_value_t>>,
+    pub replay_zone: Option<NonNull<ngx_shm_zone_t>>, // Shared zone of x402_replay_zone
+    pub verify_cache_zone: Option<NonNull<ngx_shm_zone_t>>, // Shared zone of x402_verify_cache
+    pub verify_cache_negative_ttl: u64, // Seconds invalid results are cached (x402_verify_cache)
+    pub session: Option<NonNull<SessionConfig>>, // Parsed x402_session: zone, key and cookie (config pool)
+    pub paywall_template: Option<NonNull<PaywallTemplate>>, // Parsed x402_paywall_template (config pool)
+    pub messages: Option<NonNull<MessageCatalogs>>, // Parsed x402_messages catalogs (config pool)
+    pub browser_detection: Option<NonNull<BrowserDetection>>, // Parsed x402_browser_detection (config pool)
+    pub bypass: Option<NonNull<BypassRules>>, // Parsed x402_bypass rules (config pool)
+    pub receipt: Option<NonNull<ReceiptConfig>>, // Parsed x402_receipt_key keys (config pool)
+    pub access_pass: Option<NonNull<AccessPassSettings>>, // Parsed x402_access_pass (config pool)
 }
 
+/// Default `Retry-After` of the `unavailable` fallback, in seconds
+pub const DEFAULT_FALLBACK_RETRY_AFTER: u32 = 30;
+
 /// Facilitator fallback mode
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FacilitatorFallback {
-    /// Return 500 error when facilitator fails
-    Error,
+    /// Return an error response when facilitator fails (default: 500, plain-text body)
+    Error { status: u16, body: Option<String> },
     /// Pass through (act as if middleware doesn't exist) when facilitator fails
     Pass,
+    /// Return 503 with `Retry-After` and a JSON body when facilitator fails
+    Unavailable { retry_after: u32 },
+    /// Return 402 telling the client the facilitator is down, so it can pay again later
+    Repay,
+}
+
+impl Default for FacilitatorFallback {
+    fn default() -> Self {
+        FacilitatorFallback::Error {
+            status: 500,
+            body: None,
+        }
+    }
+}
+
+impl FacilitatorFallback {
+    /// Parse `x402_facilitator_fallback` arguments
+    ///
+    /// - `error [status [body]]`
+    /// - `pass`
+    /// - `unavailable [retry_after]`
+    /// - `repay`
+    ///
+    /// # Errors
+    /// - Returns error if the mode is unknown, or its arguments are invalid
+    pub fn parse(args: &[&str]) -> Result<Self> {
+        let (mode, args) = args
+            .split_first()
+            .ok_or_else(|| ConfigError::from("facilitator_fallback requires a mode"))?;
+        let too_many = || {
+            ConfigError::from(format!(
+                "Too many arguments for facilitator_fallback '{mode}'"
+            ))
+        };
+
+        match mode.to_lowercase().as_str() {
+            "error" | "500" => {
+                if args.len() > 2 {
+                    return Err(too_many());
+                }
+                let status = match args.first() {
+                    Some(status) => status
+                        .parse::<u16>()
+                        .ok()
+                        .filter(|status| (400..=599).contains(status))
+                        .ok_or_else(|| {
+                            ConfigError::from(format!(
+                                "Invalid facilitator_fallback status '{status}', must be 400-599"
+                            ))
+                        })?,
+                    None => 500,
+                };
+                let body = args.get(1).filter(|body| !body.is_empty()).map(|body| (*body).to_string());
+                Ok(FacilitatorFallback::Error { status, body })
+            }
+            "pass" | "bypass" | "through" => {
+                if !args.is_empty() {
+                    return Err(too_many());
+                }
+                Ok(FacilitatorFallback::Pass)
+            }
+            "unavailable" | "503" => {
+                if args.len() > 1 {
+                    return Err(too_many());
+                }
+                let retry_after = match args.first() {
+                    Some(seconds) => seconds.parse::<u32>().map_err(|e| {
+                        ConfigError::from(format!("Invalid facilitator_fallback retry_after: {e}"))
+                    })?,
+                    None => DEFAULT_FALLBACK_RETRY_AFTER,
+                };
+                Ok(FacilitatorFallback::Unavailable { retry_after })
+            }
+            "repay" => {
+                if !args.is_empty() {
+                    return Err(too_many());
+                }
+                Ok(FacilitatorFallback::Repay)
+            }
+            _ => Err(ConfigError::from(
+                "Invalid facilitator_fallback value. Must be 'error', 'pass', 'unavailable' or 'repay'",
+            )),
+        }
+    }
+}
+
+/// Settlement mode
+///
+/// Controls when the facilitator's `/settle` endpoint is called for a verified payment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettleMode {
+    /// Settle right after verification, before the request reaches the content handler
+    BeforeUpstream,
+    /// Settle only after the content handler (or upstream) produced a 2xx response
+    AfterSuccess,
+    /// Never settle (verification only)
+    Off,
+}
+
+/// Verification mode
+///
+/// Controls who verifies payments (see `local_verify`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyMode {
+    /// The facilitator verifies payments (and settles them per `x402_settle`)
+    Facilitator,
+    /// Payments are verified in-process and never settled by the module
+    Local,
+    /// Payments are verified in-process, the facilitator only settles them
+    Hybrid,
+}
+
+/// Default `max_fails` of a facilitator
+pub const DEFAULT_FACILITATOR_MAX_FAILS: u32 = 3;
+
+/// Default `fail_timeout` of a facilitator
+pub const DEFAULT_FACILITATOR_FAIL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Facilitator declared with `x402_facilitator_url`
+///
+/// A location may declare several facilitators; see `failover` for how one is
+/// picked for each call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FacilitatorEndpoint {
+    pub url: String,
+    /// Share of calls among the facilitators of the same priority (default: 1)
+    pub weight: u32,
+    /// Facilitators with a lower priority are tried first (default: 0)
+    pub priority: u32,
+    /// Consecutive failures after which the facilitator is marked down (0: never)
+    pub max_fails: u32,
+    /// How long a facilitator stays marked down
+    pub fail_timeout: Duration,
+}
+
+impl FacilitatorEndpoint {
+    /// Parse an `x402_facilitator_url` line (`url weight=... priority=... max_fails=... fail_timeout=...`)
+    ///
+    /// # Errors
+    /// - Returns error if the URL is invalid
+    /// - Returns error if a parameter is unknown, duplicated or invalid
+    pub fn parse(line: &str) -> Result<Self> {
+        let mut params = line.split_whitespace();
+        let url = params
+            .next()
+            .ok_or_else(|| ConfigError::from("facilitator requires a URL"))?;
+        crate::config::validate_url(url).map_err(|e| ConfigError::from(e.to_string()))?;
+
+        let mut weight = None;
+        let mut priority = None;
+        let mut max_fails = None;
+        let mut fail_timeout = None;
+
+        for param in params {
+            let (key, value) = param.split_once('=').ok_or_else(|| {
+                ConfigError::from(format!(
+                    "facilitator parameter '{param}' is invalid, expected key=value"
+                ))
+            })?;
+            let number = || {
+                value.parse::<u32>().map_err(|e| {
+                    ConfigError::from(format!("Invalid facilitator {key} format: {e}"))
+                })
+            };
+
+            let duplicate = match key {
+                "weight" => {
+                    let value = number()?;
+                    if value == 0 {
+                        return Err(ConfigError::from("facilitator weight must be at least 1"));
+                    }
+                    weight.replace(value).is_some()
+                }
+                "priority" => priority.replace(number()?).is_some(),
+                "max_fails" => max_fails.replace(number()?).is_some(),
+                "fail_timeout" => {
+                    // Seconds, optionally with an "s" suffix
+                    let seconds = value.strip_suffix('s').unwrap_or(value);
+                    let seconds = seconds.parse::<u64>().map_err(|e| {
+                        ConfigError::from(format!("Invalid facilitator fail_timeout format: {e}"))
+                    })?;
+                    fail_timeout.replace(Duration::from_secs(seconds)).is_some()
+                }
+                _ => {
+                    return Err(ConfigError::from(format!(
+                        "Unknown facilitator parameter '{key}'. Must be 'weight', 'priority', 'max_fails' or 'fail_timeout'"
+                    )));
+                }
+            };
+            if duplicate {
+                return Err(ConfigError::from(format!(
+                    "Duplicate facilitator parameter '{key}'"
+                )));
+            }
+        }
+
+        Ok(FacilitatorEndpoint {
+            url: url.to_string(),
+            weight: weight.unwrap_or(1),
+            priority: priority.unwrap_or(0),
+            max_fails: max_fails.unwrap_or(DEFAULT_FACILITATOR_MAX_FAILS),
+            fail_timeout: fail_timeout.unwrap_or(DEFAULT_FACILITATOR_FAIL_TIMEOUT),
+        })
+    }
+}
+
+/// Parse a duration in nginx time syntax (`500ms`, `10s`, `1m`; seconds without a unit)
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (number, unit_ms) = if let Some(ms) = value.strip_suffix("ms") {
+        (ms, 1)
+    } else if let Some(s) = value.strip_suffix('s') {
+        (s, 1000)
+    } else if let Some(m) = value.strip_suffix('m') {
+        (m, 60_000)
+    } else {
+        (value, 1000)
+    };
+    let number = number.parse::<u64>().ok()?;
+    number.checked_mul(unit_ms).map(Duration::from_millis)
+}
+
+/// Default base delay between facilitator call retries
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Retries of failed facilitator calls, set with `x402_facilitator_retries`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt (default: 0)
+    pub retries: u32,
+    /// Base delay, doubled for each further retry
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: DEFAULT_RETRY_BACKOFF,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Parse an `x402_facilitator_retries` value (`count [backoff=time]`)
+    ///
+    /// # Errors
+    /// - Returns error if the count or a parameter is invalid
+    pub fn parse(line: &str) -> Result<Self> {
+        let mut params = line.split_whitespace();
+        let retries = params
+            .next()
+            .ok_or_else(|| ConfigError::from("facilitator_retries requires a count"))?
+            .parse::<u32>()
+            .map_err(|e| ConfigError::from(format!("Invalid facilitator_retries count: {e}")))?;
+        if retries > 10 {
+            return Err(ConfigError::from(
+                "facilitator_retries count too large (maximum: 10)",
+            ));
+        }
+
+        let mut policy = Self {
+            retries,
+            ..Self::default()
+        };
+        for param in params {
+            let Some(value) = param.strip_prefix("backoff=") else {
+                return Err(ConfigError::from(format!(
+                    "Unknown facilitator_retries parameter '{param}'. Must be 'backoff'"
+                )));
+            };
+            policy.backoff = parse_duration(value).ok_or_else(|| {
+                ConfigError::from(format!("Invalid facilitator_retries backoff '{value}'"))
+            })?;
+        }
+        Ok(policy)
+    }
+
+    /// Delay before a retry
+    ///
+    /// The `backoff` is doubled for each retry, and half of it is randomized
+    /// so that workers don't retry in lockstep.
+    ///
+    /// # Arguments
+    /// - `retry`: Number of the retry (1 for the first)
+    /// - `jitter`: Random value in `[0, 1]`
+    #[must_use]
+    pub fn delay(&self, retry: u32, jitter: f64) -> Duration {
+        let base = self
+            .backoff
+            .saturating_mul(1 << retry.saturating_sub(1).min(10));
+        base / 2 + (base / 2).mul_f64(jitter.clamp(0.0, 1.0))
+    }
+}
+
+/// Default minimum number of calls in a window before the circuit breaker can open
+pub const DEFAULT_BREAKER_MIN_CALLS: u32 = 10;
+
+/// Default window over which the circuit breaker computes the failure rate
+pub const DEFAULT_BREAKER_WINDOW: Duration = Duration::from_secs(30);
+
+/// Default time the circuit breaker stays open before probing the facilitators
+pub const DEFAULT_BREAKER_OPEN_TIME: Duration = Duration::from_secs(30);
+
+/// Circuit breaker of facilitator calls, set with `x402_circuit_breaker`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    /// Percentage of failed calls that opens the breaker (1-100)
+    pub failure_rate: u32,
+    /// Calls needed in a window before the breaker can open
+    pub min_calls: u32,
+    /// Window over which the failure rate is computed
+    pub window: Duration,
+    /// How long the breaker stays open before a probe call is let through
+    pub open_time: Duration,
+}
+
+impl CircuitBreakerConfig {
+    /// Parse an `x402_circuit_breaker` value (`off`, or `failure_rate=... min_calls=... window=... open_time=...`)
+    ///
+    /// # Returns
+    /// - `Ok(None)` for `off`
+    ///
+    /// # Errors
+    /// - Returns error if `failure_rate` is missing, or a parameter is unknown or invalid
+    pub fn parse(line: &str) -> Result<Option<Self>> {
+        if line.trim().eq_ignore_ascii_case("off") {
+            return Ok(None);
+        }
+
+        let mut failure_rate = None;
+        let mut min_calls = DEFAULT_BREAKER_MIN_CALLS;
+        let mut window = DEFAULT_BREAKER_WINDOW;
+        let mut open_time = DEFAULT_BREAKER_OPEN_TIME;
+
+        for param in line.split_whitespace() {
+            let (key, value) = param.split_once('=').ok_or_else(|| {
+                ConfigError::from(format!(
+                    "circuit_breaker parameter '{param}' is invalid, expected key=value"
+                ))
+            })?;
+            let duration = || {
+                parse_duration(value)
+                    .filter(|d| !d.is_zero())
+                    .ok_or_else(|| {
+                        ConfigError::from(format!("Invalid circuit_breaker {key} '{value}'"))
+                    })
+            };
+            match key {
+                "failure_rate" => {
+                    let rate = value
+                        .strip_suffix('%')
+                        .unwrap_or(value)
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|rate| (1..=100).contains(rate))
+                        .ok_or_else(|| {
+                            ConfigError::from(format!(
+                                "Invalid circuit_breaker failure_rate '{value}', must be 1-100%"
+                            ))
+                        })?;
+                    failure_rate = Some(rate);
+                }
+                "min_calls" => {
+                    min_calls = value.parse::<u32>().map_err(|e| {
+                        ConfigError::from(format!("Invalid circuit_breaker min_calls: {e}"))
+                    })?;
+                }
+                "window" => window = duration()?,
+                "open_time" => open_time = duration()?,
+                _ => {
+                    return Err(ConfigError::from(format!(
+                        "Unknown circuit_breaker parameter '{key}'. Must be 'failure_rate', 'min_calls', 'window' or 'open_time'"
+                    )));
+                }
+            }
+        }
+
+        let failure_rate = failure_rate
+            .ok_or_else(|| ConfigError::from("circuit_breaker requires failure_rate"))?;
+        Ok(Some(Self {
+            failure_rate,
+            min_calls: min_calls.max(1),
+            window,
+            open_time,
+        }))
+    }
+}
+
+/// How facilitator calls of a location are made
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FacilitatorPolicy {
+    /// Time budget of a call, retries included (None: default timeout)
+    pub timeout: Option<Duration>,
+    pub retry: RetryPolicy,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+/// Payment option declared with `x402_accept`
+///
+/// Unset fields fall back to the location's `x402_amount`, `x402_pay_to` and
+/// `x402_fee_payer`. Without an asset, the option uses USDC on its network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcceptOption {
+    pub network: String,
+    pub asset: Option<String>,
+    pub asset_decimals: Option<u8>,
+    pub amount: Option<Decimal>,
+    pub pay_to: Option<String>,
+    pub fee_payer: Option<String>,
+}
+
+impl AcceptOption {
+    /// Parse an `x402_accept` line
+    /// (`network=... asset=... decimals=... amount=... pay_to=... fee_payer=...`)
+    ///
+    /// # Errors
+    /// - Returns error if a parameter is unknown, duplicated or invalid
+    /// - Returns error if `network` is missing
+    /// - Returns error if an address does not match the network (EVM or Solana)
+    pub fn parse(line: &str) -> Result<Self> {
+        let mut network = None;
+        let mut asset = None;
+        let mut asset_decimals = None;
+        let mut amount = None;
+        let mut pay_to = None;
+        let mut fee_payer = None;
+
+        for param in line.split_whitespace() {
+            let (key, value) = param.split_once('=').ok_or_else(|| {
+                ConfigError::from(format!(
+                    "Invalid accept parameter '{param}', expected key=value"
+                ))
+            })?;
+            if value.is_empty() {
+                return Err(ConfigError::from(format!(
+                    "Empty value for accept parameter '{key}'"
+                )));
+            }
+
+            let duplicate = match key {
+                "network" => {
+                    crate::config::validate_network(value)
+                        .map_err(|e| ConfigError::from(e.to_string()))?;
+                    network.replace(value.to_string()).is_some()
+                }
+                "asset" => {
+                    crate::config::validate_payment_address(value)
+                        .map_err(|e| ConfigError::from(e.to_string()))?;
+                    asset.replace(value.to_string()).is_some()
+                }
+                "decimals" => {
+                    let decimals = value.parse::<u8>().map_err(|e| {
+                        ConfigError::from(format!("Invalid accept decimals format: {e}"))
+                    })?;
+                    if decimals > 28 {
+                        return Err(ConfigError::from(
+                            "accept decimals must be at most 28 (Decimal max precision)",
+                        ));
+                    }
+                    asset_decimals.replace(decimals).is_some()
+                }
+                "amount" => {
+                    let value = Decimal::from_str(value).map_err(|e| {
+                        ConfigError::from(format!("Invalid accept amount format: {e}"))
+                    })?;
+                    crate::config::validate_amount(value)
+                        .map_err(|e| ConfigError::from(e.to_string()))?;
+                    amount.replace(value).is_some()
+                }
+                "pay_to" => {
+                    crate::config::validate_payment_address(value)
+                        .map_err(|e| ConfigError::from(e.to_string()))?;
+                    pay_to.replace(value.to_string()).is_some()
+                }
+                "fee_payer" => {
+                    crate::config::validate_solana_address(value)
+                        .map_err(|e| ConfigError::from(e.to_string()))?;
+                    fee_payer.replace(value.to_string()).is_some()
+                }
+                _ => {
+                    return Err(ConfigError::from(format!(
+                        "Unknown accept parameter '{key}'. Must be 'network', 'asset', 'decimals', 'amount', 'pay_to' or 'fee_payer'"
+                    )));
+                }
+            };
+            if duplicate {
+                return Err(ConfigError::from(format!(
+                    "Duplicate accept parameter '{key}'"
+                )));
+            }
+        }
+
+        let network =
+            network.ok_or_else(|| ConfigError::from("accept option requires a network"))?;
+        if let Some(info) = crate::config::network_by_name(&network) {
+            for address in [&asset, &pay_to].into_iter().flatten() {
+                crate::config::validate_network_address(info.vm, address).map_err(|e| {
+                    ConfigError::from(format!("Invalid address for network {network}: {e}"))
+                })?;
+            }
+        }
+
+        Ok(AcceptOption {
+            network,
+            asset,
+            asset_decimals,
+            amount,
+            pay_to,
+            fee_payer,
+        })
+    }
 }
 
 /// Parsed configuration
+#[derive(Clone)]
 pub struct ParsedX402Config {
     pub enabled: bool,
     pub amount: Option<Decimal>,
     pub pay_to: Option<String>,
-    pub facilitator_url: Option<String>,
+    pub facilitator_url: Option<String>, // First facilitator (see `facilitators`)
+    pub facilitators: Vec<FacilitatorEndpoint>, // All facilitators from x402_facilitator_url
     pub description: Option<String>,
     pub network: Option<String>,
     pub network_id: Option<u64>, // Chain ID (e.g., 8453, 84532)
@@ -58,9 +614,91 @@ pub struct ParsedX402Config {
     pub timeout: Option<Duration>, // Timeout for facilitator requests
     pub facilitator_fallback: FacilitatorFallback, // Fallback behavior when facilitator fails
     pub ttl: Option<u32>,      // TTL for payment authorization validity in seconds (default: 60)
+    pub settle: SettleMode,    // When to settle verified payments (default: after_success)
+    pub forward_headers: bool, // Inject trusted X-X402-* headers for the upstream (default: off)
+    pub accepts: Vec<AcceptOption>, // Payment options from x402_accept (empty: single option)
+    pub upstream_pricing: bool, // Let the upstream set the price with a 402 response (default: off)
+    pub verify_mode: VerifyMode, // Who verifies payments (default: facilitator)
+    pub retry: RetryPolicy,    // Retries of failed facilitator calls (default: none)
+    pub circuit_breaker: Option<CircuitBreakerConfig>, // Circuit breaker (None: off)
+    pub fee_payer: Option<String>, // Solana fee payer advertised in `extra.feePayer`
+    pub session: Option<ConfiguredSession>, // Prepaid sessions accepted (None: off)
+    pub session_purchase: Option<SessionGrant>, // Sessions sold by this location (None: not a purchase endpoint)
+    pub access_pass: Option<ConfiguredAccessPass>, // Access passes issued and accepted (None: off)
+    pub receipt: Option<ConfiguredReceipt>,     // Signed receipts of verified payments (None: off)
+    pub paywall_template: Option<ConfiguredPaywallTemplate>, // Custom HTML paywall (None: built-in page)
+    pub paywall_assets_url: Option<String>, // Self-hosted paywall assets (None: built-in page)
+    pub response_format: ResponseFormat,    // Format of 402 responses (default: auto)
+    pub browser_detection: Option<ConfiguredBrowserDetection>, // Detection rules (None: built-in)
+    pub replay_zone: Option<SharedTable>,   // Used authorizations (None: replay protection off)
+    pub verify_cache: Option<VerifyCache>,  // Cached verification results (None: cache off)
+}
+
+impl ParsedX402Config {
+    /// How facilitator calls of the location are made
+    #[must_use]
+    pub fn facilitator_policy(&self) -> FacilitatorPolicy {
+        FacilitatorPolicy {
+            timeout: self.timeout,
+            retry: self.retry,
+            circuit_breaker: self.circuit_breaker,
+        }
+    }
+
+    /// Whether client-supplied `X-X402-*` headers are removed before the request
+    /// reaches the upstream (the upstream trusts the ones set by the module)
+    #[must_use]
+    pub fn strips_forward_headers(&self) -> bool {
+        self.forward_headers || self.upstream_pricing || self.receipt.is_some()
+    }
 }
 
 impl X402Config {
+    /// Validate a merged configuration block at configuration time
+    ///
+    /// Runs the checks of [`X402Config::parse`], so that invalid values and
+    /// combinations of directives fail `nginx -t` rather than every request.
+    /// Values containing variables are only known per request and are skipped.
+    ///
+    /// # Errors
+    /// - Returns error if the block's configuration doesn't parse
+    pub fn validate(&self) -> Result<()> {
+        let mut conf = self.clone();
+        if conf.amount_cv.is_some() {
+            conf.amount_str = ngx_str_t::default();
+        }
+        if conf.pay_to_cv.is_some() {
+            conf.pay_to_str = ngx_str_t::default();
+        }
+        if conf.description_cv.is_some() {
+            conf.description_str = ngx_str_t::default();
+        }
+        if conf.resource_cv.is_some() {
+            conf.resource_str = ngx_str_t::default();
+        }
+        conf.parse().map(|_| ())
+    }
+
+    /// Receipt lifetime in seconds (`x402_receipt_ttl`, default: 300)
+    ///
+    /// Applied to the receipt keys when the configuration is merged, since the
+    /// keys and the lifetime may be set at different levels.
+    ///
+    /// # Errors
+    /// - Returns error if the value is not a positive time
+    pub fn receipt_ttl(&self) -> Result<u64> {
+        if self.receipt_ttl_str.len == 0 {
+            return Ok(DEFAULT_RECEIPT_TTL);
+        }
+        let ngx_str = unsafe { NgxStr::from_ngx_str(self.receipt_ttl_str) };
+        ngx_str
+            .to_str()
+            .ok()
+            .and_then(crate::ngx_module::session::parse_duration)
+            .filter(|ttl| *ttl > 0)
+            .ok_or_else(|| ConfigError::from("Invalid receipt_ttl value"))
+    }
+
     /// Parse raw config strings into typed values
     ///
     /// Converts Nginx configuration strings into typed values, handling empty strings
@@ -99,26 +737,38 @@ impl X402Config {
                 .to_str()
                 .map_err(|_| ConfigError::from("Invalid pay_to string encoding"))?;
 
-            // Validate Ethereum address format
-            crate::config::validate_ethereum_address(pay_to_str)
+            // Validate EVM or Solana address format (checked against the network later)
+            crate::config::validate_payment_address(pay_to_str)
                 .map_err(|e| ConfigError::from(e.to_string()))?;
 
             Some(pay_to_str.to_string())
         };
 
-        let facilitator_url = if self.facilitator_url_str.len == 0 {
-            None
+        // Parse facilitators (one x402_facilitator_url directive per line)
+        let facilitators = if self.facilitator_url_str.len == 0 {
+            Vec::new()
         } else {
             let ngx_str = unsafe { NgxStr::from_ngx_str(self.facilitator_url_str) };
             let url_str = ngx_str
                 .to_str()
                 .map_err(|_| ConfigError::from("Invalid facilitator_url string encoding"))?;
 
-            // Validate URL format
-            crate::config::validate_url(url_str).map_err(|e| ConfigError::from(e.to_string()))?;
-
-            Some(url_str.to_string())
+            let facilitators = url_str
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(FacilitatorEndpoint::parse)
+                .collect::<Result<Vec<_>>>()?;
+            for (i, facilitator) in facilitators.iter().enumerate() {
+                if facilitators[..i].iter().any(|f| f.url == facilitator.url) {
+                    return Err(ConfigError::from(format!(
+                        "Duplicate facilitator URL '{}'",
+                        facilitator.url
+                    )));
+                }
+            }
+            facilitators
         };
+        let facilitator_url = facilitators.first().map(|f| f.url.clone());
 
         let description = if self.description_str.len == 0 {
             None
@@ -181,8 +831,8 @@ impl X402Config {
                 .to_str()
                 .map_err(|_| ConfigError::from("Invalid asset string encoding"))?;
 
-            // Validate Ethereum address format
-            crate::config::validate_ethereum_address(asset_str)
+            // Validate token contract or SPL mint address format
+            crate::config::validate_payment_address(asset_str)
                 .map_err(|e| ConfigError::from(e.to_string()))?;
 
             Some(asset_str.to_string())
@@ -242,22 +892,16 @@ impl X402Config {
 
         // Parse facilitator fallback mode
         let facilitator_fallback = if self.facilitator_fallback_str.len == 0 {
-            FacilitatorFallback::Error // Default: return error
+            FacilitatorFallback::default() // Default: return 500
         } else {
             let ngx_str = unsafe { NgxStr::from_ngx_str(self.facilitator_fallback_str) };
             let fallback_str = ngx_str
                 .to_str()
                 .map_err(|_| ConfigError::from("Invalid facilitator_fallback string encoding"))?;
 
-            match fallback_str.to_lowercase().as_str() {
-                "error" | "500" => FacilitatorFallback::Error,
-                "pass" | "bypass" | "through" => FacilitatorFallback::Pass,
-                _ => {
-                    return Err(ConfigError::from(
-                        "Invalid facilitator_fallback value. Must be 'error' or 'pass'",
-                    ));
-                }
-            }
+            // One argument per line, the body may contain spaces
+            let args: Vec<&str> = fallback_str.splitn(3, '\n').collect();
+            FacilitatorFallback::parse(&args)?
         };
 
         // Parse TTL (payment authorization validity time)
@@ -287,11 +931,232 @@ impl X402Config {
             Some(ttl_value)
         };
 
+        // Parse settlement mode
+        let settle = if self.settle_str.len == 0 {
+            SettleMode::AfterSuccess // Default: only bill when the upstream succeeded
+        } else {
+            let ngx_str = unsafe { NgxStr::from_ngx_str(self.settle_str) };
+            let settle_str = ngx_str
+                .to_str()
+                .map_err(|_| ConfigError::from("Invalid settle string encoding"))?;
+
+            match settle_str.to_lowercase().as_str() {
+                "before_upstream" => SettleMode::BeforeUpstream,
+                "after_success" => SettleMode::AfterSuccess,
+                "off" => SettleMode::Off,
+                _ => {
+                    return Err(ConfigError::from(
+                        "Invalid settle value. Must be 'before_upstream', 'after_success' or 'off'",
+                    ));
+                }
+            }
+        };
+
+        // Parse upstream header forwarding
+        let forward_headers = if self.forward_headers_str.len == 0 {
+            false // Default: don't touch request headers
+        } else {
+            let ngx_str = unsafe { NgxStr::from_ngx_str(self.forward_headers_str) };
+            let forward_headers_str = ngx_str
+                .to_str()
+                .map_err(|_| ConfigError::from("Invalid forward_headers string encoding"))?;
+
+            match forward_headers_str.to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    return Err(ConfigError::from(
+                        "Invalid forward_headers value. Must be 'on' or 'off'",
+                    ));
+                }
+            }
+        };
+
+        // Parse payment options (one x402_accept directive per line)
+        let accepts = if self.accepts_str.len == 0 {
+            Vec::new()
+        } else {
+            let ngx_str = unsafe { NgxStr::from_ngx_str(self.accepts_str) };
+            let accepts_str = ngx_str
+                .to_str()
+                .map_err(|_| ConfigError::from("Invalid accept string encoding"))?;
+
+            accepts_str
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(AcceptOption::parse)
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        // Parse upstream-driven pricing
+        let upstream_pricing = if self.upstream_pricing_str.len == 0 {
+            false // Default: the price comes from the configuration
+        } else {
+            let ngx_str = unsafe { NgxStr::from_ngx_str(self.upstream_pricing_str) };
+            let upstream_pricing_str = ngx_str
+                .to_str()
+                .map_err(|_| ConfigError::from("Invalid upstream_pricing string encoding"))?;
+
+            match upstream_pricing_str.to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    return Err(ConfigError::from(
+                        "Invalid upstream_pricing value. Must be 'on' or 'off'",
+                    ));
+                }
+            }
+        };
+
+        // Parse verification mode
+        let verify_mode = if self.verify_mode_str.len == 0 {
+            VerifyMode::Facilitator // Default: the facilitator verifies
+        } else {
+            let ngx_str = unsafe { NgxStr::from_ngx_str(self.verify_mode_str) };
+            let verify_mode_str = ngx_str
+                .to_str()
+                .map_err(|_| ConfigError::from("Invalid verify_mode string encoding"))?;
+
+            match verify_mode_str.to_lowercase().as_str() {
+                "facilitator" => VerifyMode::Facilitator,
+                "local" => VerifyMode::Local,
+                "hybrid" => VerifyMode::Hybrid,
+                _ => {
+                    return Err(ConfigError::from(
+                        "Invalid verify_mode value. Must be 'facilitator', 'local' or 'hybrid'",
+                    ));
+                }
+            }
+        };
+
+        // Parse facilitator call retries
+        let retry = if self.retries_str.len == 0 {
+            RetryPolicy::default()
+        } else {
+            let ngx_str = unsafe { NgxStr::from_ngx_str(self.retries_str) };
+            let retries_str = ngx_str
+                .to_str()
+                .map_err(|_| ConfigError::from("Invalid facilitator_retries string encoding"))?;
+            RetryPolicy::parse(retries_str)?
+        };
+
+        // Parse circuit breaker
+        let circuit_breaker = if self.circuit_breaker_str.len == 0 {
+            None // Default: off
+        } else {
+            let ngx_str = unsafe { NgxStr::from_ngx_str(self.circuit_breaker_str) };
+            let circuit_breaker_str = ngx_str
+                .to_str()
+                .map_err(|_| ConfigError::from("Invalid circuit_breaker string encoding"))?;
+            CircuitBreakerConfig::parse(circuit_breaker_str)?
+        };
+
+        // Parse Solana fee payer
+        let fee_payer = if self.fee_payer_str.len == 0 {
+            None
+        } else {
+            let ngx_str = unsafe { NgxStr::from_ngx_str(self.fee_payer_str) };
+            let fee_payer_str = ngx_str
+                .to_str()
+                .map_err(|_| ConfigError::from("Invalid fee_payer string encoding"))?;
+            crate::config::validate_solana_address(fee_payer_str)
+                .map_err(|e| ConfigError::from(e.to_string()))?;
+            Some(fee_payer_str.to_string())
+        };
+
+        // A payment verified without the facilitator, and not settled before it is
+        // served, is only kept from being reused by the replay zone
+        let unsettled_local = verify_mode == VerifyMode::Local
+            || (verify_mode == VerifyMode::Hybrid && settle != SettleMode::BeforeUpstream);
+        if unsettled_local && self.replay_zone.is_none() {
+            return Err(ConfigError::from(
+                "x402_verify_mode local and hybrid require x402_replay_zone unless x402_settle is before_upstream",
+            ));
+        }
+
+        // Parse prepaid sessions
+        let session = self.session.map(ConfiguredSession::new);
+
+        let session_purchase = if self.session_purchase_str.len == 0 {
+            None
+        } else {
+            let ngx_str = unsafe { NgxStr::from_ngx_str(self.session_purchase_str) };
+            let session_purchase_str = ngx_str
+                .to_str()
+                .map_err(|_| ConfigError::from("Invalid session_purchase string encoding"))?;
+            if session.is_none() {
+                return Err(ConfigError::from(
+                    "x402_session_purchase requires x402_session",
+                ));
+            }
+            // The token is issued before the upstream could report a price
+            if upstream_pricing {
+                return Err(ConfigError::from(
+                    "x402_session_purchase can't be combined with x402_upstream_pricing",
+                ));
+            }
+            // A payment that is never settled can be verified again and again, so it
+            // would buy any number of sessions
+            if settle == SettleMode::Off || verify_mode == VerifyMode::Local {
+                return Err(ConfigError::from(
+                    "x402_session_purchase can't be combined with x402_settle off or x402_verify_mode local",
+                ));
+            }
+            Some(SessionGrant::parse(session_purchase_str)?)
+        };
+
+        // Parse access passes
+        let access_pass = if let Some(access_pass) = self.access_pass {
+            // The pass is priced like a single request, it can't depend on the upstream
+            if upstream_pricing {
+                return Err(ConfigError::from(
+                    "x402_access_pass can't be combined with x402_upstream_pricing",
+                ));
+            }
+            // Like a session purchase, an unsettled payment would buy any number of passes
+            if settle == SettleMode::Off || verify_mode == VerifyMode::Local {
+                return Err(ConfigError::from(
+                    "x402_access_pass can't be combined with x402_settle off or x402_verify_mode local",
+                ));
+            }
+            Some(ConfiguredAccessPass::new(access_pass))
+        } else {
+            None
+        };
+
+        // Parse self-hosted paywall assets URL
+        let paywall_assets_url = if self.paywall_assets_url_str.len == 0 {
+            None
+        } else {
+            let ngx_str = unsafe { NgxStr::from_ngx_str(self.paywall_assets_url_str) };
+            let url = ngx_str
+                .to_str()
+                .map_err(|_| ConfigError::from("Invalid paywall_assets_url string encoding"))?;
+            if !crate::ngx_module::paywall::is_valid_assets_url(url) {
+                return Err(ConfigError::from(format!(
+                    "Invalid paywall_assets_url: {url}"
+                )));
+            }
+            Some(url.to_string())
+        };
+
+        // Parse response format
+        let response_format = if self.response_format_str.len == 0 {
+            ResponseFormat::default()
+        } else {
+            let ngx_str = unsafe { NgxStr::from_ngx_str(self.response_format_str) };
+            let format_str = ngx_str
+                .to_str()
+                .map_err(|_| ConfigError::from("Invalid response_format string encoding"))?;
+            ResponseFormat::parse(format_str)?
+        };
+
         Ok(ParsedX402Config {
             enabled: self.enabled != 0,
             amount,
             pay_to,
             facilitator_url,
+            facilitators,
             description,
             network,
             network_id,
@@ -301,6 +1166,27 @@ impl X402Config {
             timeout,
             facilitator_fallback,
             ttl,
+            settle,
+            forward_headers,
+            accepts,
+            upstream_pricing,
+            verify_mode,
+            retry,
+            circuit_breaker,
+            fee_payer,
+            session,
+            session_purchase,
+            access_pass,
+            receipt: self.receipt.map(ConfiguredReceipt::new),
+            paywall_template: self.paywall_template.map(ConfiguredPaywallTemplate::new),
+            paywall_assets_url,
+            response_format,
+            browser_detection: self.browser_detection.map(ConfiguredBrowserDetection::new),
+            replay_zone: self.replay_zone.map(SharedTable::new),
+            verify_cache: self.verify_cache_zone.map(|zone| VerifyCache {
+                table: SharedTable::new(zone),
+                negative_ttl: self.verify_cache_negative_ttl,
+            }),
         })
     }
 }
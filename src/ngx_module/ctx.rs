@@ -0,0 +1,158 @@
+//! Per-request module context
+//!
+//! Payment state that must outlive the access phase (for example a verified
+//! payment that is only settled once the upstream has responded) is kept in
+//! the request's module context slot (`r->ctx[ctx_index]`).
+//!
+//! The context is allocated from the request pool via `Pool::allocate`, which
+//! registers a cleanup handler so owned Rust values (`String`, etc.) are dropped
+//! when the request is finalized.
+
+use crate::ngx_module::access_pass::ConfiguredAccessPass;
+use crate::ngx_module::async_verify::{CompletedSettlement, CompletedVerification};
+use crate::ngx_module::config::{FacilitatorEndpoint, FacilitatorPolicy};
+use crate::ngx_module::filter::HeldResponse;
+use crate::ngx_module::module::ngx_http_x402_module;
+use crate::ngx_module::receipt::ConfiguredReceipt;
+use crate::ngx_module::session::SessionToken;
+use crate::ngx_module::upstream_pricing::Upstream402;
+use ngx::http::Request;
+use rust_x402::types::{PaymentRequirements, SettleResponse};
+
+/// A verified payment waiting to be settled
+pub struct PendingSettlement {
+    /// Base64-encoded payment payload from the X-PAYMENT header
+    pub payment_b64: String,
+    /// Requirements the payment was verified against
+    pub requirements: PaymentRequirements,
+    /// Facilitators of the location
+    pub facilitators: Vec<FacilitatorEndpoint>,
+    /// Timeout, retries and circuit breaker of the location
+    pub policy: FacilitatorPolicy,
+}
+
+/// Payment outcome of a request (exposed as `$x402_status`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaymentStatus {
+    /// No payment was presented (or the module did not process the request)
+    #[default]
+    None,
+    /// Payment was verified successfully
+    Valid,
+    /// Payment was presented but rejected
+    Invalid,
+    /// The facilitator could not be reached or returned an error
+    FacilitatorError,
+    /// Payment verification was skipped for this request
+    Bypassed,
+    /// The payment authorization had already been used (`x402_replay_zone`)
+    Replayed,
+    /// The request was paid with a prepaid session token (`x402_session`)
+    Session,
+    /// The request was covered by a time-based access pass (`x402_access_pass`)
+    AccessPass,
+}
+
+impl PaymentStatus {
+    /// Variable value for this status
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PaymentStatus::None => "none",
+            PaymentStatus::Valid => "valid",
+            PaymentStatus::Invalid => "invalid",
+            PaymentStatus::FacilitatorError => "facilitator_error",
+            PaymentStatus::Bypassed => "bypassed",
+            PaymentStatus::Replayed => "replayed",
+            PaymentStatus::Session => "session",
+            PaymentStatus::AccessPass => "access_pass",
+        }
+    }
+}
+
+/// Per-request x402 state
+#[derive(Default)]
+pub struct X402RequestCtx {
+    /// Payment outcome (`$x402_status`)
+    pub status: PaymentStatus,
+    /// Payer address from the payment payload, set once verified (`$x402_payer`)
+    pub payer: Option<String>,
+    /// Required amount in token units, e.g. "0.0001" (`$x402_amount`)
+    pub amount: Option<String>,
+    /// Network of the payment requirements (`$x402_network`)
+    pub network: Option<String>,
+    /// Asset address of the payment requirements (`$x402_asset`)
+    pub asset: Option<String>,
+    /// Facilitator verification time in milliseconds (`$x402_verify_ms`)
+    pub verify_ms: Option<u64>,
+    /// Resource URL of the payment requirements (`$x402_resource`)
+    pub resource: Option<String>,
+    /// Result of a non-blocking verification, delivered before the phase handler is re-run
+    pub completed_verification: Option<CompletedVerification>,
+    /// Result of a non-blocking settlement (`before_upstream`), delivered the same way
+    pub completed_settlement: Option<CompletedSettlement>,
+    /// Verified payment to settle after a successful response (`after_success` mode)
+    pub pending_settlement: Option<PendingSettlement>,
+    /// Facilitator settlement response, once the payment has been settled
+    pub settle_response: Option<SettleResponse>,
+    /// Successful response held by the header filter while its payment is settled
+    pub held_response: Option<HeldResponse>,
+    /// A 402 from the upstream is rewritten into payment requirements (`x402_upstream_pricing`)
+    pub awaiting_upstream_price: bool,
+    /// Upstream 402 response being rewritten by the body filter
+    pub upstream_402: Option<Upstream402>,
+    /// Access pass to issue with a successful response (`x402_access_pass`)
+    pub pending_access_pass: Option<ConfiguredAccessPass>,
+    /// Receipt keys for the response's `X-X402-Receipt` header (`x402_receipt_key`)
+    pub pending_receipt: Option<ConfiguredReceipt>,
+    /// Session reserved for a purchase until the payment is settled (`x402_session_purchase`)
+    pub reserved_session: Option<SessionToken>,
+}
+
+/// Get the module context pointer for this request (may be null)
+fn request_ctx_ptr(req: &Request) -> *mut X402RequestCtx {
+    // Safe: the module structure is only written by nginx during initialization
+    let module = unsafe { &*(&raw const ngx_http_x402_module) };
+    req.get_module_ctx::<X402RequestCtx>(module)
+        .map_or(std::ptr::null_mut(), |ctx| {
+            std::ptr::from_ref(ctx).cast_mut()
+        })
+}
+
+/// Get the x402 context for this request, if one has been created
+pub fn request_ctx_mut(req: &mut Request) -> Option<&mut X402RequestCtx> {
+    // Safe: the pointer is either null or was allocated by `get_or_create_request_ctx`
+    // from this request's pool, and `req` is borrowed mutably for the returned lifetime
+    unsafe { request_ctx_ptr(req).as_mut() }
+}
+
+/// Get the x402 context for this request, creating it on first use
+///
+/// # Returns
+/// - `Some(&mut X402RequestCtx)` on success
+/// - `None` if the context could not be allocated from the request pool
+pub fn get_or_create_request_ctx(req: &mut Request) -> Option<&mut X402RequestCtx> {
+    if request_ctx_ptr(req).is_null() {
+        let ctx = req.pool().allocate(X402RequestCtx::default());
+        if ctx.is_null() {
+            return None;
+        }
+        // Safe: see request_ctx_ptr
+        let module = unsafe { &*(&raw const ngx_http_x402_module) };
+        req.set_module_ctx(ctx.cast(), module);
+    }
+    request_ctx_mut(req)
+}
+
+/// Update the x402 context for this request, creating it on first use
+///
+/// Used for state that is informational only (variables): if the context cannot
+/// be allocated, the update is skipped.
+pub fn update_request_ctx<F>(req: &mut Request, update: F)
+where
+    F: FnOnce(&mut X402RequestCtx),
+{
+    if let Some(ctx) = get_or_create_request_ctx(req) {
+        update(ctx);
+    }
+}
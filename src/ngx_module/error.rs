@@ -39,4 +39,10 @@ pub mod user_errors {
     pub const INVALID_PAYMENT: &str = "Invalid payment";
     pub const CONFIGURATION_ERROR: &str = "Configuration error";
     pub const TIMEOUT: &str = "Request timeout";
+    pub const SETTLEMENT_FAILED: &str = "Payment settlement failed";
+    pub const NO_MATCHING_PAYMENT_OPTION: &str =
+        "Payment does not match any accepted payment option";
+    pub const PRICE_UNAVAILABLE: &str = "Price is not available for this resource";
+    pub const PAYMENT_ALREADY_USED: &str = "Payment authorization has already been used";
+    pub const FACILITATOR_UNAVAILABLE: &str = "Payment facilitator is unavailable";
 }
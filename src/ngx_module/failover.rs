@@ -0,0 +1,413 @@
+//! Facilitator failover
+//!
+//! A location may declare several facilitators with repeated
+//! `x402_facilitator_url` directives. For each facilitator call:
+//!
+//! 1. Facilitators that are up are tried by ascending `priority`. Within a
+//!    priority, calls are spread by `weight`, and the others follow as fallbacks
+//! 2. A failed call (network error, timeout, error response) is retried on the
+//!    next facilitator. Settlement moves funds, so it is only retried if the
+//!    request never reached the facilitator (the connection couldn't be
+//!    established): after a timeout or a response, the facilitator may have
+//!    settled the payment
+//! 3. After `max_fails` consecutive failures, a facilitator is marked down for
+//!    `fail_timeout`; after that it is tried again, and marked down again by its
+//!    next failure
+//!
+//! Only network errors, timeouts and server errors (5xx) are failures. A client
+//! error response (4xx) means the facilitator refused the payment: the call is
+//! not retried, and the facilitator's health is left as it is.
+//!
+//! If every facilitator is down, all of them are tried anyway. When all of them
+//! failed, the call is retried `x402_facilitator_retries` times, within the
+//! `x402_timeout` budget.
+//!
+//! Like nginx upstreams without a shared zone, each worker process tracks
+//! health on its own. Health is exported as the `x402_facilitator_up` and
+//! `x402_facilitator_consecutive_failures` gauges.
+
+use crate::ngx_module::config::{FacilitatorEndpoint, RetryPolicy};
+use crate::ngx_module::error::{user_errors, ConfigError};
+use crate::ngx_module::logging::log_warn;
+use crate::ngx_module::metrics::X402Metrics;
+use reqwest::StatusCode;
+use rust_x402::X402Error;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Health of the facilitators of this worker process, keyed by URL
+static FACILITATOR_HEALTH: OnceLock<Mutex<HashMap<String, FacilitatorHealth>>> = OnceLock::new();
+
+/// Counter spreading calls between facilitators of the same priority
+static ROTATION: AtomicU64 = AtomicU64::new(0);
+
+/// Health of one facilitator
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FacilitatorHealth {
+    /// Failures since the last successful call
+    pub consecutive_failures: u32,
+    /// End of the current down period
+    pub down_until: Option<Instant>,
+}
+
+impl FacilitatorHealth {
+    /// Whether the facilitator may be used at `now`
+    #[must_use]
+    pub fn is_up(&self, now: Instant) -> bool {
+        self.down_until.is_none_or(|until| now >= until)
+    }
+
+    /// Record a successful call
+    pub fn record_success(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Record a failed call
+    ///
+    /// # Returns
+    /// - `true` if the facilitator has just been marked down
+    pub fn record_failure(&mut self, endpoint: &FacilitatorEndpoint, now: Instant) -> bool {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        if endpoint.max_fails == 0
+            || self.consecutive_failures < endpoint.max_fails
+            || !self.is_up(now)
+        {
+            return false;
+        }
+        self.down_until = Some(now + endpoint.fail_timeout);
+        true
+    }
+}
+
+/// How a facilitator call failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The request never reached the facilitator (connection not established)
+    NotSent,
+    /// The request was sent, but no usable answer came back (timeout, lost
+    /// connection, server error): the facilitator may have processed it
+    NoAnswer,
+    /// The facilitator refused the request (4xx): the payment is invalid
+    Rejected,
+}
+
+/// Error of a facilitator call
+#[derive(Debug)]
+pub struct CallError {
+    /// How the call failed
+    pub kind: FailureKind,
+    /// Error reported to the caller
+    pub error: ConfigError,
+}
+
+impl CallError {
+    /// Error of a call that failed with `kind`
+    #[must_use]
+    pub fn new(kind: FailureKind, error: impl Into<ConfigError>) -> Self {
+        Self {
+            kind,
+            error: error.into(),
+        }
+    }
+}
+
+/// Error of a request to a facilitator
+#[derive(Debug)]
+pub enum FacilitatorError {
+    /// The facilitator answered with an error status (calls made by the module)
+    Status {
+        /// Endpoint that was called (`verify` or `settle`)
+        endpoint: &'static str,
+        /// Response status
+        status: StatusCode,
+        /// Response body
+        body: String,
+    },
+    /// Error of a call made by rust-x402, or of the connection
+    Client(X402Error),
+}
+
+impl FacilitatorError {
+    /// How the call failed
+    #[must_use]
+    pub fn kind(&self) -> FailureKind {
+        match self {
+            FacilitatorError::Status { status, .. } => FailureKind::of_status(*status),
+            FacilitatorError::Client(error) => FailureKind::of(error),
+        }
+    }
+}
+
+impl std::fmt::Display for FacilitatorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FacilitatorError::Status {
+                endpoint,
+                status,
+                body,
+            } => write!(
+                f,
+                "{endpoint} failed with status: {status}. Response: {body}"
+            ),
+            FacilitatorError::Client(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for FacilitatorError {}
+
+impl From<X402Error> for FacilitatorError {
+    fn from(error: X402Error) -> Self {
+        FacilitatorError::Client(error)
+    }
+}
+
+impl From<reqwest::Error> for FacilitatorError {
+    fn from(error: reqwest::Error) -> Self {
+        FacilitatorError::Client(X402Error::from(error))
+    }
+}
+
+impl FailureKind {
+    /// How a call made by rust-x402 that returned `error` failed
+    #[must_use]
+    pub fn of(error: &X402Error) -> Self {
+        match error {
+            X402Error::Http(e) if e.is_connect() => FailureKind::NotSent,
+            X402Error::FacilitatorError { message } => {
+                response_status(message).map_or(FailureKind::NoAnswer, FailureKind::of_status)
+            }
+            _ => FailureKind::NoAnswer,
+        }
+    }
+
+    /// How a call answered with an error `status` failed
+    #[must_use]
+    pub fn of_status(status: StatusCode) -> Self {
+        // Timeouts and rate limiting don't say anything about the payment
+        if status.is_client_error()
+            && status != StatusCode::REQUEST_TIMEOUT
+            && status != StatusCode::TOO_MANY_REQUESTS
+        {
+            FailureKind::Rejected
+        } else {
+            FailureKind::NoAnswer
+        }
+    }
+
+    /// Whether a call that failed this way may be made again
+    ///
+    /// # Arguments
+    /// - `idempotent`: Whether making the call twice is harmless (verification
+    ///   is, settlement isn't)
+    #[must_use]
+    pub fn may_retry(self, idempotent: bool) -> bool {
+        match self {
+            FailureKind::NotSent => true,
+            FailureKind::NoAnswer => idempotent,
+            FailureKind::Rejected => false,
+        }
+    }
+}
+
+/// HTTP status of a facilitator error response of rust-x402
+///
+/// rust-x402 only reports it in the error message ("... with status: 400 Bad Request ...").
+fn response_status(message: &str) -> Option<StatusCode> {
+    let (_, status) = message.split_once("status: ")?;
+    StatusCode::from_bytes(status.get(..3)?.as_bytes()).ok()
+}
+
+/// Order in which facilitators are tried
+///
+/// # Arguments
+/// - `endpoints`: Facilitators of the location
+/// - `up`: Whether each facilitator is up (same order as `endpoints`)
+/// - `rotation`: Call counter, selects the first facilitator within a priority
+///   according to the weights
+///
+/// # Returns
+/// Indices into `endpoints`: the facilitators that are up, or all of them if none is
+#[must_use]
+pub fn attempt_order(endpoints: &[FacilitatorEndpoint], up: &[bool], rotation: u64) -> Vec<usize> {
+    let mut candidates: Vec<usize> = (0..endpoints.len()).filter(|&i| up[i]).collect();
+    if candidates.is_empty() {
+        candidates = (0..endpoints.len()).collect();
+    }
+    // Stable: declaration order within a priority
+    candidates.sort_by_key(|&i| endpoints[i].priority);
+
+    let mut order = Vec::with_capacity(candidates.len());
+    for group in candidates.chunk_by(|&a, &b| endpoints[a].priority == endpoints[b].priority) {
+        let total_weight: u64 = group.iter().map(|&i| u64::from(endpoints[i].weight)).sum();
+        let mut point = rotation % total_weight.max(1);
+        let first = group
+            .iter()
+            .position(|&i| {
+                let weight = u64::from(endpoints[i].weight);
+                if point < weight {
+                    true
+                } else {
+                    point -= weight;
+                    false
+                }
+            })
+            .unwrap_or(0);
+        order.extend(group[first..].iter().chain(&group[..first]));
+    }
+    order
+}
+
+/// Run a facilitator call, failing over to the next facilitator on errors
+///
+/// When every facilitator failed, the whole sequence is retried up to
+/// `retry.retries` times, after a jittered backoff. All attempts share the
+/// `budget`; each attempt may use what is left of it. A call that isn't
+/// `idempotent` is only made again if it never reached a facilitator.
+///
+/// # Arguments
+/// - `endpoints`: Facilitators of the location
+/// - `retry`: Retries after all facilitators failed
+/// - `budget`: Time budget of all attempts
+/// - `idempotent`: Whether the call may be repeated after it was sent
+/// - `call`: The call, made with the URL of one facilitator and its time limit
+///
+/// # Errors
+/// - Returns the error of the last attempt if every attempt failed
+/// - Returns the error of an attempt that may not be repeated, e.g. a rejected payment
+/// - Returns timeout error if the budget ran out
+/// - Returns error if no facilitator is configured
+pub async fn call_with_failover<'a, T, F, Fut>(
+    endpoints: &'a [FacilitatorEndpoint],
+    retry: RetryPolicy,
+    budget: Duration,
+    idempotent: bool,
+    mut call: F,
+) -> std::result::Result<T, CallError>
+where
+    F: FnMut(&'a str, Duration) -> Fut,
+    Fut: Future<Output = std::result::Result<T, CallError>>,
+{
+    let deadline = Instant::now() + budget;
+    let mut last_error = None;
+
+    for pass in 0..=retry.retries {
+        if pass > 0 {
+            let delay = retry.delay(pass, jitter());
+            if Instant::now() + delay >= deadline {
+                break;
+            }
+            log_warn(
+                None,
+                &format!(
+                    "All facilitators failed, retrying in {}ms (retry {pass} of {})",
+                    delay.as_millis(),
+                    retry.retries
+                ),
+            );
+            tokio::time::sleep(delay).await;
+            X402Metrics::get().record_facilitator_retry();
+        }
+
+        let order = {
+            let now = Instant::now();
+            let up: Vec<bool> = with_health(|health| {
+                endpoints
+                    .iter()
+                    .map(|endpoint| health.get(&endpoint.url).is_none_or(|h| h.is_up(now)))
+                    .collect()
+            })
+            .unwrap_or_else(|| vec![true; endpoints.len()]);
+            attempt_order(endpoints, &up, ROTATION.fetch_add(1, Ordering::Relaxed))
+        };
+
+        for (attempt, &index) in order.iter().enumerate() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(CallError::new(FailureKind::NoAnswer, user_errors::TIMEOUT));
+            }
+
+            let endpoint = &endpoints[index];
+            match call(&endpoint.url, remaining).await {
+                Ok(result) => {
+                    record_result(endpoint, true);
+                    return Ok(result);
+                }
+                // The facilitator works, the payment doesn't
+                Err(e) if e.kind == FailureKind::Rejected => return Err(e),
+                Err(e) => {
+                    record_result(endpoint, false);
+                    if !e.kind.may_retry(idempotent) {
+                        return Err(e);
+                    }
+                    if attempt + 1 < order.len() {
+                        log_warn(
+                            None,
+                            &format!(
+                                "Facilitator {} failed ({}), trying the next facilitator",
+                                endpoint.url, e.error
+                            ),
+                        );
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+    }
+
+    Err(last_error
+        .unwrap_or_else(|| CallError::new(FailureKind::NotSent, user_errors::CONFIGURATION_ERROR)))
+}
+
+/// Random value in `[0, 1]` for retry jitter
+fn jitter() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+
+    // RandomState is seeded randomly, no need for a random number generator
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u64(ROTATION.load(Ordering::Relaxed));
+    hasher.finish() as f64 / u64::MAX as f64
+}
+
+/// Run `f` on the health table (`None` if the lock is poisoned)
+fn with_health<T>(f: impl FnOnce(&mut HashMap<String, FacilitatorHealth>) -> T) -> Option<T> {
+    let health = FACILITATOR_HEALTH.get_or_init(|| Mutex::new(HashMap::new()));
+    health.lock().ok().map(|mut guard| f(&mut guard))
+}
+
+/// Update the health of a facilitator after a call
+fn record_result(endpoint: &FacilitatorEndpoint, success: bool) {
+    let now = Instant::now();
+    let Some((health, marked_down)) = with_health(|table| {
+        let health = table.entry(endpoint.url.clone()).or_default();
+        let marked_down = if success {
+            health.record_success();
+            false
+        } else {
+            health.record_failure(endpoint, now)
+        };
+        (*health, marked_down)
+    }) else {
+        return;
+    };
+
+    if marked_down {
+        log_warn(
+            None,
+            &format!(
+                "Facilitator {} failed {} times in a row, marked down for {}s",
+                endpoint.url,
+                health.consecutive_failures,
+                endpoint.fail_timeout.as_secs()
+            ),
+        );
+    }
+    X402Metrics::get().record_facilitator_health(
+        &endpoint.url,
+        health.is_up(now),
+        health.consecutive_failures,
+    );
+}
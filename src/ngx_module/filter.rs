@@ -0,0 +1,449 @@
+//! Response header and body filters
+//!
+//! The header filter runs once the content handler (or upstream) has produced
+//! the response status and headers, which makes it the point where we know
+//! whether the request succeeded. It is used to:
+//!
+//! - settle payments in `after_success` mode so clients are only billed for 2xx responses;
+//!   the response is held while the settlement runs, without blocking the worker
+//! - attach the `X-PAYMENT-RESPONSE` settlement receipt to the response, including
+//!   responses produced by `proxy_pass`
+//! - start rewriting a 402 from the upstream into payment requirements
+//!   (`x402_upstream_pricing`)
+//! - issue access pass cookies for successful paid responses (`x402_access_pass`)
+//! - add signed `X-X402-Receipt` JWTs for verified payments (`x402_receipt_key`)
+//!
+//! The body filter holds the body of a held response until the settlement has
+//! finished (see `send_held_response`). It also rewrites upstream 402 responses:
+//! it consumes the upstream body (looking for a JSON price hint) and sends the
+//! x402 body instead.
+//!
+//! Both filters are installed at the top of nginx's filter chains in
+//! `postconfiguration` and pass the request on to the next filter (a held
+//! response once it is released).
+
+use crate::ngx_module::access_pass::issue_access_pass;
+use crate::ngx_module::async_verify::start_response_settlement;
+use crate::ngx_module::ctx::request_ctx_mut;
+use crate::ngx_module::logging::{log_debug, log_error, log_warn};
+use crate::ngx_module::metrics::X402Metrics;
+use crate::ngx_module::panic_handler::{catch_panic, catch_panic_or_default};
+use crate::ngx_module::receipt::add_receipt_header;
+use crate::ngx_module::upstream_pricing::{intercept_upstream_402, render_upstream_402};
+use ngx::ffi::{
+    ngx_alloc_chain_link, ngx_buf_t, ngx_chain_t, ngx_create_temp_buf,
+    ngx_http_output_body_filter_pt, ngx_http_output_header_filter_pt, ngx_http_request_t,
+    ngx_int_t,
+};
+use ngx::http::Request;
+
+/// Response header carrying the base64-encoded settlement receipt
+pub const PAYMENT_RESPONSE_HEADER: &str = "X-PAYMENT-RESPONSE";
+
+/// `r->buffered` flag of a held response, which keeps nginx from finishing the request
+///
+/// The bit of the image filter: filters installed before ours only see the
+/// response once it is released, so they can't be buffering it meanwhile.
+const HELD_RESPONSE_BUFFERED: u32 = 0x08;
+
+/// Response held by the header filter until its payment has been settled
+#[derive(Default)]
+pub struct HeldResponse {
+    /// Body buffers passed to the body filter meanwhile, in order
+    body: Vec<*mut ngx_buf_t>,
+}
+
+/// Next header filter in the chain (saved when our filter is installed)
+static mut NEXT_HEADER_FILTER: ngx_http_output_header_filter_pt = None;
+
+/// Next body filter in the chain (saved when our filter is installed)
+static mut NEXT_BODY_FILTER: ngx_http_output_body_filter_pt = None;
+
+/// Install the x402 header filter at the top of the header filter chain
+///
+/// # Safety
+///
+/// Must only be called from `postconfiguration`, while nginx is single-threaded.
+pub unsafe fn init_header_filter() {
+    NEXT_HEADER_FILTER = ngx::ffi::ngx_http_top_header_filter;
+    ngx::ffi::ngx_http_top_header_filter = Some(x402_header_filter);
+}
+
+/// Install the x402 body filter at the top of the body filter chain
+///
+/// # Safety
+///
+/// Must only be called from `postconfiguration`, while nginx is single-threaded.
+pub unsafe fn init_body_filter() {
+    NEXT_BODY_FILTER = ngx::ffi::ngx_http_top_body_filter;
+    ngx::ffi::ngx_http_top_body_filter = Some(x402_body_filter);
+}
+
+/// Header filter entry point
+///
+/// # Safety
+///
+/// Called by nginx with a valid request pointer.
+unsafe extern "C" fn x402_header_filter(r: *mut ngx_http_request_t) -> ngx_int_t {
+    // Never let a panic in x402 code abort the response
+    let held = !r.is_null()
+        && catch_panic_or_default(
+            || {
+                let req = Request::from_ngx_http_request(r);
+                process_response(req)
+            },
+            "x402_header_filter",
+            false,
+        );
+    if held {
+        // Sent by send_held_response once the payment has been settled
+        return ngx::ffi::NGX_OK as ngx_int_t;
+    }
+
+    next_header_filter(r)
+}
+
+/// Pass the response headers to the next header filter
+unsafe fn next_header_filter(r: *mut ngx_http_request_t) -> ngx_int_t {
+    let next = NEXT_HEADER_FILTER;
+    match next {
+        Some(next_filter) => next_filter(r),
+        None => ngx::ffi::NGX_OK as ngx_int_t,
+    }
+}
+
+/// Run x402 response processing for main requests
+///
+/// # Returns
+/// - `true` if the response is held until its payment has been settled
+fn process_response(req: &mut Request) -> bool {
+    if !req.is_main() {
+        return false;
+    }
+
+    if hold_for_settlement(req) {
+        return true;
+    }
+    if intercept_upstream_402(req) {
+        return false;
+    }
+    add_response_headers(req);
+    false
+}
+
+/// Add the access pass, receipt and settlement headers of a paid response
+fn add_response_headers(req: &mut Request) {
+    issue_access_pass(req);
+    add_receipt_header(req);
+    add_payment_response_header(req);
+}
+
+/// Body filter entry point
+///
+/// # Safety
+///
+/// Called by nginx with a valid request pointer and output chain.
+unsafe extern "C" fn x402_body_filter(
+    r: *mut ngx_http_request_t,
+    chain: *mut ngx_chain_t,
+) -> ngx_int_t {
+    let (held, rewriting) = if r.is_null() {
+        (false, false)
+    } else {
+        catch_panic_or_default(
+            || {
+                let req = Request::from_ngx_http_request(r);
+                if !req.is_main() {
+                    return (false, false);
+                }
+                request_ctx_mut(req).map_or((false, false), |ctx| {
+                    (ctx.held_response.is_some(), ctx.upstream_402.is_some())
+                })
+            },
+            "x402_body_filter",
+            (false, false),
+        )
+    };
+    if held {
+        return catch_panic_or_default(
+            || hold_response_body(r, chain),
+            "x402_body_filter",
+            ngx::ffi::NGX_ERROR as ngx_int_t,
+        );
+    }
+    if rewriting {
+        return catch_panic_or_default(
+            || rewrite_upstream_402_body(r, chain),
+            "x402_body_filter",
+            ngx::ffi::NGX_ERROR as ngx_int_t,
+        );
+    }
+
+    next_body_filter(r, chain)
+}
+
+/// Pass a chain to the next body filter
+unsafe fn next_body_filter(r: *mut ngx_http_request_t, chain: *mut ngx_chain_t) -> ngx_int_t {
+    let next = NEXT_BODY_FILTER;
+    match next {
+        Some(next_filter) => next_filter(r, chain),
+        None => ngx::ffi::NGX_OK as ngx_int_t,
+    }
+}
+
+/// Keep the body buffers of a held response
+///
+/// The buffers are not consumed, so their producer (e.g. the upstream module)
+/// sees them as busy and stops reading once it runs out of buffers. Empty
+/// buffers (flush and last buffer marks) count as consumed and may be reused by
+/// their producer, so they are copied.
+///
+/// # Safety
+///
+/// `r` must be a valid request whose context holds a held response.
+unsafe fn hold_response_body(r: *mut ngx_http_request_t, chain: *mut ngx_chain_t) -> ngx_int_t {
+    let req = Request::from_ngx_http_request(r);
+    let pool = (*r).pool;
+    let Some(held) = request_ctx_mut(req).and_then(|ctx| ctx.held_response.as_mut()) else {
+        return ngx::ffi::NGX_ERROR as ngx_int_t;
+    };
+
+    let mut cl = chain;
+    while !cl.is_null() {
+        let mut buf = (*cl).buf;
+        if !buf.is_null() && buf_size(buf) == 0 {
+            let copy =
+                ngx::ffi::ngx_pcalloc(pool, std::mem::size_of::<ngx_buf_t>()).cast::<ngx_buf_t>();
+            if copy.is_null() {
+                return ngx::ffi::NGX_ERROR as ngx_int_t;
+            }
+            std::ptr::copy_nonoverlapping(buf, copy, 1);
+            buf = copy;
+        }
+        if !buf.is_null() {
+            held.body.push(buf);
+        }
+        cl = (*cl).next;
+    }
+    ngx::ffi::NGX_OK as ngx_int_t
+}
+
+/// Send a response held by the header filter
+///
+/// Adds the headers of the paid response (`X-PAYMENT-RESPONSE` once the payment
+/// has been settled), then passes the headers and the body received so far to
+/// the next filters. The rest of the body is passed on as it comes.
+///
+/// # Returns
+/// - The result of the next header filter, or of the next body filter
+///
+/// # Safety
+///
+/// `r` must be a valid main request, on the nginx worker thread.
+pub unsafe fn send_held_response(r: *mut ngx_http_request_t) -> ngx_int_t {
+    let req = Request::from_ngx_http_request(r);
+    let Some(held) = request_ctx_mut(req).and_then(|ctx| ctx.held_response.take()) else {
+        return ngx::ffi::NGX_OK as ngx_int_t;
+    };
+    (*r).set_buffered((*r).buffered() & !HELD_RESPONSE_BUFFERED);
+
+    catch_panic(|| add_response_headers(req), "send_held_response");
+    let rc = next_header_filter(r);
+    if rc == ngx::ffi::NGX_ERROR as ngx_int_t
+        || rc > ngx::ffi::NGX_OK as ngx_int_t
+        || (*r).header_only() != 0
+        || held.body.is_empty()
+    {
+        return rc;
+    }
+
+    let pool = (*r).pool;
+    let mut out: *mut ngx_chain_t = std::ptr::null_mut();
+    let mut last = &raw mut out;
+    for buf in held.body {
+        let cl = ngx_alloc_chain_link(pool);
+        if cl.is_null() {
+            return ngx::ffi::NGX_ERROR as ngx_int_t;
+        }
+        (*cl).buf = buf;
+        (*cl).next = std::ptr::null_mut();
+        *last = cl;
+        last = &raw mut (*cl).next;
+    }
+    next_body_filter(r, out)
+}
+
+/// Size of a buffer's content, like nginx's `ngx_buf_size()`
+///
+/// # Safety
+///
+/// `buf` must be a valid buffer.
+unsafe fn buf_size(buf: *const ngx_buf_t) -> usize {
+    // Same test as nginx's ngx_buf_in_memory()
+    if (*buf).temporary() != 0 || (*buf).memory() != 0 || (*buf).mmap() != 0 {
+        ((*buf).last as usize).saturating_sub((*buf).pos as usize)
+    } else {
+        usize::try_from((*buf).file_last - (*buf).file_pos).unwrap_or(0)
+    }
+}
+
+/// Replace the body of an upstream 402 response
+///
+/// Upstream buffers are consumed (collected for the price hint, never passed on).
+/// Once the last buffer has been seen, the x402 body is sent instead.
+///
+/// # Safety
+///
+/// `r` must be a valid request whose context holds an upstream 402 rewrite state.
+unsafe fn rewrite_upstream_402_body(
+    r: *mut ngx_http_request_t,
+    chain: *mut ngx_chain_t,
+) -> ngx_int_t {
+    let req = Request::from_ngx_http_request(r);
+    let Some(mut state) = request_ctx_mut(req).and_then(|ctx| ctx.upstream_402.take()) else {
+        return ngx::ffi::NGX_ERROR as ngx_int_t;
+    };
+
+    let mut last = false;
+    let mut cl = chain;
+    while !cl.is_null() {
+        let buf = (*cl).buf;
+        if !buf.is_null() {
+            // Same test as nginx's ngx_buf_in_memory()
+            if (*buf).temporary() != 0 || (*buf).memory() != 0 || (*buf).mmap() != 0 {
+                let len = ((*buf).last as usize).saturating_sub((*buf).pos as usize);
+                if len > 0 && !(*buf).pos.is_null() {
+                    state.push_body(std::slice::from_raw_parts((*buf).pos, len));
+                }
+                (*buf).pos = (*buf).last;
+            }
+            if (*buf).in_file() != 0 {
+                (*buf).file_pos = (*buf).file_last;
+            }
+            last = last || (*buf).last_buf() != 0;
+        }
+        cl = (*cl).next;
+    }
+
+    if !last || state.done {
+        if let Some(ctx) = request_ctx_mut(req) {
+            ctx.upstream_402 = Some(state);
+        }
+        return ngx::ffi::NGX_OK as ngx_int_t;
+    }
+
+    let body = match render_upstream_402(req, &state) {
+        Ok(body) => body,
+        Err(e) => {
+            log_error(
+                Some(req),
+                &format!("Failed to rewrite upstream 402 response: {e}"),
+            );
+            return ngx::ffi::NGX_ERROR as ngx_int_t;
+        }
+    };
+    state.done = true;
+    state.body = Vec::new();
+    if let Some(ctx) = request_ctx_mut(req) {
+        ctx.upstream_402 = Some(state);
+    }
+
+    let pool = req.pool();
+    let buf = ngx_create_temp_buf(pool.as_ptr(), body.len().max(1));
+    let out = ngx_alloc_chain_link(pool.as_ptr());
+    if buf.is_null() || out.is_null() {
+        return ngx::ffi::NGX_ERROR as ngx_int_t;
+    }
+    std::ptr::copy_nonoverlapping(body.as_ptr(), (*buf).pos, body.len());
+    (*buf).last = (*buf).pos.add(body.len());
+    (*buf).set_last_buf(1);
+    (*buf).set_last_in_chain(1);
+    (*out).buf = buf;
+    (*out).next = std::ptr::null_mut();
+
+    next_body_filter(r, out)
+}
+
+/// Start settling a pending payment if the response was successful
+///
+/// Each payment is settled at most once (the pending settlement is taken out
+/// of the context). The response is held until the facilitator has responded,
+/// so the settlement can be reported in `X-PAYMENT-RESPONSE`; the worker keeps
+/// serving other requests meanwhile.
+///
+/// # Returns
+/// - `true` if the response is held
+fn hold_for_settlement(req: &mut Request) -> bool {
+    let Some(ctx) = request_ctx_mut(req) else {
+        return false;
+    };
+    if let Some(ref mut held) = ctx.held_response {
+        // nginx replaced the held response, e.g. with an error page
+        held.body.clear();
+        return true;
+    }
+    let Some(pending) = ctx.pending_settlement.take() else {
+        return false;
+    };
+
+    let status = req.as_ref().headers_out.status;
+    if !(200..300).contains(&status) {
+        log_debug(
+            Some(req),
+            &format!("Response status {status} is not successful, skipping settlement"),
+        );
+        X402Metrics::get().record_settlement_skipped();
+        return false;
+    }
+
+    if let Err(e) = start_response_settlement(req, pending) {
+        log_error(
+            Some(req),
+            &format!("Failed to start payment settlement: {e}"),
+        );
+        let metrics = X402Metrics::get();
+        metrics.record_settlement_attempt();
+        metrics.record_settlement_failed();
+        return false;
+    }
+    // The context was created by start_response_settlement
+    let Some(ctx) = request_ctx_mut(req) else {
+        return false;
+    };
+    ctx.held_response = Some(HeldResponse::default());
+    let r: *mut ngx_http_request_t = req.as_mut();
+    // Safe: r is the live request, on the worker thread
+    unsafe { (*r).set_buffered((*r).buffered() | HELD_RESPONSE_BUFFERED) };
+    true
+}
+
+/// Add the `X-PAYMENT-RESPONSE` header if the payment has been settled
+///
+/// The header value is the base64-encoded JSON settlement response
+/// (`success`, `transaction`, `network`, `payer`), as defined by the x402 protocol.
+fn add_payment_response_header(req: &mut Request) {
+    let Some(response) = request_ctx_mut(req).and_then(|ctx| ctx.settle_response.as_ref()) else {
+        return;
+    };
+
+    let encoded = match response.to_base64() {
+        Ok(encoded) => encoded,
+        Err(e) => {
+            log_error(
+                Some(req),
+                &format!("Failed to encode {PAYMENT_RESPONSE_HEADER} header: {e}"),
+            );
+            return;
+        }
+    };
+
+    if req
+        .add_header_out(PAYMENT_RESPONSE_HEADER, &encoded)
+        .is_none()
+    {
+        log_warn(
+            Some(req),
+            &format!("Failed to add {PAYMENT_RESPONSE_HEADER} header"),
+        );
+    }
+}
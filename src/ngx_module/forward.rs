@@ -0,0 +1,65 @@
+//! Trusted payment headers for the upstream
+//!
+//! With `x402_forward_headers on`, the backend behind `proxy_pass` receives the
+//! verified payment details as plain request headers instead of decoding the raw
+//! `X-PAYMENT` header itself:
+//!
+//! - `X-X402-Payer`: Payer address from the verified payment
+//! - `X-X402-Amount`: Required amount in token units (e.g. `0.0001`)
+//! - `X-X402-Network`: Payment network (e.g. `base-sepolia`)
+//! - `X-X402-Verified`: Always `1`
+//!
+//! Any `X-X402-*` header sent by the client is removed first, so the backend can
+//! trust these headers whenever the request went through an x402 location.
+
+use crate::ngx_module::ctx::request_ctx_mut;
+use crate::ngx_module::logging::{log_debug, log_warn};
+use crate::ngx_module::request::remove_headers_in;
+use ngx::http::Request;
+
+/// Prefix of the headers reserved for the module
+pub const FORWARD_HEADER_PREFIX: &str = "X-X402-";
+
+/// Check whether a request header name is reserved for the module
+#[must_use]
+pub fn is_forward_header(name: &str) -> bool {
+    name.len() >= FORWARD_HEADER_PREFIX.len()
+        && name.as_bytes()[..FORWARD_HEADER_PREFIX.len()]
+            .eq_ignore_ascii_case(FORWARD_HEADER_PREFIX.as_bytes())
+}
+
+/// Remove client-supplied `X-X402-*` request headers
+pub fn strip_forward_headers(r: &mut Request) {
+    let removed = remove_headers_in(r, is_forward_header);
+    if removed > 0 {
+        log_debug(
+            Some(r),
+            &format!("Removed {removed} client-supplied {FORWARD_HEADER_PREFIX}* header(s)"),
+        );
+    }
+}
+
+/// Add trusted payment headers for the upstream
+///
+/// Must be called after the payment has been verified; values come from the
+/// request context.
+pub fn add_forward_headers(r: &mut Request) {
+    let Some(ctx) = request_ctx_mut(r) else {
+        return;
+    };
+    let headers = [
+        ("X-X402-Payer", ctx.payer.clone()),
+        ("X-X402-Amount", ctx.amount.clone()),
+        ("X-X402-Network", ctx.network.clone()),
+        ("X-X402-Verified", Some("1".to_string())),
+    ];
+
+    for (name, value) in headers {
+        let Some(value) = value else {
+            continue;
+        };
+        if r.add_header_in(name, &value).is_none() {
+            log_warn(Some(r), &format!("Failed to add {name} request header"));
+        }
+    }
+}
@@ -1,18 +1,50 @@
 //! Request handler implementation
 
 use crate::config::validate_payment_header;
-use crate::ngx_module::config::{FacilitatorFallback, ParsedX402Config};
+use crate::ngx_module::access_pass::redeem_access_pass;
+use crate::ngx_module::async_verify::{
+    start_settlement, start_verification, take_completed_settlement, take_completed_verification,
+    VerificationMode,
+};
+use crate::ngx_module::config::{
+    FacilitatorFallback, ParsedX402Config, SettleMode, VerifyMode, DEFAULT_FALLBACK_RETRY_AFTER,
+};
+use crate::ngx_module::ctx::{
+    get_or_create_request_ctx, update_request_ctx, PaymentStatus, PendingSettlement,
+};
 use crate::ngx_module::error::{user_errors, ConfigError, Result};
+use crate::ngx_module::forward::{add_forward_headers, strip_forward_headers};
+use crate::ngx_module::local_verify::verify_payment_locally;
 use crate::ngx_module::logging::{log_debug, log_error, log_info, log_warn};
 use crate::ngx_module::metrics::X402Metrics;
-use crate::ngx_module::module::get_module_config;
+use crate::ngx_module::module::{get_module_config, get_request_config};
+use crate::ngx_module::paywall::{etag_matches, find_paywall_asset};
+use crate::ngx_module::receipt::{forward_receipt, render_jwks};
+use crate::ngx_module::replay::{
+    forget_authorization, record_authorization, reserve_authorization, unix_now, ReplayCheck,
+};
 use crate::ngx_module::request::{build_full_url, get_header_value, infer_mime_type};
-use crate::ngx_module::requirements::create_requirements;
-use crate::ngx_module::response::{send_402_response, send_response_body};
-use crate::ngx_module::runtime::{get_runtime, verify_payment};
+use crate::ngx_module::requirements::{create_payment_options, select_requirements, PaymentOption};
+use crate::ngx_module::response::{
+    send_402_response, send_500_response, send_503_response, send_error_response,
+    send_response_body,
+};
+use crate::ngx_module::runtime::{
+    get_runtime, verify_payment, FacilitatorPayload, DEFAULT_FACILITATOR_TIMEOUT,
+};
+use crate::ngx_module::session::{
+    complete_session_purchase, redeem_session, release_session_purchase, reserve_session_purchase,
+};
+use crate::ngx_module::settlement::{finish_settlement, settle_pending_payment};
+use crate::ngx_module::shm::SharedTable;
+use crate::ngx_module::svm::{select_svm_requirements, SvmPaymentPayload};
+use crate::ngx_module::upstream_pricing::{authorized_amount, covers_price};
+use crate::ngx_module::verify_cache::{caches_result, verify_cache_key, VerifyCache};
 use ngx::core::Status;
 use ngx::http::{HTTPStatus, Request};
 use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use rust_x402::types::PaymentPayload;
 use std::time::Instant;
 
 /// Handler result indicating what action was taken
@@ -22,6 +54,8 @@ pub enum HandlerResult {
     PaymentValid,
     /// Response was sent (402 or error), request processing should stop
     ResponseSent,
+    /// Verification is running in the background, the request is suspended
+    Pending,
     /// Error occurred during processing
     Error,
 }
@@ -32,20 +66,37 @@ pub enum HandlerResult {
 /// It handles the complete payment verification flow:
 ///
 /// 1. Check if module is enabled for this location
-/// 2. Create payment requirements from configuration
+/// 2. Create payment requirements from configuration (one per `x402_accept` option)
 /// 3. Check for X-PAYMENT header in the request
-/// 4. If present, validate and verify payment with facilitator
-/// 5. If valid, allow request to proceed; if invalid or missing, send 402 response
+/// 4. If present, match the payment to one of the options, reject reused
+///    authorizations (`x402_replay_zone`), then validate and
+///    verify it with the facilitator (or in-process, see `x402_verify_mode`). In
+///    `VerificationMode::NonBlocking` the facilitator call runs in the background
+///    and this function returns `Pending`; it is called again with the result
+///    once the request is resumed
+/// 5. If valid, settle according to `x402_settle` (a `before_upstream` settlement
+///    suspends the request the same way), add trusted upstream headers
+///    (`x402_forward_headers`) and allow request to proceed;
+///    if invalid or missing, send 402 response
+///
+/// With `x402_upstream_pricing`, requests without a payment are passed to the
+/// upstream instead, and payments are verified against the amount they authorize
+/// (at least the location's price, if it has one).
+///
+/// With `x402_session`, a valid session token is accepted in place of a payment
+/// before any of the above (see `session`).
 ///
 /// # Arguments
 ///
 /// * `r` - Nginx request object
 /// * `config` - Parsed module configuration for the current location
+/// * `mode` - Whether the facilitator call may suspend the request
 ///
 /// # Returns
 ///
 /// * `Ok(HandlerResult::PaymentValid)` - Payment verified, request should proceed
 /// * `Ok(HandlerResult::ResponseSent)` - Response was sent (402 or error), request processing should stop
+/// * `Ok(HandlerResult::Pending)` - Verification started in the background, request is suspended
 /// * `Ok(HandlerResult::Error)` - Error occurred during processing
 /// * `Err` - Error occurred during processing (configuration error, etc.)
 ///
@@ -56,15 +107,79 @@ pub enum HandlerResult {
 /// * Facilitator URL is not configured
 /// * Payment verification fails (depending on fallback mode)
 /// * 402 response cannot be sent
-pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<HandlerResult> {
-    // Record request metric
+pub fn x402_handler_impl(
+    r: &mut Request,
+    config: &ParsedX402Config,
+    mode: VerificationMode,
+) -> Result<HandlerResult> {
     let metrics = X402Metrics::get();
-    metrics.record_request();
+
+    // A completed background verification means this is the resumed pass for a
+    // request that was already counted, so per-request metrics are skipped
+    let completed = take_completed_verification(r);
+    // A completed settlement means the payment was verified on an earlier pass
+    let settled = take_completed_settlement(r);
+    let resumed = completed.is_some() || settled.is_some();
+
+    // Record request metric
+    if !resumed {
+        metrics.record_request();
+    }
 
     if !config.enabled {
         return Ok(HandlerResult::PaymentValid); // Module disabled, pass through
     }
 
+    // A prepaid session token pays for the request without a facilitator call
+    // (read before the X-X402-Session header is stripped below)
+    if let (Some(session), None, false) = (config.session, config.session_purchase, resumed) {
+        if redeem_session(r, session.get()) {
+            if config.strips_forward_headers() {
+                strip_forward_headers(r);
+            }
+            return Ok(HandlerResult::PaymentValid);
+        }
+    }
+
+    // Client-supplied X-X402-* headers must never reach the upstream
+    // (upstream pricing and receipts rely on the trusted headers as well)
+    let forward_headers = config.forward_headers || config.upstream_pricing;
+    if config.strips_forward_headers() && !resumed {
+        strip_forward_headers(r);
+    }
+
+    // Check for X-PAYMENT header
+    let payment_header = get_header_value(r, "X-PAYMENT");
+
+    // With upstream pricing, unpaid requests reach the upstream, which answers
+    // with a 402 price hint if it wants to be paid (see upstream_pricing)
+    if config.upstream_pricing && payment_header.is_none() {
+        log_debug(
+            Some(r),
+            "No X-PAYMENT header found, passing request to the upstream for pricing",
+        );
+        get_or_create_request_ctx(r)
+            .ok_or_else(|| ConfigError::from("Failed to allocate request context"))?
+            .awaiting_upstream_price = true;
+        return Ok(HandlerResult::PaymentValid);
+    }
+    let paid_amount = if config.upstream_pricing {
+        payment_header.as_deref().and_then(authorized_amount)
+    } else {
+        None
+    };
+    let priced_config;
+    let config = if config.upstream_pricing && config.amount.is_none() {
+        // The real price is the authorized amount, set below
+        priced_config = ParsedX402Config {
+            amount: Some(Decimal::ZERO),
+            ..config.clone()
+        };
+        &priced_config
+    } else {
+        config
+    };
+
     // Determine resource URL:
     // 1. Use configured resource if set
     // 2. Otherwise, build full URL from request (scheme://host/path)
@@ -89,83 +204,288 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
         &format!("x402 handler processing request for resource: {resource}, mimeType: {mime_type}"),
     );
 
-    // Create payment requirements
-    let requirements = create_requirements(config, resource, Some(&mime_type)).map_err(|e| {
+    // Create payment requirements (one per accepted payment option)
+    let mut options = create_payment_options(config, resource, Some(&mime_type)).map_err(|e| {
         log_error(
             Some(r),
             &format!("Failed to create payment requirements: {e}"),
         );
         e
     })?;
-    // Create slice reference for send_402_response (supports multiple requirements)
-    let requirements_slice = std::slice::from_ref(&requirements);
+    if let Some(ref paid_amount) = paid_amount {
+        // The payment may authorize more than the location's price, never less:
+        // options it doesn't cover keep their price and the payment is rejected
+        for option in &mut options {
+            if covers_price(paid_amount, &option.requirements.max_amount_required) {
+                option
+                    .requirements
+                    .max_amount_required
+                    .clone_from(paid_amount);
+            }
+        }
+    }
+    // All options are offered in the 402 response's `accepts` array
+    let requirements_list: Vec<_> = options
+        .iter()
+        .map(|option| option.requirements.clone())
+        .collect();
+    let requirements_slice = requirements_list.as_slice();
+
+    // Expose the payment requirements as variables ($x402_amount, $x402_network, ...)
+    // until the payment has been matched to an option
+    record_option_in_ctx(r, &options[0]);
 
     // Record payment amount metric (convert from smallest units to decimal units)
-    if let Ok(amount_decimal) = requirements.amount_in_decimal_units(6) {
-        // Convert Decimal to f64 for metrics
-        // Use to_f64_retain() to preserve precision, or fallback to to_f64()
-        if let Some(amount_f64) = amount_decimal.to_f64() {
-            metrics.record_payment_amount(amount_f64);
+    if !resumed {
+        let option = &options[0];
+        if let Ok(amount_decimal) = option.requirements.amount_in_decimal_units(option.decimals) {
+            // Convert Decimal to f64 for metrics
+            // Use to_f64_retain() to preserve precision, or fallback to to_f64()
+            if let Some(amount_f64) = amount_decimal.to_f64() {
+                metrics.record_payment_amount(amount_f64);
+            }
         }
     }
 
-    // Check for X-PAYMENT header
-    let payment_header = get_header_value(r, "X-PAYMENT");
-
     if let Some(payment_b64) = payment_header {
-        // Get current timestamp for debugging time-related issues
-        let current_timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
+        if !resumed {
+            // Get current timestamp for debugging time-related issues
+            let current_timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
 
-        log_debug(
-            Some(r),
-            &format!(
-                "X-PAYMENT header found, validating and verifying payment, current_timestamp={}, maxTimeoutSeconds={}",
-                current_timestamp,
-                requirements.max_timeout_seconds
-            ),
-        );
+            log_debug(
+                Some(r),
+                &format!(
+                    "X-PAYMENT header found, validating and verifying payment, current_timestamp={}, maxTimeoutSeconds={}",
+                    current_timestamp,
+                    options[0].requirements.max_timeout_seconds
+                ),
+            );
 
-        // Record verification attempt
-        metrics.record_verification_attempt();
+            // Record verification attempt
+            metrics.record_verification_attempt();
+
+            // Validate payment header format and size
+            // If validation fails, send 402 response instead of returning error
+            // This ensures that invalid payment headers don't cause the request to be
+            // proxied to the backend (when proxy_pass is configured)
+            if let Err(e) = validate_payment_header(&payment_b64) {
+                log_warn(Some(r), &format!("Invalid payment header format: {e}"));
+                update_request_ctx(r, |ctx| ctx.status = PaymentStatus::Invalid);
+                metrics.record_verification_failed();
+                metrics.record_402_response();
+                send_402_response(
+                    r,
+                    requirements_slice,
+                    config,
+                    Some(user_errors::PAYMENT_VERIFICATION_FAILED),
+                )?;
+                return Ok(HandlerResult::ResponseSent);
+            }
+        }
 
-        // Validate payment header format and size
-        // If validation fails, send 402 response instead of returning error
-        // This ensures that invalid payment headers don't cause the request to be
-        // proxied to the backend (when proxy_pass is configured)
-        if let Err(e) = validate_payment_header(&payment_b64) {
-            log_warn(Some(r), &format!("Invalid payment header format: {e}"));
+        // Match the payment to the option it was made for
+        let payload = PaymentPayload::from_base64(&payment_b64).ok();
+        // Solana payloads carry a transaction instead of an EIP-3009 authorization
+        let svm_payload = payload
+            .is_none()
+            .then(|| SvmPaymentPayload::from_base64(&payment_b64).ok())
+            .flatten();
+        // Authorization recorded by the replay zone (EIP-3009 or Solana)
+        let replay_payload = match (&payload, &svm_payload) {
+            _ if config.replay_zone.is_none() => None,
+            (Some(payload), _) => Some(FacilitatorPayload::Evm(payload.clone())),
+            (None, Some(payload)) => Some(FacilitatorPayload::Svm(payload.clone())),
+            (None, None) => None,
+        };
+        let selected = if options.len() == 1 {
+            Some(0)
+        } else if let Some(ref payload) = payload {
+            select_requirements(requirements_slice, payload)
+        } else {
+            svm_payload
+                .as_ref()
+                .and_then(|payload| select_svm_requirements(requirements_slice, payload))
+        };
+        let Some(selected) = selected else {
+            log_warn(
+                Some(r),
+                "Payment does not match any accepted payment option, sending 402 response",
+            );
+            update_request_ctx(r, |ctx| ctx.status = PaymentStatus::Invalid);
             metrics.record_verification_failed();
             metrics.record_402_response();
             send_402_response(
                 r,
                 requirements_slice,
                 config,
-                Some(user_errors::PAYMENT_VERIFICATION_FAILED),
+                Some(user_errors::NO_MATCHING_PAYMENT_OPTION),
             )?;
             return Ok(HandlerResult::ResponseSent);
+        };
+        let requirements = &options[selected].requirements;
+        if selected != 0 {
+            record_option_in_ctx(r, &options[selected]);
         }
 
+        // With upstream pricing, a payment below the location's price is rejected
+        // without asking the facilitator
+        if paid_amount
+            .as_ref()
+            .is_some_and(|paid| *paid != requirements.max_amount_required)
+        {
+            log_warn(
+                Some(r),
+                &format!(
+                    "Payment authorizes less than the price of {} for this location, sending 402 response",
+                    requirements.max_amount_required
+                ),
+            );
+            update_request_ctx(r, |ctx| ctx.status = PaymentStatus::Invalid);
+            metrics.record_verification_failed();
+            metrics.record_402_response();
+            send_402_response(
+                r,
+                requirements_slice,
+                config,
+                Some(user_errors::INVALID_PAYMENT),
+            )?;
+            return Ok(HandlerResult::ResponseSent);
+        }
+
+        // Timeout, retries and circuit breaker of facilitator calls
+        let policy = config.facilitator_policy();
+
+        // Reject authorizations that were already presented (x402_replay_zone). The
+        // authorization is only held while it is verified, and recorded once valid
+        if !resumed {
+            if let (Some(zone), Some(payload)) = (config.replay_zone, replay_payload.as_ref()) {
+                let window = policy
+                    .timeout
+                    .unwrap_or(DEFAULT_FACILITATOR_TIMEOUT)
+                    .as_secs()
+                    + 1;
+                match reserve_authorization(zone, payload, unix_now(), window)? {
+                    ReplayCheck::Fresh => {}
+                    ReplayCheck::Full => return replay_zone_full(r, zone),
+                    ReplayCheck::Replayed => {
+                        log_warn(
+                            Some(r),
+                            "Payment authorization has already been used, sending 402 response",
+                        );
+                        update_request_ctx(r, |ctx| ctx.status = PaymentStatus::Replayed);
+                        metrics.record_replay_rejected();
+                        metrics.record_402_response();
+                        send_402_response(
+                            r,
+                            requirements_slice,
+                            config,
+                            Some(user_errors::PAYMENT_ALREADY_USED),
+                        )?;
+                        return Ok(HandlerResult::ResponseSent);
+                    }
+                }
+            }
+        }
+
+        // Payments verified locally are only settled by the facilitator in hybrid mode.
+        // Sessions and access passes are only handed out for settled payments (the
+        // configuration rejects `x402_settle off` and `x402_verify_mode local` there),
+        // so they settle first
+        let local = config.verify_mode != VerifyMode::Facilitator;
+        let settle = match config.settle {
+            _ if config.verify_mode == VerifyMode::Local => SettleMode::Off,
+            SettleMode::AfterSuccess
+                if config.session_purchase.is_some() || config.access_pass.is_some() =>
+            {
+                SettleMode::BeforeUpstream
+            }
+            settle => settle,
+        };
+
         // Verify payment
-        let facilitator_url = config.facilitator_url.as_deref().ok_or_else(|| {
+        let facilitators = config.facilitators.as_slice();
+        // Never contacted when verifying locally without settlement
+        if facilitators.is_empty() && !(local && settle == SettleMode::Off) {
             log_error(Some(r), "Facilitator URL not configured");
-            ConfigError::from("Facilitator URL not configured")
-        })?;
-
-        // Block on async verification
-        // Use configured timeout or default
-        let timeout = config.timeout;
-        let runtime = get_runtime()?;
-        let verification_start = Instant::now();
-        let verification_result = runtime.block_on(async {
-            verify_payment(&payment_b64, &requirements, facilitator_url, timeout).await
-        });
-        let verification_duration = verification_start.elapsed().as_secs_f64();
-
-        // Record verification duration
-        metrics.record_verification_duration(verification_duration);
+            return Err(ConfigError::from("Facilitator URL not configured"));
+        }
+
+        // An identical payment may have been verified already (x402_verify_cache)
+        let cache = config
+            .verify_cache
+            .filter(|_| !local)
+            .map(|cache| (cache, verify_cache_key(&payment_b64, requirements)));
+        let cached = match cache {
+            Some((cache, ref key)) if !resumed => lookup_cached_verification(r, cache, key),
+            _ => None,
+        };
+        let from_cache = cached.is_some();
+
+        let (verification_result, verification_duration) = match (completed, cached, mode) {
+            _ if settled.is_some() => (Ok(true), 0.0),
+            (Some(done), _, _) => (done.result, done.duration_secs),
+            (None, Some(valid), _) => (Ok(valid), 0.0),
+            (None, None, _) if local => {
+                let verification_start = Instant::now();
+                let valid = match (payload.as_ref(), svm_payload.as_ref()) {
+                    (Some(payload), _) => verify_payment_locally(payload, requirements, unix_now()),
+                    (None, Some(_)) => Err(ConfigError::from(
+                        "Solana payments can only be verified by the facilitator",
+                    )),
+                    (None, None) => Err(ConfigError::from("Invalid payment payload")),
+                };
+                if let Err(ref e) = valid {
+                    log_debug(Some(r), &format!("Local payment verification failed: {e}"));
+                }
+                (
+                    Ok(valid.is_ok()),
+                    verification_start.elapsed().as_secs_f64(),
+                )
+            }
+            (None, None, VerificationMode::NonBlocking) => {
+                // Run the facilitator call in the background and suspend the request;
+                // the phase handler is re-run with the result (see async_verify)
+                start_verification(r, &payment_b64, requirements, facilitators, policy)?;
+                log_debug(Some(r), "Payment verification started, request suspended");
+                return Ok(HandlerResult::Pending);
+            }
+            (None, None, VerificationMode::Blocking) => {
+                // Block on async verification
+                let runtime = get_runtime()?;
+                let verification_start = Instant::now();
+                let result = runtime.block_on(async {
+                    verify_payment(&payment_b64, requirements, facilitators, policy).await
+                });
+                (result, verification_start.elapsed().as_secs_f64())
+            }
+        };
+
+        if from_cache {
+            log_debug(Some(r), "Using cached payment verification result");
+        } else if settled.is_none() {
+            // Record verification duration
+            metrics.record_verification_duration(verification_duration);
+            let verify_ms = (verification_duration * 1000.0).round() as u64;
+            update_request_ctx(r, |ctx| ctx.verify_ms = Some(verify_ms));
+
+            // Facilitator errors are not cached, valid results only if never settled
+            if let (Some((cache, key)), Ok(valid)) = (cache, &verification_result) {
+                let stored = if caches_result(*valid, settle) {
+                    cache.store(&key, *valid, payload.as_ref(), unix_now())
+                } else {
+                    Ok(())
+                };
+                if let Err(e) = stored {
+                    log_warn(
+                        Some(r),
+                        &format!("Failed to cache payment verification result: {e}"),
+                    );
+                }
+            }
+        }
 
         // Handle verification result with fallback logic
         let is_valid = match verification_result {
@@ -182,19 +502,13 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
             Err(e) => {
                 // Facilitator verification failed (network error, timeout, etc.)
                 log_error(Some(r), &format!("Facilitator verification error: {e}"));
+                update_request_ctx(r, |ctx| ctx.status = PaymentStatus::FacilitatorError);
                 metrics.record_facilitator_error();
-                match config.facilitator_fallback {
-                    FacilitatorFallback::Error => {
-                        // Return 500 error
-                        r.set_status(
-                            HTTPStatus::from_u16(500)
-                                .map_err(|_| ConfigError::from("Invalid status code"))?,
-                        );
-                        r.add_header_out("Content-Type", "text/plain; charset=utf-8")
-                            .ok_or_else(|| {
-                                ConfigError::from("Failed to set Content-Type header")
-                            })?;
-                        send_response_body(r, b"Internal server error")?;
+                match &config.facilitator_fallback {
+                    FacilitatorFallback::Error { status, body } => {
+                        // Return error (500 by default)
+                        forget_rejected_payment(r, config, replay_payload.as_ref());
+                        send_error_response(r, *status, body.as_deref())?;
                         return Ok(HandlerResult::ResponseSent);
                     }
                     FacilitatorFallback::Pass => {
@@ -202,21 +516,138 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
                         log_info(Some(r), "Facilitator error, passing through request");
                         return Ok(HandlerResult::PaymentValid);
                     }
+                    FacilitatorFallback::Unavailable { retry_after } => {
+                        // 503 with Retry-After, the client retries the same payment later
+                        forget_rejected_payment(r, config, replay_payload.as_ref());
+                        send_503_response(r, *retry_after)?;
+                        return Ok(HandlerResult::ResponseSent);
+                    }
+                    FacilitatorFallback::Repay => {
+                        // 402 telling the client the facilitator is down
+                        forget_rejected_payment(r, config, replay_payload.as_ref());
+                        metrics.record_402_response();
+                        send_402_response(
+                            r,
+                            requirements_slice,
+                            config,
+                            Some(user_errors::FACILITATOR_UNAVAILABLE),
+                        )?;
+                        return Ok(HandlerResult::ResponseSent);
+                    }
                 }
             }
         };
 
         if is_valid {
+            if settled.is_none() {
+                log_info(Some(r), "Payment verification successful, allowing request");
+                metrics.record_verification_success();
+
+                // Keep the verified authorization until it expires
+                if let (Some(zone), Some(payload)) = (config.replay_zone, replay_payload.as_ref()) {
+                    if record_authorization(zone, payload, unix_now())? == ReplayCheck::Full {
+                        return replay_zone_full(r, zone);
+                    }
+                }
+            }
+
+            // A session is only sold if it can be stored: its slot is taken before the
+            // payment is settled, never by evicting a live session
+            if let (Some(session), Some(grant)) = (config.session, config.session_purchase) {
+                if !reserve_session_purchase(r, session.get(), grant)? {
+                    forget_rejected_payment(r, config, replay_payload.as_ref());
+                    send_503_response(r, DEFAULT_FALLBACK_RETRY_AFTER)?;
+                    return Ok(HandlerResult::ResponseSent);
+                }
+            }
+
+            // The payer is only exposed once the facilitator has vouched for the payload
+            let payer = payload
+                .as_ref()
+                .map(|payload| payload.payload.authorization.from.clone());
+            update_request_ctx(r, |ctx| {
+                ctx.status = PaymentStatus::Valid;
+                ctx.payer = payer;
+            });
+
+            let pending = PendingSettlement {
+                payment_b64,
+                requirements: requirements.clone(),
+                facilitators: facilitators.to_vec(),
+                policy,
+            };
+
+            match settle {
+                SettleMode::BeforeUpstream => {
+                    // Settle now - the request only proceeds if funds were actually moved
+                    let response = match (settled, mode) {
+                        (Some(done), _) => finish_settlement(r, done),
+                        (None, VerificationMode::NonBlocking) => {
+                            // Resumed with the result like a verification (see async_verify)
+                            start_settlement(r, pending)?;
+                            log_debug(Some(r), "Payment settlement started, request suspended");
+                            return Ok(HandlerResult::Pending);
+                        }
+                        (None, VerificationMode::Blocking) => settle_pending_payment(r, &pending),
+                    };
+                    let Some(response) = response else {
+                        forget_rejected_payment(r, config, replay_payload.as_ref());
+                        update_request_ctx(r, |ctx| ctx.status = PaymentStatus::Invalid);
+                        metrics.record_402_response();
+                        send_402_response(
+                            r,
+                            requirements_slice,
+                            config,
+                            Some(user_errors::SETTLEMENT_FAILED),
+                        )?;
+                        return Ok(HandlerResult::ResponseSent);
+                    };
+                    let ctx = get_or_create_request_ctx(r)
+                        .ok_or_else(|| ConfigError::from("Failed to allocate request context"))?;
+                    ctx.settle_response = Some(response);
+                }
+                SettleMode::AfterSuccess => {
+                    // Settle from the header filter once the response status is known
+                    let ctx = get_or_create_request_ctx(r)
+                        .ok_or_else(|| ConfigError::from("Failed to allocate request context"))?;
+                    ctx.pending_settlement = Some(pending);
+                }
+                SettleMode::Off => {
+                    log_debug(
+                        Some(r),
+                        "Settlement disabled (x402_settle off or x402_verify_mode local)",
+                    );
+                }
+            }
+
+            if let Some(access_pass) = config.access_pass {
+                // Issued by the header filter once the response is known to be successful
+                update_request_ctx(r, |ctx| ctx.pending_access_pass = Some(access_pass));
+            }
+
+            if forward_headers {
+                add_forward_headers(r);
+            }
+            if let Some(receipt) = config.receipt {
+                forward_receipt(r, receipt.get());
+                // The client's receipt is minted by the header filter, with the settlement
+                update_request_ctx(r, |ctx| ctx.pending_receipt = Some(receipt));
+            }
+            if config.upstream_pricing {
+                // The upstream may still ask for more with a 402
+                update_request_ctx(r, |ctx| ctx.awaiting_upstream_price = true);
+            }
+
             // Payment valid, allow request to proceed
-            log_info(Some(r), "Payment verification successful, allowing request");
-            metrics.record_verification_success();
             Ok(HandlerResult::PaymentValid)
         } else {
             // Payment invalid - send user-facing error message
             log_warn(
                 Some(r),
-                "Payment verification failed (facilitator returned is_valid=false), sending 402 response",
+                "Payment verification failed (is_valid=false), sending 402 response",
             );
+            forget_rejected_payment(r, config, replay_payload.as_ref());
+            update_request_ctx(r, |ctx| ctx.status = PaymentStatus::Invalid);
             metrics.record_verification_failed();
             metrics.record_402_response();
             send_402_response(
@@ -236,6 +667,74 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
     }
 }
 
+/// Remove a rejected authorization from the replay zone, so the client can retry it
+fn forget_rejected_payment(
+    r: &Request,
+    config: &ParsedX402Config,
+    payload: Option<&FacilitatorPayload>,
+) {
+    if let (Some(zone), Some(payload)) = (config.replay_zone, payload) {
+        if let Err(e) = forget_authorization(zone, payload) {
+            log_warn(
+                Some(r),
+                &format!("Failed to remove authorization from replay zone: {e}"),
+            );
+        }
+    }
+}
+
+/// Answer a payment that can't be recorded in a full replay zone with 503
+///
+/// Accepting it unrecorded would let it be replayed, so the client retries later.
+fn replay_zone_full(r: &mut Request, zone: SharedTable) -> Result<HandlerResult> {
+    log_warn(
+        Some(r),
+        &format!(
+            "Replay zone \"{}\" is full, sending 503 response",
+            zone.name()
+        ),
+    );
+    send_503_response(r, DEFAULT_FALLBACK_RETRY_AFTER)?;
+    Ok(HandlerResult::ResponseSent)
+}
+
+/// Look up a cached verification result, counting hits and misses
+///
+/// A cache that can't be read counts as a miss.
+fn lookup_cached_verification(r: &Request, cache: VerifyCache, key: &str) -> Option<bool> {
+    let metrics = X402Metrics::get();
+    match cache.lookup(key, unix_now()) {
+        Ok(Some(valid)) => {
+            metrics.record_verify_cache_hit();
+            Some(valid)
+        }
+        Ok(None) => {
+            metrics.record_verify_cache_miss();
+            None
+        }
+        Err(e) => {
+            log_warn(Some(r), &format!("Failed to read verification cache: {e}"));
+            metrics.record_verify_cache_miss();
+            None
+        }
+    }
+}
+
+/// Expose a payment option as variables (`$x402_amount`, `$x402_network`, ...)
+fn record_option_in_ctx(r: &mut Request, option: &PaymentOption) {
+    let requirements = &option.requirements;
+    let display_amount = requirements
+        .amount_in_decimal_units(option.decimals)
+        .ok()
+        .map(|amount| amount.normalize().to_string());
+    update_request_ctx(r, |ctx| {
+        ctx.amount = display_amount;
+        ctx.network = Some(requirements.network.clone());
+        ctx.asset = Some(requirements.asset.clone());
+        ctx.resource = Some(requirements.resource.clone());
+    });
+}
+
 /// Request handler wrapper for ngx-rust
 ///
 /// This function wraps the core payment verification logic and adapts it
@@ -248,37 +747,88 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
 ///
 /// * `Status::NGX_OK` - Payment verified, request should proceed
 /// * `Status::NGX_DECLINED` - Response was sent (402 or error), request processing should stop
+/// * `Status::NGX_AGAIN` - Verification is pending, the request is suspended
 /// * `Status::NGX_ERROR` - Error occurred (configuration error or handler failure)
-pub fn x402_ngx_handler_impl(req: &mut Request) -> (Status, HandlerResult) {
-    // Get module configuration from request
-    let conf = match get_module_config(req) {
+pub fn x402_ngx_handler_impl(req: &mut Request, mode: VerificationMode) -> (Status, HandlerResult) {
+    // Get module configuration from request (variables are evaluated for this request)
+    let conf = match get_request_config(req) {
         Ok(c) => c,
         Err(e) => {
             log_error(Some(req), &format!("Failed to get module config: {e}"));
-            return (Status::NGX_ERROR, HandlerResult::Error);
+            return internal_error_result(req);
         }
     };
 
+    // With variable-based values, invalid input (e.g. a bad `$arg_price`) is only
+    // detected here, so the reason is logged and the client gets a 500
     let parsed_config = match conf.parse() {
         Ok(c) => c,
         Err(e) => {
             log_error(Some(req), &format!("Failed to parse config: {e}"));
-            return (Status::NGX_ERROR, HandlerResult::Error);
+            return internal_error_result(req);
         }
     };
 
+    // A valid access pass skips the payment flow entirely
+    if let Some(access_pass) = parsed_config.access_pass {
+        if parsed_config.enabled && redeem_access_pass(req, access_pass.get()) {
+            X402Metrics::get().record_request();
+            if parsed_config.strips_forward_headers() {
+                strip_forward_headers(req);
+            }
+            return (Status::NGX_OK, HandlerResult::PaymentValid);
+        }
+    }
+
     // Call the core handler
-    match x402_handler_impl(req, &parsed_config) {
+    let result = x402_handler_impl(req, &parsed_config, mode);
+
+    // Locations selling sessions answer a paid request with the session token
+    // instead of passing it on
+    let result = match (
+        result,
+        parsed_config.session,
+        parsed_config.session_purchase,
+    ) {
+        (Ok(HandlerResult::PaymentValid), Some(session), Some(grant)) if parsed_config.enabled => {
+            complete_session_purchase(req, session.get(), grant)
+                .map(|()| HandlerResult::ResponseSent)
+        }
+        // e.g. the settlement failed: the reserved session is not sold
+        (result, Some(session), Some(_)) if !matches!(result, Ok(HandlerResult::Pending)) => {
+            release_session_purchase(req, session.get());
+            result
+        }
+        (result, _, _) => result,
+    };
+
+    match result {
         Ok(HandlerResult::PaymentValid) => (Status::NGX_OK, HandlerResult::PaymentValid),
         Ok(HandlerResult::ResponseSent) => (Status::NGX_DECLINED, HandlerResult::ResponseSent),
+        Ok(HandlerResult::Pending) => (Status::NGX_AGAIN, HandlerResult::Pending),
         Ok(HandlerResult::Error) => (Status::NGX_ERROR, HandlerResult::Error),
         Err(e) => {
             log_error(Some(req), &format!("Handler error: {e}"));
-            (Status::NGX_ERROR, HandlerResult::Error)
+            internal_error_result(req)
         }
     }
 }
 
+/// Answer a request that failed with a configuration or processing error
+///
+/// Sends a 500 response unless a response was already started. Returning a bare
+/// error from the access phase would otherwise let the request reach `proxy_pass`
+/// without payment.
+fn internal_error_result(req: &mut Request) -> (Status, HandlerResult) {
+    if req.as_ref().header_sent() == 0 {
+        match send_500_response(req) {
+            Ok(()) => return (Status::NGX_DECLINED, HandlerResult::ResponseSent),
+            Err(e) => log_error(Some(req), &format!("Failed to send 500 response: {e}")),
+        }
+    }
+    (Status::NGX_ERROR, HandlerResult::Error)
+}
+
 /// Metrics handler for exposing Prometheus metrics
 ///
 /// This handler exposes Prometheus metrics via a `/metrics` endpoint.
@@ -337,3 +887,123 @@ pub fn x402_metrics_handler_impl(req: &mut Request) -> Status {
         }
     }
 }
+
+/// JWKS handler publishing the public receipt keys
+///
+/// Serves the keys of the location's `x402_receipt_key` directives as a JSON Web
+/// Key Set, so upstreams can verify `X-X402-Receipt` tokens.
+///
+/// # Usage
+///
+/// In Nginx configuration:
+/// ```nginx
+/// location = /.well-known/jwks.json {
+///     x402_receipt_key /etc/nginx/x402/receipt.pem;
+///     x402_receipt_jwks;
+/// }
+/// ```
+///
+/// # Returns
+///
+/// * `Status::NGX_OK` - Key set successfully sent
+/// * `Status::NGX_ERROR` - No receipt keys, or the response could not be sent
+pub fn x402_jwks_handler_impl(req: &mut Request) -> Status {
+    let receipt = match get_module_config(req).and_then(|conf| conf.parse()) {
+        Ok(parsed) => parsed.receipt,
+        Err(e) => {
+            log_error(Some(req), &format!("Failed to parse config for JWKS: {e}"));
+            return Status::NGX_ERROR;
+        }
+    };
+    let Some(receipt) = receipt.filter(|receipt| !receipt.get().keys.is_empty()) else {
+        log_error(
+            Some(req),
+            "x402_receipt_jwks location has no x402_receipt_key",
+        );
+        return Status::NGX_ERROR;
+    };
+
+    if req
+        .add_header_out("Content-Type", "application/jwk-set+json")
+        .is_none()
+    {
+        log_error(Some(req), "Failed to set Content-Type header for JWKS");
+        return Status::NGX_ERROR;
+    }
+    if let Ok(status) = HTTPStatus::from_u16(200) {
+        req.set_status(status);
+    } else {
+        log_error(Some(req), "Failed to set status code 200 for JWKS");
+        return Status::NGX_ERROR;
+    }
+
+    match send_response_body(req, &render_jwks(&receipt.get().keys)) {
+        Ok(()) => Status::NGX_OK,
+        Err(e) => {
+            log_error(Some(req), &format!("Failed to send JWKS response: {e}"));
+            Status::NGX_ERROR
+        }
+    }
+}
+
+/// Handler serving the self-hosted paywall assets
+///
+/// Serves the script and stylesheet bundled into the module by the last segment
+/// of the request path, with a strong `ETag` answering `If-None-Match` with 304.
+///
+/// # Usage
+///
+/// In Nginx configuration:
+/// ```nginx
+/// location /x402/assets/ {
+///     x402_paywall_assets on;
+/// }
+/// ```
+///
+/// # Returns
+///
+/// * `Status::NGX_OK` - Asset (or 304 response) successfully sent
+/// * `NGX_HTTP_NOT_FOUND` - Unknown asset
+/// * `Status::NGX_ERROR` - The response could not be sent
+pub fn x402_paywall_assets_handler_impl(req: &mut Request) -> Status {
+    let path = req.path().to_str().unwrap_or_default();
+    let Some(asset) = path.rsplit('/').next().and_then(find_paywall_asset) else {
+        return HTTPStatus::NOT_FOUND.into();
+    };
+
+    let etag = asset.etag();
+    let not_modified = get_header_value(req, "If-None-Match")
+        .is_some_and(|if_none_match| etag_matches(&if_none_match, &etag));
+
+    for (name, value) in [
+        ("Content-Type", asset.content_type),
+        ("ETag", etag.as_str()),
+        ("Cache-Control", "public, max-age=86400"),
+    ] {
+        if req.add_header_out(name, value).is_none() {
+            log_error(
+                Some(req),
+                &format!("Failed to set {name} header for paywall asset"),
+            );
+            return Status::NGX_ERROR;
+        }
+    }
+
+    if not_modified {
+        req.set_status(HTTPStatus::NOT_MODIFIED);
+        req.as_mut().set_header_only(1);
+        return req.send_header();
+    }
+
+    req.set_status(HTTPStatus::OK);
+    match send_response_body(req, asset.body) {
+        Ok(()) => Status::NGX_OK,
+        Err(e) => {
+            log_error(
+                Some(req),
+                &format!("Failed to send paywall asset {}: {e}", asset.name),
+            );
+            Status::NGX_ERROR
+        }
+    }
+}
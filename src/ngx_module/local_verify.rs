@@ -0,0 +1,330 @@
+//! Local payment verification
+//!
+//! With `x402_verify_mode local` or `hybrid`, `exact` EVM payments are verified
+//! in-process instead of by the facilitator. The payment must:
+//!
+//! - use the scheme and network of the payment requirements
+//! - pay at least `maxAmountRequired` to `payTo`
+//! - be valid now (`validAfter <= now < validBefore`)
+//! - carry an EIP-712 `TransferWithAuthorization` signature by `from`, for the
+//!   token contract `asset` (domain name and version from the requirements'
+//!   `extra`, as set for USDC)
+//!
+//! Unlike the facilitator, local verification can't check on-chain state: the
+//! payer's balance and whether the nonce was already used. Signatures of smart
+//! contract wallets (EIP-1271) are not supported.
+
+use crate::ngx_module::error::{ConfigError, Result};
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use rust_x402::types::{PaymentPayload, PaymentRequirements};
+use sha3::{Digest, Keccak256};
+
+/// EIP-712 type of the EIP-3009 authorization signed by the payer
+pub const TRANSFER_WITH_AUTHORIZATION_TYPE: &str = "TransferWithAuthorization(address from,address to,uint256 value,uint256 validAfter,uint256 validBefore,bytes32 nonce)";
+
+/// EIP-712 type of the signing domain
+const EIP712_DOMAIN_TYPE: &str =
+    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+
+/// EVM address
+pub type Address = [u8; 20];
+
+/// 256-bit word (big-endian)
+pub type Word = [u8; 32];
+
+/// Keccak-256 hash
+#[must_use]
+pub fn keccak256(data: &[u8]) -> Word {
+    Keccak256::digest(data).into()
+}
+
+/// Chain ID of an EVM network in the network registry
+#[must_use]
+pub fn chain_id(network: &str) -> Option<u64> {
+    crate::config::network_by_name(network).and_then(|network| network.chain_id)
+}
+
+/// Parse a `0x`-prefixed hex string of exactly `N` bytes
+fn parse_hex<const N: usize>(value: &str) -> Option<[u8; N]> {
+    let digits = value.trim().strip_prefix("0x")?;
+    let bytes = hex::decode(digits).ok()?;
+    bytes.try_into().ok()
+}
+
+/// Parse an EVM address (`0x` + 40 hex digits, any case)
+#[must_use]
+pub fn parse_address(value: &str) -> Option<Address> {
+    parse_hex(value)
+}
+
+/// Parse a decimal `uint256`
+///
+/// # Returns
+/// - `None` if the value is empty, not decimal or does not fit 256 bits
+#[must_use]
+pub fn parse_uint256(value: &str) -> Option<Word> {
+    let value = value.trim();
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut word = [0u8; 32];
+    for digit in value.bytes() {
+        // word = word * 10 + digit
+        let mut carry = u32::from(digit - b'0');
+        for byte in word.iter_mut().rev() {
+            let product = u32::from(*byte) * 10 + carry;
+            *byte = (product & 0xff) as u8;
+            carry = product >> 8;
+        }
+        if carry != 0 {
+            return None;
+        }
+    }
+    Some(word)
+}
+
+/// ABI-encode an integer as a 256-bit word
+fn uint_word(value: u64) -> Word {
+    let mut word = [0u8; 32];
+    word[24..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+/// ABI-encode an address as a 256-bit word
+fn address_word(address: &Address) -> Word {
+    let mut word = [0u8; 32];
+    word[12..].copy_from_slice(address);
+    word
+}
+
+/// EIP-712 domain separator of a token contract
+#[must_use]
+pub fn domain_separator(
+    name: &str,
+    version: &str,
+    chain_id: u64,
+    verifying_contract: &Address,
+) -> Word {
+    let mut encoded = Vec::with_capacity(5 * 32);
+    encoded.extend_from_slice(&keccak256(EIP712_DOMAIN_TYPE.as_bytes()));
+    encoded.extend_from_slice(&keccak256(name.as_bytes()));
+    encoded.extend_from_slice(&keccak256(version.as_bytes()));
+    encoded.extend_from_slice(&uint_word(chain_id));
+    encoded.extend_from_slice(&address_word(verifying_contract));
+    keccak256(&encoded)
+}
+
+/// EIP-3009 authorization, decoded from the payment payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Authorization {
+    pub from: Address,
+    pub to: Address,
+    pub value: Word,
+    pub valid_after: Word,
+    pub valid_before: Word,
+    pub nonce: Word,
+}
+
+impl Authorization {
+    /// Decode the authorization of a payment payload
+    ///
+    /// # Errors
+    /// - Returns error naming the first field that is malformed
+    pub fn from_payload(payload: &PaymentPayload) -> Result<Self> {
+        let authorization = &payload.payload.authorization;
+        let field = |name: &str| ConfigError::from(format!("Invalid authorization {name}"));
+        Ok(Self {
+            from: parse_address(&authorization.from).ok_or_else(|| field("from"))?,
+            to: parse_address(&authorization.to).ok_or_else(|| field("to"))?,
+            value: parse_uint256(&authorization.value).ok_or_else(|| field("value"))?,
+            valid_after: parse_uint256(&authorization.valid_after)
+                .ok_or_else(|| field("validAfter"))?,
+            valid_before: parse_uint256(&authorization.valid_before)
+                .ok_or_else(|| field("validBefore"))?,
+            nonce: parse_hex(&authorization.nonce).ok_or_else(|| field("nonce"))?,
+        })
+    }
+
+    /// EIP-712 digest signed by the payer
+    #[must_use]
+    pub fn signing_hash(&self, domain_separator: &Word) -> Word {
+        let mut encoded = Vec::with_capacity(7 * 32);
+        encoded.extend_from_slice(&keccak256(TRANSFER_WITH_AUTHORIZATION_TYPE.as_bytes()));
+        encoded.extend_from_slice(&address_word(&self.from));
+        encoded.extend_from_slice(&address_word(&self.to));
+        encoded.extend_from_slice(&self.value);
+        encoded.extend_from_slice(&self.valid_after);
+        encoded.extend_from_slice(&self.valid_before);
+        encoded.extend_from_slice(&self.nonce);
+        let struct_hash = keccak256(&encoded);
+
+        let mut message = Vec::with_capacity(2 + 2 * 32);
+        message.extend_from_slice(&[0x19, 0x01]);
+        message.extend_from_slice(domain_separator);
+        message.extend_from_slice(&struct_hash);
+        keccak256(&message)
+    }
+}
+
+/// Address of a public key
+#[must_use]
+pub fn address_of(key: &VerifyingKey) -> Address {
+    let point = key.to_encoded_point(false);
+    // Uncompressed point: 0x04 || x || y
+    let hash = keccak256(&point.as_bytes()[1..]);
+    let mut address = [0u8; 20];
+    address.copy_from_slice(&hash[12..]);
+    address
+}
+
+/// Recover the address that produced a 65-byte `r || s || v` signature of `digest`
+///
+/// # Errors
+/// - Returns error if the signature is malformed or no key can be recovered
+pub fn recover_signer(digest: &Word, signature: &str) -> Result<Address> {
+    let bytes: [u8; 65] =
+        parse_hex(signature).ok_or_else(|| ConfigError::from("Signature must be 65 bytes"))?;
+
+    let recovery = match bytes[64] {
+        v @ (0 | 1) => v,
+        v @ (27 | 28) => v - 27,
+        _ => return Err(ConfigError::from("Invalid signature recovery id")),
+    };
+    let signature =
+        Signature::from_slice(&bytes[..64]).map_err(|_| ConfigError::from("Invalid signature"))?;
+    // Token contracts reject malleable (high-s) signatures, so the settlement would fail
+    if signature.normalize_s().is_some() {
+        return Err(ConfigError::from("Invalid signature 's' value"));
+    }
+    let recovery = RecoveryId::from_byte(recovery)
+        .ok_or_else(|| ConfigError::from("Invalid signature recovery id"))?;
+
+    let key = VerifyingKey::recover_from_prehash(digest, &signature, recovery)
+        .map_err(|_| ConfigError::from("Signature does not match the authorization"))?;
+    Ok(address_of(&key))
+}
+
+/// Error for a network without a known chain ID
+fn unsupported_network(network: &str) -> ConfigError {
+    ConfigError::from(format!(
+        "Network '{network}' is not supported by local verification"
+    ))
+}
+
+/// EIP-712 domain separator of the requirements' token contract
+///
+/// # Errors
+/// - Returns error if the network, the asset or its domain name and version are unknown
+pub fn asset_domain_separator(requirements: &PaymentRequirements) -> Result<Word> {
+    let chain_id = chain_id(&requirements.network)
+        .ok_or_else(|| unsupported_network(&requirements.network))?;
+    let asset = parse_address(&requirements.asset)
+        .ok_or_else(|| ConfigError::from("Invalid asset address"))?;
+    let extra = requirements.extra.as_ref();
+    let domain_field = |field: &str| {
+        extra
+            .and_then(|extra| extra.get(field))
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| {
+                ConfigError::from(format!(
+                    "Asset EIP-712 domain {field} is unknown, it can't be verified locally"
+                ))
+            })
+    };
+    Ok(domain_separator(
+        domain_field("name")?,
+        domain_field("version")?,
+        chain_id,
+        &asset,
+    ))
+}
+
+/// Whether a payment was signed for the token contract of the requirements
+///
+/// The payload doesn't name its asset, but the EIP-712 signature commits to it:
+/// only the domain of the token it was made for recovers the payer's address.
+#[must_use]
+pub fn signed_for_asset(payload: &PaymentPayload, requirements: &PaymentRequirements) -> bool {
+    let (Ok(domain), Ok(authorization)) = (
+        asset_domain_separator(requirements),
+        Authorization::from_payload(payload),
+    ) else {
+        return false;
+    };
+    recover_signer(
+        &authorization.signing_hash(&domain),
+        &payload.payload.signature,
+    )
+    .is_ok_and(|signer| signer == authorization.from)
+}
+
+/// Verify a payment against its requirements without the facilitator
+///
+/// # Arguments
+/// - `payload`: Decoded `X-PAYMENT` payload
+/// - `requirements`: Payment requirements the payment was matched to
+/// - `now`: Current time (unix seconds)
+///
+/// # Errors
+/// - Returns error describing why the payment is not valid
+pub fn verify_payment_locally(
+    payload: &PaymentPayload,
+    requirements: &PaymentRequirements,
+    now: u64,
+) -> Result<()> {
+    if payload.scheme != rust_x402::types::schemes::EXACT || payload.scheme != requirements.scheme {
+        return Err(ConfigError::from(format!(
+            "Unsupported payment scheme '{}'",
+            payload.scheme
+        )));
+    }
+    if payload.network != requirements.network {
+        return Err(ConfigError::from(format!(
+            "Payment network '{}' does not match '{}'",
+            payload.network, requirements.network
+        )));
+    }
+    if chain_id(&requirements.network).is_none() {
+        return Err(unsupported_network(&requirements.network));
+    }
+
+    let authorization = Authorization::from_payload(payload)?;
+
+    let pay_to = parse_address(&requirements.pay_to)
+        .ok_or_else(|| ConfigError::from("Invalid payTo address"))?;
+    if authorization.to != pay_to {
+        return Err(ConfigError::from("Payment recipient does not match payTo"));
+    }
+
+    let required = parse_uint256(&requirements.max_amount_required)
+        .ok_or_else(|| ConfigError::from("Invalid maxAmountRequired"))?;
+    // Big-endian words compare like the numbers they encode
+    if authorization.value < required {
+        return Err(ConfigError::from(
+            "Payment value is below maxAmountRequired",
+        ));
+    }
+
+    let now = uint_word(now);
+    if authorization.valid_after > now {
+        return Err(ConfigError::from("Payment authorization is not valid yet"));
+    }
+    if authorization.valid_before <= now {
+        return Err(ConfigError::from("Payment authorization has expired"));
+    }
+
+    let domain = asset_domain_separator(requirements)?;
+
+    let signer = recover_signer(
+        &authorization.signing_hash(&domain),
+        &payload.payload.signature,
+    )?;
+    if signer != authorization.from {
+        return Err(ConfigError::from(
+            "Payment signature was not made by the payer",
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,550 @@
+//! Localized client-facing messages
+//!
+//! Error messages of 402 and error responses and the texts of the module's
+//! paywall page are selected from the request's `Accept-Language` header. The
+//! module has built-in catalogs for English, Spanish, French, German,
+//! Portuguese, Chinese and Japanese; `x402_messages` loads a JSON file that
+//! overrides messages of a language or adds a language:
+//!
+//! ```json
+//! {
+//!   "payment_required": "Contenu réservé aux abonnés",
+//!   "payment_required_subtitle": "Achetez cet article pour le lire."
+//! }
+//! ```
+//!
+//! The language is the file name (`fr.json`, `pt-BR.json`). Messages missing
+//! from a catalog fall back to the built-in catalog of the language, then to
+//! English. Messages that are not module messages (e.g. `x402_description`)
+//! are never translated.
+
+use crate::ngx_module::error::{user_errors, ConfigError, Result};
+use crate::ngx_module::module::get_module_config;
+use crate::ngx_module::request::get_header_value;
+use ngx::http::Request;
+use std::collections::HashMap;
+use std::ptr::NonNull;
+
+/// Language of responses to requests without a matching `Accept-Language`
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// Number of messages in a catalog
+const MESSAGE_COUNT: usize = 19;
+
+/// Client-facing message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Message {
+    PaymentVerificationFailed,
+    InvalidPayment,
+    ConfigurationError,
+    Timeout,
+    SettlementFailed,
+    NoMatchingPaymentOption,
+    PriceUnavailable,
+    PaymentAlreadyUsed,
+    FacilitatorUnavailable,
+    InternalServerError,
+    PaymentRequired,
+    PaymentRequiredSubtitle,
+    Amount,
+    Network,
+    HowToPay,
+    ConnectWallet,
+    SwitchNetwork,
+    CheckBalance,
+    RetryWithPayment,
+}
+
+impl Message {
+    /// All messages, in catalog order
+    pub const ALL: [Message; MESSAGE_COUNT] = [
+        Message::PaymentVerificationFailed,
+        Message::InvalidPayment,
+        Message::ConfigurationError,
+        Message::Timeout,
+        Message::SettlementFailed,
+        Message::NoMatchingPaymentOption,
+        Message::PriceUnavailable,
+        Message::PaymentAlreadyUsed,
+        Message::FacilitatorUnavailable,
+        Message::InternalServerError,
+        Message::PaymentRequired,
+        Message::PaymentRequiredSubtitle,
+        Message::Amount,
+        Message::Network,
+        Message::HowToPay,
+        Message::ConnectWallet,
+        Message::SwitchNetwork,
+        Message::CheckBalance,
+        Message::RetryWithPayment,
+    ];
+
+    /// Key of the message in `x402_messages` files
+    #[must_use]
+    pub fn key(self) -> &'static str {
+        match self {
+            Message::PaymentVerificationFailed => "payment_verification_failed",
+            Message::InvalidPayment => "invalid_payment",
+            Message::ConfigurationError => "configuration_error",
+            Message::Timeout => "timeout",
+            Message::SettlementFailed => "settlement_failed",
+            Message::NoMatchingPaymentOption => "no_matching_payment_option",
+            Message::PriceUnavailable => "price_unavailable",
+            Message::PaymentAlreadyUsed => "payment_already_used",
+            Message::FacilitatorUnavailable => "facilitator_unavailable",
+            Message::InternalServerError => "internal_server_error",
+            Message::PaymentRequired => "payment_required",
+            Message::PaymentRequiredSubtitle => "payment_required_subtitle",
+            Message::Amount => "amount",
+            Message::Network => "network",
+            Message::HowToPay => "how_to_pay",
+            Message::ConnectWallet => "connect_wallet",
+            Message::SwitchNetwork => "switch_network",
+            Message::CheckBalance => "check_balance",
+            Message::RetryWithPayment => "retry_with_payment",
+        }
+    }
+
+    /// Message for a key of an `x402_messages` file
+    #[must_use]
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|message| message.key() == key)
+    }
+
+    /// Message for an English text, e.g. a `user_errors` constant
+    #[must_use]
+    pub fn from_english(text: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|message| message.english() == text)
+    }
+
+    /// English text of the message
+    #[must_use]
+    pub fn english(self) -> &'static str {
+        EN[self as usize]
+    }
+}
+
+/// Built-in catalog: the texts of all messages, in `Message::ALL` order
+type Catalog = [&'static str; MESSAGE_COUNT];
+
+const EN: Catalog = [
+    user_errors::PAYMENT_VERIFICATION_FAILED,
+    user_errors::INVALID_PAYMENT,
+    user_errors::CONFIGURATION_ERROR,
+    user_errors::TIMEOUT,
+    user_errors::SETTLEMENT_FAILED,
+    user_errors::NO_MATCHING_PAYMENT_OPTION,
+    user_errors::PRICE_UNAVAILABLE,
+    user_errors::PAYMENT_ALREADY_USED,
+    user_errors::FACILITATOR_UNAVAILABLE,
+    "Internal server error",
+    "Payment Required",
+    "This resource requires payment to access.",
+    "Amount",
+    "Network",
+    "How to pay:",
+    "Connect your wallet",
+    "Switch to the required network",
+    "Make sure you have enough balance",
+    "Retry the request with payment",
+];
+
+const ES: Catalog = [
+    "La verificación del pago ha fallado",
+    "Pago no válido",
+    "Error de configuración",
+    "Tiempo de espera agotado",
+    "La liquidación del pago ha fallado",
+    "El pago no coincide con ninguna de las opciones de pago aceptadas",
+    "El precio de este recurso no está disponible",
+    "La autorización de pago ya se ha utilizado",
+    "El facilitador de pagos no está disponible",
+    "Error interno del servidor",
+    "Pago requerido",
+    "Este recurso requiere un pago para acceder.",
+    "Importe",
+    "Red",
+    "Cómo pagar:",
+    "Conecta tu billetera",
+    "Cambia a la red requerida",
+    "Asegúrate de tener saldo suficiente",
+    "Vuelve a enviar la solicitud con el pago",
+];
+
+const FR: Catalog = [
+    "La vérification du paiement a échoué",
+    "Paiement invalide",
+    "Erreur de configuration",
+    "Délai d'attente dépassé",
+    "Le règlement du paiement a échoué",
+    "Le paiement ne correspond à aucune option de paiement acceptée",
+    "Le prix de cette ressource n'est pas disponible",
+    "L'autorisation de paiement a déjà été utilisée",
+    "Le facilitateur de paiement est indisponible",
+    "Erreur interne du serveur",
+    "Paiement requis",
+    "L'accès à cette ressource est payant.",
+    "Montant",
+    "Réseau",
+    "Comment payer :",
+    "Connectez votre portefeuille",
+    "Passez sur le réseau requis",
+    "Vérifiez que votre solde est suffisant",
+    "Renvoyez la requête avec le paiement",
+];
+
+const DE: Catalog = [
+    "Die Zahlungsprüfung ist fehlgeschlagen",
+    "Ungültige Zahlung",
+    "Konfigurationsfehler",
+    "Zeitüberschreitung der Anfrage",
+    "Die Abwicklung der Zahlung ist fehlgeschlagen",
+    "Die Zahlung entspricht keiner der akzeptierten Zahlungsoptionen",
+    "Für diese Ressource ist kein Preis verfügbar",
+    "Die Zahlungsautorisierung wurde bereits verwendet",
+    "Der Zahlungsdienstleister ist nicht erreichbar",
+    "Interner Serverfehler",
+    "Zahlung erforderlich",
+    "Für den Zugriff auf diese Ressource ist eine Zahlung erforderlich.",
+    "Betrag",
+    "Netzwerk",
+    "So bezahlen Sie:",
+    "Verbinden Sie Ihre Wallet",
+    "Wechseln Sie zum erforderlichen Netzwerk",
+    "Stellen Sie sicher, dass Ihr Guthaben ausreicht",
+    "Senden Sie die Anfrage mit der Zahlung erneut",
+];
+
+const PT: Catalog = [
+    "A verificação do pagamento falhou",
+    "Pagamento inválido",
+    "Erro de configuração",
+    "Tempo limite da solicitação esgotado",
+    "A liquidação do pagamento falhou",
+    "O pagamento não corresponde a nenhuma opção de pagamento aceita",
+    "O preço deste recurso não está disponível",
+    "A autorização de pagamento já foi utilizada",
+    "O facilitador de pagamento está indisponível",
+    "Erro interno do servidor",
+    "Pagamento necessário",
+    "Este recurso requer pagamento para acesso.",
+    "Valor",
+    "Rede",
+    "Como pagar:",
+    "Conecte sua carteira",
+    "Mude para a rede necessária",
+    "Verifique se você tem saldo suficiente",
+    "Reenvie a solicitação com o pagamento",
+];
+
+const ZH: Catalog = [
+    "支付验证失败",
+    "无效的支付",
+    "配置错误",
+    "请求超时",
+    "支付结算失败",
+    "支付与任何可接受的支付选项都不匹配",
+    "该资源的价格不可用",
+    "该支付授权已被使用",
+    "支付服务商不可用",
+    "服务器内部错误",
+    "需要付款",
+    "访问此资源需要付款。",
+    "金额",
+    "网络",
+    "付款方式：",
+    "连接您的钱包",
+    "切换到所需的网络",
+    "确保您的余额充足",
+    "附带付款重新发送请求",
+];
+
+const JA: Catalog = [
+    "支払いの検証に失敗しました",
+    "無効な支払いです",
+    "設定エラー",
+    "リクエストがタイムアウトしました",
+    "支払いの決済に失敗しました",
+    "支払いが受け付け可能な支払いオプションのいずれにも一致しません",
+    "このリソースの価格を取得できません",
+    "この支払い承認はすでに使用されています",
+    "支払いファシリテーターを利用できません",
+    "サーバー内部エラー",
+    "お支払いが必要です",
+    "このリソースへのアクセスには支払いが必要です。",
+    "金額",
+    "ネットワーク",
+    "お支払い方法：",
+    "ウォレットを接続してください",
+    "必要なネットワークに切り替えてください",
+    "残高が十分にあることを確認してください",
+    "支払いを付けてリクエストを再送信してください",
+];
+
+/// Built-in catalogs by language
+const BUILTIN: [(&str, &Catalog); 7] = [
+    (DEFAULT_LANGUAGE, &EN),
+    ("es", &ES),
+    ("fr", &FR),
+    ("de", &DE),
+    ("pt", &PT),
+    ("zh", &ZH),
+    ("ja", &JA),
+];
+
+/// Languages with a built-in catalog
+pub fn builtin_languages() -> impl Iterator<Item = &'static str> {
+    BUILTIN.into_iter().map(|(language, _)| language)
+}
+
+/// Built-in catalog of a language, or of its primary language (`pt` for `pt-BR`)
+fn builtin_catalog(language: &str) -> Option<&'static Catalog> {
+    let primary = language.split('-').next().unwrap_or(language);
+    [language, primary].into_iter().find_map(|language| {
+        BUILTIN
+            .into_iter()
+            .find(|(builtin, _)| builtin.eq_ignore_ascii_case(language))
+            .map(|(_, catalog)| catalog)
+    })
+}
+
+/// Check a language tag (`fr`, `pt-BR`, `zh-Hant-TW`)
+#[must_use]
+pub fn is_valid_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    subtags.next().is_some_and(|primary| {
+        (2..=3).contains(&primary.len()) && primary.bytes().all(|b| b.is_ascii_alphabetic())
+    }) && subtags.all(|subtag| {
+        (1..=8).contains(&subtag.len()) && subtag.bytes().all(|b| b.is_ascii_alphanumeric())
+    })
+}
+
+/// Messages of one language loaded by `x402_messages`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomCatalog {
+    /// Language tag (from the file name)
+    pub language: String,
+    pub messages: HashMap<Message, String>,
+}
+
+impl CustomCatalog {
+    /// Parse a JSON object of message keys and texts
+    ///
+    /// # Errors
+    /// - Returns error if the language tag is invalid, the JSON is not an object
+    ///   of strings, or a key is unknown
+    pub fn parse(language: &str, json: &str) -> Result<Self> {
+        if !is_valid_language_tag(language) {
+            return Err(ConfigError::from(format!(
+                "Invalid language \"{language}\" (expected a file name like \"fr.json\")"
+            )));
+        }
+        let value: serde_json::Value = serde_json::from_str(json)
+            .map_err(|e| ConfigError::from(format!("Invalid JSON: {e}")))?;
+        let object = value
+            .as_object()
+            .ok_or_else(|| ConfigError::from("Messages must be a JSON object"))?;
+
+        let mut messages = HashMap::with_capacity(object.len());
+        for (key, text) in object {
+            let message = Message::from_key(key)
+                .ok_or_else(|| ConfigError::from(format!("Unknown message \"{key}\"")))?;
+            let text = text
+                .as_str()
+                .ok_or_else(|| ConfigError::from(format!("Message \"{key}\" is not a string")))?;
+            messages.insert(message, text.to_string());
+        }
+        Ok(CustomCatalog {
+            language: language.to_string(),
+            messages,
+        })
+    }
+}
+
+/// Catalogs of a location's `x402_messages` directives
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageCatalogs {
+    catalogs: Vec<CustomCatalog>,
+}
+
+impl MessageCatalogs {
+    /// Add the catalog of a language
+    ///
+    /// # Errors
+    /// - Returns error if the language already has a catalog
+    pub fn add(&mut self, catalog: CustomCatalog) -> Result<()> {
+        if self.get(&catalog.language).is_some() {
+            return Err(ConfigError::from(format!(
+                "Duplicate messages for language \"{}\"",
+                catalog.language
+            )));
+        }
+        self.catalogs.push(catalog);
+        Ok(())
+    }
+
+    /// Catalog of a language (case-insensitive)
+    #[must_use]
+    pub fn get(&self, language: &str) -> Option<&CustomCatalog> {
+        self.catalogs
+            .iter()
+            .find(|catalog| catalog.language.eq_ignore_ascii_case(language))
+    }
+}
+
+/// Language ranges of an `Accept-Language` header, most preferred first
+///
+/// Ranges with `q=0` are dropped; ranges with the same quality keep their order.
+#[must_use]
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut ranges: Vec<(String, u16)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let range = parts.next()?.trim();
+            if range.is_empty() {
+                return None;
+            }
+            let mut quality = 1000;
+            for param in parts {
+                if let Some(q) = param.trim().strip_prefix("q=") {
+                    // Invalid weights rank the range last instead of dropping it
+                    quality = q
+                        .trim()
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|q| (0.0..=1.0).contains(q))
+                        .map_or(1, |q| (q * 1000.0).round() as u16);
+                }
+            }
+            (quality > 0).then(|| (range.to_ascii_lowercase(), quality))
+        })
+        .collect();
+    ranges.sort_by_key(|(_, quality)| std::cmp::Reverse(*quality));
+    ranges.into_iter().map(|(range, _)| range).collect()
+}
+
+/// Messages in the language negotiated for a request
+#[derive(Debug, Clone, Copy)]
+pub struct Messages<'a> {
+    language: &'a str,
+    custom: Option<&'a CustomCatalog>,
+    builtin: &'static Catalog,
+}
+
+impl Default for Messages<'_> {
+    fn default() -> Self {
+        Messages {
+            language: DEFAULT_LANGUAGE,
+            custom: None,
+            builtin: &EN,
+        }
+    }
+}
+
+impl<'a> Messages<'a> {
+    /// Select the language of an `Accept-Language` header
+    ///
+    /// Each range is tried as is, then with its last subtag removed
+    /// (`pt-BR` before `pt`), against the `x402_messages` catalogs and the
+    /// built-in catalogs. Without a match, messages are in English.
+    #[must_use]
+    pub fn negotiate(accept_language: Option<&str>, catalogs: Option<&'a MessageCatalogs>) -> Self {
+        let ranges = accept_language
+            .map(parse_accept_language)
+            .unwrap_or_default();
+        for range in &ranges {
+            let mut candidate = range.as_str();
+            loop {
+                if let Some(messages) = Self::for_language(candidate, catalogs) {
+                    return messages;
+                }
+                match candidate.rfind('-') {
+                    Some(end) => candidate = &candidate[..end],
+                    None => break,
+                }
+            }
+        }
+        Self::for_language(DEFAULT_LANGUAGE, catalogs).unwrap_or_default()
+    }
+
+    /// Messages of a language, if it has a catalog
+    fn for_language(language: &str, catalogs: Option<&'a MessageCatalogs>) -> Option<Self> {
+        if language == "*" {
+            return None;
+        }
+        if let Some(custom) = catalogs.and_then(|catalogs| catalogs.get(language)) {
+            return Some(Messages {
+                language: &custom.language,
+                custom: Some(custom),
+                builtin: builtin_catalog(&custom.language).unwrap_or(&EN),
+            });
+        }
+        BUILTIN
+            .into_iter()
+            .find(|(builtin, _)| builtin.eq_ignore_ascii_case(language))
+            .map(|(language, builtin)| Messages {
+                language,
+                custom: None,
+                builtin,
+            })
+    }
+
+    /// Language tag of the messages
+    #[must_use]
+    pub fn language(&self) -> &'a str {
+        self.language
+    }
+
+    /// Whether the messages are the built-in English ones
+    #[must_use]
+    pub fn is_default(&self) -> bool {
+        self.custom.is_none() && self.language == DEFAULT_LANGUAGE
+    }
+
+    /// Text of a message
+    #[must_use]
+    pub fn get(&self, message: Message) -> &'a str {
+        self.custom
+            .and_then(|custom| custom.messages.get(&message))
+            .map_or(self.builtin[message as usize], String::as_str)
+    }
+
+    /// Translate a module message given in English (e.g. a `user_errors` constant)
+    ///
+    /// Other texts are returned unchanged.
+    #[must_use]
+    pub fn localize<'b>(&self, text: &'b str) -> &'b str
+    where
+        'a: 'b,
+    {
+        Message::from_english(text).map_or(text, |message| self.get(message))
+    }
+}
+
+/// `x402_messages` catalogs allocated from the configuration pool
+///
+/// # Safety
+/// `catalogs` must point to catalogs allocated by the `x402_messages` directive,
+/// which live as long as the configuration cycle.
+unsafe fn configured_catalogs(catalogs: NonNull<MessageCatalogs>) -> &'static MessageCatalogs {
+    catalogs.as_ref()
+}
+
+/// Messages for a request, from its `Accept-Language` header and the location's
+/// `x402_messages` catalogs
+///
+/// Works without a readable location configuration (built-in catalogs only), so
+/// it can be used for configuration error responses.
+#[must_use]
+pub fn request_messages(r: &Request) -> Messages<'static> {
+    let catalogs = get_module_config(r)
+        .ok()
+        .and_then(|conf| conf.messages)
+        // Safe: set by the x402_messages directive
+        .map(|catalogs| unsafe { configured_catalogs(catalogs) });
+    let accept_language = get_header_value(r, "Accept-Language");
+    Messages::negotiate(accept_language.as_deref(), catalogs)
+}
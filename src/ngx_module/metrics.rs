@@ -5,8 +5,9 @@
 //! and visualized in Grafana.
 
 use prometheus::{
-    register_histogram_with_registry, register_int_counter_with_registry, Histogram, IntCounter,
-    Registry,
+    register_histogram_with_registry, register_int_counter_vec_with_registry,
+    register_int_counter_with_registry, register_int_gauge_vec_with_registry, Histogram,
+    IntCounter, IntCounterVec, IntGaugeVec, Registry,
 };
 use std::sync::OnceLock;
 
@@ -31,6 +32,44 @@ pub struct X402Metrics {
     pub verification_duration_seconds: Histogram,
     /// Payment amount histogram (for tracking payment amounts)
     pub payment_amount: Histogram,
+    /// Total number of payment settlements attempted
+    pub settlements_total: IntCounter,
+    /// Total number of successful payment settlements
+    pub settlements_success_total: IntCounter,
+    /// Total number of failed payment settlements
+    pub settlements_failed_total: IntCounter,
+    /// Total number of settlements skipped because the response was not 2xx
+    pub settlements_skipped_total: IntCounter,
+    /// Payment settlement duration in seconds
+    pub settlement_duration_seconds: Histogram,
+    /// Total number of payments rejected because the authorization was reused
+    pub replays_rejected_total: IntCounter,
+    /// Total number of payment verifications answered from the verification cache
+    pub verify_cache_hits_total: IntCounter,
+    /// Total number of payment verifications not found in the verification cache
+    pub verify_cache_misses_total: IntCounter,
+    /// Whether each facilitator is up (1) or marked down (0), by URL
+    pub facilitator_up: IntGaugeVec,
+    /// Consecutive failed calls of each facilitator, by URL
+    pub facilitator_consecutive_failures: IntGaugeVec,
+    /// Total number of facilitator call retries
+    pub facilitator_retries_total: IntCounter,
+    /// Circuit breaker state (0 closed, 1 half-open, 2 open), by facilitators
+    pub circuit_breaker_state: IntGaugeVec,
+    /// Total number of facilitator calls failed immediately by an open circuit breaker
+    pub circuit_breaker_rejected_total: IntCounter,
+    /// Total number of prepaid session tokens issued
+    pub sessions_issued_total: IntCounter,
+    /// Total number of requests paid with a prepaid session token
+    pub session_requests_total: IntCounter,
+    /// Total number of access passes issued
+    pub access_passes_issued_total: IntCounter,
+    /// Total number of requests covered by an access pass
+    pub access_pass_requests_total: IntCounter,
+    /// Total number of signed receipts returned to clients
+    pub receipts_issued_total: IntCounter,
+    /// Total number of requests that skipped payment verification, by reason
+    pub bypassed_requests_total: IntCounterVec,
 }
 
 impl X402Metrics {
@@ -88,6 +127,125 @@ impl X402Metrics {
             registry
         )?;
 
+        let settlements_total = register_int_counter_with_registry!(
+            "x402_settlements_total",
+            "Total number of payment settlements attempted",
+            registry
+        )?;
+
+        let settlements_success_total = register_int_counter_with_registry!(
+            "x402_settlements_success_total",
+            "Total number of successful payment settlements",
+            registry
+        )?;
+
+        let settlements_failed_total = register_int_counter_with_registry!(
+            "x402_settlements_failed_total",
+            "Total number of failed payment settlements",
+            registry
+        )?;
+
+        let settlements_skipped_total = register_int_counter_with_registry!(
+            "x402_settlements_skipped_total",
+            "Total number of settlements skipped because the response was not successful",
+            registry
+        )?;
+
+        let settlement_duration_seconds = register_histogram_with_registry!(
+            "x402_settlement_duration_seconds",
+            "Payment settlement duration in seconds",
+            vec![0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0],
+            registry
+        )?;
+
+        let replays_rejected_total = register_int_counter_with_registry!(
+            "x402_replays_rejected_total",
+            "Total number of payments rejected because the authorization had already been used",
+            registry
+        )?;
+
+        let verify_cache_hits_total = register_int_counter_with_registry!(
+            "x402_verify_cache_hits_total",
+            "Total number of payment verifications answered from the verification cache",
+            registry
+        )?;
+
+        let verify_cache_misses_total = register_int_counter_with_registry!(
+            "x402_verify_cache_misses_total",
+            "Total number of payment verifications not found in the verification cache",
+            registry
+        )?;
+
+        let facilitator_up = register_int_gauge_vec_with_registry!(
+            "x402_facilitator_up",
+            "Whether the facilitator is up (1) or marked down after consecutive failures (0)",
+            &["facilitator"],
+            registry
+        )?;
+
+        let facilitator_consecutive_failures = register_int_gauge_vec_with_registry!(
+            "x402_facilitator_consecutive_failures",
+            "Number of consecutive failed calls to the facilitator",
+            &["facilitator"],
+            registry
+        )?;
+
+        let facilitator_retries_total = register_int_counter_with_registry!(
+            "x402_facilitator_retries_total",
+            "Total number of facilitator call retries after all facilitators failed",
+            registry
+        )?;
+
+        let circuit_breaker_state = register_int_gauge_vec_with_registry!(
+            "x402_circuit_breaker_state",
+            "Circuit breaker state of the facilitators (0 closed, 1 half-open, 2 open)",
+            &["facilitators"],
+            registry
+        )?;
+
+        let circuit_breaker_rejected_total = register_int_counter_with_registry!(
+            "x402_circuit_breaker_rejected_total",
+            "Total number of facilitator calls failed immediately by an open circuit breaker",
+            registry
+        )?;
+
+        let sessions_issued_total = register_int_counter_with_registry!(
+            "x402_sessions_issued_total",
+            "Total number of prepaid session tokens issued",
+            registry
+        )?;
+
+        let session_requests_total = register_int_counter_with_registry!(
+            "x402_session_requests_total",
+            "Total number of requests paid with a prepaid session token",
+            registry
+        )?;
+
+        let access_passes_issued_total = register_int_counter_with_registry!(
+            "x402_access_passes_issued_total",
+            "Total number of access passes issued",
+            registry
+        )?;
+
+        let access_pass_requests_total = register_int_counter_with_registry!(
+            "x402_access_pass_requests_total",
+            "Total number of requests covered by an access pass",
+            registry
+        )?;
+
+        let receipts_issued_total = register_int_counter_with_registry!(
+            "x402_receipts_issued_total",
+            "Total number of signed receipts returned to clients",
+            registry
+        )?;
+
+        let bypassed_requests_total = register_int_counter_vec_with_registry!(
+            "x402_bypassed_requests_total",
+            "Total number of requests that skipped payment verification, by reason",
+            &["reason"],
+            registry
+        )?;
+
         Ok(Self {
             requests_total,
             payment_verifications_total,
@@ -97,6 +255,25 @@ impl X402Metrics {
             facilitator_errors_total,
             verification_duration_seconds,
             payment_amount,
+            settlements_total,
+            settlements_success_total,
+            settlements_failed_total,
+            settlements_skipped_total,
+            settlement_duration_seconds,
+            replays_rejected_total,
+            verify_cache_hits_total,
+            verify_cache_misses_total,
+            facilitator_up,
+            facilitator_consecutive_failures,
+            facilitator_retries_total,
+            circuit_breaker_state,
+            circuit_breaker_rejected_total,
+            sessions_issued_total,
+            session_requests_total,
+            access_passes_issued_total,
+            access_pass_requests_total,
+            receipts_issued_total,
+            bypassed_requests_total,
         })
     }
 
@@ -156,6 +333,105 @@ impl X402Metrics {
     pub fn record_payment_amount(&self, amount: f64) {
         self.payment_amount.observe(amount);
     }
+
+    /// Record a payment settlement attempt
+    pub fn record_settlement_attempt(&self) {
+        self.settlements_total.inc();
+    }
+
+    /// Record a successful payment settlement
+    pub fn record_settlement_success(&self) {
+        self.settlements_success_total.inc();
+    }
+
+    /// Record a failed payment settlement
+    pub fn record_settlement_failed(&self) {
+        self.settlements_failed_total.inc();
+    }
+
+    /// Record a settlement skipped due to an unsuccessful response
+    pub fn record_settlement_skipped(&self) {
+        self.settlements_skipped_total.inc();
+    }
+
+    /// Record payment settlement duration
+    pub fn record_settlement_duration(&self, duration_seconds: f64) {
+        self.settlement_duration_seconds.observe(duration_seconds);
+    }
+
+    /// Record a payment rejected as a replay
+    pub fn record_replay_rejected(&self) {
+        self.replays_rejected_total.inc();
+    }
+
+    /// Record a prepaid session token being issued
+    pub fn record_session_issued(&self) {
+        self.sessions_issued_total.inc();
+    }
+
+    /// Record a request paid with a prepaid session token
+    pub fn record_session_request(&self) {
+        self.session_requests_total.inc();
+    }
+
+    /// Record an access pass being issued
+    pub fn record_access_pass_issued(&self) {
+        self.access_passes_issued_total.inc();
+    }
+
+    /// Record a request covered by an access pass
+    pub fn record_access_pass_request(&self) {
+        self.access_pass_requests_total.inc();
+    }
+
+    /// Record a signed receipt returned to a client
+    pub fn record_receipt_issued(&self) {
+        self.receipts_issued_total.inc();
+    }
+
+    /// Record a request that skipped payment verification
+    pub fn record_bypassed_request(&self, reason: &str) {
+        self.bypassed_requests_total
+            .with_label_values(&[reason])
+            .inc();
+    }
+
+    /// Record a verification cache hit
+    pub fn record_verify_cache_hit(&self) {
+        self.verify_cache_hits_total.inc();
+    }
+
+    /// Record a verification cache miss
+    pub fn record_verify_cache_miss(&self) {
+        self.verify_cache_misses_total.inc();
+    }
+
+    /// Record the health of a facilitator after a call
+    pub fn record_facilitator_health(&self, url: &str, up: bool, consecutive_failures: u32) {
+        self.facilitator_up
+            .with_label_values(&[url])
+            .set(i64::from(up));
+        self.facilitator_consecutive_failures
+            .with_label_values(&[url])
+            .set(i64::from(consecutive_failures));
+    }
+
+    /// Record a retry of a facilitator call
+    pub fn record_facilitator_retry(&self) {
+        self.facilitator_retries_total.inc();
+    }
+
+    /// Record a circuit breaker state change
+    pub fn record_circuit_breaker_state(&self, facilitators: &str, state: i64) {
+        self.circuit_breaker_state
+            .with_label_values(&[facilitators])
+            .set(state);
+    }
+
+    /// Record a facilitator call failed immediately by an open circuit breaker
+    pub fn record_circuit_breaker_rejected(&self) {
+        self.circuit_breaker_rejected_total.inc();
+    }
 }
 
 /// Get the Prometheus registry
@@ -263,6 +539,117 @@ mod tests {
         // Histogram should have recorded these values
     }
 
+    #[test]
+    fn test_record_settlement() {
+        let metrics = X402Metrics::get();
+        let initial_attempts = metrics.settlements_total.get();
+        let initial_success = metrics.settlements_success_total.get();
+        let initial_failed = metrics.settlements_failed_total.get();
+        let initial_skipped = metrics.settlements_skipped_total.get();
+
+        metrics.record_settlement_attempt();
+        assert_eq!(metrics.settlements_total.get(), initial_attempts + 1);
+
+        metrics.record_settlement_success();
+        assert_eq!(metrics.settlements_success_total.get(), initial_success + 1);
+
+        metrics.record_settlement_failed();
+        assert_eq!(metrics.settlements_failed_total.get(), initial_failed + 1);
+
+        metrics.record_settlement_skipped();
+        assert_eq!(metrics.settlements_skipped_total.get(), initial_skipped + 1);
+
+        metrics.record_settlement_duration(0.2);
+    }
+
+    #[test]
+    fn test_record_replay_rejected() {
+        let metrics = X402Metrics::get();
+        let initial = metrics.replays_rejected_total.get();
+        metrics.record_replay_rejected();
+        assert_eq!(metrics.replays_rejected_total.get(), initial + 1);
+    }
+
+    #[test]
+    fn test_record_sessions() {
+        let metrics = X402Metrics::get();
+        let issued = metrics.sessions_issued_total.get();
+        let requests = metrics.session_requests_total.get();
+        metrics.record_session_issued();
+        metrics.record_session_request();
+        assert_eq!(metrics.sessions_issued_total.get(), issued + 1);
+        assert_eq!(metrics.session_requests_total.get(), requests + 1);
+    }
+
+    #[test]
+    fn test_record_access_passes() {
+        let metrics = X402Metrics::get();
+        let issued = metrics.access_passes_issued_total.get();
+        let requests = metrics.access_pass_requests_total.get();
+        metrics.record_access_pass_issued();
+        metrics.record_access_pass_request();
+        assert_eq!(metrics.access_passes_issued_total.get(), issued + 1);
+        assert_eq!(metrics.access_pass_requests_total.get(), requests + 1);
+    }
+
+    #[test]
+    fn test_record_receipt_issued() {
+        let metrics = X402Metrics::get();
+        let issued = metrics.receipts_issued_total.get();
+        metrics.record_receipt_issued();
+        assert_eq!(metrics.receipts_issued_total.get(), issued + 1);
+    }
+
+    #[test]
+    fn test_record_verify_cache() {
+        let metrics = X402Metrics::get();
+        let hits = metrics.verify_cache_hits_total.get();
+        let misses = metrics.verify_cache_misses_total.get();
+        metrics.record_verify_cache_hit();
+        metrics.record_verify_cache_miss();
+        assert_eq!(metrics.verify_cache_hits_total.get(), hits + 1);
+        assert_eq!(metrics.verify_cache_misses_total.get(), misses + 1);
+    }
+
+    #[test]
+    fn test_record_facilitator_health() {
+        let metrics = X402Metrics::get();
+        let url = "https://facilitator.test/health";
+        metrics.record_facilitator_health(url, false, 3);
+        assert_eq!(metrics.facilitator_up.with_label_values(&[url]).get(), 0);
+        assert_eq!(
+            metrics
+                .facilitator_consecutive_failures
+                .with_label_values(&[url])
+                .get(),
+            3
+        );
+
+        metrics.record_facilitator_health(url, true, 0);
+        assert_eq!(metrics.facilitator_up.with_label_values(&[url]).get(), 1);
+    }
+
+    #[test]
+    fn test_record_retries_and_circuit_breaker() {
+        let metrics = X402Metrics::get();
+        let retries = metrics.facilitator_retries_total.get();
+        let rejected = metrics.circuit_breaker_rejected_total.get();
+        metrics.record_facilitator_retry();
+        metrics.record_circuit_breaker_rejected();
+        assert_eq!(metrics.facilitator_retries_total.get(), retries + 1);
+        assert_eq!(metrics.circuit_breaker_rejected_total.get(), rejected + 1);
+
+        let facilitators = "https://facilitator.test/breaker";
+        metrics.record_circuit_breaker_state(facilitators, 2);
+        assert_eq!(
+            metrics
+                .circuit_breaker_state
+                .with_label_values(&[facilitators])
+                .get(),
+            2
+        );
+    }
+
     #[test]
     fn test_collect_metrics() {
         let metrics = X402Metrics::get();
@@ -6,9 +6,29 @@
 //! # Features
 //!
 //! - ✅ **Payment Verification**: Validates X-PAYMENT headers against facilitator service
+//! - ✅ **Solana Payments**: `exact` payments on Solana, verified and settled by the facilitator
+//! - ✅ **Local Verification**: EIP-712 signatures checked in-process, without the facilitator
+//! - ✅ **Non-blocking Verification**: Facilitator calls never block the nginx worker
+//! - ✅ **Payment Settlement**: Settles verified payments before or after the upstream responds
+//! - ✅ **Verification Cache**: Repeated payments skip the facilitator round trip
+//! - ✅ **Prepaid Sessions**: One payment buys a token worth N requests or T seconds
+//! - ✅ **Access Passes**: Pay once for time-limited access to a path prefix
+//! - ✅ **Bypass Rules**: Trusted networks, shared-secret headers and API keys skip payment
+//! - ✅ **Replay Protection**: Reused payment authorizations are rejected across all workers
+//! - ✅ **Settlement Receipts**: `X-PAYMENT-RESPONSE` header
+//! - ✅ **Signed Receipts**: Ed25519/ES256 JWTs for the client and upstream, with a JWKS endpoint
+//! - ✅ **Upstream Headers**: Verified payer, amount and network forwarded as trusted headers
+//! - ✅ **Upstream Pricing**: Backends announce the price with a plain 402 response
+//! - ✅ **Variables**: `$x402_status`, `$x402_payer`, `$x402_amount`, etc. for logging and routing
 //! - ✅ **402 Response Generation**: Sends HTML paywall or JSON responses based on client type
+//! - ✅ **Response Negotiation**: Fixed or detected 402 format, with configurable browser detection rules
+//! - ✅ **Localized Messages**: Paywall and error messages in the client's `Accept-Language`
+//! - ✅ **Paywall Templates**: Per-location HTML paywall pages, checked by `nginx -t`
+//! - ✅ **Self-hosted Paywall Assets**: Paywall script and stylesheet served by the module, with a nonce-based CSP
 //! - ✅ **Configuration Parsing**: Full nginx configuration directive support
 //! - ✅ **Metrics**: Prometheus metrics endpoint for monitoring
+//! - ✅ **Facilitator Failover**: Weighted, prioritized facilitators with health tracking
+//! - ✅ **Retries and Circuit Breaker**: Jittered retries, fast fallback while facilitators fail
 //! - ✅ **Fallback Handling**: Configurable error handling (error/pass modes)
 //! - ✅ **Type Safety**: Full Rust type safety with ngx-rust bindings
 //!
@@ -16,44 +36,93 @@
 //!
 //! The module is organized into several submodules:
 //!
+//! - `access_pass`: Time-based access passes
+//! - `async_verify`: Non-blocking facilitator calls (event loop integration)
+//! - `bypass`: Payment bypass rules for trusted clients (CIDR ranges, secrets, API keys)
+//! - `circuit_breaker`: Circuit breaker for facilitator calls
 //! - `commands`: Nginx configuration directive handlers
 //! - `config`: Configuration parsing and validation
+//! - `ctx`: Per-request module context
+//! - `failover`: Facilitator selection, failover, retries and health tracking
+//! - `filter`: Response filters (post-response settlement, receipts, upstream 402 rewriting)
+//! - `forward`: Trusted `X-X402-*` request headers for the upstream
 //! - `handler`: Request processing and payment verification
+//! - `local_verify`: In-process verification of payment signatures
+//! - `messages`: Localized client-facing messages (`Accept-Language`)
+//! - `negotiation`: 402 response format and browser detection rules
+//! - `paywall`: Custom HTML paywall templates and self-hosted paywall assets
+//! - `receipt`: Signed JWT payment receipts and the JWKS endpoint
+//! - `replay`: Replay protection for payment authorizations
 //! - `response`: HTTP response generation (402, HTML, JSON)
 //! - `runtime`: Async runtime and facilitator client
+//! - `session`: Prepaid session tokens and balances
+//! - `settlement`: Payment settlement with the facilitator
+//! - `shm`: Hash tables in shared memory zones
+//! - `svm`: Solana payment payloads and facilitator calls
+//! - `upstream_pricing`: Rewriting upstream 402 price hints into payment requirements
+//! - `variables`: Nginx variables (`$x402_status`, `$x402_payer`, ...)
+//! - `verify_cache`: Shared cache of facilitator verification results
 //! - `metrics`: Prometheus metrics collection
 //! - `module`: Module registration and nginx integration
 
+pub mod access_pass;
+pub mod async_verify;
+pub mod bypass;
+pub mod circuit_breaker;
 pub mod commands;
 pub mod config;
+pub mod ctx;
 pub mod error;
+pub mod failover;
+pub mod filter;
+pub mod forward;
 pub mod handler;
+pub mod local_verify;
 pub mod logging;
+pub mod messages;
 pub mod metrics;
 pub mod module;
+pub mod negotiation;
 pub mod panic_handler;
+pub mod paywall;
+pub mod receipt;
+pub mod replay;
 pub mod request;
 pub mod requirements;
 pub mod response;
 pub mod runtime;
+pub mod session;
+pub mod settlement;
+pub mod shm;
+pub mod svm;
+pub mod upstream_pricing;
+pub mod variables;
+pub mod verify_cache;
 
 // Re-export public types and functions
-pub use config::{FacilitatorFallback, ParsedX402Config, X402Config};
+pub use async_verify::VerificationMode;
+pub use bypass::{request_bypass_reason, BypassReason};
+pub use config::{
+    AcceptOption, CircuitBreakerConfig, FacilitatorEndpoint, FacilitatorFallback,
+    FacilitatorPolicy, ParsedX402Config, RetryPolicy, SettleMode, VerifyMode, X402Config,
+};
 pub use error::{user_errors, ConfigError, Result};
 pub use handler::{
-    x402_handler_impl, x402_metrics_handler_impl, x402_ngx_handler_impl, HandlerResult,
+    x402_handler_impl, x402_jwks_handler_impl, x402_metrics_handler_impl, x402_ngx_handler_impl,
+    x402_paywall_assets_handler_impl, HandlerResult,
 };
 pub use logging::{log_debug, log_error, log_info, log_warn};
 pub use metrics::{collect_metrics, X402Metrics};
-pub use module::{get_module_config, ngx_http_x402_module};
+pub use module::{get_module_config, get_request_config, ngx_http_x402_module};
 pub use request::{
     get_header_value, get_http_method, is_browser_request, should_skip_payment_for_method,
+    wants_html_response,
 };
-pub use requirements::create_requirements;
+pub use requirements::{create_payment_options, create_requirements, select_requirements};
 pub use response::{send_402_response, send_response_body};
 pub use runtime::{
-    get_facilitator_client, get_runtime, verify_payment, DEFAULT_FACILITATOR_TIMEOUT,
-    FACILITATOR_CLIENTS, MAX_PAYMENT_HEADER_SIZE, RUNTIME,
+    get_facilitator_client, get_runtime, settle_payment, verify_payment,
+    DEFAULT_FACILITATOR_TIMEOUT, FACILITATOR_CLIENTS, MAX_PAYMENT_HEADER_SIZE, RUNTIME,
 };
 
 /// Metrics handler C export
@@ -91,6 +160,68 @@ pub unsafe extern "C" fn x402_metrics_handler(
     )
 }
 
+/// JWKS handler C export
+///
+/// Serves the public receipt keys (`x402_receipt_jwks`). Wraps
+/// `x402_jwks_handler_impl` like `x402_metrics_handler` wraps the metrics handler.
+///
+/// # Safety
+///
+/// The caller must ensure that `r` is a valid pointer to a `ngx_http_request_t`
+/// structure. The pointer must remain valid for the duration of this function call.
+#[no_mangle]
+pub unsafe extern "C" fn x402_jwks_handler(
+    r: *mut ngx::ffi::ngx_http_request_t,
+) -> ngx::ffi::ngx_int_t {
+    use crate::ngx_module::panic_handler::catch_panic_or_default;
+
+    if r.is_null() {
+        return ngx::ffi::NGX_ERROR as ngx::ffi::ngx_int_t;
+    }
+
+    catch_panic_or_default(
+        || {
+            let req_mut = ngx::http::Request::from_ngx_http_request(r);
+            match x402_jwks_handler_impl(req_mut) {
+                ngx::core::Status::NGX_OK => ngx::ffi::NGX_OK as ngx::ffi::ngx_int_t,
+                _ => ngx::ffi::NGX_ERROR as ngx::ffi::ngx_int_t,
+            }
+        },
+        "x402_jwks_handler",
+        ngx::ffi::NGX_ERROR as ngx::ffi::ngx_int_t,
+    )
+}
+
+/// Paywall assets handler C export
+///
+/// Serves the self-hosted paywall assets (`x402_paywall_assets`). Wraps
+/// `x402_paywall_assets_handler_impl` like `x402_metrics_handler` wraps the
+/// metrics handler; HTTP status codes (404, 304 via `send_header`) are passed through.
+///
+/// # Safety
+///
+/// The caller must ensure that `r` is a valid pointer to a `ngx_http_request_t`
+/// structure. The pointer must remain valid for the duration of this function call.
+#[no_mangle]
+pub unsafe extern "C" fn x402_paywall_assets_handler(
+    r: *mut ngx::ffi::ngx_http_request_t,
+) -> ngx::ffi::ngx_int_t {
+    use crate::ngx_module::panic_handler::catch_panic_or_default;
+
+    if r.is_null() {
+        return ngx::ffi::NGX_ERROR as ngx::ffi::ngx_int_t;
+    }
+
+    catch_panic_or_default(
+        || {
+            let req_mut = ngx::http::Request::from_ngx_http_request(r);
+            x402_paywall_assets_handler_impl(req_mut).0
+        },
+        "x402_paywall_assets_handler",
+        ngx::ffi::NGX_ERROR as ngx::ffi::ngx_int_t,
+    )
+}
+
 /// Clear x402 content handler if it's set
 ///
 /// This helper function clears the content handler if it's set to `x402_ngx_handler`.
@@ -137,6 +268,34 @@ fn clear_x402_content_handler(req: &mut ngx::http::Request, reason: &str) {
     }
 }
 
+/// Record `$x402_status = bypassed` for a request that skips payment verification
+///
+/// Only applies to locations where x402 is enabled, so unrelated locations don't
+/// get a module context and aren't counted in `x402_bypassed_requests_total`.
+/// With `x402_forward_headers`, `x402_upstream_pricing` or `x402_receipt_key`,
+/// client-supplied `X-X402-*` headers are removed as well, so a bypassed request
+/// can't pose as a verified one upstream.
+fn mark_payment_bypassed(req: &mut ngx::http::Request, reason: BypassReason) {
+    use crate::ngx_module::ctx::{update_request_ctx, PaymentStatus};
+    use crate::ngx_module::forward::strip_forward_headers;
+
+    let Ok(conf) = get_request_config(req) else {
+        return;
+    };
+    if conf.enabled == 0 {
+        return;
+    }
+
+    update_request_ctx(req, |ctx| ctx.status = PaymentStatus::Bypassed);
+    X402Metrics::get().record_bypassed_request(reason.as_str());
+    if conf
+        .parse()
+        .is_ok_and(|parsed| parsed.strips_forward_headers())
+    {
+        strip_forward_headers(req);
+    }
+}
+
 /// Phase handler for ACCESS phase
 ///
 /// This handler is registered as a phase handler in `ACCESS_PHASE` (before `CONTENT_PHASE`).
@@ -145,7 +304,8 @@ fn clear_x402_content_handler(req: &mut ngx::http::Request, reason: &str) {
 ///
 /// The handler:
 /// 1. Checks if the module is enabled for the current location
-/// 2. If enabled, performs payment verification
+/// 2. If enabled, starts payment verification in the background and returns `NGX_AGAIN`
+///    (the handler is called again once the facilitator has responded)
 /// 3. If payment is valid, returns `NGX_OK` to allow request to proceed
 /// 4. If payment is invalid or missing, sends 402 response and finalizes request
 ///
@@ -219,6 +379,7 @@ pub unsafe extern "C" fn x402_phase_handler(
                     req_mut,
                     &format!("for {} request to prevent payment verification", method),
                 );
+                mark_payment_bypassed(req_mut, BypassReason::Method);
                 return ngx::ffi::NGX_DECLINED as ngx::ffi::ngx_int_t;
             }
 
@@ -233,6 +394,7 @@ pub unsafe extern "C" fn x402_phase_handler(
                     req_mut,
                     "for WebSocket request to prevent payment verification",
                 );
+                mark_payment_bypassed(req_mut, BypassReason::WebSocket);
                 return ngx::ffi::NGX_DECLINED as ngx::ffi::ngx_int_t;
             }
 
@@ -293,10 +455,30 @@ pub unsafe extern "C" fn x402_phase_handler(
                 return ngx::ffi::NGX_DECLINED as ngx::ffi::ngx_int_t;
             }
 
+            // Trusted clients matching an x402_bypass rule (CIDR range, secret header, API key)
+            if let Some(reason) = request_bypass_reason(req_mut) {
+                log_debug(
+                    Some(req_mut),
+                    &format!(
+                        "[x402] Phase handler: x402_bypass {} rule matched, skipping payment verification",
+                        reason.as_str()
+                    ),
+                );
+                clear_x402_content_handler(
+                    req_mut,
+                    "for bypassed request to prevent payment verification",
+                );
+                mark_payment_bypassed(req_mut, reason);
+                return ngx::ffi::NGX_DECLINED as ngx::ffi::ngx_int_t;
+            }
+
             // Module is enabled - perform payment verification
             // This will verify payment and send 402 if needed, or allow request to proceed
+            // Verification is non-blocking here: the handler may return NGX_AGAIN and the
+            // request is resumed from the event loop once the facilitator responds
+            use crate::ngx_module::async_verify::VerificationMode;
             use crate::ngx_module::handler::HandlerResult;
-            let (status, result) = x402_ngx_handler_impl(req_mut);
+            let (status, result) = x402_ngx_handler_impl(req_mut, VerificationMode::NonBlocking);
             match (status, result) {
                 (ngx::core::Status::NGX_OK, HandlerResult::PaymentValid) => {
                     // Payment verified - allow request to continue
@@ -310,6 +492,11 @@ pub unsafe extern "C" fn x402_phase_handler(
                     // This will proceed to CONTENT_PHASE where proxy_pass handler will run (if set)
                     ngx::ffi::NGX_OK as ngx::ffi::ngx_int_t
                 }
+                (ngx::core::Status::NGX_AGAIN, HandlerResult::Pending) => {
+                    // Facilitator call in flight - suspend the phase engine for this request.
+                    // This handler is called again when the verification result is delivered.
+                    ngx::ffi::NGX_AGAIN as ngx::ffi::ngx_int_t
+                }
                 (ngx::core::Status::NGX_DECLINED, HandlerResult::ResponseSent) => {
                     // Response was sent (402 or error) - stop processing
                     // Return OK to indicate we handled the request and prevent further processing
@@ -365,7 +552,11 @@ pub unsafe extern "C" fn x402_ngx_handler(
     catch_panic_or_default(
         || {
             let req_mut = ngx::http::Request::from_ngx_http_request(r);
-            let (status, _result) = x402_ngx_handler_impl(req_mut);
+            // Content handlers cannot be suspended with NGX_AGAIN, so verify synchronously
+            let (status, _result) = x402_ngx_handler_impl(
+                req_mut,
+                crate::ngx_module::async_verify::VerificationMode::Blocking,
+            );
             match status {
                 ngx::core::Status::NGX_OK => ngx::ffi::NGX_OK as ngx::ffi::ngx_int_t,
                 ngx::core::Status::NGX_ERROR => ngx::ffi::NGX_ERROR as ngx::ffi::ngx_int_t,
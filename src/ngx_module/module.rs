@@ -1,10 +1,12 @@
 //! Module registration and configuration access
 
+use crate::ngx_module::commands::common::conf_server_identity;
 use crate::ngx_module::commands::ngx_http_x402_commands;
 use crate::ngx_module::config::X402Config;
 use crate::ngx_module::error::{ConfigError, Result};
 use crate::ngx_module::panic_handler::catch_panic;
-use ngx::core::NgxStr;
+use crate::ngx_module::receipt::ReceiptConfig;
+use ngx::core::{NgxStr, Pool};
 use ngx::ffi::{ngx_http_core_main_conf_t, ngx_str_t};
 use ngx::http::Request;
 use std::ffi::c_char;
@@ -30,6 +32,20 @@ macro_rules! merge_string_field {
     };
 }
 
+/// Macro to inherit a compiled complex value from previous config
+///
+/// Must run before the matching string field is merged: the compiled value is
+/// only inherited when the current level doesn't set the directive itself.
+/// Complex values are allocated from the cycle's configuration pool, so the
+/// pointer can be shared.
+macro_rules! merge_complex_value_field {
+    ($conf_mut:expr, $prev_conf:expr, $str_field:ident, $cv_field:ident) => {
+        if $conf_mut.$str_field.len == 0 && $conf_mut.$cv_field.is_none() {
+            $conf_mut.$cv_field = $prev_conf.$cv_field;
+        }
+    };
+}
+
 /// Helper function to copy a string from config to request pool
 ///
 /// This function safely copies a string field from configuration to the request's
@@ -157,9 +173,92 @@ fn clone_config_to_request_pool(req: &Request, src: &X402Config) -> Result<X402C
         timeout_str: safe_copy_field!(timeout_str),
         facilitator_fallback_str: safe_copy_field!(facilitator_fallback_str),
         ttl_str: safe_copy_field!(ttl_str),
+        settle_str: safe_copy_field!(settle_str),
+        forward_headers_str: safe_copy_field!(forward_headers_str),
+        accepts_str: safe_copy_field!(accepts_str),
+        upstream_pricing_str: safe_copy_field!(upstream_pricing_str),
+        verify_mode_str: safe_copy_field!(verify_mode_str),
+        retries_str: safe_copy_field!(retries_str),
+        circuit_breaker_str: safe_copy_field!(circuit_breaker_str),
+        fee_payer_str: safe_copy_field!(fee_payer_str),
+        session_purchase_str: safe_copy_field!(session_purchase_str),
+        receipt_ttl_str: safe_copy_field!(receipt_ttl_str),
+        paywall_assets_url_str: safe_copy_field!(paywall_assets_url_str),
+        response_format_str: safe_copy_field!(response_format_str),
+        amount_cv: src.amount_cv,
+        pay_to_cv: src.pay_to_cv,
+        description_cv: src.description_cv,
+        resource_cv: src.resource_cv,
+        replay_zone: src.replay_zone,
+        verify_cache_zone: src.verify_cache_zone,
+        verify_cache_negative_ttl: src.verify_cache_negative_ttl,
+        session: src.session,
+        paywall_template: src.paywall_template,
+        messages: src.messages,
+        browser_detection: src.browser_detection,
+        bypass: src.bypass,
+        receipt: src.receipt,
+        access_pass: src.access_pass,
     })
 }
 
+/// Evaluate one complex value into its string field
+///
+/// The value is allocated from the request pool by nginx. Directives without
+/// variables (no compiled value) are left unchanged.
+fn evaluate_complex_value(
+    req: &Request,
+    cv: Option<ptr::NonNull<ngx::ffi::ngx_http_complex_value_t>>,
+    directive: &str,
+    field: &mut ngx_str_t,
+) -> Result<()> {
+    let Some(cv) = cv else {
+        return Ok(());
+    };
+
+    // Safe: compiled in the directive handler from the configuration pool
+    let value = req
+        .get_complex_value(unsafe { cv.as_ref() })
+        .ok_or_else(|| ConfigError::from(format!("Failed to evaluate {directive}")))?;
+    let bytes = value.as_bytes();
+    *field = ngx_str_t {
+        len: bytes.len(),
+        data: bytes.as_ptr().cast_mut(),
+    };
+    Ok(())
+}
+
+/// Get module configuration for a request, with variables evaluated
+///
+/// `x402_amount`, `x402_pay_to`, `x402_description` and `x402_resource` may
+/// reference nginx variables (e.g. `x402_amount $price_from_map;`). Their values
+/// are evaluated for this request, so the returned configuration can be parsed
+/// and validated like a static one.
+///
+/// # Errors
+/// - Returns error if the configuration cannot be retrieved
+/// - Returns error if a complex value cannot be evaluated
+pub fn get_request_config(req: &Request) -> Result<X402Config> {
+    let mut conf = get_module_config(req)?;
+
+    evaluate_complex_value(req, conf.amount_cv, "x402_amount", &mut conf.amount_str)?;
+    evaluate_complex_value(req, conf.pay_to_cv, "x402_pay_to", &mut conf.pay_to_str)?;
+    evaluate_complex_value(
+        req,
+        conf.description_cv,
+        "x402_description",
+        &mut conf.description_str,
+    )?;
+    evaluate_complex_value(
+        req,
+        conf.resource_cv,
+        "x402_resource",
+        &mut conf.resource_str,
+    )?;
+
+    Ok(conf)
+}
+
 /// Helper function to get `ngx_http_core_main_conf_t` from `ngx_conf_t`
 ///
 /// This is equivalent to `ngx_http_conf_get_module_main_conf(cf`, `ngx_http_core_module`)
@@ -223,10 +322,26 @@ unsafe fn get_core_main_conf(
     .flatten()
 }
 
+/// Preconfiguration hook
+///
+/// Called before the `http` block is parsed. Variables must be registered here
+/// so they can be referenced by directives such as `log_format`. Networks defined
+/// by a previous configuration are dropped, so a reload starts from the built-in ones.
+unsafe extern "C" fn preconfiguration(cf: *mut ngx::ffi::ngx_conf_t) -> ngx::ffi::ngx_int_t {
+    if cf.is_null() {
+        return ngx::ffi::NGX_ERROR as ngx::ffi::ngx_int_t;
+    }
+
+    crate::config::reset_networks();
+
+    crate::ngx_module::variables::register_variables(cf)
+}
+
 /// Postconfiguration hook
 ///
 /// This is called after all configuration is parsed.
-/// We use this to register phase handler as a fallback if clcf->handler is not set.
+/// We use this to register phase handler as a fallback if clcf->handler is not set,
+/// and to install the response header filter used for settlement.
 ///
 /// NOTE: We cannot verify handler settings here because we don't have access to
 /// individual location configurations. Handler verification happens in the command
@@ -266,6 +381,11 @@ unsafe extern "C" fn postconfiguration(cf: *mut ngx::ffi::ngx_conf_t) -> ngx::ff
         return ngx::ffi::NGX_ERROR as ngx::ffi::ngx_int_t;
     }
 
+    // Install response filters used to settle payments once the response status is known
+    // and to rewrite upstream 402 responses (x402_upstream_pricing)
+    crate::ngx_module::filter::init_header_filter();
+    crate::ngx_module::filter::init_body_filter();
+
     ngx::ffi::NGX_OK as ngx::ffi::ngx_int_t
 }
 
@@ -279,7 +399,7 @@ unsafe extern "C" fn postconfiguration(cf: *mut ngx::ffi::ngx_conf_t) -> ngx::ff
 /// compatibility with nginx's module system.
 #[allow(non_upper_case_globals)] // Required by nginx C API naming conventions
 static mut ngx_http_x402_module_ctx: ngx::ffi::ngx_http_module_t = ngx::ffi::ngx_http_module_t {
-    preconfiguration: None,
+    preconfiguration: Some(preconfiguration),
     postconfiguration: Some(postconfiguration),
     create_main_conf: None,
     init_main_conf: None,
@@ -341,6 +461,39 @@ unsafe extern "C" fn merge_loc_conf(
     // Merge string fields: use current if non-empty, otherwise copy from previous using current pool
     // IMPORTANT: We must copy strings to the current configuration pool instead of copying pointers
     // because prev_conf may use a different memory pool that could be freed, causing segfaults
+    // Inherit compiled complex values along with their source strings
+    merge_complex_value_field!(conf_mut, prev_conf, amount_str, amount_cv);
+    merge_complex_value_field!(conf_mut, prev_conf, pay_to_str, pay_to_cv);
+    merge_complex_value_field!(conf_mut, prev_conf, description_str, description_cv);
+    merge_complex_value_field!(conf_mut, prev_conf, resource_str, resource_cv);
+
+    // Shared memory zones belong to the cycle, so the pointer can be inherited
+    if conf_mut.replay_zone.is_none() {
+        conf_mut.replay_zone = prev_conf.replay_zone;
+    }
+    if conf_mut.verify_cache_zone.is_none() {
+        conf_mut.verify_cache_zone = prev_conf.verify_cache_zone;
+        conf_mut.verify_cache_negative_ttl = prev_conf.verify_cache_negative_ttl;
+    }
+    if conf_mut.session.is_none() {
+        conf_mut.session = prev_conf.session;
+    }
+    // The parsed paywall template is allocated from the cycle's configuration pool
+    if conf_mut.paywall_template.is_none() {
+        conf_mut.paywall_template = prev_conf.paywall_template;
+    }
+    // Like the template; a location with its own x402_messages doesn't inherit any
+    if conf_mut.messages.is_none() {
+        conf_mut.messages = prev_conf.messages;
+    }
+    if conf_mut.browser_detection.is_none() {
+        conf_mut.browser_detection = prev_conf.browser_detection;
+    }
+    // Like x402_messages, a block with its own x402_bypass rules doesn't inherit any
+    if conf_mut.bypass.is_none() {
+        conf_mut.bypass = prev_conf.bypass;
+    }
+
     merge_string_field!(cf, conf_mut, prev_conf, amount_str);
     merge_string_field!(cf, conf_mut, prev_conf, pay_to_str);
     merge_string_field!(cf, conf_mut, prev_conf, facilitator_url_str);
@@ -353,6 +506,68 @@ unsafe extern "C" fn merge_loc_conf(
     merge_string_field!(cf, conf_mut, prev_conf, timeout_str);
     merge_string_field!(cf, conf_mut, prev_conf, facilitator_fallback_str);
     merge_string_field!(cf, conf_mut, prev_conf, ttl_str);
+    merge_string_field!(cf, conf_mut, prev_conf, settle_str);
+    merge_string_field!(cf, conf_mut, prev_conf, forward_headers_str);
+    merge_string_field!(cf, conf_mut, prev_conf, accepts_str);
+    merge_string_field!(cf, conf_mut, prev_conf, upstream_pricing_str);
+    merge_string_field!(cf, conf_mut, prev_conf, verify_mode_str);
+    merge_string_field!(cf, conf_mut, prev_conf, retries_str);
+    merge_string_field!(cf, conf_mut, prev_conf, circuit_breaker_str);
+    merge_string_field!(cf, conf_mut, prev_conf, fee_payer_str);
+    merge_string_field!(cf, conf_mut, prev_conf, session_purchase_str);
+    merge_string_field!(cf, conf_mut, prev_conf, receipt_ttl_str);
+    merge_string_field!(cf, conf_mut, prev_conf, paywall_assets_url_str);
+    merge_string_field!(cf, conf_mut, prev_conf, response_format_str);
+
+    // Like x402_messages, a block with its own x402_receipt_key keys doesn't
+    // inherit any. x402_receipt_ttl may be set at another level than the keys:
+    // a block with another lifetime gets its own copy
+    if conf_mut.receipt.is_none() {
+        conf_mut.receipt = prev_conf.receipt;
+    }
+    if let Some(receipt) = conf_mut.receipt {
+        let ttl = match conf_mut.receipt_ttl() {
+            Ok(ttl) => ttl,
+            Err(e) => {
+                ngx::ngx_conf_log_error!(ngx::ffi::NGX_LOG_EMERG, cf, "x402: {}", e);
+                return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+            }
+        };
+        if receipt.as_ref().ttl != ttl {
+            let receipt = ReceiptConfig {
+                keys: receipt.as_ref().keys.clone(),
+                ttl,
+            };
+            let pool = Pool::from_ngx_pool((*cf).pool);
+            match ptr::NonNull::new(pool.allocate(receipt)) {
+                Some(receipt) => conf_mut.receipt = Some(receipt),
+                None => return ngx::core::NGX_CONF_ERROR.cast::<c_char>(),
+            }
+        }
+    }
+
+    // Passes are signed for one server: inherited settings, and settings of the
+    // server block itself, get their own key in each server
+    if conf_mut.access_pass.is_none() {
+        conf_mut.access_pass = prev_conf.access_pass;
+    }
+    if let Some(access_pass) = conf_mut.access_pass {
+        let server = conf_server_identity(cf);
+        if access_pass.as_ref().server.as_deref() != Some(server.as_str()) {
+            let pool = Pool::from_ngx_pool((*cf).pool);
+            match ptr::NonNull::new(pool.allocate(access_pass.as_ref().for_server(&server))) {
+                Some(access_pass) => conf_mut.access_pass = Some(access_pass),
+                None => return ngx::core::NGX_CONF_ERROR.cast::<c_char>(),
+            }
+        }
+    }
+
+    // Directives may be set at different levels, so combinations can only be
+    // checked once the block is merged
+    if let Err(e) = conf_mut.validate() {
+        ngx::ngx_conf_log_error!(ngx::ffi::NGX_LOG_EMERG, cf, "x402: {}", e);
+        return ngx::core::NGX_CONF_ERROR.cast::<c_char>();
+    }
 
     // Note: Handler is set in ngx_http_x402 command handler when x402 on; is parsed
     // We cannot set handler here in merge_loc_conf because accessing clcf during merging
@@ -0,0 +1,314 @@
+//! Response format negotiation
+//!
+//! 402 responses are an HTML paywall for browsers and JSON payment requirements
+//! for API clients. `x402_response_format` fixes the format of a location
+//! (`json` or `html`); with `auto` (the default) it is detected from the
+//! request, using the rules of `x402_browser_detection`:
+//!
+//! 1. The query argument named by `format_arg=` (`?format=json` or `?format=html`)
+//! 2. `api_ua=` regexes matching the User-Agent (JSON), then `browser_ua=` regexes (HTML)
+//! 3. `Content-Type: application/json` (JSON)
+//! 4. The `Accept` header: `text/html` with a quality above `html_q=` (HTML), or
+//!    `application/json` above `json_q=` with `text/html` below 0.3 (JSON)
+//! 5. A browser User-Agent (not a known API client) accepting HTML, or a form
+//!    submission (HTML); anything else gets JSON
+//!
+//! # Example
+//! ```nginx
+//! x402_browser_detection api_ua=Electron api_ua=HeadlessChrome format_arg=format;
+//! ```
+
+use crate::ngx_module::error::{ConfigError, Result};
+use regex::{Regex, RegexBuilder};
+use std::ptr::NonNull;
+
+/// Default quality above which `text/html` makes a request a browser request
+pub const DEFAULT_HTML_Q: f64 = 0.5;
+
+/// Default quality above which `application/json` makes a request an API request
+pub const DEFAULT_JSON_Q: f64 = 0.5;
+
+/// `Accept` quality of `text/html` below which `json_q` applies
+const HTML_Q_LOW: f64 = 0.3;
+
+/// Format of 402 responses (`x402_response_format`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResponseFormat {
+    /// Detected from the request (default)
+    #[default]
+    Auto,
+    /// Always JSON payment requirements
+    Json,
+    /// Always the HTML paywall
+    Html,
+}
+
+impl ResponseFormat {
+    /// Parse an `x402_response_format` value
+    ///
+    /// # Errors
+    /// - Returns error if the value is not `auto`, `json` or `html`
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "auto" => Ok(ResponseFormat::Auto),
+            "json" => Ok(ResponseFormat::Json),
+            "html" => Ok(ResponseFormat::Html),
+            _ => Err(ConfigError::from(
+                "Invalid response_format value. Must be 'auto', 'json' or 'html'",
+            )),
+        }
+    }
+
+    /// `Vary` header of 402 responses
+    ///
+    /// Lists the request headers the body depends on, so caches don't serve
+    /// the paywall to API clients or the other way around.
+    #[must_use]
+    pub fn vary(self) -> &'static str {
+        match self {
+            ResponseFormat::Auto => "Accept, Accept-Language, Content-Type, User-Agent",
+            ResponseFormat::Json | ResponseFormat::Html => "Accept-Language",
+        }
+    }
+}
+
+/// Browser detection rules (`x402_browser_detection`)
+#[derive(Debug, Clone)]
+pub struct BrowserDetection {
+    /// User-Agents of browsers (case-insensitive)
+    pub browser_ua: Vec<Regex>,
+    /// User-Agents of API clients (case-insensitive), checked first
+    pub api_ua: Vec<Regex>,
+    /// `Accept` quality above which `text/html` means a browser
+    pub html_q: f64,
+    /// `Accept` quality above which `application/json` means an API client
+    pub json_q: f64,
+    /// Query argument overriding the format (None: no override)
+    pub format_arg: Option<String>,
+}
+
+impl Default for BrowserDetection {
+    fn default() -> Self {
+        BrowserDetection {
+            browser_ua: Vec::new(),
+            api_ua: Vec::new(),
+            html_q: DEFAULT_HTML_Q,
+            json_q: DEFAULT_JSON_Q,
+            format_arg: None,
+        }
+    }
+}
+
+impl BrowserDetection {
+    /// Parse `x402_browser_detection` arguments
+    ///
+    /// - `browser_ua=<regex>`, `api_ua=<regex>` (repeatable)
+    /// - `html_q=<quality>`, `json_q=<quality>` (0 to 1)
+    /// - `format_arg=<name>`
+    ///
+    /// # Errors
+    /// - Returns error if a parameter is unknown, duplicated or invalid
+    pub fn parse(args: &[&str]) -> Result<Self> {
+        let mut rules = BrowserDetection::default();
+        let (mut html_q, mut json_q) = (None, None);
+        for arg in args {
+            let (name, value) = arg
+                .split_once('=')
+                .ok_or_else(|| ConfigError::from(format!("Invalid parameter \"{arg}\"")))?;
+            match name {
+                "browser_ua" => rules.browser_ua.push(compile_ua(value)?),
+                "api_ua" => rules.api_ua.push(compile_ua(value)?),
+                "html_q" | "json_q" => {
+                    let quality = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|q| (0.0..=1.0).contains(q))
+                        .ok_or_else(|| {
+                            ConfigError::from(format!(
+                                "Invalid {name} \"{value}\", expected a quality from 0 to 1"
+                            ))
+                        })?;
+                    let slot = if name == "html_q" {
+                        &mut html_q
+                    } else {
+                        &mut json_q
+                    };
+                    if slot.replace(quality).is_some() {
+                        return Err(ConfigError::from(format!("Duplicate parameter \"{name}\"")));
+                    }
+                }
+                "format_arg" => {
+                    if rules.format_arg.is_some() {
+                        return Err(ConfigError::from("Duplicate parameter \"format_arg\""));
+                    }
+                    let valid = !value.is_empty()
+                        && value
+                            .bytes()
+                            .all(|b| b.is_ascii_alphanumeric() || b"-_".contains(&b));
+                    if !valid {
+                        return Err(ConfigError::from(format!("Invalid format_arg \"{value}\"")));
+                    }
+                    rules.format_arg = Some(value.to_string());
+                }
+                _ => return Err(ConfigError::from(format!("Unknown parameter \"{name}\""))),
+            }
+        }
+        rules.html_q = html_q.unwrap_or(DEFAULT_HTML_Q);
+        rules.json_q = json_q.unwrap_or(DEFAULT_JSON_Q);
+        Ok(rules)
+    }
+}
+
+/// Compile a User-Agent regex
+fn compile_ua(pattern: &str) -> Result<Regex> {
+    if pattern.is_empty() {
+        return Err(ConfigError::from("Empty User-Agent regex"));
+    }
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| ConfigError::from(format!("Invalid User-Agent regex \"{pattern}\": {e}")))
+}
+
+/// Rules parsed at configuration time
+///
+/// The rules are allocated from the configuration pool, so regexes are compiled
+/// once and shared by all requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfiguredBrowserDetection {
+    rules: NonNull<BrowserDetection>,
+}
+
+impl ConfiguredBrowserDetection {
+    /// Wrap rules allocated by the `x402_browser_detection` directive
+    #[must_use]
+    pub fn new(rules: NonNull<BrowserDetection>) -> Self {
+        Self { rules }
+    }
+
+    /// The parsed rules
+    #[must_use]
+    pub fn get(&self) -> &BrowserDetection {
+        // Safe: the rules live as long as the configuration cycle
+        unsafe { self.rules.as_ref() }
+    }
+}
+
+/// Request headers and query string used to pick the response format
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientHints {
+    pub user_agent: Option<String>,
+    pub accept: Option<String>,
+    pub content_type: Option<String>,
+    /// Whether the request has an `Upgrade` header
+    pub upgrade: bool,
+    /// Query string (without `?`)
+    pub args: Option<String>,
+}
+
+/// Value of a query argument
+fn query_arg<'a>(args: &'a str, name: &str) -> Option<&'a str> {
+    args.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Whether a request gets the HTML paywall
+#[must_use]
+pub fn wants_html(format: ResponseFormat, rules: &BrowserDetection, hints: &ClientHints) -> bool {
+    match format {
+        ResponseFormat::Json => return false,
+        ResponseFormat::Html => return true,
+        ResponseFormat::Auto => {}
+    }
+
+    // 1. Explicit override in the query string
+    if let (Some(name), Some(args)) = (&rules.format_arg, &hints.args) {
+        match query_arg(args, name)
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            Some("html") => return true,
+            Some("json") => return false,
+            _ => {}
+        }
+    }
+
+    // 2. Configured User-Agents, API clients first
+    if let Some(ref ua) = hints.user_agent {
+        if rules.api_ua.iter().any(|regex| regex.is_match(ua)) {
+            return false;
+        }
+        if rules.browser_ua.iter().any(|regex| regex.is_match(ua)) {
+            return true;
+        }
+    }
+
+    // 3. A JSON request body is sent by API clients
+    let content_type = hints.content_type.as_deref().map(str::to_lowercase);
+    if content_type
+        .as_deref()
+        .is_some_and(|ct| ct.starts_with("application/json"))
+    {
+        return false;
+    }
+
+    // 4. Accept header with q-values
+    if let Some(ref accept) = hints.accept {
+        let html_priority = crate::config::parse_accept_priority(accept, "text/html");
+        let json_priority = crate::config::parse_accept_priority(accept, "application/json");
+        if html_priority > rules.html_q {
+            return true;
+        }
+        if json_priority > rules.json_q && html_priority < HTML_Q_LOW {
+            return false;
+        }
+    }
+
+    // 5. Browser User-Agent (not an API client) or browser form submission
+    let has_browser_ua = hints
+        .user_agent
+        .as_deref()
+        .is_some_and(is_builtin_browser_ua);
+    let is_browser_content_type = content_type.as_deref().is_some_and(|ct| {
+        ct.starts_with("multipart/form-data") || ct.starts_with("application/x-www-form-urlencoded")
+    });
+
+    is_browser_content_type
+        || (has_browser_ua
+            && (hints.upgrade
+                || hints.accept.as_deref().is_none_or(|accept| {
+                    crate::config::parse_accept_priority(accept, "text/html") > 0.0
+                })))
+}
+
+/// Built-in check for a browser User-Agent that isn't a known API client
+fn is_builtin_browser_ua(ua: &str) -> bool {
+    let ua_lower = ua.to_lowercase();
+
+    // Check for browser identifiers
+    let has_browser = ua_lower.contains("mozilla")
+        && (ua_lower.contains("chrome")
+            || ua_lower.contains("safari")
+            || ua_lower.contains("firefox")
+            || ua_lower.contains("edge")
+            || ua_lower.contains("opera")
+            || ua_lower.contains("brave")
+            || ua_lower.contains("webkit"));
+
+    // Exclude common API clients
+    let is_api_client = ua_lower.contains("curl")
+        || ua_lower.contains("wget")
+        || ua_lower.contains("python-requests")
+        || ua_lower.contains("go-http-client")
+        || ua_lower.contains("java/")
+        || ua_lower.contains("okhttp")
+        || ua_lower.contains("httpie")
+        || ua_lower.contains("postman")
+        || ua_lower.contains("insomnia")
+        || ua_lower.starts_with("rest-client")
+        || ua_lower.starts_with("http");
+
+    has_browser && !is_api_client
+}
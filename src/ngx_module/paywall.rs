@@ -0,0 +1,506 @@
+//! Custom HTML paywall templates
+//!
+//! `x402_paywall_template` replaces the generic paywall page rendered by
+//! `rust_x402` with a site-specific HTML file. The file is read and parsed while
+//! nginx reads its configuration, so a broken template fails `nginx -t` instead
+//! of the first paid request.
+//!
+//! Templates use `{{name}}` placeholders:
+//!
+//! - `{{amount}}`: Price in token units of the first payment option (e.g. `0.01`)
+//! - `{{asset}}`: Token address of the first payment option
+//! - `{{asset_symbol}}`: `USDC` for the network's USDC, the token address otherwise
+//! - `{{network}}`: Network of the first payment option (e.g. `base`)
+//! - `{{description}}`: Description of the first payment option
+//! - `{{resource}}`: Resource URL
+//! - `{{error}}`: Error message of the 402 response (may be empty), in the
+//!   client's language
+//! - `{{lang}}`: Language of the error message (e.g. `fr`), see `messages`
+//! - `{{requirements}}`: The JSON payment requirements response (`x402Version`,
+//!   `error`, `accepts`), safe to embed in a `<script>` element
+//!
+//! All other values are HTML-escaped.
+//!
+//! # Self-hosted assets
+//!
+//! The page rendered by `rust_x402` inlines its scripts and styles, which a
+//! strict `Content-Security-Policy` blocks. With `x402_paywall_assets_url`, the
+//! module renders its own page instead: it references `paywall.css` and
+//! `paywall.js`, bundled into the module and served by a location with
+//! `x402_paywall_assets on`, and is sent with a CSP that only allows elements
+//! carrying the response's nonce.
+
+use crate::ngx_module::error::{ConfigError, Result};
+use crate::ngx_module::messages::{Message, Messages, DEFAULT_LANGUAGE};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rust_decimal::Decimal;
+use rust_x402::types::{PaymentRequirements, PaymentRequirementsResponse};
+use sha2::{Digest, Sha256};
+use std::ptr::NonNull;
+
+/// Value inserted by a template placeholder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    Amount,
+    Asset,
+    AssetSymbol,
+    Network,
+    Description,
+    Resource,
+    Error,
+    Lang,
+    Requirements,
+}
+
+impl Placeholder {
+    /// Placeholder for a name between `{{` and `}}`
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "amount" => Some(Placeholder::Amount),
+            "asset" => Some(Placeholder::Asset),
+            "asset_symbol" => Some(Placeholder::AssetSymbol),
+            "network" => Some(Placeholder::Network),
+            "description" => Some(Placeholder::Description),
+            "resource" => Some(Placeholder::Resource),
+            "error" => Some(Placeholder::Error),
+            "lang" => Some(Placeholder::Lang),
+            "requirements" => Some(Placeholder::Requirements),
+            _ => None,
+        }
+    }
+}
+
+/// Part of a parsed template
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+/// Parsed paywall template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaywallTemplate {
+    segments: Vec<Segment>,
+}
+
+impl PaywallTemplate {
+    /// Parse a template
+    ///
+    /// # Errors
+    /// - Returns error if a placeholder is not closed or unknown (with its line number)
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            let offset = source.len() - rest.len() + start;
+            let line = source[..offset].matches('\n').count() + 1;
+            let after = &rest[start + 2..];
+            let end = after.find("}}").ok_or_else(|| {
+                ConfigError::from(format!(
+                    "Unclosed placeholder in paywall template on line {line}"
+                ))
+            })?;
+            let name = after[..end].trim();
+            let placeholder = Placeholder::from_name(name).ok_or_else(|| {
+                ConfigError::from(format!(
+                    "Unknown placeholder '{{{{{name}}}}}' in paywall template on line {line}"
+                ))
+            })?;
+            segments.push(Segment::Placeholder(placeholder));
+            rest = &after[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+        Ok(PaywallTemplate { segments })
+    }
+
+    /// Placeholders used by the template, in order
+    pub fn placeholders(&self) -> impl Iterator<Item = Placeholder> + '_ {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Placeholder(placeholder) => Some(*placeholder),
+            Segment::Text(_) => None,
+        })
+    }
+
+    /// Render the template
+    #[must_use]
+    pub fn render(&self, values: &PaywallValues) -> String {
+        let mut html = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => html.push_str(text),
+                Segment::Placeholder(Placeholder::Requirements) => {
+                    html.push_str(&escape_script_json(&values.requirements));
+                }
+                Segment::Placeholder(placeholder) => {
+                    html.push_str(&escape_html(values.text(*placeholder)));
+                }
+            }
+        }
+        html
+    }
+}
+
+/// Template parsed at configuration time
+///
+/// The template is allocated from the configuration pool, so it lives as long
+/// as the configuration cycle and can be shared by all requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfiguredPaywallTemplate {
+    template: NonNull<PaywallTemplate>,
+}
+
+impl ConfiguredPaywallTemplate {
+    /// Wrap a template allocated by the `x402_paywall_template` directive
+    #[must_use]
+    pub fn new(template: NonNull<PaywallTemplate>) -> Self {
+        Self { template }
+    }
+
+    /// The parsed template
+    #[must_use]
+    pub fn get(&self) -> &PaywallTemplate {
+        // Safe: the template lives as long as the configuration cycle
+        unsafe { self.template.as_ref() }
+    }
+}
+
+/// Values of the template placeholders for one 402 response
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PaywallValues {
+    pub amount: String,
+    pub asset: String,
+    pub asset_symbol: String,
+    pub network: String,
+    pub description: String,
+    pub resource: String,
+    pub error: String,
+    /// Language of the messages (e.g. `en`)
+    pub lang: String,
+    /// JSON payment requirements response
+    pub requirements: String,
+}
+
+impl PaywallValues {
+    /// Values for a 402 response offering `requirements`
+    ///
+    /// `decimals` are the token decimals of the first payment option, used to
+    /// show its amount in token units.
+    ///
+    /// # Errors
+    /// - Returns error if the requirements can't be serialized
+    pub fn new(requirements: &[PaymentRequirements], error: &str, decimals: u8) -> Result<Self> {
+        let response = PaymentRequirementsResponse::new(error, requirements.to_vec());
+        let json = serde_json::to_string(&response)
+            .map_err(|_| ConfigError::from("Failed to serialize response"))?;
+        let mut values = PaywallValues {
+            error: error.to_string(),
+            lang: DEFAULT_LANGUAGE.to_string(),
+            requirements: json,
+            ..PaywallValues::default()
+        };
+        if let Some(first) = requirements.first() {
+            values.amount = token_amount(&first.max_amount_required, decimals);
+            values.asset = first.asset.clone();
+            values.asset_symbol = asset_symbol(&first.network, &first.asset);
+            values.network = first.network.clone();
+            values.description = first.description.clone();
+            values.resource = first.resource.clone();
+        }
+        Ok(values)
+    }
+
+    /// Text inserted for a placeholder (before HTML escaping)
+    fn text(&self, placeholder: Placeholder) -> &str {
+        match placeholder {
+            Placeholder::Amount => &self.amount,
+            Placeholder::Asset => &self.asset,
+            Placeholder::AssetSymbol => &self.asset_symbol,
+            Placeholder::Network => &self.network,
+            Placeholder::Description => &self.description,
+            Placeholder::Resource => &self.resource,
+            Placeholder::Error => &self.error,
+            Placeholder::Lang => &self.lang,
+            Placeholder::Requirements => &self.requirements,
+        }
+    }
+}
+
+/// Amount in token units for an amount in the token's smallest unit
+///
+/// Amounts that don't fit a decimal are shown in the smallest unit.
+#[must_use]
+pub fn token_amount(atomic: &str, decimals: u8) -> String {
+    atomic
+        .parse::<i128>()
+        .ok()
+        .and_then(|atomic| Decimal::try_from_i128_with_scale(atomic, u32::from(decimals)).ok())
+        .map_or_else(
+            || atomic.to_string(),
+            |amount| amount.normalize().to_string(),
+        )
+}
+
+/// Symbol shown for an asset: `USDC` for the network's USDC, the address otherwise
+#[must_use]
+pub fn asset_symbol(network: &str, asset: &str) -> String {
+    let is_usdc = crate::config::network_by_name(network)
+        .and_then(|network| network.usdc)
+        .is_some_and(|usdc| usdc.eq_ignore_ascii_case(asset));
+    if is_usdc {
+        "USDC".to_string()
+    } else {
+        asset.to_string()
+    }
+}
+
+/// Escape text for HTML element content and quoted attribute values
+#[must_use]
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape JSON for a `<script>` element, so it can't close the element
+#[must_use]
+pub fn escape_script_json(json: &str) -> String {
+    json.replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026")
+        .replace('\u{2028}', "\\u2028")
+        .replace('\u{2029}', "\\u2029")
+}
+
+/// File bundled into the module and served by `x402_paywall_assets`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaywallAsset {
+    /// File name (last segment of the request path)
+    pub name: &'static str,
+    /// `Content-Type` of the file
+    pub content_type: &'static str,
+    /// File content
+    pub body: &'static [u8],
+}
+
+impl PaywallAsset {
+    /// Content version: first 16 hex digits of the SHA-256 of the content
+    #[must_use]
+    pub fn version(&self) -> String {
+        hex::encode(&Sha256::digest(self.body)[..8])
+    }
+
+    /// Strong `ETag` of the content
+    #[must_use]
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.version())
+    }
+}
+
+/// Paywall page stylesheet
+pub const PAYWALL_CSS: PaywallAsset = PaywallAsset {
+    name: "paywall.css",
+    content_type: "text/css; charset=utf-8",
+    body: include_bytes!("assets/paywall.css"),
+};
+
+/// Paywall page script
+pub const PAYWALL_JS: PaywallAsset = PaywallAsset {
+    name: "paywall.js",
+    content_type: "application/javascript; charset=utf-8",
+    body: include_bytes!("assets/paywall.js"),
+};
+
+/// Find a bundled asset by file name
+#[must_use]
+pub fn find_paywall_asset(name: &str) -> Option<PaywallAsset> {
+    [PAYWALL_CSS, PAYWALL_JS]
+        .into_iter()
+        .find(|asset| asset.name == name)
+}
+
+/// Check an `If-None-Match` header value against an `ETag`
+///
+/// Uses the weak comparison required for `If-None-Match`.
+#[must_use]
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+/// Check an `x402_paywall_assets_url` value: a path or an `https://` URL ending with `/`
+#[must_use]
+pub fn is_valid_assets_url(url: &str) -> bool {
+    (url.starts_with('/') || url.starts_with("https://"))
+        && url.ends_with('/')
+        && !url
+            .bytes()
+            .any(|b| b.is_ascii_whitespace() || b.is_ascii_control() || b"\"'<>".contains(&b))
+}
+
+/// Generate a random CSP nonce
+///
+/// # Errors
+/// - Returns error if no random bytes are available
+pub fn generate_csp_nonce() -> Result<String> {
+    let mut nonce = [0u8; 16];
+    getrandom::getrandom(&mut nonce)
+        .map_err(|e| ConfigError::from(format!("Failed to generate CSP nonce: {e}")))?;
+    Ok(STANDARD.encode(nonce))
+}
+
+/// `Content-Security-Policy` of the self-hosted paywall page
+#[must_use]
+pub fn content_security_policy(nonce: &str) -> String {
+    format!(
+        "default-src 'none'; script-src 'nonce-{nonce}'; style-src 'nonce-{nonce}'; \
+         img-src 'self' data:; base-uri 'none'; form-action 'none'; frame-ancestors 'none'"
+    )
+}
+
+/// Render the self-hosted paywall page
+///
+/// The page loads `paywall.css` and `paywall.js` from `assets_url`; both tags
+/// carry `nonce`, which must match the response's `Content-Security-Policy`.
+#[must_use]
+pub fn render_hosted_paywall(
+    values: &PaywallValues,
+    messages: &Messages,
+    assets_url: &str,
+    nonce: &str,
+) -> String {
+    let assets_url = escape_html(assets_url);
+    let nonce = escape_html(nonce);
+    render_page(
+        values,
+        messages,
+        &format!(
+            r#"<link rel="stylesheet" href="{assets_url}paywall.css?v={}" nonce="{nonce}">"#,
+            PAYWALL_CSS.version()
+        ),
+        &format!(r#" nonce="{nonce}""#),
+        &format!(
+            r#"<script src="{assets_url}paywall.js?v={}" nonce="{nonce}"></script>"#,
+            PAYWALL_JS.version()
+        ),
+    )
+}
+
+/// Render the module's paywall page with its script and stylesheet inlined
+///
+/// Used for languages other than English, which the `rust_x402` page doesn't
+/// support.
+#[must_use]
+pub fn render_inline_paywall(values: &PaywallValues, messages: &Messages) -> String {
+    render_page(
+        values,
+        messages,
+        &format!(
+            "<style>\n{}</style>",
+            String::from_utf8_lossy(PAYWALL_CSS.body)
+        ),
+        "",
+        &format!(
+            "<script>\n{}</script>",
+            String::from_utf8_lossy(PAYWALL_JS.body)
+        ),
+    )
+}
+
+/// Render the module's paywall page
+///
+/// `stylesheet` and `script` are the elements including the assets,
+/// `requirements_attributes` the extra attributes of the requirements element.
+fn render_page(
+    values: &PaywallValues,
+    messages: &Messages,
+    stylesheet: &str,
+    requirements_attributes: &str,
+    script: &str,
+) -> String {
+    let text = |message| escape_html(messages.get(message));
+    let subtitle = if values.description.is_empty() {
+        text(Message::PaymentRequiredSubtitle)
+    } else {
+        escape_html(&values.description)
+    };
+    let details = if values.network.is_empty() {
+        String::new()
+    } else {
+        format!(
+            r#"
+    <dl class="payment-info">
+      <dt>{}</dt><dd>{} {}</dd>
+      <dt>{}</dt><dd>{}</dd>
+    </dl>"#,
+            text(Message::Amount),
+            escape_html(&values.amount),
+            escape_html(&values.asset_symbol),
+            text(Message::Network),
+            escape_html(&values.network),
+        )
+    };
+    let error_hidden = if values.error.is_empty() || values.error == values.description {
+        " hidden"
+    } else {
+        ""
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="{lang}">
+<head>
+  <meta charset="UTF-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1.0">
+  <title>{title}</title>
+  {stylesheet}
+</head>
+<body>
+  <main class="container">
+    <div class="logo" aria-hidden="true">💰</div>
+    <h1>{title}</h1>
+    <p class="subtitle">{subtitle}</p>{details}
+    <p class="error"{error_hidden}>{error}</p>
+    <div class="instructions">
+      <strong>{how_to_pay}</strong>
+      <ol>
+        <li>{connect_wallet}</li>
+        <li>{switch_network}</li>
+        <li>{check_balance}</li>
+        <li>{retry}</li>
+      </ol>
+    </div>
+  </main>
+  <script type="application/json" id="x402-requirements"{requirements_attributes}>{requirements}</script>
+  {script}
+</body>
+</html>
+"#,
+        lang = escape_html(messages.language()),
+        title = text(Message::PaymentRequired),
+        error = escape_html(&values.error),
+        how_to_pay = text(Message::HowToPay),
+        connect_wallet = text(Message::ConnectWallet),
+        switch_network = text(Message::SwitchNetwork),
+        check_balance = text(Message::CheckBalance),
+        retry = text(Message::RetryWithPayment),
+        requirements = escape_script_json(&values.requirements),
+    )
+}
@@ -0,0 +1,169 @@
+//! Replay protection for payment authorizations
+//!
+//! A payment is verified with the facilitator, but unless `x402_settle
+//! before_upstream` is used it is not settled before the request is served. Until
+//! then, the facilitator keeps accepting the same `X-PAYMENT` header, so it could
+//! be replayed against many requests before its `validBefore` time.
+//!
+//! With `x402_replay_zone`, each authorization (payer and EIP-3009 nonce, or the
+//! hash of a Solana transaction) is recorded in a shared memory zone. Any worker
+//! answers a reused authorization with 402 (`$x402_status = replayed`):
+//!
+//! - while it is being verified, a short in-flight entry holds it, so concurrent
+//!   requests with the same authorization are rejected
+//! - once verified, it is recorded until its `validBefore` time. A Solana
+//!   transaction has none the module can read; it is recorded for
+//!   `SVM_REPLAY_WINDOW`, longer than its blockhash stays valid
+//!
+//! Authorizations that fail verification are removed, so unverified payloads
+//! can't fill the zone. If the zone is full, the request is answered with 503.
+
+use crate::ngx_module::error::Result;
+use crate::ngx_module::runtime::FacilitatorPayload;
+use crate::ngx_module::shm::{InsertResult, SharedTable};
+use sha2::{Digest, Sha256};
+
+/// Lifetime of an entry whose authorization has no usable `validBefore` (seconds)
+pub const DEFAULT_REPLAY_WINDOW: u64 = 60;
+
+/// Lifetime of an entry of a Solana transaction (seconds)
+///
+/// A transaction can only land while its recent blockhash is valid, 150 slots
+/// (about a minute).
+pub const SVM_REPLAY_WINDOW: u64 = 120;
+
+/// Outcome of recording an authorization
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayCheck {
+    /// First use of the authorization
+    Fresh,
+    /// The authorization has been used before (or is being verified)
+    Replayed,
+    /// The zone has no room for the authorization
+    Full,
+}
+
+impl From<InsertResult> for ReplayCheck {
+    fn from(result: InsertResult) -> Self {
+        match result {
+            InsertResult::Inserted => ReplayCheck::Fresh,
+            InsertResult::Exists => ReplayCheck::Replayed,
+            InsertResult::Full => ReplayCheck::Full,
+        }
+    }
+}
+
+/// Key identifying an authorization
+///
+/// Network, payer and nonce of an EIP-3009 authorization, or network and
+/// SHA-256 hash of the bytes of a Solana transaction.
+#[must_use]
+pub fn replay_key(payload: &FacilitatorPayload) -> String {
+    match payload {
+        FacilitatorPayload::Evm(payload) => {
+            let authorization = &payload.payload.authorization;
+            format!(
+                "{}:{}:{}",
+                payload.network,
+                authorization.from.to_lowercase(),
+                authorization.nonce.to_lowercase()
+            )
+        }
+        FacilitatorPayload::Svm(payload) => {
+            use base64::{engine::general_purpose, Engine as _};
+
+            let transaction = &payload.payload.transaction;
+            // The facilitator rejects a transaction that isn't base64
+            let bytes = general_purpose::STANDARD
+                .decode(transaction)
+                .unwrap_or_else(|_| transaction.as_bytes().to_vec());
+            format!(
+                "{}:tx:{}",
+                payload.network,
+                hex::encode(Sha256::digest(bytes))
+            )
+        }
+    }
+}
+
+/// Time until which an authorization must be remembered (unix seconds)
+///
+/// This is the authorization's `validBefore`, or `now + DEFAULT_REPLAY_WINDOW`
+/// if it is missing or already in the past. Solana transactions are remembered
+/// for `SVM_REPLAY_WINDOW`.
+#[must_use]
+pub fn replay_expiry(payload: &FacilitatorPayload, now: u64) -> u64 {
+    match payload {
+        FacilitatorPayload::Evm(payload) => payload
+            .payload
+            .authorization
+            .valid_before
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|valid_before| *valid_before > now)
+            .unwrap_or(now + DEFAULT_REPLAY_WINDOW),
+        FacilitatorPayload::Svm(_) => now + SVM_REPLAY_WINDOW,
+    }
+}
+
+/// Time until which an authorization being verified is held (unix seconds)
+///
+/// `window` seconds (at least one) from now, at the latest the authorization's
+/// expiry (see [`replay_expiry`]).
+#[must_use]
+pub fn in_flight_expiry(payload: &FacilitatorPayload, now: u64, window: u64) -> u64 {
+    replay_expiry(payload, now).min(now + window.max(1))
+}
+
+/// Hold an authorization while it is being verified, detecting reuse
+///
+/// The entry expires after `window` seconds (see [`in_flight_expiry`]), unless
+/// [`record_authorization`] keeps it.
+///
+/// # Errors
+/// - Returns error if the zone is not usable
+pub fn reserve_authorization(
+    zone: SharedTable,
+    payload: &FacilitatorPayload,
+    now: u64,
+    window: u64,
+) -> Result<ReplayCheck> {
+    let key = replay_key(payload);
+    let expires = in_flight_expiry(payload, now, window);
+    let result = zone.with_table(|table| table.insert(table.fingerprint(&key), 0, expires, now))?;
+    Ok(result.into())
+}
+
+/// Record a verified authorization until it expires (`validBefore`)
+///
+/// # Errors
+/// - Returns error if the zone is not usable
+pub fn record_authorization(
+    zone: SharedTable,
+    payload: &FacilitatorPayload,
+    now: u64,
+) -> Result<ReplayCheck> {
+    let key = replay_key(payload);
+    let expires = replay_expiry(payload, now);
+    let result = zone.with_table(|table| table.set(table.fingerprint(&key), 0, expires, now))?;
+    Ok(result.into())
+}
+
+/// Forget an authorization that was not accepted, so the client can retry it
+///
+/// # Errors
+/// - Returns error if the zone is not usable
+pub fn forget_authorization(zone: SharedTable, payload: &FacilitatorPayload) -> Result<()> {
+    let key = replay_key(payload);
+    zone.with_table(|table| table.remove(table.fingerprint(&key)))
+}
+
+/// Current time in unix seconds
+#[must_use]
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -1,6 +1,13 @@
 //! Request handling utilities
 
+use crate::ngx_module::config::ParsedX402Config;
+use crate::ngx_module::negotiation::{
+    wants_html, BrowserDetection, ClientHints, ConfiguredBrowserDetection, ResponseFormat,
+};
+use ngx::core::NgxStr;
+use ngx::ffi::{ngx_list_part_t, ngx_table_elt_t};
 use ngx::http::Request;
+use std::ptr;
 
 /// Get header value from request
 ///
@@ -28,6 +35,144 @@ pub fn get_header_value(r: &Request, name: &str) -> Option<String> {
     None
 }
 
+/// Remove request headers whose name matches `predicate`
+///
+/// Elements of the `headers_in` list are never moved, because other `headers_in`
+/// fields (`host`, `user_agent`, `next` chains, ...) point into it. A removed
+/// element is dropped by shrinking its list part or splitting the part around it.
+///
+/// # Arguments
+/// - `r`: Nginx request object
+/// - `predicate`: Called with each header name, returns `true` to remove the header
+///
+/// # Returns
+/// Number of removed headers
+pub fn remove_headers_in<F>(r: &mut Request, mut predicate: F) -> usize
+where
+    F: FnMut(&str) -> bool,
+{
+    let pool = r.pool();
+    let raw: *mut ngx::ffi::ngx_http_request_t = r.as_mut();
+    let mut removed = 0;
+
+    // Safe: the list belongs to the live request, parts are allocated from its pool
+    unsafe {
+        let list = &raw mut (*raw).headers_in.headers;
+        let first = &raw mut (*list).part;
+        let first_elts = (*first).elts;
+        let mut prev: *mut ngx_list_part_t = ptr::null_mut();
+        let mut part = first;
+        let mut i = 0;
+
+        while !part.is_null() {
+            if i >= (*part).nelts {
+                if (*part).nelts == 0 && part != first {
+                    // nginx doesn't expect empty parts after the first one, unlink it
+                    (*prev).next = (*part).next;
+                    if (*list).last == part {
+                        (*list).last = prev;
+                    }
+                } else {
+                    prev = part;
+                }
+                part = (*part).next;
+                i = 0;
+                continue;
+            }
+
+            let elts = (*part).elts.cast::<ngx_table_elt_t>();
+            let key = NgxStr::from_ngx_str((*elts.add(i)).key);
+            if !key.to_str().is_ok_and(&mut predicate) {
+                i += 1;
+                continue;
+            }
+
+            if i + 1 == (*part).nelts {
+                // Last element of the part
+                (*part).nelts -= 1;
+            } else if i == 0 {
+                // First element of the part
+                (*part).elts = elts.add(1).cast();
+                (*part).nelts -= 1;
+            } else {
+                // Split the part around the element, scanning continues in the tail
+                let tail = pool
+                    .alloc(std::mem::size_of::<ngx_list_part_t>())
+                    .cast::<ngx_list_part_t>();
+                if tail.is_null() {
+                    break;
+                }
+                (*tail).elts = elts.add(i + 1).cast();
+                (*tail).nelts = (*part).nelts - i - 1;
+                (*tail).next = (*part).next;
+                (*part).nelts = i;
+                (*part).next = tail;
+                if (*list).last == part {
+                    (*list).last = tail;
+                }
+                prev = part;
+                part = tail;
+                i = 0;
+            }
+            removed += 1;
+        }
+
+        if removed > 0 {
+            // ngx_list_push assumes the last part has room for `nalloc` elements, which
+            // no longer holds once its start moved. Make the next push allocate a new part.
+            let last = (*list).last;
+            if (*last).nelts > 0 {
+                (*list).nalloc = (*last).nelts;
+            } else {
+                // Only the first part can be empty: every element of its array was
+                // removed, so the array can be reused from the start
+                (*last).elts = first_elts;
+            }
+        }
+    }
+
+    removed
+}
+
+/// Remove a response header and return its value
+///
+/// The header is disabled the way nginx does it (`hash = 0`), so it is not sent
+/// to the client. Only the first header with this name is taken.
+///
+/// # Arguments
+/// - `r`: Nginx request object
+/// - `name`: Header name (case-insensitive)
+///
+/// # Returns
+/// - `Some(String)` if the header was set on the response
+/// - `None` if the header doesn't exist or cannot be read
+pub fn take_header_out(r: &mut Request, name: &str) -> Option<String> {
+    let raw: *mut ngx::ffi::ngx_http_request_t = r.as_mut();
+
+    // Safe: the list belongs to the live request
+    unsafe {
+        let mut part = &raw mut (*raw).headers_out.headers.part;
+        while !part.is_null() {
+            let elts = (*part).elts.cast::<ngx_table_elt_t>();
+            for i in 0..(*part).nelts {
+                let header = elts.add(i);
+                if (*header).hash == 0 {
+                    continue;
+                }
+                let key = NgxStr::from_ngx_str((*header).key);
+                if key.to_str().is_ok_and(|key| key.eq_ignore_ascii_case(name)) {
+                    (*header).hash = 0;
+                    let value = NgxStr::from_ngx_str((*header).value);
+                    return value.to_str().ok().map(std::string::ToString::to_string);
+                }
+            }
+            part = (*part).next;
+        }
+    }
+
+    None
+}
+
 /// Check if request is from a browser
 ///
 /// Uses a strict, priority-based detection algorithm:
@@ -44,6 +189,9 @@ pub fn get_header_value(r: &Request, name: &str) -> Option<String> {
 ///    - `application/x-www-form-urlencoded` (browser forms)
 /// 4. **Upgrade header**: Check for protocol upgrades (WebSocket, etc.)
 ///
+/// These are the default rules of `x402_browser_detection`, see
+/// [`wants_html_response`] for the configured ones.
+///
 /// # Arguments
 /// - `r`: Nginx request object
 ///
@@ -52,98 +200,49 @@ pub fn get_header_value(r: &Request, name: &str) -> Option<String> {
 /// - `false` if request appears to be from an API client
 #[must_use]
 pub fn is_browser_request(r: &Request) -> bool {
-    let user_agent = get_header_value(r, "User-Agent");
-    let accept = get_header_value(r, "Accept");
-    let content_type = get_header_value(r, "Content-Type");
-    let upgrade = get_header_value(r, "Upgrade");
-
-    // Priority 0: Check Content-Type header first (strongest indicator)
-    // If Content-Type is application/json, this is definitely an API request
-    if let Some(ref content_type_header) = content_type {
-        let ct_lower = content_type_header.to_lowercase();
-        if ct_lower.starts_with("application/json") {
-            return false; // API request
-        }
-    }
-
-    // Priority 1: Check Accept header with q-value parsing
-    if let Some(ref accept_header) = accept {
-        let html_priority = crate::config::parse_accept_priority(accept_header, "text/html");
-        let json_priority = crate::config::parse_accept_priority(accept_header, "application/json");
-        let wildcard_priority = crate::config::parse_accept_priority(accept_header, "*/*");
+    wants_html(
+        ResponseFormat::Auto,
+        &BrowserDetection::default(),
+        &client_hints(r),
+    )
+}
 
-        // If HTML has high priority (>0.5), likely browser
-        if html_priority > 0.5 {
-            return true;
-        }
+/// Check if a 402 response to the request is the HTML paywall
+///
+/// Applies `x402_response_format` and the `x402_browser_detection` rules of
+/// the location.
+///
+/// # Arguments
+/// - `r`: Nginx request object
+/// - `config`: Parsed module configuration
+#[must_use]
+pub fn wants_html_response(r: &Request, config: &ParsedX402Config) -> bool {
+    let default_rules = BrowserDetection::default();
+    let rules = config
+        .browser_detection
+        .as_ref()
+        .map_or(&default_rules, ConfiguredBrowserDetection::get);
+    wants_html(config.response_format, rules, &client_hints(r))
+}
 
-        // If JSON has high priority (>0.5) and HTML is low or absent, likely API
-        if json_priority > 0.5 && html_priority < 0.3 {
-            return false;
-        }
+/// Request headers and query string used to pick the response format
+fn client_hints(r: &Request) -> ClientHints {
+    let args = r.as_ref().args;
+    let args = if args.len == 0 || args.data.is_null() {
+        None
+    } else {
+        // Safe: nginx keeps the query string alive for the request's lifetime
+        let bytes = unsafe { std::slice::from_raw_parts(args.data, args.len) };
+        std::str::from_utf8(bytes).ok().map(str::to_string)
+    };
 
-        // If wildcard with high priority, check other indicators
-        if wildcard_priority > 0.8 {
-            // Continue to other checks
-        } else if wildcard_priority > 0.5 {
-            // Medium priority wildcard, prefer other indicators
-        }
+    ClientHints {
+        user_agent: get_header_value(r, "User-Agent"),
+        accept: get_header_value(r, "Accept"),
+        content_type: get_header_value(r, "Content-Type"),
+        upgrade: get_header_value(r, "Upgrade").is_some(),
+        args,
     }
-
-    // Priority 2: Check User-Agent for browser identifiers
-    // Use stricter matching: must contain browser identifier AND not be an API client
-    let has_browser_ua = user_agent.as_ref().is_some_and(|ua| {
-        let ua_lower = ua.to_lowercase();
-
-        // Check for browser identifiers
-        let has_browser = ua_lower.contains("mozilla")
-            && (ua_lower.contains("chrome")
-                || ua_lower.contains("safari")
-                || ua_lower.contains("firefox")
-                || ua_lower.contains("edge")
-                || ua_lower.contains("opera")
-                || ua_lower.contains("brave")
-                || ua_lower.contains("webkit"));
-
-        // Exclude common API clients
-        let is_api_client = ua_lower.contains("curl")
-            || ua_lower.contains("wget")
-            || ua_lower.contains("python-requests")
-            || ua_lower.contains("go-http-client")
-            || ua_lower.contains("java/")
-            || ua_lower.contains("okhttp")
-            || ua_lower.contains("httpie")
-            || ua_lower.contains("postman")
-            || ua_lower.contains("insomnia")
-            || ua_lower.starts_with("rest-client")
-            || ua_lower.starts_with("http");
-
-        has_browser && !is_api_client
-    });
-
-    // Priority 3: Check Content-Type for browser-specific types
-    // Note: application/json is already handled above as API indicator
-    let is_browser_content_type = content_type.as_ref().is_some_and(|ct| {
-        let ct_lower = ct.to_lowercase();
-        ct_lower.starts_with("multipart/form-data")
-            || ct_lower.starts_with("application/x-www-form-urlencoded")
-    });
-
-    // Priority 4: Check Upgrade header (WebSocket, etc.)
-    let has_upgrade = upgrade.is_some();
-
-    // Combine indicators with priority weighting
-    // Browser UA is strong indicator, but not sufficient alone
-    // Content-Type and Upgrade are strong indicators
-    // Accept header already handled above
-    is_browser_content_type
-        || (has_browser_ua
-            && (has_upgrade
-                || accept.is_none()
-                || crate::config::parse_accept_priority(
-                    accept.as_deref().unwrap_or(""),
-                    "text/html",
-                ) > 0.0))
 }
 
 /// Check if request is a WebSocket upgrade request
@@ -1,9 +1,11 @@
 //! Payment requirements creation
 
+use crate::config::NetworkVm;
 use crate::ngx_module::config::ParsedX402Config;
 use crate::ngx_module::error::{ConfigError, Result};
+use crate::ngx_module::local_verify::{asset_domain_separator, signed_for_asset};
 use rust_decimal::Decimal;
-use rust_x402::types::{networks, PaymentRequirements};
+use rust_x402::types::{networks, PaymentPayload, PaymentRequirements};
 
 /// Create payment requirements from config
 ///
@@ -20,7 +22,9 @@ use rust_x402::types::{networks, PaymentRequirements};
 /// - Returns error if amount is not configured
 /// - Returns error if `pay_to` address is not configured
 /// - Returns error if network is not supported
-/// - Returns error if USDC info cannot be set (when using default USDC)
+/// - Returns error if the network has no USDC contract and no asset is configured
+/// - Returns error if `pay_to` or the asset is not an address of the network
+/// - Returns error if a Solana network has no fee payer (`x402_fee_payer`)
 pub fn create_requirements(
     config: &ParsedX402Config,
     resource: &str,
@@ -46,42 +50,33 @@ pub fn create_requirements(
 
     // Determine network - priority: network_id (chainId) > network name > default
     let network = if let Some(chain_id) = config.network_id {
-        // Convert chainId to network name
-        let network_name = crate::config::chain_id_to_network(chain_id)
-            .map_err(|e| ConfigError::from(e.to_string()))?;
-        // Store the network name temporarily to avoid lifetime issues
-        // We'll use it as a string slice below
-        if network_name == networks::BASE_SEPOLIA {
-            networks::BASE_SEPOLIA
-        } else if network_name == networks::BASE_MAINNET {
-            networks::BASE_MAINNET
-        } else {
-            return Err(ConfigError::from(format!(
-                "Unsupported network from chainId {}: {}",
-                chain_id, network_name
-            )));
-        }
-    } else if let Some(ref net) = config.network {
-        net.as_str()
+        crate::config::network_by_chain_id(chain_id)
+            .ok_or_else(|| ConfigError::from(format!("Unsupported chainId: {chain_id}")))?
     } else {
-        networks::BASE_MAINNET
+        let name = config.network.as_deref().unwrap_or(networks::BASE_MAINNET);
+        crate::config::network_by_name(name)
+            .ok_or_else(|| ConfigError::from(format!("Network not supported: {name}")))?
     };
 
     // Determine asset address - use custom asset if provided, otherwise use USDC for the network
     let asset_address = if let Some(ref custom_asset) = config.asset {
-        // Validate that network is supported even when using custom asset
-        if !networks::is_supported(network) {
-            return Err(ConfigError::from(format!(
-                "Network not supported: {network}"
-            )));
-        }
         custom_asset.as_str()
     } else {
-        // Get USDC address for the network (default behavior)
-        networks::get_usdc_address(network)
-            .ok_or_else(|| ConfigError::from(format!("Network not supported: {network}")))?
+        network.usdc.as_deref().ok_or_else(|| {
+            ConfigError::from(format!(
+                "Network {} has no USDC contract, x402_asset is required",
+                network.name
+            ))
+        })?
     };
 
+    // EVM and Solana addresses are not interchangeable
+    for address in [pay_to.as_str(), asset_address] {
+        crate::config::validate_network_address(network.vm, address).map_err(|e| {
+            ConfigError::from(format!("Invalid address for network {}: {e}", network.name))
+        })?;
+    }
+
     // Use configured resource or fall back to provided resource
     // Validate and sanitize the resource path to prevent path traversal attacks
     let resource = if let Some(ref resource_url) = config.resource {
@@ -142,12 +137,18 @@ pub fn create_requirements(
     // Convert amount to max_amount_required (in smallest unit based on token decimals)
     let max_amount_required = (amount * multiplier).normalize().to_string();
 
+    // EVM addresses are case-insensitive, base58 Solana addresses are not
+    let pay_to = match network.vm {
+        NetworkVm::Evm => pay_to.to_lowercase(),
+        NetworkVm::Svm => pay_to.clone(),
+    };
+
     let mut requirements = PaymentRequirements::new(
         rust_x402::types::schemes::EXACT,
-        network,
+        network.name.as_str(),
         max_amount_required,
         asset_address,
-        pay_to.to_lowercase(),
+        pay_to,
         resource,
         config.description.as_deref().unwrap_or(""),
     );
@@ -172,17 +173,144 @@ pub fn create_requirements(
     };
     requirements.mime_type = Some(final_mime_type.to_string());
 
-    // Set network-specific USDC info only if using default USDC (not custom asset)
-    // This ensures compatibility with USDC-specific metadata while allowing custom tokens
-    if config.asset.is_none() {
-        // Determine network enum from network string
-        let network_enum = if network == networks::BASE_SEPOLIA {
-            rust_x402::types::Network::Testnet
-        } else {
-            rust_x402::types::Network::Mainnet
-        };
-        requirements.set_usdc_info(network_enum)?;
+    match network.vm {
+        // Set the USDC EIP-712 domain only if using default USDC (not custom asset)
+        // This ensures compatibility with USDC-specific metadata while allowing custom tokens
+        NetworkVm::Evm => {
+            if config.asset.is_none() {
+                if let (Some(name), Some(version)) = (&network.eip712_name, &network.eip712_version)
+                {
+                    requirements.extra =
+                        Some(serde_json::json!({ "name": name, "version": version }));
+                }
+            }
+        }
+        // The client builds a transaction whose fees are paid by the facilitator
+        NetworkVm::Svm => {
+            let fee_payer = config.fee_payer.as_deref().ok_or_else(|| {
+                ConfigError::from(format!(
+                    "Network {} requires x402_fee_payer (the facilitator's fee payer address)",
+                    network.name
+                ))
+            })?;
+            requirements.extra = Some(serde_json::json!({ "feePayer": fee_payer }));
+        }
     }
 
     Ok(requirements)
 }
+
+/// Payment requirements together with the token precision used to build them
+pub struct PaymentOption {
+    pub requirements: PaymentRequirements,
+    /// Token decimals (used to convert the amount back to token units)
+    pub decimals: u8,
+}
+
+/// Create the payment options offered by a location
+///
+/// Without `x402_accept`, the location offers the single option built by
+/// [`create_requirements`]. Otherwise there is one option per `x402_accept`
+/// directive; unset `amount`/`pay_to` fall back to `x402_amount`/`x402_pay_to`,
+/// and an option without `asset` uses USDC on its network.
+///
+/// # Errors
+/// - Returns error if any option cannot be turned into payment requirements
+pub fn create_payment_options(
+    config: &ParsedX402Config,
+    resource: &str,
+    mime_type: Option<&str>,
+) -> Result<Vec<PaymentOption>> {
+    if config.accepts.is_empty() {
+        return Ok(vec![PaymentOption {
+            requirements: create_requirements(config, resource, mime_type)?,
+            decimals: config.asset_decimals.unwrap_or(6),
+        }]);
+    }
+
+    config
+        .accepts
+        .iter()
+        .map(|accept| {
+            let mut option_config = config.clone();
+            option_config.network = Some(accept.network.clone());
+            option_config.network_id = None;
+            option_config.asset = accept.asset.clone();
+            option_config.asset_decimals = accept.asset_decimals;
+            option_config.amount = accept.amount.or(config.amount);
+            option_config.pay_to = accept.pay_to.clone().or_else(|| config.pay_to.clone());
+            option_config.fee_payer = accept
+                .fee_payer
+                .clone()
+                .or_else(|| config.fee_payer.clone());
+
+            let requirements =
+                create_requirements(&option_config, resource, mime_type).map_err(|e| {
+                    ConfigError::from(format!(
+                        "Invalid payment option for network {}: {e}",
+                        accept.network
+                    ))
+                })?;
+            Ok(PaymentOption {
+                requirements,
+                decimals: accept.asset_decimals.unwrap_or(6),
+            })
+        })
+        .collect()
+}
+
+/// Find the payment requirements an incoming payment was made for
+///
+/// Candidates must match the payload's scheme, network and recipient. If they
+/// differ in asset, only those whose token contract the authorization was signed
+/// for remain (see [`signed_for_asset`]), or, if there is none, those whose token
+/// domain is unknown. If several are left, the one whose amount matches the
+/// signed value is used.
+///
+/// # Returns
+/// - `Some(index)` of the matching requirements
+/// - `None` if no requirements match, or the payment matches several equally well
+#[must_use]
+pub fn select_requirements(
+    requirements: &[PaymentRequirements],
+    payload: &PaymentPayload,
+) -> Option<usize> {
+    let authorization = &payload.payload.authorization;
+    let mut candidates: Vec<usize> = requirements
+        .iter()
+        .enumerate()
+        .filter(|(_, req)| {
+            req.scheme == payload.scheme
+                && req.network == payload.network
+                && req.pay_to.eq_ignore_ascii_case(&authorization.to)
+        })
+        .map(|(index, _)| index)
+        .collect();
+
+    let first_asset = candidates.first().map(|index| &requirements[*index].asset);
+    let same_asset = candidates.iter().all(|index| {
+        first_asset.is_some_and(|asset| requirements[*index].asset.eq_ignore_ascii_case(asset))
+    });
+    if !same_asset {
+        let signed: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|index| signed_for_asset(payload, &requirements[*index]))
+            .collect();
+        if signed.is_empty() {
+            // Not signed for any token with a known domain: only tokens whose
+            // domain is unknown remain (e.g. custom `x402_accept asset=...`)
+            candidates.retain(|index| asset_domain_separator(&requirements[*index]).is_err());
+        } else {
+            candidates = signed;
+        }
+    }
+
+    if candidates.len() > 1 {
+        candidates.retain(|index| requirements[*index].max_amount_required == authorization.value);
+    }
+    match candidates.as_slice() {
+        [index] => Some(*index),
+        _ => None,
+    }
+}
@@ -2,12 +2,18 @@
 
 use crate::ngx_module::config::ParsedX402Config;
 use crate::ngx_module::error::{ConfigError, Result};
-use crate::ngx_module::request::is_browser_request;
+use crate::ngx_module::messages::{request_messages, Message, Messages};
+use crate::ngx_module::paywall::{
+    content_security_policy, generate_csp_nonce, render_hosted_paywall, render_inline_paywall,
+    PaywallValues,
+};
+use crate::ngx_module::request::wants_html_response;
 use ngx::core::Status;
 use ngx::http::{HTTPStatus, Request};
 use rust_x402::template::generate_paywall_html;
 use rust_x402::types::{PaymentRequirements, PaymentRequirementsResponse};
 use serde_json;
+use std::borrow::Cow;
 
 /// Send 402 Payment Required response
 ///
@@ -20,12 +26,16 @@ use serde_json;
 /// The `requirements` slice can contain multiple `PaymentRequirements` objects,
 /// which will all be included in the response's `accepts` array.
 ///
-/// Browser detection considers:
-/// - User-Agent header with browser identifiers
+/// The format is set by `x402_response_format`; in `auto` mode, browser
+/// detection (`x402_browser_detection`) considers:
+/// - The format query argument (`format_arg=`)
+/// - User-Agent header with browser identifiers or configured regexes
 /// - Accept header with HTML preference
 /// - Content-Type header with multipart/form-data (browser form submissions)
 /// - Upgrade header (browser-initiated protocol upgrades like WebSocket)
 ///
+/// A `Vary` header lists the request headers the format and language depend on.
+///
 /// # Arguments
 /// - `r`: Nginx request object
 /// - `requirements`: Slice of payment requirements to include in the response (supports multiple)
@@ -50,36 +60,214 @@ pub fn send_402_response(
     // Set status code 402 (Payment Required)
     r.set_status(HTTPStatus::from_u16(402).map_err(|_| ConfigError::from("Invalid status code"))?);
 
-    let is_browser = is_browser_request(r);
+    let is_browser = wants_html_response(r, config);
+    r.add_header_out("Vary", config.response_format.vary())
+        .ok_or_else(|| ConfigError::from("Failed to set Vary header"))?;
+    let csp_nonce = paywall_csp_nonce(r, config, is_browser)?;
+    let messages = request_messages(r);
+    let body = render_402_body(
+        requirements,
+        config,
+        error_msg,
+        is_browser,
+        csp_nonce.as_deref(),
+        &messages,
+    )?;
+
+    // Set Content-Type header
+    r.add_header_out("Content-Type", content_type_402(is_browser))
+        .ok_or_else(|| ConfigError::from("Failed to set Content-Type header"))?;
+
+    // Send body using buffer and chain
+    send_response_body(r, &body)?;
 
-    // Use error_msg if provided, otherwise use config description, otherwise use empty string
-    let error_message = error_msg.or(config.description.as_deref()).unwrap_or("");
+    Ok(())
+}
+
+/// Add the `Content-Security-Policy` of the self-hosted paywall page
+///
+/// # Returns
+/// - `Some(nonce)` if the response is the self-hosted paywall page
+///   (`x402_paywall_assets_url` set, no `x402_paywall_template`)
+/// - `None` otherwise
+///
+/// # Errors
+/// - Returns error if the nonce cannot be generated or the header cannot be set
+pub fn paywall_csp_nonce(
+    r: &mut Request,
+    config: &ParsedX402Config,
+    is_browser: bool,
+) -> Result<Option<String>> {
+    if !is_browser || config.paywall_template.is_some() || config.paywall_assets_url.is_none() {
+        return Ok(None);
+    }
+    let nonce = generate_csp_nonce()?;
+    r.add_header_out("Content-Security-Policy", &content_security_policy(&nonce))
+        .ok_or_else(|| ConfigError::from("Failed to set Content-Security-Policy header"))?;
+    Ok(Some(nonce))
+}
 
+/// Content type of a 402 response body (HTML paywall or JSON)
+#[must_use]
+pub fn content_type_402(is_browser: bool) -> &'static str {
     if is_browser {
-        // Send HTML paywall
-        let html = generate_paywall_html(error_message, requirements, None);
+        "text/html; charset=utf-8"
+    } else {
+        "application/json; charset=utf-8"
+    }
+}
 
-        // Set Content-Type header
-        r.add_header_out("Content-Type", "text/html; charset=utf-8")
-            .ok_or_else(|| ConfigError::from("Failed to set Content-Type header"))?;
+/// Render the body of a 402 response
+///
+/// Browsers get the HTML paywall (the location's `x402_paywall_template`, the
+/// self-hosted page when `csp_nonce` is given, or the built-in page), API
+/// clients a JSON `PaymentRequirementsResponse` listing all `requirements` in
+/// its `accepts` array. Module error messages are translated into the language
+/// of `messages`; the built-in `rust_x402` page is only used for English.
+///
+/// # Errors
+/// - Returns error if JSON serialization fails
+pub fn render_402_body(
+    requirements: &[PaymentRequirements],
+    config: &ParsedX402Config,
+    error_msg: Option<&str>,
+    is_browser: bool,
+    csp_nonce: Option<&str>,
+    messages: &Messages,
+) -> Result<Vec<u8>> {
+    // Use error_msg if provided, otherwise use config description, otherwise use empty string
+    let error_message = error_msg
+        .map(|error_msg| messages.localize(error_msg))
+        .or(config.description.as_deref())
+        .unwrap_or("");
 
-        // Send body using buffer and chain
-        send_response_body(r, html.as_bytes())?;
+    if is_browser {
+        // HTML paywall, describing the access pass bought with the payment (if any)
+        let requirements = match config.access_pass {
+            Some(access_pass) => {
+                let terms = access_pass.get().terms();
+                let requirements: Vec<_> = requirements
+                    .iter()
+                    .map(|requirements| {
+                        let mut requirements = requirements.clone();
+                        requirements.description = if requirements.description.is_empty() {
+                            terms.clone()
+                        } else {
+                            format!("{} ({terms})", requirements.description)
+                        };
+                        requirements
+                    })
+                    .collect();
+                Cow::Owned(requirements)
+            }
+            None => Cow::Borrowed(requirements),
+        };
+        let decimals = requirements
+            .first()
+            .map_or(6, |first| option_decimals(config, first));
+        let mut values = PaywallValues::new(&requirements, error_message, decimals)?;
+        values.lang = messages.language().to_string();
+        if let Some(template) = config.paywall_template {
+            return Ok(template.get().render(&values).into_bytes());
+        }
+        if let (Some(assets_url), Some(nonce)) = (config.paywall_assets_url.as_deref(), csp_nonce) {
+            return Ok(render_hosted_paywall(&values, messages, assets_url, nonce).into_bytes());
+        }
+        if !messages.is_default() {
+            return Ok(render_inline_paywall(&values, messages).into_bytes());
+        }
+        Ok(generate_paywall_html(error_message, &requirements, None).into_bytes())
     } else {
-        // Send JSON response
+        // JSON response
         let response = PaymentRequirementsResponse::new(error_message, requirements.to_vec());
-        let json = serde_json::to_string(&response)
-            .map_err(|_| ConfigError::from("Failed to serialize response"))?;
+        serde_json::to_vec(&response).map_err(|_| ConfigError::from("Failed to serialize response"))
+    }
+}
 
-        // Set Content-Type header
-        r.add_header_out("Content-Type", "application/json; charset=utf-8")
-            .ok_or_else(|| ConfigError::from("Failed to set Content-Type header"))?;
+/// Token decimals of the payment option `requirements` were created from
+fn option_decimals(config: &ParsedX402Config, requirements: &PaymentRequirements) -> u8 {
+    config
+        .accepts
+        .iter()
+        .find(|accept| {
+            accept.network == requirements.network
+                && accept
+                    .asset
+                    .as_ref()
+                    .is_none_or(|asset| asset.eq_ignore_ascii_case(&requirements.asset))
+        })
+        .map_or(config.asset_decimals, |accept| accept.asset_decimals)
+        .unwrap_or(6)
+}
 
-        // Send body using buffer and chain
-        send_response_body(r, json.as_bytes())?;
+/// Send 500 Internal Server Error response
+///
+/// Used when a request can't be checked for payment, e.g. when the facilitator
+/// fails in `error` fallback mode or a variable-based amount evaluates to an
+/// invalid value. The reason is logged by the caller and not exposed to the client.
+///
+/// # Errors
+/// - Returns error if status, content type or body cannot be sent
+pub fn send_500_response(r: &mut Request) -> Result<()> {
+    send_error_response(r, 500, None)
+}
+
+/// Send an error response with a configured status and body
+///
+/// Used by the `error` facilitator fallback. Without a body, a plain-text
+/// "Internal server error" is sent in the client's language.
+///
+/// # Errors
+/// - Returns error if status, content type or body cannot be sent
+pub fn send_error_response(r: &mut Request, status: u16, body: Option<&str>) -> Result<()> {
+    let messages = request_messages(r);
+    let body = body.unwrap_or_else(|| messages.get(Message::InternalServerError));
+    r.set_status(
+        HTTPStatus::from_u16(status).map_err(|_| ConfigError::from("Invalid status code"))?,
+    );
+    r.add_header_out("Content-Type", error_body_content_type(body))
+        .ok_or_else(|| ConfigError::from("Failed to set Content-Type header"))?;
+    send_response_body(r, body.as_bytes())
+}
+
+/// Content type of a configured error body: JSON if it looks like a JSON object
+/// or array, plain text otherwise
+#[must_use]
+pub fn error_body_content_type(body: &str) -> &'static str {
+    let body = body.trim_start();
+    if body.starts_with('{') || body.starts_with('[') {
+        "application/json; charset=utf-8"
+    } else {
+        "text/plain; charset=utf-8"
     }
+}
 
-    Ok(())
+/// Send 503 Service Unavailable response
+///
+/// Used by the `unavailable` facilitator fallback: x402 clients can retry the
+/// payment after `Retry-After` seconds.
+///
+/// # Errors
+/// - Returns error if status, headers or body cannot be sent
+pub fn send_503_response(r: &mut Request, retry_after: u32) -> Result<()> {
+    r.set_status(HTTPStatus::from_u16(503).map_err(|_| ConfigError::from("Invalid status code"))?);
+    r.add_header_out("Retry-After", &retry_after.to_string())
+        .ok_or_else(|| ConfigError::from("Failed to set Retry-After header"))?;
+    r.add_header_out("Content-Type", "application/json; charset=utf-8")
+        .ok_or_else(|| ConfigError::from("Failed to set Content-Type header"))?;
+    let messages = request_messages(r);
+    send_response_body(r, &render_unavailable_body(retry_after, &messages))
+}
+
+/// Render the JSON body of a 503 response sent because the facilitator is down
+#[must_use]
+pub fn render_unavailable_body(retry_after: u32, messages: &Messages) -> Vec<u8> {
+    serde_json::json!({
+        "error": messages.get(Message::FacilitatorUnavailable),
+        "retryAfter": retry_after,
+    })
+    .to_string()
+    .into_bytes()
 }
 
 /// Send response body using ngx buffer and chain
@@ -1,8 +1,12 @@
 //! Runtime and facilitator client management
 
+use crate::ngx_module::circuit_breaker::call_with_breaker;
+use crate::ngx_module::config::{FacilitatorEndpoint, FacilitatorPolicy};
 use crate::ngx_module::error::{ConfigError, Result};
+use crate::ngx_module::failover::{call_with_failover, CallError, FacilitatorError, FailureKind};
+use crate::ngx_module::svm::{settle_svm_payment, verify_svm_payment, SvmPaymentPayload};
 use rust_x402::facilitator::FacilitatorClient;
-use rust_x402::types::FacilitatorConfig;
+use rust_x402::types::{FacilitatorConfig, PaymentPayload};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Duration;
@@ -93,52 +97,117 @@ pub fn get_facilitator_client(url: &str) -> Result<Arc<FacilitatorClient>> {
     Ok(client_arc)
 }
 
+/// Payment payload sent to the facilitator
+pub enum FacilitatorPayload {
+    /// EVM payload (EIP-3009 authorization)
+    Evm(PaymentPayload),
+    /// Solana payload (signed transaction)
+    Svm(SvmPaymentPayload),
+}
+
+impl FacilitatorPayload {
+    /// Decode a base64-encoded `X-PAYMENT` header, EVM or Solana
+    ///
+    /// # Errors
+    /// - Returns error if the header is neither an EVM nor a Solana payload
+    pub fn from_base64(payment_b64: &str) -> Result<Self> {
+        match PaymentPayload::from_base64(payment_b64) {
+            Ok(payload) => Ok(Self::Evm(payload)),
+            Err(e) => SvmPaymentPayload::from_base64(payment_b64)
+                .map(Self::Svm)
+                .map_err(|_| ConfigError::from(e.to_string())),
+        }
+    }
+}
+
 /// Verify payment with facilitator service
 ///
+/// Fails over to the next facilitator if one can't be reached, and retries
+/// (see `failover`), unless the circuit breaker is open (see `circuit_breaker`).
+///
 /// # Arguments
 /// - `payment_b64`: Base64-encoded payment payload
 /// - `requirements`: Payment requirements to verify against
-/// - `facilitator_url`: Facilitator service URL
-/// - `timeout`: Optional timeout (uses default if None)
+/// - `facilitators`: Facilitators of the location
+/// - `policy`: Timeout, retries and circuit breaker of the location
 ///
 /// # Returns
 /// - `Ok(true)` if payment is valid
-/// - `Ok(false)` if payment is invalid
-/// - `Err` if verification fails (network error, timeout, etc.)
+/// - `Ok(false)` if payment is invalid, or the facilitator refused it (4xx)
+/// - `Err` if verification fails (network error, timeout, 5xx, etc.)
 pub async fn verify_payment(
     payment_b64: &str,
     requirements: &rust_x402::types::PaymentRequirements,
-    facilitator_url: &str,
-    timeout_duration: Option<Duration>,
+    facilitators: &[FacilitatorEndpoint],
+    policy: FacilitatorPolicy,
 ) -> Result<bool> {
     use crate::ngx_module::error::user_errors;
-    use crate::ngx_module::logging::{log_debug, log_error, log_warn};
-    use rust_x402::types::PaymentPayload;
+    use crate::ngx_module::logging::log_error;
 
     // Validate inputs
     if payment_b64.is_empty() {
         return Err(ConfigError::from(user_errors::INVALID_PAYMENT));
     }
-    if facilitator_url.is_empty() {
+    if facilitators.is_empty() {
         return Err(ConfigError::from(user_errors::CONFIGURATION_ERROR));
     }
 
     // Parse payment payload - use generic error for users
-    let payment_payload = PaymentPayload::from_base64(payment_b64).map_err(|e| {
+    let payment_payload = FacilitatorPayload::from_base64(payment_b64).map_err(|e| {
         // Log internal error details
         log_error(None, &format!("Failed to parse payment payload: {e}"));
         // User gets generic error
         ConfigError::from(user_errors::INVALID_PAYMENT)
     })?;
 
-    // Get facilitator client
-    let client = get_facilitator_client(facilitator_url)?;
-
     // Use configured timeout or default
-    let timeout_duration = timeout_duration.unwrap_or(DEFAULT_FACILITATOR_TIMEOUT);
+    let budget = policy.timeout.unwrap_or(DEFAULT_FACILITATOR_TIMEOUT);
+
+    let result = call_with_breaker(facilitators, policy.circuit_breaker, || {
+        call_with_failover(
+            facilitators,
+            policy.retry,
+            budget,
+            true,
+            |url, remaining| {
+                verify_with_facilitator(url, &payment_payload, requirements, remaining)
+            },
+        )
+    })
+    .await;
+    match result {
+        // The facilitator refused the request (4xx): the payment is invalid
+        Err(e) if e.kind == FailureKind::Rejected => Ok(false),
+        result => result.map_err(|e| e.error),
+    }
+}
+
+/// Verify a payment with one facilitator
+async fn verify_with_facilitator(
+    facilitator_url: &str,
+    payment_payload: &FacilitatorPayload,
+    requirements: &rust_x402::types::PaymentRequirements,
+    timeout_duration: Duration,
+) -> std::result::Result<bool, CallError> {
+    use crate::ngx_module::error::user_errors;
+    use crate::ngx_module::logging::{log_debug, log_error, log_warn};
+
+    // Get facilitator client
+    let client = get_facilitator_client(facilitator_url)
+        .map_err(|e| CallError::new(FailureKind::NotSent, e))?;
 
     // Verify with timeout
-    let verify_future = client.verify(&payment_payload, requirements);
+    let verify_future = async {
+        match payment_payload {
+            FacilitatorPayload::Evm(payload) => client
+                .verify(payload, requirements)
+                .await
+                .map_err(FacilitatorError::from),
+            FacilitatorPayload::Svm(payload) => {
+                verify_svm_payment(client.url(), payload, requirements, timeout_duration).await
+            }
+        }
+    };
     match timeout(timeout_duration, verify_future).await {
         Ok(Ok(response)) => {
             // Get current timestamp for debugging time-related issues
@@ -162,16 +231,137 @@ pub async fn verify_payment(
         }
         Ok(Err(e)) => {
             // Verification failure - log internal details, user gets generic error
-            log_error(None, &format!("Payment verification failed: {e}"));
-            Err(ConfigError::from(user_errors::PAYMENT_VERIFICATION_FAILED))
+            log_error(
+                None,
+                &format!("Payment verification failed with {facilitator_url}: {e}"),
+            );
+            Err(CallError::new(
+                e.kind(),
+                user_errors::PAYMENT_VERIFICATION_FAILED,
+            ))
         }
         Err(_) => {
             // Timeout - log and return user-facing error
             log_warn(
                 None,
-                &format!("Payment verification timeout after {timeout_duration:?}"),
+                &format!(
+                    "Payment verification timeout after {timeout_duration:?} with {facilitator_url}"
+                ),
+            );
+            Err(CallError::new(FailureKind::NoAnswer, user_errors::TIMEOUT))
+        }
+    }
+}
+
+/// Settle payment with facilitator service
+///
+/// Calls the facilitator's `/settle` endpoint for a payment that has already
+/// been verified. Settlement is what actually moves funds on-chain. Fails over
+/// to the next facilitator and retries like `verify_payment`, but only while the
+/// request couldn't be sent: after a timeout or an error response the
+/// facilitator may have settled the payment, and settling it again elsewhere
+/// would report a failure for a settled payment.
+///
+/// # Arguments
+/// - `payment_b64`: Base64-encoded payment payload
+/// - `requirements`: Payment requirements the payment was verified against
+/// - `facilitators`: Facilitators of the location
+/// - `policy`: Timeout, retries and circuit breaker of the location
+///
+/// # Returns
+/// - `Ok(SettleResponse)` with the facilitator's settlement result (check `success`)
+/// - `Err` if settlement could not be performed (network error, timeout, etc.)
+pub async fn settle_payment(
+    payment_b64: &str,
+    requirements: &rust_x402::types::PaymentRequirements,
+    facilitators: &[FacilitatorEndpoint],
+    policy: FacilitatorPolicy,
+) -> Result<rust_x402::types::SettleResponse> {
+    use crate::ngx_module::error::user_errors;
+    use crate::ngx_module::logging::log_error;
+
+    // Validate inputs
+    if payment_b64.is_empty() {
+        return Err(ConfigError::from(user_errors::INVALID_PAYMENT));
+    }
+    if facilitators.is_empty() {
+        return Err(ConfigError::from(user_errors::CONFIGURATION_ERROR));
+    }
+
+    let payment_payload = FacilitatorPayload::from_base64(payment_b64).map_err(|e| {
+        log_error(None, &format!("Failed to parse payment payload: {e}"));
+        ConfigError::from(user_errors::INVALID_PAYMENT)
+    })?;
+
+    let budget = policy.timeout.unwrap_or(DEFAULT_FACILITATOR_TIMEOUT);
+
+    call_with_breaker(facilitators, policy.circuit_breaker, || {
+        call_with_failover(
+            facilitators,
+            policy.retry,
+            budget,
+            false,
+            |url, remaining| {
+                settle_with_facilitator(url, &payment_payload, requirements, remaining)
+            },
+        )
+    })
+    .await
+    .map_err(|e| e.error)
+}
+
+/// Settle a payment with one facilitator
+async fn settle_with_facilitator(
+    facilitator_url: &str,
+    payment_payload: &FacilitatorPayload,
+    requirements: &rust_x402::types::PaymentRequirements,
+    timeout_duration: Duration,
+) -> std::result::Result<rust_x402::types::SettleResponse, CallError> {
+    use crate::ngx_module::error::user_errors;
+    use crate::ngx_module::logging::{log_debug, log_error, log_warn};
+
+    let client = get_facilitator_client(facilitator_url)
+        .map_err(|e| CallError::new(FailureKind::NotSent, e))?;
+
+    let settle_future = async {
+        match payment_payload {
+            FacilitatorPayload::Evm(payload) => client
+                .settle(payload, requirements)
+                .await
+                .map_err(FacilitatorError::from),
+            FacilitatorPayload::Svm(payload) => {
+                settle_svm_payment(client.url(), payload, requirements, timeout_duration).await
+            }
+        }
+    };
+    match timeout(timeout_duration, settle_future).await {
+        Ok(Ok(response)) => {
+            log_debug(
+                None,
+                &format!(
+                    "Facilitator settle response: success={}, error_reason={:?}, transaction={}",
+                    response.success,
+                    response.error_reason.as_deref().unwrap_or("none"),
+                    response.transaction
+                ),
+            );
+            Ok(response)
+        }
+        Ok(Err(e)) => {
+            log_error(
+                None,
+                &format!("Payment settlement failed with {facilitator_url}: {e}"),
+            );
+            Err(CallError::new(e.kind(), user_errors::SETTLEMENT_FAILED))
+        }
+        Err(_) => {
+            log_warn(
+                None,
+                &format!(
+                    "Payment settlement timeout after {timeout_duration:?} with {facilitator_url}"
+                ),
             );
-            Err(ConfigError::from(user_errors::TIMEOUT))
+            Err(CallError::new(FailureKind::NoAnswer, user_errors::TIMEOUT))
         }
     }
 }
@@ -0,0 +1,498 @@
+//! Prepaid sessions
+//!
+//! Signing a payment for every request is too expensive for chatty clients. With
+//! `x402_session`, a location can sell sessions instead: a paid request to an
+//! `x402_session_purchase` location is answered with a bearer token worth a number
+//! of requests and/or a period of time. Locations sharing the session zone accept
+//! the token (`X-X402-Session` header or cookie) in place of a payment, without
+//! calling the facilitator (`$x402_status = session`).
+//!
+//! Tokens have the form `<id>.<expires>.<mac>`: a random session ID, the expiry
+//! time (unix seconds) and an HMAC-SHA256 of both under the `x402_session` key.
+//! The remaining number of requests is kept in the shared memory zone, keyed by
+//! session ID, and decremented under the zone lock, so a token can't be spent
+//! twice across workers. Live sessions are never evicted: a purchase reserves the
+//! session's slot before the payment is settled, and is answered with 503 if the
+//! zone is full. Sessions are lost when nginx is restarted.
+
+use crate::ngx_module::config::DEFAULT_FALLBACK_RETRY_AFTER;
+use crate::ngx_module::ctx::{
+    get_or_create_request_ctx, request_ctx_mut, update_request_ctx, PaymentStatus,
+};
+use crate::ngx_module::error::{ConfigError, Result};
+use crate::ngx_module::logging::{log_debug, log_info, log_warn};
+use crate::ngx_module::metrics::X402Metrics;
+use crate::ngx_module::replay::unix_now;
+use crate::ngx_module::request::get_header_value;
+use crate::ngx_module::response::{send_503_response, send_response_body};
+use crate::ngx_module::shm::{InsertResult, SharedTable, SlotTable};
+use hmac::{Hmac, Mac};
+use ngx::http::{HTTPStatus, Request};
+use sha2::Sha256;
+use std::ptr::NonNull;
+
+/// Request header carrying a session token
+pub const SESSION_HEADER: &str = "X-X402-Session";
+
+/// Default name of the session cookie
+pub const DEFAULT_SESSION_COOKIE: &str = "x402_session";
+
+/// Lifetime of a session sold without `duration` (seconds)
+pub const DEFAULT_SESSION_LIFETIME: u64 = 24 * 60 * 60;
+
+/// Minimum length of the session key (bytes)
+pub const MIN_SESSION_KEY_LEN: usize = 32;
+
+/// Zone value of a session without a request limit
+const UNLIMITED: u64 = u64::MAX;
+
+/// Zone value of a session reserved for a purchase that isn't settled yet
+const RESERVED: u64 = 0;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Session settings of a location (`x402_session`)
+///
+/// Allocated from the configuration pool by the directive, which reads the key once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionConfig {
+    /// Shared memory zone holding the session balances
+    pub table: SharedTable,
+    /// Key signing the tokens
+    pub key: Vec<u8>,
+    /// Name of the session cookie
+    pub cookie: String,
+}
+
+/// Session settings parsed at configuration time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfiguredSession {
+    config: NonNull<SessionConfig>,
+}
+
+impl ConfiguredSession {
+    /// Wrap settings allocated by the `x402_session` directive
+    #[must_use]
+    pub fn new(config: NonNull<SessionConfig>) -> Self {
+        Self { config }
+    }
+
+    /// The parsed settings
+    #[must_use]
+    pub fn get(&self) -> &SessionConfig {
+        // Safe: the settings live as long as the configuration cycle
+        unsafe { self.config.as_ref() }
+    }
+}
+
+/// What a session purchase buys (`x402_session_purchase`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionGrant {
+    /// Number of requests (None: unlimited until the session expires)
+    pub requests: Option<u64>,
+    /// Session lifetime (seconds)
+    pub duration: u64,
+}
+
+impl SessionGrant {
+    /// Parse `x402_session_purchase` arguments (`requests=N duration=T`)
+    ///
+    /// At least one of `requests` and `duration` is required. Without `duration`,
+    /// the session expires after `DEFAULT_SESSION_LIFETIME`.
+    ///
+    /// # Errors
+    /// - Returns error if a parameter is unknown, duplicated or invalid
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut requests = None;
+        let mut duration = None;
+
+        for param in value.split_whitespace() {
+            let (key, value) = param.split_once('=').ok_or_else(|| {
+                ConfigError::from(format!(
+                    "Invalid session_purchase parameter '{param}', expected key=value"
+                ))
+            })?;
+            let duplicate = match key {
+                "requests" => {
+                    let count = value
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|count| *count > 0 && *count < UNLIMITED)
+                        .ok_or_else(|| {
+                            ConfigError::from(format!(
+                                "Invalid session_purchase requests '{value}'"
+                            ))
+                        })?;
+                    requests.replace(count).is_some()
+                }
+                "duration" => {
+                    let seconds = parse_duration(value)
+                        .filter(|seconds| *seconds > 0)
+                        .ok_or_else(|| {
+                            ConfigError::from(format!(
+                                "Invalid session_purchase duration '{value}'"
+                            ))
+                        })?;
+                    duration.replace(seconds).is_some()
+                }
+                _ => {
+                    return Err(ConfigError::from(format!(
+                        "Unknown session_purchase parameter '{key}'. Must be 'requests' or 'duration'"
+                    )));
+                }
+            };
+            if duplicate {
+                return Err(ConfigError::from(format!(
+                    "Duplicate session_purchase parameter '{key}'"
+                )));
+            }
+        }
+
+        if requests.is_none() && duration.is_none() {
+            return Err(ConfigError::from(
+                "session_purchase requires requests and/or duration",
+            ));
+        }
+        Ok(SessionGrant {
+            requests,
+            duration: duration.unwrap_or(DEFAULT_SESSION_LIFETIME),
+        })
+    }
+}
+
+/// Parse a duration in seconds, with an optional `s`, `m`, `h` or `d` unit
+#[must_use]
+pub fn parse_duration(value: &str) -> Option<u64> {
+    let (number, unit) = match value.char_indices().last()? {
+        (index, unit) if unit.is_ascii_alphabetic() => (&value[..index], unit),
+        _ => (value, 's'),
+    };
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Session token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionToken {
+    /// Random session ID (hex)
+    pub id: String,
+    /// Expiry time (unix seconds)
+    pub expires: u64,
+}
+
+impl SessionToken {
+    /// Create a token for a new session
+    ///
+    /// # Errors
+    /// - Returns error if no random session ID can be generated
+    pub fn generate(expires: u64) -> Result<Self> {
+        let mut id = [0u8; 16];
+        getrandom::getrandom(&mut id)
+            .map_err(|e| ConfigError::from(format!("Failed to generate session ID: {e}")))?;
+        Ok(SessionToken {
+            id: hex::encode(id),
+            expires,
+        })
+    }
+
+    /// Encode the token, signed with `key`
+    #[must_use]
+    pub fn encode(&self, key: &[u8]) -> String {
+        let mac = token_mac(key, &self.id, self.expires)
+            .finalize()
+            .into_bytes();
+        format!("{}.{}.{}", self.id, self.expires, hex::encode(mac))
+    }
+
+    /// Decode a token signed with `key`
+    ///
+    /// # Returns
+    /// - `Some(SessionToken)` if the signature is valid and the token has not expired
+    /// - `None` otherwise
+    #[must_use]
+    pub fn decode(token: &str, key: &[u8], now: u64) -> Option<Self> {
+        let mut parts = token.trim().split('.');
+        let (id, expires, mac) = (parts.next()?, parts.next()?, parts.next()?);
+        if parts.next().is_some() || id.is_empty() {
+            return None;
+        }
+        let expires = expires.parse::<u64>().ok()?;
+        let mac = hex::decode(mac).ok()?;
+        // Constant-time comparison
+        token_mac(key, id, expires).verify_slice(&mac).ok()?;
+
+        (expires > now).then(|| SessionToken {
+            id: id.to_string(),
+            expires,
+        })
+    }
+}
+
+/// HMAC of a token's ID and expiry time
+fn token_mac(key: &[u8], id: &str, expires: u64) -> HmacSha256 {
+    // HMAC accepts keys of any length
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC key of any length");
+    mac.update(format!("{id}.{expires}").as_bytes());
+    mac
+}
+
+/// Find a cookie in a `Cookie` header value
+#[must_use]
+pub fn cookie_value<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    cookie_values(header, name).next()
+}
+
+/// All values of a cookie in a `Cookie` header value
+///
+/// Browsers send a cookie once per matching `Path`, most specific path first.
+pub fn cookie_values<'a>(header: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    header
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .filter(move |(key, _)| *key == name)
+        .map(|(_, value)| value.trim_matches('"'))
+}
+
+/// Check whether a cookie name is accepted by `x402_session` and `x402_access_pass`
+#[must_use]
+pub fn is_valid_cookie_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"_-.".contains(&b))
+}
+
+/// `Set-Cookie` header value for a session token
+#[must_use]
+pub fn session_cookie(name: &str, token: &str, max_age: u64) -> String {
+    format!("{name}={token}; Path=/; Max-Age={max_age}; HttpOnly; Secure; SameSite=Lax")
+}
+
+/// JSON body answering a session purchase
+#[must_use]
+pub fn render_session_body(token: &str, expires: u64, requests: Option<u64>) -> Vec<u8> {
+    serde_json::json!({
+        "token": token,
+        "expiresAt": expires,
+        "requests": requests,
+    })
+    .to_string()
+    .into_bytes()
+}
+
+/// Reserve the slot of a new session
+///
+/// The reserved session has no requests, so its token can't be redeemed until
+/// [`activate_session`] gives it its balance. Live sessions are never evicted.
+///
+/// # Returns
+/// - `InsertResult::Full` if the probe window of the session only holds live sessions
+pub fn reserve_session(table: &mut SlotTable, token: &SessionToken, now: u64) -> InsertResult {
+    table.insert(table.fingerprint(&token.id), RESERVED, token.expires, now)
+}
+
+/// Give a reserved session its balance of requests
+///
+/// # Returns
+/// - `InsertResult::Full` if the reservation expired and its slot was taken
+pub fn activate_session(
+    table: &mut SlotTable,
+    token: &SessionToken,
+    requests: Option<u64>,
+    now: u64,
+) -> InsertResult {
+    let balance = requests.unwrap_or(UNLIMITED);
+    table.set(table.fingerprint(&token.id), balance, token.expires, now)
+}
+
+/// Session token presented with the request (header first, then cookie)
+fn presented_token(r: &Request, cookie: &str) -> Option<String> {
+    if let Some(token) = get_header_value(r, SESSION_HEADER) {
+        return Some(token);
+    }
+    r.headers_in_iterator()
+        .filter(|(key, _)| {
+            key.to_str()
+                .is_ok_and(|key| key.eq_ignore_ascii_case("Cookie"))
+        })
+        .find_map(|(_, value)| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|header| cookie_value(header, cookie))
+                .map(str::to_string)
+        })
+}
+
+/// Accept a session token in place of a payment
+///
+/// Takes one request from the session's balance. Missing, invalid, expired and
+/// used up tokens are ignored, so the request goes through the payment flow.
+///
+/// # Returns
+/// - `true` if the request is paid for by the session
+pub fn redeem_session(r: &mut Request, session: &SessionConfig) -> bool {
+    let Some(token) = presented_token(r, &session.cookie) else {
+        return false;
+    };
+    let now = unix_now();
+    let Some(token) = SessionToken::decode(&token, &session.key, now) else {
+        log_debug(Some(r), "Ignoring invalid or expired session token");
+        return false;
+    };
+
+    let remaining = match session
+        .table
+        .with_table(|table| table.decrement(table.fingerprint(&token.id), now))
+    {
+        Ok(Some(remaining)) => remaining,
+        Ok(None) => {
+            log_debug(Some(r), "Session has no requests left");
+            return false;
+        }
+        Err(e) => {
+            log_warn(Some(r), &format!("Failed to read session zone: {e}"));
+            return false;
+        }
+    };
+
+    if remaining == UNLIMITED {
+        log_debug(Some(r), "Request paid by session");
+    } else {
+        log_debug(
+            Some(r),
+            &format!("Request paid by session, {remaining} requests left"),
+        );
+    }
+    X402Metrics::get().record_session_request();
+    update_request_ctx(r, |ctx| ctx.status = PaymentStatus::Session);
+    true
+}
+
+/// Reserve a session for a verified payment at an `x402_session_purchase` location
+///
+/// Called by the handler before the payment is settled, so funds are never moved
+/// for a session that can't be stored. A request resumed after a non-blocking
+/// settlement keeps its reservation.
+///
+/// # Returns
+/// - `false` if the session zone is full
+///
+/// # Errors
+/// - Returns error if no session ID can be generated or the zone can't be read
+pub fn reserve_session_purchase(
+    r: &mut Request,
+    session: &SessionConfig,
+    grant: SessionGrant,
+) -> Result<bool> {
+    if request_ctx_mut(r).is_some_and(|ctx| ctx.reserved_session.is_some()) {
+        return Ok(true);
+    }
+
+    let now = unix_now();
+    let token = SessionToken::generate(now.saturating_add(grant.duration))?;
+    let reserved = session
+        .table
+        .with_table(|table| reserve_session(table, &token, now))?;
+    if reserved == InsertResult::Full {
+        log_warn(
+            Some(r),
+            &format!(
+                "Session zone \"{}\" is full, not selling a session",
+                session.table.name()
+            ),
+        );
+        return Ok(false);
+    }
+
+    let ctx = get_or_create_request_ctx(r)
+        .ok_or_else(|| ConfigError::from("Failed to allocate request context"))?;
+    ctx.reserved_session = Some(token);
+    Ok(true)
+}
+
+/// Free the slot reserved for a session purchase that didn't go through
+///
+/// e.g. the settlement failed.
+pub fn release_session_purchase(r: &mut Request, session: &SessionConfig) {
+    let Some(token) = request_ctx_mut(r).and_then(|ctx| ctx.reserved_session.take()) else {
+        return;
+    };
+    if let Err(e) = session
+        .table
+        .with_table(|table| table.remove(table.fingerprint(&token.id)))
+    {
+        log_warn(Some(r), &format!("Failed to release session slot: {e}"));
+    }
+}
+
+/// Answer a verified payment at an `x402_session_purchase` location with a token
+///
+/// The payment has been settled by the handler before (purchase locations can't
+/// use `x402_settle off`), so a session is only issued for funds that were
+/// actually moved. Its slot was
+/// reserved before the settlement (see [`reserve_session_purchase`]).
+///
+/// # Errors
+/// - Returns error if the session can't be stored or the response can't be sent
+pub fn complete_session_purchase(
+    r: &mut Request,
+    session: &SessionConfig,
+    grant: SessionGrant,
+) -> Result<()> {
+    let verified = request_ctx_mut(r).is_some_and(|ctx| ctx.status == PaymentStatus::Valid);
+    if !verified {
+        // e.g. the facilitator failed with `x402_facilitator_fallback pass`
+        log_warn(
+            Some(r),
+            "Payment was not verified, not issuing a session token",
+        );
+        return send_503_response(r, DEFAULT_FALLBACK_RETRY_AFTER);
+    }
+
+    let Some(token) = request_ctx_mut(r).and_then(|ctx| ctx.reserved_session.take()) else {
+        log_warn(
+            Some(r),
+            "No session was reserved, not issuing a session token",
+        );
+        return send_503_response(r, DEFAULT_FALLBACK_RETRY_AFTER);
+    };
+
+    let now = unix_now();
+    let expires = token.expires;
+    let activated = session
+        .table
+        .with_table(|table| activate_session(table, &token, grant.requests, now))?;
+    if activated == InsertResult::Full {
+        log_warn(
+            Some(r),
+            "Session reservation expired before the payment was settled, not issuing a session token",
+        );
+        return send_503_response(r, DEFAULT_FALLBACK_RETRY_AFTER);
+    }
+
+    let encoded = token.encode(&session.key);
+    log_info(Some(r), &format!("Session issued, expires at {expires}"));
+    X402Metrics::get().record_session_issued();
+
+    r.set_status(HTTPStatus::from_u16(200).map_err(|_| ConfigError::from("Invalid status code"))?);
+    r.add_header_out(
+        "Set-Cookie",
+        &session_cookie(&session.cookie, &encoded, grant.duration),
+    )
+    .ok_or_else(|| ConfigError::from("Failed to set Set-Cookie header"))?;
+    r.add_header_out("Cache-Control", "no-store")
+        .ok_or_else(|| ConfigError::from("Failed to set Cache-Control header"))?;
+    r.add_header_out("Content-Type", "application/json; charset=utf-8")
+        .ok_or_else(|| ConfigError::from("Failed to set Content-Type header"))?;
+    send_response_body(r, &render_session_body(&encoded, expires, grant.requests))
+}
@@ -0,0 +1,103 @@
+//! Payment settlement
+//!
+//! Settlement calls the facilitator's `/settle` endpoint for a payment that has
+//! already been verified. Depending on `x402_settle`, this happens either right
+//! after verification (`before_upstream`, the request is suspended until the
+//! facilitator responds) or once the response is known to be successful
+//! (`after_success`, the response is held meanwhile). Neither blocks the nginx
+//! worker, see `async_verify`.
+
+use crate::ngx_module::async_verify::CompletedSettlement;
+use crate::ngx_module::ctx::PendingSettlement;
+use crate::ngx_module::logging::{log_error, log_info, log_warn};
+use crate::ngx_module::metrics::X402Metrics;
+use crate::ngx_module::runtime::{get_runtime, settle_payment};
+use ngx::http::Request;
+use rust_x402::types::SettleResponse;
+use std::time::Instant;
+
+/// Settle a verified payment with the facilitator, blocking the worker
+///
+/// Only used by the content handler, which can't suspend the request (see
+/// `VerificationMode::Blocking`).
+///
+/// # Returns
+/// - `Some(SettleResponse)` if the facilitator reported a successful settlement
+/// - `None` if settlement failed or the facilitator rejected it (details are logged)
+pub fn settle_pending_payment(r: &Request, pending: &PendingSettlement) -> Option<SettleResponse> {
+    let runtime = match get_runtime() {
+        Ok(rt) => rt,
+        Err(e) => {
+            log_error(
+                Some(r),
+                &format!("Failed to get runtime for settlement: {e}"),
+            );
+            let metrics = X402Metrics::get();
+            metrics.record_settlement_attempt();
+            metrics.record_settlement_failed();
+            return None;
+        }
+    };
+
+    let settlement_start = Instant::now();
+    let result = runtime.block_on(async {
+        settle_payment(
+            &pending.payment_b64,
+            &pending.requirements,
+            &pending.facilitators,
+            pending.policy,
+        )
+        .await
+    });
+    finish_settlement(
+        r,
+        CompletedSettlement {
+            result,
+            duration_secs: settlement_start.elapsed().as_secs_f64(),
+        },
+    )
+}
+
+/// Record the outcome of a settlement call
+///
+/// Records settlement metrics and logs the result.
+///
+/// # Returns
+/// - `Some(SettleResponse)` if the facilitator reported a successful settlement
+/// - `None` if settlement failed or the facilitator rejected it (details are logged)
+pub fn finish_settlement(r: &Request, settlement: CompletedSettlement) -> Option<SettleResponse> {
+    let metrics = X402Metrics::get();
+    metrics.record_settlement_attempt();
+    metrics.record_settlement_duration(settlement.duration_secs);
+
+    match settlement.result {
+        Ok(response) if response.success => {
+            log_info(
+                Some(r),
+                &format!(
+                    "Payment settled: transaction={}, network={}",
+                    response.transaction, response.network
+                ),
+            );
+            metrics.record_settlement_success();
+            Some(response)
+        }
+        Ok(response) => {
+            log_warn(
+                Some(r),
+                &format!(
+                    "Facilitator rejected settlement: error_reason={}",
+                    response.error_reason.as_deref().unwrap_or("none")
+                ),
+            );
+            metrics.record_settlement_failed();
+            None
+        }
+        Err(e) => {
+            log_error(Some(r), &format!("Payment settlement error: {e}"));
+            metrics.record_facilitator_error();
+            metrics.record_settlement_failed();
+            None
+        }
+    }
+}
@@ -0,0 +1,374 @@
+//! Shared memory tables
+//!
+//! State that must be consistent across all worker processes (e.g. which payment
+//! authorizations have already been used) lives in nginx shared memory zones.
+//!
+//! Each zone holds a fixed-size hash table allocated once, when the zone is
+//! created. Keys are stored as 128-bit fingerprints together with an expiry time,
+//! so entries never need to be freed: an expired slot is simply reused. Lookups
+//! probe a bounded window of slots. Live entries are never evicted: if the window
+//! is full, nothing is stored and the caller fails closed.
+//!
+//! Fingerprints are keyed with a random secret generated when the zone is
+//! created, so clients can't choose keys that collide in a probe window.
+//!
+//! All table operations take the zone's slab pool mutex, which nginx creates for
+//! every shared memory zone.
+
+use crate::ngx_module::error::{ConfigError, Result};
+use crate::ngx_module::panic_handler::catch_panic;
+use hmac::{Hmac, Mac};
+use ngx::core::NgxStr;
+use ngx::ffi::{
+    ngx_conf_t, ngx_int_t, ngx_shm_zone_t, ngx_shmtx_lock, ngx_shmtx_unlock, ngx_slab_alloc,
+    ngx_slab_pool_t, ngx_str_t,
+};
+use sha2::Sha256;
+use std::ffi::c_void;
+use std::ptr::{self, NonNull};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Number of slots probed for a key
+pub const PROBE_WINDOW: usize = 32;
+
+/// Minimum zone size in pages (same limit as nginx's own zones)
+const MIN_ZONE_PAGES: usize = 8;
+
+/// Secret keying the fingerprints of a zone
+pub type ZoneSecret = [u8; 32];
+
+/// Table slot
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Slot {
+    /// Key fingerprint
+    pub key: [u64; 2],
+    /// Expiry time (unix seconds), 0 if the slot is empty
+    pub expires: u64,
+    /// Value stored with the key (meaning depends on the table)
+    pub value: u64,
+}
+
+impl Slot {
+    /// Whether the slot holds an entry that has not expired at `now`
+    #[must_use]
+    pub fn is_live(&self, now: u64) -> bool {
+        self.expires > now
+    }
+}
+
+/// Result of [`SlotTable::insert`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertResult {
+    /// The key was added
+    Inserted,
+    /// The key is already present and has not expired
+    Exists,
+    /// The probe window only holds live entries, the key was not added
+    Full,
+}
+
+/// Hash table over a slice of slots
+pub struct SlotTable<'a> {
+    slots: &'a mut [Slot],
+    secret: ZoneSecret,
+}
+
+impl<'a> SlotTable<'a> {
+    /// Create a table over `slots`, keyed with `secret`
+    pub fn new(slots: &'a mut [Slot], secret: ZoneSecret) -> Self {
+        Self { slots, secret }
+    }
+
+    /// Fingerprint of `key` in this table
+    #[must_use]
+    pub fn fingerprint(&self, key: &str) -> [u64; 2] {
+        fingerprint(&self.secret, key)
+    }
+
+    /// Indices of the slots probed for `key`
+    fn probe(&self, key: [u64; 2]) -> impl Iterator<Item = usize> {
+        let capacity = self.slots.len();
+        let start = if capacity == 0 {
+            0
+        } else {
+            (key[0] % capacity as u64) as usize
+        };
+        (0..PROBE_WINDOW.min(capacity)).map(move |i| (start + i) % capacity)
+    }
+
+    /// Add `key` unless a live entry for it exists
+    ///
+    /// # Arguments
+    /// - `key`: Key fingerprint (see [`SlotTable::fingerprint`])
+    /// - `value`: Value stored with the key
+    /// - `expires`: Expiry time of the new entry (unix seconds, must be > `now`)
+    /// - `now`: Current time (unix seconds)
+    pub fn insert(&mut self, key: [u64; 2], value: u64, expires: u64, now: u64) -> InsertResult {
+        let mut free = None;
+
+        // The whole window is scanned: removals leave holes before the key's slot
+        for index in self.probe(key) {
+            let slot = self.slots[index];
+            if !slot.is_live(now) {
+                free.get_or_insert(index);
+            } else if slot.key == key {
+                return InsertResult::Exists;
+            }
+        }
+
+        let Some(index) = free else {
+            return InsertResult::Full;
+        };
+        self.slots[index] = Slot {
+            key,
+            expires,
+            value,
+        };
+        InsertResult::Inserted
+    }
+
+    /// Add or replace the entry for `key`
+    pub fn set(&mut self, key: [u64; 2], value: u64, expires: u64, now: u64) -> InsertResult {
+        self.remove(key);
+        self.insert(key, value, expires, now)
+    }
+
+    /// Get the value of the live entry for `key`
+    #[must_use]
+    pub fn get(&self, key: [u64; 2], now: u64) -> Option<u64> {
+        self.probe(key)
+            .map(|index| self.slots[index])
+            .find(|slot| slot.is_live(now) && slot.key == key)
+            .map(|slot| slot.value)
+    }
+
+    /// Check whether a live entry exists for `key`
+    #[must_use]
+    pub fn contains(&self, key: [u64; 2], now: u64) -> bool {
+        self.get(key, now).is_some()
+    }
+
+    /// Take one unit from the value of the live entry for `key`
+    ///
+    /// A value of `u64::MAX` stands for an unlimited balance and is left unchanged.
+    ///
+    /// # Returns
+    /// - `Some(remaining)` if a unit was taken
+    /// - `None` if there is no live entry for `key` or its value is 0
+    pub fn decrement(&mut self, key: [u64; 2], now: u64) -> Option<u64> {
+        let index = self
+            .probe(key)
+            .find(|index| self.slots[*index].is_live(now) && self.slots[*index].key == key)?;
+        let slot = &mut self.slots[index];
+        match slot.value {
+            0 => None,
+            u64::MAX => Some(u64::MAX),
+            _ => {
+                slot.value -= 1;
+                Some(slot.value)
+            }
+        }
+    }
+
+    /// Remove the entry for `key`, if any
+    pub fn remove(&mut self, key: [u64; 2]) {
+        for index in self.probe(key) {
+            if self.slots[index].expires != 0 && self.slots[index].key == key {
+                self.slots[index] = Slot::default();
+            }
+        }
+    }
+}
+
+/// Compute the 128-bit fingerprint of a key (HMAC-SHA256 with the zone's secret)
+#[must_use]
+pub fn fingerprint(secret: &ZoneSecret, key: &str) -> [u64; 2] {
+    // HMAC accepts keys of any length
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC key of any length");
+    mac.update(key.as_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let mut parts = [0u64; 2];
+    for (part, bytes) in parts.iter_mut().zip(digest.chunks_exact(8)) {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(bytes);
+        *part = u64::from_le_bytes(word);
+    }
+    parts
+}
+
+/// Header of the table stored in a shared memory zone (followed by the slots)
+#[repr(C)]
+struct SharedTableHeader {
+    capacity: usize,
+    secret: ZoneSecret,
+}
+
+/// Hash table in a shared memory zone
+///
+/// Created with [`add_table_zone`] at configuration time; usable in worker
+/// processes once nginx has initialized the zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SharedTable {
+    zone: NonNull<ngx_shm_zone_t>,
+}
+
+impl SharedTable {
+    /// Wrap a zone created by [`add_table_zone`]
+    #[must_use]
+    pub fn new(zone: NonNull<ngx_shm_zone_t>) -> Self {
+        Self { zone }
+    }
+
+    /// Zone name (for logging)
+    #[must_use]
+    pub fn name(&self) -> String {
+        // Safe: the zone lives as long as the configuration cycle
+        unsafe {
+            NgxStr::from_ngx_str(self.zone.as_ref().shm.name)
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+
+    /// Run `f` on the table with the zone locked
+    ///
+    /// # Errors
+    /// - Returns error if the zone has not been initialized
+    pub fn with_table<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut SlotTable<'_>) -> T,
+    {
+        // Safe: the zone was registered with add_table_zone and nginx initialized it
+        // (table_zone_init) before workers started; access is serialized by the
+        // slab pool mutex
+        unsafe {
+            let zone = self.zone.as_ptr();
+            let header = (*zone).data.cast::<SharedTableHeader>();
+            let shpool = (*zone).shm.addr.cast::<ngx_slab_pool_t>();
+            if header.is_null() || shpool.is_null() {
+                return Err(ConfigError::from(format!(
+                    "Shared memory zone \"{}\" is not initialized",
+                    self.name()
+                )));
+            }
+
+            let slots =
+                std::slice::from_raw_parts_mut(header.add(1).cast::<Slot>(), (*header).capacity);
+            let secret = (*header).secret;
+            ngx_shmtx_lock(&raw mut (*shpool).mutex);
+            let result = catch_panic(
+                || f(&mut SlotTable::new(slots, secret)),
+                "shared table operation",
+            );
+            ngx_shmtx_unlock(&raw mut (*shpool).mutex);
+
+            result.ok_or_else(|| ConfigError::from("Shared table operation panicked"))
+        }
+    }
+}
+
+/// Register a shared memory zone holding a [`SharedTable`]
+///
+/// Zones with the same name and size are shared between all directives that
+/// declare them.
+///
+/// # Arguments
+/// - `cf`: Nginx configuration context
+/// - `name`: Zone name
+/// - `size`: Zone size as written in the configuration (e.g. `10m`)
+/// - `tag`: Owner tag (the module)
+///
+/// # Errors
+/// - Returns error if the size is invalid or too small
+/// - Returns error if the zone cannot be added (e.g. a zone with the same name
+///   but a different size exists)
+///
+/// # Safety
+///
+/// `cf` must be a valid configuration context, and `name` must be allocated from
+/// the configuration pool.
+pub unsafe fn add_table_zone(
+    cf: *mut ngx_conf_t,
+    name: ngx_str_t,
+    size: ngx_str_t,
+    tag: *mut c_void,
+) -> Result<NonNull<ngx_shm_zone_t>> {
+    let mut size_str = size;
+    let size = ngx::ffi::ngx_parse_size(&raw mut size_str);
+    if size == ngx::ffi::NGX_ERROR as isize {
+        return Err(ConfigError::from(format!(
+            "invalid zone size \"{}\"",
+            NgxStr::from_ngx_str(size_str).to_string_lossy()
+        )));
+    }
+    let min_size = MIN_ZONE_PAGES * ngx::ffi::ngx_pagesize;
+    if (size as usize) < min_size {
+        return Err(ConfigError::from(format!(
+            "zone \"{}\" is too small",
+            NgxStr::from_ngx_str(name).to_string_lossy()
+        )));
+    }
+
+    let mut name = name;
+    let zone = ngx::ffi::ngx_shared_memory_add(cf, &raw mut name, size as usize, tag);
+    let zone = NonNull::new(zone).ok_or_else(|| ConfigError::from("failed to add zone"))?;
+
+    // Declared by another directive already: the same table is shared
+    if (*zone.as_ptr()).init.is_none() {
+        (*zone.as_ptr()).init = Some(table_zone_init);
+        (*zone.as_ptr()).data = ptr::null_mut();
+    }
+
+    Ok(zone)
+}
+
+/// Zone initialization: allocate the table in the zone's slab pool
+///
+/// On reload, the table of the previous cycle (`data`) is kept, so its entries
+/// (and its secret) survive `nginx -s reload`.
+unsafe extern "C" fn table_zone_init(
+    shm_zone: *mut ngx_shm_zone_t,
+    data: *mut c_void,
+) -> ngx_int_t {
+    if !data.is_null() {
+        (*shm_zone).data = data;
+        return ngx::ffi::NGX_OK as ngx_int_t;
+    }
+
+    let shpool = (*shm_zone).shm.addr.cast::<ngx_slab_pool_t>();
+    if (*shm_zone).shm.exists != 0 {
+        (*shm_zone).data = (*shpool).data;
+        return ngx::ffi::NGX_OK as ngx_int_t;
+    }
+
+    // Use all free pages but one (slab bookkeeping) for the table
+    let pagesize = ngx::ffi::ngx_pagesize;
+    let bytes = ((*shpool).pfree as usize).saturating_sub(1) * pagesize;
+    let header_size = std::mem::size_of::<SharedTableHeader>();
+    let capacity = bytes.saturating_sub(header_size) / std::mem::size_of::<Slot>();
+    if capacity == 0 {
+        return ngx::ffi::NGX_ERROR as ngx_int_t;
+    }
+
+    let mut secret = ZoneSecret::default();
+    if getrandom::getrandom(&mut secret).is_err() {
+        return ngx::ffi::NGX_ERROR as ngx_int_t;
+    }
+
+    let table_size = header_size + capacity * std::mem::size_of::<Slot>();
+    let header = ngx_slab_alloc(shpool, table_size).cast::<SharedTableHeader>();
+    if header.is_null() {
+        return ngx::ffi::NGX_ERROR as ngx_int_t;
+    }
+    ptr::write_bytes(header.cast::<u8>(), 0, table_size);
+    (*header).capacity = capacity;
+    (*header).secret = secret;
+
+    (*shpool).data = header.cast();
+    (*shm_zone).data = header.cast();
+
+    ngx::ffi::NGX_OK as ngx_int_t
+}
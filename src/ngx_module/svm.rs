@@ -0,0 +1,162 @@
+//! Solana (SVM) payments
+//!
+//! On Solana, the `exact` scheme carries a partially signed transaction instead
+//! of an EIP-3009 authorization:
+//!
+//! ```json
+//! {"x402Version": 1, "scheme": "exact", "network": "solana",
+//!  "payload": {"transaction": "<base64 transaction>"}}
+//! ```
+//!
+//! The transaction transfers `maxAmountRequired` of the SPL mint `asset` to
+//! `payTo`, with the facilitator's `extra.feePayer` as fee payer. The module
+//! can't check it itself: it is verified and settled by the facilitator, which
+//! adds its signature. rust-x402 only models EVM payloads, so the facilitator is
+//! called with the payload as sent by the client.
+
+use crate::ngx_module::error::{ConfigError, Result};
+use crate::ngx_module::failover::FacilitatorError;
+use crate::ngx_module::runtime::DEFAULT_FACILITATOR_TIMEOUT;
+use rust_x402::types::{PaymentRequirements, SettleResponse, VerifyResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// HTTP client for facilitator calls with Solana payloads
+///
+/// rust-x402 doesn't expose the client of its `FacilitatorClient`, so these
+/// calls share their own connection pool.
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/// Solana payment payload (`X-PAYMENT`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SvmPaymentPayload {
+    /// Protocol version identifier
+    #[serde(rename = "x402Version")]
+    pub x402_version: u32,
+    /// Payment scheme identifier
+    pub scheme: String,
+    /// Solana network (e.g., "solana-devnet")
+    pub network: String,
+    /// Signed transaction
+    pub payload: SvmTransaction,
+}
+
+/// Transaction of a Solana payment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SvmTransaction {
+    /// Base64-encoded, partially signed transaction
+    pub transaction: String,
+}
+
+impl SvmPaymentPayload {
+    /// Decode a base64-encoded `X-PAYMENT` header
+    ///
+    /// # Errors
+    /// - Returns error if the header is not base64-encoded JSON of a Solana payload
+    pub fn from_base64(encoded: &str) -> Result<Self> {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let decoded = general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| ConfigError::from(format!("Invalid payment encoding: {e}")))?;
+        serde_json::from_slice(&decoded)
+            .map_err(|e| ConfigError::from(format!("Invalid Solana payment payload: {e}")))
+    }
+}
+
+/// Find the payment requirements a Solana payment was made for
+///
+/// The transaction is opaque to the module, so the first option with the
+/// payload's scheme and network is used.
+#[must_use]
+pub fn select_svm_requirements(
+    requirements: &[PaymentRequirements],
+    payload: &SvmPaymentPayload,
+) -> Option<usize> {
+    requirements
+        .iter()
+        .position(|req| req.scheme == payload.scheme && req.network == payload.network)
+}
+
+/// URL of a facilitator endpoint (`verify` or `settle`)
+///
+/// The base URL may be configured with a trailing slash.
+#[must_use]
+pub fn facilitator_endpoint_url(facilitator_url: &str, endpoint: &str) -> String {
+    format!("{}/{endpoint}", facilitator_url.trim_end_matches('/'))
+}
+
+/// HTTP client of facilitator calls with Solana payloads
+fn http_client() -> rust_x402::Result<&'static reqwest::Client> {
+    if let Some(client) = HTTP_CLIENT.get() {
+        return Ok(client);
+    }
+    let client = reqwest::Client::builder()
+        .connect_timeout(DEFAULT_FACILITATOR_TIMEOUT)
+        .timeout(DEFAULT_FACILITATOR_TIMEOUT)
+        .build()?;
+    Ok(HTTP_CLIENT.get_or_init(|| client))
+}
+
+/// Call a facilitator endpoint (`verify` or `settle`) with a Solana payload
+///
+/// `timeout` (`x402_timeout`) bounds the whole call, connection included.
+async fn call_facilitator<T: serde::de::DeserializeOwned>(
+    facilitator_url: &str,
+    endpoint: &'static str,
+    payload: &SvmPaymentPayload,
+    requirements: &PaymentRequirements,
+    timeout: Duration,
+) -> std::result::Result<T, FacilitatorError> {
+    let client = http_client()?;
+    let request_body = serde_json::json!({
+        "x402Version": payload.x402_version,
+        "paymentPayload": payload,
+        "paymentRequirements": requirements,
+    });
+
+    let response = client
+        .post(facilitator_endpoint_url(facilitator_url, endpoint))
+        .timeout(timeout)
+        .json(&request_body)
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(FacilitatorError::Status {
+            endpoint,
+            status,
+            body,
+        });
+    }
+
+    Ok(response.json().await?)
+}
+
+/// Verify a Solana payment with a facilitator
+///
+/// # Errors
+/// - Returns error if the facilitator can't be reached or answers with an error
+pub async fn verify_svm_payment(
+    facilitator_url: &str,
+    payload: &SvmPaymentPayload,
+    requirements: &PaymentRequirements,
+    timeout: Duration,
+) -> std::result::Result<VerifyResponse, FacilitatorError> {
+    call_facilitator(facilitator_url, "verify", payload, requirements, timeout).await
+}
+
+/// Settle a Solana payment with a facilitator
+///
+/// # Errors
+/// - Returns error if the facilitator can't be reached or answers with an error
+pub async fn settle_svm_payment(
+    facilitator_url: &str,
+    payload: &SvmPaymentPayload,
+    requirements: &PaymentRequirements,
+    timeout: Duration,
+) -> std::result::Result<SettleResponse, FacilitatorError> {
+    call_facilitator(facilitator_url, "settle", payload, requirements, timeout).await
+}
@@ -0,0 +1,327 @@
+//! Upstream-driven pricing
+//!
+//! With `x402_upstream_pricing on`, requests without a payment are passed to the
+//! upstream, which decides the price itself (e.g. per-token LLM pricing). If it
+//! answers with a `402` status, the module rewrites that response into a standard
+//! x402 payment requirements response (JSON or HTML paywall) using the location's
+//! `x402_pay_to`, `x402_network` and `x402_asset` (or `x402_accept` options).
+//!
+//! The price is taken from, in order:
+//!
+//! 1. The `X-X402-Amount` response header (token units, e.g. `0.002`)
+//! 2. A JSON response body: `{"amount": "0.002", "description": "..."}`
+//!    (`amount` may also be a JSON number)
+//! 3. The location's `x402_amount`
+//!
+//! Paid requests carry the amount actually paid in the trusted `X-X402-Amount`
+//! request header, so the upstream can answer `402` again if it is not enough.
+
+use crate::ngx_module::ctx::request_ctx_mut;
+use crate::ngx_module::error::{user_errors, Result};
+use crate::ngx_module::local_verify::parse_uint256;
+use crate::ngx_module::logging::{log_debug, log_warn};
+use crate::ngx_module::messages::request_messages;
+use crate::ngx_module::metrics::X402Metrics;
+use crate::ngx_module::module::get_request_config;
+use crate::ngx_module::request::{
+    build_full_url, infer_mime_type, is_browser_request, take_header_out, wants_html_response,
+};
+use crate::ngx_module::requirements::create_payment_options;
+use crate::ngx_module::response::{content_type_402, paywall_csp_nonce, render_402_body};
+use ngx::http::Request;
+use rust_decimal::Decimal;
+use rust_x402::types::PaymentPayload;
+use std::str::FromStr;
+
+/// Response header the upstream uses to announce the price
+pub const UPSTREAM_AMOUNT_HEADER: &str = "X-X402-Amount";
+
+/// Maximum size of an upstream 402 body that is parsed for a price hint
+pub const MAX_PRICE_HINT_SIZE: usize = 64 * 1024;
+
+/// Price announced by the upstream in a 402 response body
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PriceHint {
+    /// Amount in token units (e.g. `0.002`)
+    pub amount: Option<Decimal>,
+    /// Description shown to the client
+    pub description: Option<String>,
+}
+
+/// Upstream 402 response being rewritten
+#[derive(Debug, Default)]
+pub struct Upstream402 {
+    /// Amount from the `X-X402-Amount` response header
+    pub header_amount: Option<Decimal>,
+    /// Response body collected so far
+    pub body: Vec<u8>,
+    /// Whether the body is collected for a price hint (not for compressed bodies)
+    pub parse_body: bool,
+    /// Body exceeded `MAX_PRICE_HINT_SIZE` and is ignored
+    pub truncated: bool,
+    /// Whether the HTML paywall is sent instead of JSON
+    pub is_browser: bool,
+    /// Nonce of the self-hosted paywall page (`Content-Security-Policy` sent)
+    pub csp_nonce: Option<String>,
+    /// The rewritten body has been sent
+    pub done: bool,
+}
+
+impl Upstream402 {
+    /// Append a chunk of the upstream body
+    pub fn push_body(&mut self, data: &[u8]) {
+        if !self.parse_body || self.truncated {
+            return;
+        }
+        if self.body.len() + data.len() > MAX_PRICE_HINT_SIZE {
+            self.truncated = true;
+            self.body = Vec::new();
+            return;
+        }
+        self.body.extend_from_slice(data);
+    }
+}
+
+/// Parse an amount in token units (e.g. `0.002`)
+///
+/// # Returns
+/// - `Some(Decimal)` if the amount is a valid, non-negative amount
+/// - `None` otherwise
+#[must_use]
+pub fn parse_amount(value: &str) -> Option<Decimal> {
+    let amount = Decimal::from_str(value.trim()).ok()?;
+    crate::config::validate_amount(amount).ok()?;
+    Some(amount)
+}
+
+/// Parse a JSON price hint from an upstream 402 body
+///
+/// # Returns
+/// - `Some(PriceHint)` if the body is a JSON object (fields that are missing or
+///   invalid are `None`)
+/// - `None` if the body is not a JSON object
+#[must_use]
+pub fn parse_price_hint(body: &[u8]) -> Option<PriceHint> {
+    let value: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let object = value.as_object()?;
+
+    let amount = match object.get("amount") {
+        Some(serde_json::Value::String(amount)) => parse_amount(amount),
+        Some(serde_json::Value::Number(amount)) => parse_amount(&amount.to_string()),
+        _ => None,
+    };
+    let description = object
+        .get("description")
+        .and_then(serde_json::Value::as_str)
+        .map(std::string::ToString::to_string);
+
+    Some(PriceHint {
+        amount,
+        description,
+    })
+}
+
+/// Amount authorized by a payment, in atomic token units
+///
+/// With upstream pricing the location has no fixed price, so a payment is
+/// verified against the amount it authorizes, if it covers the location's
+/// `x402_amount` (if any); the upstream then checks the paid amount
+/// (`X-X402-Amount` request header).
+///
+/// # Returns
+/// - `Some(String)` with the authorized value if it is a non-zero integer
+/// - `None` if the payload cannot be decoded or the value is invalid
+#[must_use]
+pub fn authorized_amount(payment_b64: &str) -> Option<String> {
+    let payload = PaymentPayload::from_base64(payment_b64).ok()?;
+    let value = payload.payload.authorization.value;
+    let valid = !value.is_empty()
+        && value.bytes().all(|b| b.is_ascii_digit())
+        && value.bytes().any(|b| b != b'0');
+    valid.then_some(value)
+}
+
+/// Whether an authorized amount covers a price, both in atomic token units
+///
+/// # Returns
+/// - `false` if either amount is not a valid integer
+#[must_use]
+pub fn covers_price(authorized: &str, price: &str) -> bool {
+    parse_uint256(authorized)
+        .zip(parse_uint256(price))
+        .is_some_and(|(authorized, price)| authorized >= price)
+}
+
+/// Start rewriting the response if the upstream asked for a payment
+///
+/// Called from the header filter. Rewrites the response headers for the x402
+/// body and stores the rewrite state in the request context; the body itself is
+/// replaced by the body filter.
+///
+/// # Returns
+/// - `true` if the response is being rewritten
+pub fn intercept_upstream_402(req: &mut Request) -> bool {
+    if req.as_ref().headers_out.status != 402 {
+        return false;
+    }
+    let awaiting = request_ctx_mut(req)
+        .is_some_and(|ctx| std::mem::replace(&mut ctx.awaiting_upstream_price, false));
+    if !awaiting {
+        return false;
+    }
+
+    let header_amount = take_header_out(req, UPSTREAM_AMOUNT_HEADER).and_then(|value| {
+        let amount = parse_amount(&value);
+        if amount.is_none() {
+            log_warn(
+                Some(req),
+                &format!("Ignoring invalid upstream {UPSTREAM_AMOUNT_HEADER} header: {value}"),
+            );
+        }
+        amount
+    });
+    let (is_browser, csp_nonce) = match get_request_config(req).and_then(|conf| conf.parse()) {
+        Ok(config) => {
+            let is_browser = wants_html_response(req, &config);
+            if req
+                .add_header_out("Vary", config.response_format.vary())
+                .is_none()
+            {
+                log_warn(Some(req), "Cannot set Vary header");
+            }
+            let csp_nonce = paywall_csp_nonce(req, &config, is_browser).unwrap_or_else(|e| {
+                log_warn(
+                    Some(req),
+                    &format!("Cannot set paywall Content-Security-Policy: {e}"),
+                );
+                None
+            });
+            (is_browser, csp_nonce)
+        }
+        Err(e) => {
+            log_warn(
+                Some(req),
+                &format!("Cannot evaluate response format, using browser detection: {e}"),
+            );
+            (is_browser_request(req), None)
+        }
+    };
+
+    let r = req.as_mut();
+    // A compressed body can't be parsed, it's replaced without looking at it
+    let parse_body = r.headers_out.content_encoding.is_null()
+        || unsafe { (*r.headers_out.content_encoding).hash } == 0;
+    let header_only = r.header_only() != 0;
+
+    // Safe: the header pointers belong to the live request
+    unsafe {
+        for header in [
+            &mut r.headers_out.content_length,
+            &mut r.headers_out.content_encoding,
+            &mut r.headers_out.etag,
+            &mut r.headers_out.last_modified,
+        ] {
+            if !header.is_null() {
+                (**header).hash = 0;
+                *header = std::ptr::null_mut();
+            }
+        }
+    }
+    r.headers_out.content_length_n = -1;
+    r.headers_out.last_modified_time = -1;
+    r.set_allow_ranges(0);
+
+    let content_type = content_type_402(is_browser);
+    r.headers_out.content_type.len = content_type.len();
+    r.headers_out.content_type.data = content_type.as_ptr().cast_mut();
+    r.headers_out.content_type_len = content_type.len();
+    r.headers_out.content_type_lowcase = std::ptr::null_mut();
+    r.headers_out.content_type_hash = 0;
+
+    log_debug(
+        Some(req),
+        "Upstream responded with 402, rewriting it into payment requirements",
+    );
+    X402Metrics::get().record_402_response();
+
+    if header_only {
+        // No body is sent (HEAD request)
+        return true;
+    }
+    let Some(ctx) = request_ctx_mut(req) else {
+        return false;
+    };
+    ctx.upstream_402 = Some(Upstream402 {
+        header_amount,
+        parse_body,
+        is_browser,
+        csp_nonce,
+        ..Upstream402::default()
+    });
+    true
+}
+
+/// Render the x402 body replacing the upstream 402 response
+///
+/// If no payment requirements can be created (no price), the body only carries
+/// an error message.
+///
+/// # Errors
+/// - Returns error if the location configuration cannot be evaluated
+/// - Returns error if the body cannot be serialized
+pub fn render_upstream_402(req: &mut Request, state: &Upstream402) -> Result<Vec<u8>> {
+    let mut config = get_request_config(req)?.parse()?;
+    let messages = request_messages(req);
+
+    let hint = if state.parse_body && !state.truncated {
+        parse_price_hint(&state.body).unwrap_or_default()
+    } else {
+        PriceHint::default()
+    };
+
+    if let Some(amount) = state.header_amount.or(hint.amount) {
+        // The upstream price applies to every accepted payment option
+        config.amount = Some(amount);
+        for accept in &mut config.accepts {
+            accept.amount = None;
+        }
+    }
+    if hint.description.is_some() {
+        config.description = hint.description;
+    }
+
+    let resource =
+        build_full_url(req).unwrap_or_else(|| req.path().to_str().unwrap_or("/").to_string());
+    let mime_type = infer_mime_type(req);
+    // Fails if neither the upstream nor the location set a price
+    let options = match create_payment_options(&config, &resource, Some(&mime_type)) {
+        Ok(options) => options,
+        Err(e) => {
+            log_warn(
+                Some(req),
+                &format!("Cannot create payment requirements for upstream 402 response: {e}"),
+            );
+            return render_402_body(
+                &[],
+                &config,
+                Some(user_errors::PRICE_UNAVAILABLE),
+                state.is_browser,
+                state.csp_nonce.as_deref(),
+                &messages,
+            );
+        }
+    };
+    let requirements: Vec<_> = options
+        .into_iter()
+        .map(|option| option.requirements)
+        .collect();
+
+    render_402_body(
+        &requirements,
+        &config,
+        None,
+        state.is_browser,
+        state.csp_nonce.as_deref(),
+        &messages,
+    )
+}
@@ -0,0 +1,225 @@
+//! Nginx variables exposed by the x402 module
+//!
+//! Variables are registered in `preconfiguration` so they can be used in any
+//! directive that accepts variables, most notably `log_format`:
+//!
+//! ```nginx
+//! log_format x402 '$remote_addr "$request" $status tx=$x402_tx_hash payer=$x402_payer';
+//! ```
+//!
+//! - `$x402_status`: Payment outcome (`none`, `valid`, `invalid`, `facilitator_error`, `bypassed`, `replayed`, `session`, `access_pass`)
+//! - `$x402_payer`: Verified payer address
+//! - `$x402_amount`: Required amount in token units (e.g. `0.0001`)
+//! - `$x402_network`: Payment network (e.g. `base-sepolia`)
+//! - `$x402_asset`: Payment asset (token contract address)
+//! - `$x402_verify_ms`: Facilitator verification time in milliseconds
+//! - `$x402_resource`: Resource URL of the payment requirements
+//! - `$x402_tx_hash`: Settlement transaction hash
+//!
+//! Variables that have no value for the current request evaluate as "not found"
+//! (logged as `-`). `$x402_status` is always set and is `none` for requests the
+//! module did not process.
+
+use crate::ngx_module::ctx::{request_ctx_mut, PaymentStatus, X402RequestCtx};
+use crate::ngx_module::panic_handler::catch_panic_or_default;
+use ngx::ffi::{
+    ngx_conf_t, ngx_http_add_variable, ngx_http_get_variable_pt, ngx_http_request_t,
+    ngx_http_variable_value_t, ngx_int_t, ngx_str_t, ngx_uint_t,
+};
+use ngx::http::Request;
+use std::ptr;
+
+/// Variable definition (name and getter)
+struct X402Variable {
+    name: &'static str,
+    get_handler: ngx_http_get_variable_pt,
+}
+
+/// Declare a variable getter that reads the request's x402 context
+macro_rules! ctx_variable_handler {
+    ($name:ident, |$ctx:ident| $value:expr) => {
+        unsafe extern "C" fn $name(
+            r: *mut ngx_http_request_t,
+            v: *mut ngx_http_variable_value_t,
+            _data: usize,
+        ) -> ngx_int_t {
+            ctx_variable(r, v, |$ctx: Option<&X402RequestCtx>| $value)
+        }
+    };
+}
+
+/// All variables provided by the module
+static X402_VARIABLES: [X402Variable; 8] = [
+    X402Variable {
+        name: "x402_status",
+        get_handler: Some(x402_status_variable),
+    },
+    X402Variable {
+        name: "x402_payer",
+        get_handler: Some(x402_payer_variable),
+    },
+    X402Variable {
+        name: "x402_amount",
+        get_handler: Some(x402_amount_variable),
+    },
+    X402Variable {
+        name: "x402_network",
+        get_handler: Some(x402_network_variable),
+    },
+    X402Variable {
+        name: "x402_asset",
+        get_handler: Some(x402_asset_variable),
+    },
+    X402Variable {
+        name: "x402_verify_ms",
+        get_handler: Some(x402_verify_ms_variable),
+    },
+    X402Variable {
+        name: "x402_resource",
+        get_handler: Some(x402_resource_variable),
+    },
+    X402Variable {
+        name: "x402_tx_hash",
+        get_handler: Some(x402_tx_hash_variable),
+    },
+];
+
+/// Register module variables
+///
+/// # Safety
+///
+/// `cf` must be a valid configuration context (called from `preconfiguration`).
+pub unsafe fn register_variables(cf: *mut ngx_conf_t) -> ngx_int_t {
+    for variable in &X402_VARIABLES {
+        // ngx_http_add_variable copies the name into the configuration pool
+        let mut name = ngx_str_t {
+            len: variable.name.len(),
+            data: variable.name.as_ptr().cast_mut(),
+        };
+        let var = ngx_http_add_variable(
+            cf,
+            &raw mut name,
+            ngx::ffi::NGX_HTTP_VAR_NOCACHEABLE as ngx_uint_t,
+        );
+        if var.is_null() {
+            return ngx::ffi::NGX_ERROR as ngx_int_t;
+        }
+        (*var).get_handler = variable.get_handler;
+        (*var).data = 0;
+    }
+
+    ngx::ffi::NGX_OK as ngx_int_t
+}
+
+/// Evaluate a variable from the request's x402 context
+///
+/// `getter` receives `None` if the module has not created a context for this
+/// request. The variable is set to "not found" if `getter` returns None.
+unsafe fn ctx_variable<F>(
+    r: *mut ngx_http_request_t,
+    v: *mut ngx_http_variable_value_t,
+    getter: F,
+) -> ngx_int_t
+where
+    F: FnOnce(Option<&X402RequestCtx>) -> Option<String>,
+{
+    if r.is_null() || v.is_null() {
+        return ngx::ffi::NGX_ERROR as ngx_int_t;
+    }
+
+    catch_panic_or_default(
+        || {
+            let req = Request::from_ngx_http_request(r);
+            let value = getter(request_ctx_mut(req).map(|ctx| &*ctx));
+            match value {
+                Some(value) => set_variable_value(req, v, &value),
+                None => {
+                    set_variable_not_found(v);
+                    ngx::ffi::NGX_OK as ngx_int_t
+                }
+            }
+        },
+        "x402 variable",
+        ngx::ffi::NGX_ERROR as ngx_int_t,
+    )
+}
+
+/// Copy `value` to the request pool and store it in the variable
+unsafe fn set_variable_value(
+    req: &Request,
+    v: *mut ngx_http_variable_value_t,
+    value: &str,
+) -> ngx_int_t {
+    let data = if value.is_empty() {
+        ptr::null_mut()
+    } else {
+        let data = req.pool().alloc(value.len()).cast::<u8>();
+        if data.is_null() {
+            return ngx::ffi::NGX_ERROR as ngx_int_t;
+        }
+        ptr::copy_nonoverlapping(value.as_ptr(), data, value.len());
+        data
+    };
+
+    (*v).data = data;
+    (*v).set_len(value.len() as u32);
+    (*v).set_valid(1);
+    (*v).set_no_cacheable(0);
+    (*v).set_not_found(0);
+
+    ngx::ffi::NGX_OK as ngx_int_t
+}
+
+/// Mark the variable as not found
+unsafe fn set_variable_not_found(v: *mut ngx_http_variable_value_t) {
+    (*v).set_valid(0);
+    (*v).set_not_found(1);
+}
+
+// `$x402_status` - payment outcome, `none` if the module did not process the request
+ctx_variable_handler!(x402_status_variable, |ctx| {
+    let status = ctx.map_or(PaymentStatus::None, |ctx| ctx.status);
+    Some(status.as_str().to_string())
+});
+
+// `$x402_payer` - verified payer address (falls back to the payer reported at settlement)
+ctx_variable_handler!(x402_payer_variable, |ctx| {
+    ctx.and_then(|ctx| {
+        ctx.payer.clone().or_else(|| {
+            ctx.settle_response
+                .as_ref()
+                .and_then(|response| response.payer.clone())
+        })
+    })
+});
+
+// `$x402_amount` - required amount in token units
+ctx_variable_handler!(x402_amount_variable, |ctx| {
+    ctx.and_then(|ctx| ctx.amount.clone())
+});
+
+// `$x402_network` - payment network
+ctx_variable_handler!(x402_network_variable, |ctx| {
+    ctx.and_then(|ctx| ctx.network.clone())
+});
+
+// `$x402_asset` - payment asset address
+ctx_variable_handler!(x402_asset_variable, |ctx| {
+    ctx.and_then(|ctx| ctx.asset.clone())
+});
+
+// `$x402_verify_ms` - facilitator verification time in milliseconds
+ctx_variable_handler!(x402_verify_ms_variable, |ctx| {
+    ctx.and_then(|ctx| ctx.verify_ms).map(|ms| ms.to_string())
+});
+
+// `$x402_resource` - resource URL of the payment requirements
+ctx_variable_handler!(x402_resource_variable, |ctx| {
+    ctx.and_then(|ctx| ctx.resource.clone())
+});
+
+// `$x402_tx_hash` - settlement transaction hash
+ctx_variable_handler!(x402_tx_hash_variable, |ctx| {
+    ctx.and_then(|ctx| ctx.settle_response.as_ref())
+        .map(|response| response.transaction.clone())
+});
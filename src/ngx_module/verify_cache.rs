@@ -0,0 +1,112 @@
+//! Facilitator verification cache
+//!
+//! Every request carrying a payment normally costs a facilitator round trip. With
+//! `x402_verify_cache`, verification results are kept in a shared memory zone,
+//! keyed by a hash of the payment payload and the payment requirements it was
+//! verified against:
+//!
+//! - valid results are kept until the authorization's `validBefore` time, but
+//!   only where payments are never settled (`x402_settle off`): elsewhere a
+//!   cached result would keep accepting a payment the facilitator has settled
+//! - invalid results are kept for `negative_ttl` seconds
+//!
+//! Facilitator errors are never cached.
+
+use crate::ngx_module::config::SettleMode;
+use crate::ngx_module::error::Result;
+use crate::ngx_module::shm::SharedTable;
+use rust_x402::types::{PaymentPayload, PaymentRequirements};
+
+/// Default lifetime of cached invalid results (seconds)
+pub const DEFAULT_NEGATIVE_TTL: u64 = 10;
+
+/// Slot value of a valid result
+const VALID: u64 = 1;
+/// Slot value of an invalid result
+const INVALID: u64 = 2;
+
+/// Verification cache of a location
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyCache {
+    /// Shared memory zone holding the results
+    pub table: SharedTable,
+    /// Lifetime of cached invalid results (seconds)
+    pub negative_ttl: u64,
+}
+
+/// Cache key of a payment verified against `requirements`
+#[must_use]
+pub fn verify_cache_key(payment_b64: &str, requirements: &PaymentRequirements) -> String {
+    let requirements = serde_json::to_string(requirements).unwrap_or_default();
+    format!("{payment_b64}\n{requirements}")
+}
+
+/// Whether a verification result may be cached for a location settling with `settle`
+#[must_use]
+pub fn caches_result(valid: bool, settle: SettleMode) -> bool {
+    !valid || settle == SettleMode::Off
+}
+
+/// Time until which a result may be cached (unix seconds)
+///
+/// # Returns
+/// - `Some(expiry)`: `validBefore` for valid payments, `now + negative_ttl` otherwise
+/// - `None` if a valid payment has no usable `validBefore` (not cached)
+#[must_use]
+pub fn cache_expiry(
+    valid: bool,
+    payload: Option<&PaymentPayload>,
+    negative_ttl: u64,
+    now: u64,
+) -> Option<u64> {
+    if !valid {
+        return (negative_ttl > 0).then_some(now + negative_ttl);
+    }
+    payload?
+        .payload
+        .authorization
+        .valid_before
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|valid_before| *valid_before > now)
+}
+
+impl VerifyCache {
+    /// Look up a cached result
+    ///
+    /// # Errors
+    /// - Returns error if the zone is not usable
+    pub fn lookup(&self, key: &str, now: u64) -> Result<Option<bool>> {
+        let value = self
+            .table
+            .with_table(|table| table.get(table.fingerprint(key), now))?;
+        Ok(match value {
+            Some(VALID) => Some(true),
+            Some(INVALID) => Some(false),
+            _ => None,
+        })
+    }
+
+    /// Cache a facilitator result
+    ///
+    /// A result that finds the zone full is not cached.
+    ///
+    /// # Errors
+    /// - Returns error if the zone is not usable
+    pub fn store(
+        &self,
+        key: &str,
+        valid: bool,
+        payload: Option<&PaymentPayload>,
+        now: u64,
+    ) -> Result<()> {
+        let Some(expires) = cache_expiry(valid, payload, self.negative_ttl, now) else {
+            return Ok(());
+        };
+        let value = if valid { VALID } else { INVALID };
+        self.table
+            .with_table(|table| table.set(table.fingerprint(key), value, expires, now))?;
+        Ok(())
+    }
+}
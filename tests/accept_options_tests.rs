@@ -0,0 +1,128 @@
+//! Tests for matching an incoming payment to one of the accepted payment options
+
+use nginx_x402::ngx_module::select_requirements;
+use rust_x402::types::{
+    ExactEvmPayload, ExactEvmPayloadAuthorization, PaymentPayload, PaymentRequirements,
+};
+
+const PAY_TO: &str = "0x209693bc6afc0c5328ba36faf03c514ef312287c";
+const OTHER_PAY_TO: &str = "0x1111111111111111111111111111111111111111";
+
+fn requirements(network: &str, amount: &str, pay_to: &str) -> PaymentRequirements {
+    PaymentRequirements::new(
+        "exact",
+        network,
+        amount,
+        "0x036CbD53842c5426634e7929541eC2318f3dCF7e",
+        pay_to,
+        "https://example.com/api",
+        "",
+    )
+}
+
+fn payload(network: &str, amount: &str, to: &str) -> PaymentPayload {
+    PaymentPayload::new(
+        "exact",
+        network,
+        ExactEvmPayload {
+            signature: "0x".to_string(),
+            authorization: ExactEvmPayloadAuthorization {
+                from: "0x857b06519E91e3A54538791bDbb0E22373e36b66".to_string(),
+                to: to.to_string(),
+                value: amount.to_string(),
+                valid_after: "0".to_string(),
+                valid_before: "9999999999".to_string(),
+                nonce: "0x00".to_string(),
+            },
+        },
+    )
+}
+
+#[test]
+fn test_select_by_network() {
+    let options = [
+        requirements("base", "1000", PAY_TO),
+        requirements("base-sepolia", "100", PAY_TO),
+    ];
+
+    assert_eq!(
+        select_requirements(&options, &payload("base-sepolia", "100", PAY_TO)),
+        Some(1)
+    );
+    assert_eq!(
+        select_requirements(&options, &payload("base", "1000", PAY_TO)),
+        Some(0)
+    );
+}
+
+#[test]
+fn test_select_prefers_matching_amount_and_recipient() {
+    // Same network, different assets/prices/recipients
+    let options = [
+        requirements("base", "1000", PAY_TO),
+        requirements("base", "500000000000000000", PAY_TO),
+        requirements("base", "2000", OTHER_PAY_TO),
+    ];
+
+    assert_eq!(
+        select_requirements(&options, &payload("base", "500000000000000000", PAY_TO)),
+        Some(1)
+    );
+    assert_eq!(
+        select_requirements(&options, &payload("base", "2000", OTHER_PAY_TO)),
+        Some(2)
+    );
+    // Recipient addresses are compared case-insensitively (checksummed payloads)
+    assert_eq!(
+        select_requirements(
+            &options,
+            &payload(
+                "base",
+                "500000000000000000",
+                "0x209693Bc6afc0C5328bA36FaF03C514EF312287C"
+            )
+        ),
+        Some(1)
+    );
+    // Several options pay the recipient, none for the signed amount
+    assert_eq!(
+        select_requirements(&options, &payload("base", "1", PAY_TO)),
+        None
+    );
+}
+
+#[test]
+fn test_select_ambiguous_payment() {
+    let options = [
+        requirements("base", "1000", PAY_TO),
+        requirements("base", "1000", PAY_TO),
+    ];
+    assert_eq!(
+        select_requirements(&options, &payload("base", "1000", PAY_TO)),
+        None
+    );
+
+    // A single option for the network is only used for its recipient
+    let options = [
+        requirements("base", "1000", PAY_TO),
+        requirements("base-sepolia", "1000", PAY_TO),
+    ];
+    assert_eq!(
+        select_requirements(&options, &payload("base", "1000", OTHER_PAY_TO)),
+        None
+    );
+    assert_eq!(
+        select_requirements(&options, &payload("base", "1", PAY_TO)),
+        Some(0)
+    );
+}
+
+#[test]
+fn test_select_no_matching_network() {
+    let options = [requirements("base", "1000", PAY_TO)];
+
+    assert_eq!(
+        select_requirements(&options, &payload("avalanche", "1000", PAY_TO)),
+        None
+    );
+}
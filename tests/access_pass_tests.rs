@@ -0,0 +1,194 @@
+//! Tests for time-based access passes
+
+use nginx_x402::ngx_module::access_pass::{
+    access_pass_cookie, default_scope, format_duration, generated_key, location_key, AccessPass,
+    AccessPassConfig, AccessPassSettings, DEFAULT_ACCESS_PASS_COOKIE, DEFAULT_ACCESS_PASS_SCOPE,
+};
+
+const NOW: u64 = 1_700_000_000;
+const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+const PAYER: &str = "0x209693bc6afc0c5328ba36faf03c514ef312287c";
+
+fn pass(scope: &str, expires: u64) -> AccessPass {
+    AccessPass {
+        payer: PAYER.to_string(),
+        scope: scope.to_string(),
+        expires,
+    }
+}
+
+#[test]
+fn test_parse_access_pass() {
+    let config =
+        AccessPassConfig::parse("duration=24h scope=/articles/ cookie=pass", KEY.to_vec()).unwrap();
+    assert_eq!(config.duration, 86_400);
+    assert_eq!(config.scope, "/articles/");
+    assert_eq!(config.cookie, "pass");
+    assert_eq!(config.key, KEY);
+
+    let config = AccessPassConfig::parse("duration=30m", KEY.to_vec()).unwrap();
+    assert_eq!(config.duration, 1800);
+    assert_eq!(config.scope, DEFAULT_ACCESS_PASS_SCOPE);
+    assert_eq!(config.cookie, DEFAULT_ACCESS_PASS_COOKIE);
+}
+
+#[test]
+fn test_parse_access_pass_rejects_invalid() {
+    for invalid in [
+        "",
+        "scope=/articles/",
+        "duration=0",
+        "duration=1w",
+        "duration=1h duration=2h",
+        "duration=1h scope=articles",
+        "duration=1h scope=/a;b",
+        "duration=1h scope=/a|b",
+        "duration=1h cookie=a;b",
+        "duration=1h price=1",
+        "duration",
+    ] {
+        assert!(
+            AccessPassConfig::parse(invalid, KEY.to_vec()).is_err(),
+            "Accepted '{invalid}'"
+        );
+    }
+}
+
+#[test]
+fn test_scope_and_terms() {
+    let config = AccessPassConfig::parse("duration=24h scope=/articles/", KEY.to_vec()).unwrap();
+    assert!(config.covers("/articles/"));
+    assert!(config.covers("/articles/2024/x402"));
+    assert!(!config.covers("/videos/1"));
+    assert!(!config.covers("/articles"));
+    assert_eq!(config.terms(), "Includes 1 day of access to /articles/");
+}
+
+#[test]
+fn test_format_duration() {
+    assert_eq!(format_duration(45), "45 seconds");
+    assert_eq!(format_duration(60), "1 minute");
+    assert_eq!(format_duration(90 * 60), "90 minutes");
+    assert_eq!(format_duration(12 * 3600), "12 hours");
+    assert_eq!(format_duration(7 * 86_400), "7 days");
+}
+
+#[test]
+fn test_pass_round_trip() {
+    let encoded = pass("/articles/", NOW + 60).encode(KEY);
+    assert!(!encoded.contains(';') && !encoded.contains(' '));
+    assert_eq!(
+        AccessPass::decode(&encoded, KEY, NOW),
+        Some(pass("/articles/", NOW + 60))
+    );
+}
+
+#[test]
+fn test_pass_rejects_tampering_and_expiry() {
+    let encoded = pass("/articles/", NOW + 60).encode(KEY);
+
+    // Wrong key
+    assert_eq!(
+        AccessPass::decode(&encoded, b"another key of at least 32 bytes", NOW),
+        None
+    );
+
+    // Payload of another pass with this pass's signature
+    let (_, mac) = encoded.split_once('.').unwrap();
+    let other = pass("/", NOW + 60).encode(KEY);
+    let (payload, _) = other.split_once('.').unwrap();
+    assert_eq!(
+        AccessPass::decode(&format!("{payload}.{mac}"), KEY, NOW),
+        None
+    );
+
+    // Expired
+    assert_eq!(AccessPass::decode(&encoded, KEY, NOW + 60), None);
+
+    // Malformed
+    for malformed in ["", "abc", "!!!.00", "YQ.zz"] {
+        assert_eq!(AccessPass::decode(malformed, KEY, NOW), None);
+    }
+}
+
+#[test]
+fn test_access_pass_cookie() {
+    let config = AccessPassConfig::parse("duration=24h scope=/articles/", KEY.to_vec()).unwrap();
+    assert_eq!(
+        access_pass_cookie(&config, "abc.ff"),
+        "x402_pass=abc.ff; Path=/articles/; Max-Age=86400; HttpOnly; Secure; SameSite=Lax"
+    );
+}
+
+#[test]
+fn test_generated_key_is_stable() {
+    let key = generated_key().unwrap();
+    assert_eq!(key.len(), 32);
+    assert_eq!(generated_key().unwrap(), key);
+}
+
+#[test]
+fn test_default_scope_is_location_prefix() {
+    assert_eq!(default_scope("/articles/"), Some("/articles/"));
+    assert_eq!(default_scope(""), Some(DEFAULT_ACCESS_PASS_SCOPE));
+    // Named and regex locations have no prefix
+    assert_eq!(default_scope("@fallback"), None);
+    assert_eq!(default_scope("^/articles/"), None);
+    assert_eq!(default_scope("/articles/[0-9]+$"), None);
+}
+
+#[test]
+fn test_pass_rejected_by_another_location() {
+    // Same key file and scope, as with the generated key
+    let key = generated_key().unwrap();
+    let articles = AccessPassConfig::parse(
+        "duration=24h scope=/",
+        location_key(key, "example.com", "/articles/"),
+    )
+    .unwrap();
+    let videos = AccessPassConfig::parse(
+        "duration=24h scope=/",
+        location_key(key, "example.com", "/videos/"),
+    )
+    .unwrap();
+    assert_ne!(articles.key, videos.key);
+
+    let encoded = pass("/", NOW + 60).encode(&articles.key);
+    assert_eq!(
+        AccessPass::decode(&encoded, &articles.key, NOW),
+        Some(pass("/", NOW + 60))
+    );
+    assert_eq!(AccessPass::decode(&encoded, &videos.key, NOW), None);
+}
+
+#[test]
+fn test_pass_rejected_by_another_server() {
+    // Identical locations in two servers, or one directive inherited by both
+    let config = AccessPassConfig::parse("duration=24h scope=/articles/", Vec::new()).unwrap();
+    let settings = AccessPassSettings::new(
+        config,
+        generated_key().unwrap().to_vec(),
+        "/articles/".to_string(),
+    );
+    let shop = settings.for_server("shop.example.com 0.0.0.0:443");
+    let blog = settings.for_server("blog.example.com 0.0.0.0:443");
+    let other_port = settings.for_server("shop.example.com 0.0.0.0:8443");
+    assert_eq!(shop.server.as_deref(), Some("shop.example.com 0.0.0.0:443"));
+    assert_ne!(shop.config.key, blog.config.key);
+    assert_ne!(shop.config.key, other_port.config.key);
+    assert_eq!(
+        shop.config.key,
+        settings
+            .for_server("shop.example.com 0.0.0.0:443")
+            .config
+            .key
+    );
+
+    let encoded = pass("/articles/", NOW + 60).encode(&shop.config.key);
+    assert!(AccessPass::decode(&encoded, &shop.config.key, NOW).is_some());
+    assert_eq!(AccessPass::decode(&encoded, &blog.config.key, NOW), None);
+    assert_eq!(
+        AccessPass::decode(&encoded, &other_port.config.key, NOW),
+        None
+    );
+}
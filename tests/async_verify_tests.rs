@@ -0,0 +1,128 @@
+//! Tests for the bookkeeping of non-blocking facilitator calls
+
+use nginx_x402::ngx_module::async_verify::{
+    block_request, unblock_request, CompletionQueue, Delivery, NotifyChannel,
+};
+use ngx::ffi::ngx_http_request_t;
+use std::sync::Arc;
+
+#[test]
+fn test_completion_queue_tracks_calls_in_flight() {
+    let queue = Arc::new(CompletionQueue::new());
+    for _ in 0..3 {
+        queue.start();
+    }
+    assert_eq!(queue.in_flight(), 3);
+
+    // Results are pushed from tokio threads
+    let workers: Vec<_> = (0..2)
+        .map(|id| {
+            let queue = Arc::clone(&queue);
+            std::thread::spawn(move || queue.push(id))
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    let mut drained = queue.drain();
+    drained.sort_unstable();
+    assert_eq!(drained, vec![0, 1]);
+    assert_eq!(queue.in_flight(), 1);
+    assert!(queue.drain().is_empty());
+    assert_eq!(queue.in_flight(), 1);
+
+    queue.push(2);
+    assert_eq!(queue.drain(), vec![2]);
+    assert_eq!(queue.in_flight(), 0);
+}
+
+#[test]
+fn test_notify_channel_wakes_reader() {
+    let channel = NotifyChannel::new().unwrap();
+    assert!(!channel.clear());
+
+    channel.notify();
+    channel.notify();
+    assert!(channel.clear());
+    assert!(!channel.clear());
+
+    // A full socket buffer never blocks the notifying thread
+    for _ in 0..100_000 {
+        channel.notify();
+    }
+    assert!(channel.clear());
+    assert!(!channel.clear());
+}
+
+/// Zeroed request, its own main request unless `main` is given
+fn request(main: Option<*mut ngx_http_request_t>) -> *mut ngx_http_request_t {
+    // Safe: all-zero bytes are a valid request for the fields under test
+    let r = Box::into_raw(Box::new(unsafe {
+        std::mem::zeroed::<ngx_http_request_t>()
+    }));
+    unsafe {
+        (*r).main = main.unwrap_or(r);
+        (*r).set_count(1);
+    }
+    r
+}
+
+fn free(r: *mut ngx_http_request_t) {
+    drop(unsafe { Box::from_raw(r) });
+}
+
+#[test]
+fn test_suspended_request_is_blocked_until_resumed() {
+    let r = request(None);
+    unsafe {
+        block_request(r, Delivery::Resume);
+        assert_eq!((*r).blocked(), 1);
+        assert_eq!((*r).aio(), 1);
+        assert_eq!((*r).count(), 1);
+
+        unblock_request(r, Delivery::Resume);
+        assert_eq!((*r).blocked(), 0);
+        assert_eq!((*r).aio(), 0);
+    }
+    free(r);
+}
+
+#[test]
+fn test_held_response_keeps_request_referenced() {
+    let r = request(None);
+    unsafe {
+        block_request(r, Delivery::Release);
+        assert_eq!((*r).blocked(), 1);
+        assert_eq!((*r).count(), 2);
+        // The request isn't suspended, the upstream response is still read
+        assert_eq!((*r).aio(), 0);
+
+        // The reference is released by ngx_http_finalize_request(r, NGX_DONE)
+        unblock_request(r, Delivery::Release);
+        assert_eq!((*r).blocked(), 0);
+        assert_eq!((*r).count(), 2);
+    }
+    free(r);
+}
+
+#[test]
+fn test_calls_of_subrequests_block_the_main_request() {
+    let main = request(None);
+    let sub = request(Some(main));
+    unsafe {
+        block_request(main, Delivery::Resume);
+        block_request(sub, Delivery::Resume);
+        assert_eq!((*main).blocked(), 2);
+        assert_eq!((*sub).blocked(), 0);
+        assert_eq!((*sub).aio(), 1);
+
+        unblock_request(sub, Delivery::Resume);
+        assert_eq!((*main).blocked(), 1);
+        assert_eq!((*main).aio(), 1);
+        unblock_request(main, Delivery::Resume);
+        assert_eq!((*main).blocked(), 0);
+    }
+    free(sub);
+    free(main);
+}
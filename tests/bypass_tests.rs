@@ -0,0 +1,138 @@
+//! Tests for payment bypass rules
+
+use nginx_x402::ngx_module::bypass::{BypassReason, BypassRule, BypassRules, Cidr};
+use std::net::IpAddr;
+
+const SECRET: &str = "s3cr3t-internal-token";
+const KEYS: &str = "# partner keys\npartner-a-0123456789\n\n  partner-b-0123456789  \n";
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+fn rules() -> BypassRules {
+    let mut rules = BypassRules::default();
+    rules.add(BypassRule::cidr(&["10.0.0.0/8", "fd00::/8", "192.0.2.7"]).unwrap());
+    rules.add(BypassRule::header("X-Internal-Token", &format!("{SECRET}\n")).unwrap());
+    rules.add(BypassRule::api_keys("X-API-Key", KEYS).unwrap());
+    rules
+}
+
+#[test]
+fn test_cidr_contains() {
+    let range = Cidr::parse("10.1.0.0/16").unwrap();
+    assert!(range.contains(ip("10.1.255.3")));
+    assert!(!range.contains(ip("10.2.0.1")));
+    assert!(range.contains(ip("::ffff:10.1.0.9")));
+    assert!(!range.contains(ip("::1")));
+
+    let range = Cidr::parse("2001:db8::/32").unwrap();
+    assert!(range.contains(ip("2001:db8:1::1")));
+    assert!(!range.contains(ip("2001:db9::1")));
+    assert!(!range.contains(ip("32.1.13.184")));
+
+    assert!(Cidr::parse("0.0.0.0/0")
+        .unwrap()
+        .contains(ip("203.0.113.1")));
+    assert!(Cidr::parse("127.0.0.1").unwrap().contains(ip("127.0.0.1")));
+    assert!(!Cidr::parse("127.0.0.1").unwrap().contains(ip("127.0.0.2")));
+}
+
+#[test]
+fn test_cidr_rejects_invalid() {
+    for invalid in [
+        "10.0.0.0/33",
+        "::/129",
+        "10.0.0.1/8",
+        "10.0.0/8",
+        "10.0.0.0/",
+        "example.com",
+        "",
+    ] {
+        assert!(Cidr::parse(invalid).is_err(), "Accepted {invalid}");
+    }
+}
+
+#[test]
+fn test_match_cidr() {
+    let rules = rules();
+    let no_headers = |_: &str| None;
+    assert_eq!(
+        rules.matches(Some(ip("10.20.30.40")), no_headers),
+        Some(BypassReason::Cidr)
+    );
+    assert_eq!(
+        rules.matches(Some(ip("fd12::1")), no_headers),
+        Some(BypassReason::Cidr)
+    );
+    assert_eq!(
+        rules.matches(Some(ip("192.0.2.7")), no_headers),
+        Some(BypassReason::Cidr)
+    );
+    assert_eq!(rules.matches(Some(ip("192.0.2.8")), no_headers), None);
+    assert_eq!(rules.matches(None, no_headers), None);
+}
+
+#[test]
+fn test_match_secret_header() {
+    let rules = rules();
+    let client = Some(ip("203.0.113.1"));
+    let header = |value: &'static str| {
+        move |name: &str| (name == "X-Internal-Token").then(|| value.to_string())
+    };
+
+    assert_eq!(
+        rules.matches(client, header(SECRET)),
+        Some(BypassReason::Header)
+    );
+    assert_eq!(rules.matches(client, header("s3cr3t-internal-toke")), None);
+    assert_eq!(rules.matches(client, header("")), None);
+}
+
+#[test]
+fn test_match_api_key() {
+    let rules = rules();
+    let client = Some(ip("203.0.113.1"));
+    let header =
+        |value: &'static str| move |name: &str| (name == "X-API-Key").then(|| value.to_string());
+
+    assert_eq!(
+        rules.matches(client, header("partner-a-0123456789")),
+        Some(BypassReason::ApiKey)
+    );
+    assert_eq!(
+        rules.matches(client, header("partner-b-0123456789")),
+        Some(BypassReason::ApiKey)
+    );
+    assert_eq!(rules.matches(client, header("# partner keys")), None);
+    assert_eq!(rules.matches(client, header("partner-c-0123456789")), None);
+
+    // Secrets of one rule don't open another
+    assert_eq!(rules.matches(client, header(SECRET)), None);
+    assert_eq!(BypassRules::default().matches(client, header(SECRET)), None);
+}
+
+#[test]
+fn test_rules_reject_invalid() {
+    assert!(BypassRule::cidr(&[]).is_err());
+    assert!(BypassRule::cidr(&["10.0.0.0/8", "bad"]).is_err());
+    assert!(BypassRule::header("X-Internal-Token", "short\n").is_err());
+    assert!(BypassRule::header("X Internal", SECRET).is_err());
+    assert!(BypassRule::header("", SECRET).is_err());
+    assert!(BypassRule::api_keys("X-API-Key", "# no keys\n\n").is_err());
+    assert!(BypassRule::api_keys("X-API-Key", "partner-a-0123456789\nshort\n").is_err());
+    assert!(BypassRule::api_keys("X-API-Key:", KEYS).is_err());
+}
+
+#[test]
+fn test_reason_labels() {
+    for (reason, label) in [
+        (BypassReason::Method, "method"),
+        (BypassReason::WebSocket, "websocket"),
+        (BypassReason::Cidr, "cidr"),
+        (BypassReason::Header, "header"),
+        (BypassReason::ApiKey, "api_key"),
+    ] {
+        assert_eq!(reason.as_str(), label);
+    }
+}
@@ -0,0 +1,148 @@
+//! Tests for the facilitator circuit breaker
+
+use nginx_x402::ngx_module::circuit_breaker::{call_with_breaker, BreakerState, CircuitBreaker};
+use nginx_x402::ngx_module::failover::{CallError, FailureKind};
+use nginx_x402::ngx_module::{CircuitBreakerConfig, FacilitatorEndpoint};
+use std::time::{Duration, Instant};
+
+fn config() -> CircuitBreakerConfig {
+    CircuitBreakerConfig::parse("failure_rate=50% min_calls=4 window=10s open_time=5s")
+        .unwrap()
+        .unwrap()
+}
+
+/// Breaker opened at `now` by four failed calls
+fn open_breaker(now: Instant) -> CircuitBreaker {
+    let config = config();
+    let mut breaker = CircuitBreaker::new(now);
+    for _ in 0..4 {
+        assert!(breaker.allow(&config, now));
+        breaker.record(&config, false, now);
+    }
+    assert_eq!(breaker.state(), BreakerState::Open);
+    breaker
+}
+
+#[test]
+fn test_opens_at_failure_rate() {
+    let config = config();
+    let now = Instant::now();
+    let mut breaker = CircuitBreaker::new(now);
+
+    breaker.record(&config, true, now);
+    breaker.record(&config, false, now);
+    breaker.record(&config, true, now);
+    assert_eq!(breaker.state(), BreakerState::Closed);
+    // 2 of 4 calls failed
+    breaker.record(&config, false, now);
+    assert_eq!(breaker.state(), BreakerState::Open);
+    assert!(!breaker.allow(&config, now));
+}
+
+#[test]
+fn test_needs_min_calls() {
+    let config = config();
+    let now = Instant::now();
+    let mut breaker = CircuitBreaker::new(now);
+    for _ in 0..3 {
+        breaker.record(&config, false, now);
+    }
+    assert_eq!(breaker.state(), BreakerState::Closed);
+}
+
+#[test]
+fn test_window_resets_counts() {
+    let config = config();
+    let now = Instant::now();
+    let mut breaker = CircuitBreaker::new(now);
+    for _ in 0..3 {
+        breaker.record(&config, false, now);
+    }
+    // Failures of the previous window are forgotten
+    let later = now + Duration::from_secs(10);
+    breaker.record(&config, false, later);
+    assert_eq!(breaker.state(), BreakerState::Closed);
+}
+
+#[test]
+fn test_half_open_probe_closes() {
+    let config = config();
+    let now = Instant::now();
+    let mut breaker = open_breaker(now);
+
+    let later = now + Duration::from_secs(5);
+    assert!(breaker.allow(&config, later));
+    assert_eq!(breaker.state(), BreakerState::HalfOpen);
+    // Only one probe at a time
+    assert!(!breaker.allow(&config, later));
+
+    breaker.record(&config, true, later);
+    assert_eq!(breaker.state(), BreakerState::Closed);
+    assert!(breaker.allow(&config, later));
+}
+
+#[test]
+fn test_half_open_probe_failure_reopens() {
+    let config = config();
+    let now = Instant::now();
+    let mut breaker = open_breaker(now);
+
+    let later = now + Duration::from_secs(5);
+    assert!(breaker.allow(&config, later));
+    breaker.record(&config, false, later);
+    assert_eq!(breaker.state(), BreakerState::Open);
+    // Open for another open_time
+    assert!(!breaker.allow(&config, later + Duration::from_secs(4)));
+    assert!(breaker.allow(&config, later + Duration::from_secs(5)));
+}
+
+#[test]
+fn test_stale_probe_replaced() {
+    let config = config();
+    let now = Instant::now();
+    let mut breaker = open_breaker(now);
+
+    let later = now + Duration::from_secs(5);
+    assert!(breaker.allow(&config, later));
+    // The probe never reported back
+    assert!(breaker.allow(&config, later + Duration::from_secs(5)));
+}
+
+#[test]
+fn test_released_probe_replaced() {
+    let config = config();
+    let now = Instant::now();
+    let mut breaker = open_breaker(now);
+
+    let later = now + Duration::from_secs(5);
+    assert!(breaker.allow(&config, later));
+    // The probe's payment was rejected: the next call probes
+    breaker.release();
+    assert_eq!(breaker.state(), BreakerState::HalfOpen);
+    assert!(breaker.allow(&config, later));
+}
+
+#[test]
+fn test_rejected_payments_not_counted() {
+    let endpoints = [FacilitatorEndpoint::parse("https://rejecting.example.com").unwrap()];
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let call = |kind: FailureKind| {
+        let mut called = false;
+        let result: Result<(), CallError> =
+            runtime.block_on(call_with_breaker(&endpoints, Some(config()), || {
+                called = true;
+                async move { Err(CallError::new(kind, "failed")) }
+            }));
+        assert!(result.is_err());
+        called
+    };
+
+    for _ in 0..10 {
+        assert!(call(FailureKind::Rejected));
+    }
+    // Failures still open it
+    for _ in 0..4 {
+        assert!(call(FailureKind::NoAnswer));
+    }
+    assert!(!call(FailureKind::NoAnswer));
+}
@@ -7,9 +7,14 @@
 
 mod tests {
     use rust_x402::types::networks;
+    use std::ptr::NonNull;
 
     // Import the module to access validation functions
     // Since validation functions are private, we test them through the parse() method
+    use nginx_x402::ngx_module::access_pass::{location_key, AccessPassConfig, AccessPassSettings};
+    use nginx_x402::ngx_module::negotiation::ResponseFormat;
+    use nginx_x402::ngx_module::session::SessionConfig;
+    use nginx_x402::ngx_module::shm::SharedTable;
     use nginx_x402::X402Config;
 
     // Helper to create a minimal X402Config for testing
@@ -28,6 +33,32 @@ mod tests {
             timeout_str: ngx::ffi::ngx_str_t::default(),
             facilitator_fallback_str: ngx::ffi::ngx_str_t::default(),
             ttl_str: ngx::ffi::ngx_str_t::default(),
+            settle_str: ngx::ffi::ngx_str_t::default(),
+            forward_headers_str: ngx::ffi::ngx_str_t::default(),
+            accepts_str: ngx::ffi::ngx_str_t::default(),
+            upstream_pricing_str: ngx::ffi::ngx_str_t::default(),
+            verify_mode_str: ngx::ffi::ngx_str_t::default(),
+            retries_str: ngx::ffi::ngx_str_t::default(),
+            circuit_breaker_str: ngx::ffi::ngx_str_t::default(),
+            fee_payer_str: ngx::ffi::ngx_str_t::default(),
+            session_purchase_str: ngx::ffi::ngx_str_t::default(),
+            receipt_ttl_str: ngx::ffi::ngx_str_t::default(),
+            paywall_assets_url_str: ngx::ffi::ngx_str_t::default(),
+            response_format_str: ngx::ffi::ngx_str_t::default(),
+            amount_cv: None,
+            pay_to_cv: None,
+            description_cv: None,
+            resource_cv: None,
+            replay_zone: None,
+            verify_cache_zone: None,
+            verify_cache_negative_ttl: 0,
+            session: None,
+            paywall_template: None,
+            messages: None,
+            browser_detection: None,
+            bypass: None,
+            receipt: None,
+            access_pass: None,
         }
     }
 
@@ -403,6 +434,887 @@ mod tests {
         );
     }
 
+    // ============================================================================
+    // Variable Value Tests
+    // ============================================================================
+    //
+    // `x402_amount $arg_price;` and `x402_pay_to $payee;` are evaluated per request
+    // into `amount_str`/`pay_to_str` before `parse()`. An error here (or from
+    // `create_payment_options`) is answered with a 500, never passed through.
+
+    #[test]
+    fn test_variable_amount_invalid_value_fails() {
+        for value in [
+            "abc",
+            "-0.01",
+            "1.2.3",
+            "2000000000",
+            "0.0000000000000000001",
+            "$arg_price",
+        ] {
+            let mut config = create_test_config();
+            config.amount_str = ngx_string(value);
+            config.pay_to_str = ngx_string("0x209693Bc6afc0C5328bA36FaF03C514EF312287C");
+
+            assert!(config.parse().is_err(), "Accepted amount {value:?}");
+        }
+    }
+
+    #[test]
+    fn test_variable_pay_to_invalid_value_fails() {
+        for value in [
+            "0x123",
+            "not-an-address",
+            "0x209693Bc6afc0C5328bA36FaF03C514EF312287G",
+            "$payee",
+        ] {
+            let mut config = create_test_config();
+            config.amount_str = ngx_string("0.001");
+            config.pay_to_str = ngx_string(value);
+
+            assert!(config.parse().is_err(), "Accepted pay_to {value:?}");
+        }
+    }
+
+    #[test]
+    fn test_variable_empty_value_fails_requirements() {
+        use nginx_x402::ngx_module::create_payment_options;
+
+        // An unset variable evaluates to an empty string
+        let mut config = create_test_config();
+        config.pay_to_str = ngx_string("0x209693Bc6afc0C5328bA36FaF03C514EF312287C");
+        let parsed = config.parse().unwrap();
+        assert!(parsed.amount.is_none());
+        let error = create_payment_options(&parsed, "/api", None)
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(error.contains("Amount not configured"), "got: {error}");
+
+        let mut config = create_test_config();
+        config.amount_str = ngx_string("0.001");
+        let parsed = config.parse().unwrap();
+        assert!(parsed.pay_to.is_none());
+        let error = create_payment_options(&parsed, "/api", None)
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(
+            error.contains("Pay-to address not configured"),
+            "got: {error}"
+        );
+    }
+
+    // ============================================================================
+    // Settlement Mode Tests
+    // ============================================================================
+
+    #[test]
+    fn test_settle_mode_default() {
+        use nginx_x402::ngx_module::SettleMode;
+
+        let config = create_test_config();
+        let parsed = config.parse().unwrap();
+        assert_eq!(
+            parsed.settle,
+            SettleMode::AfterSuccess,
+            "Settlement should default to after_success"
+        );
+    }
+
+    #[test]
+    fn test_settle_mode_values() {
+        use nginx_x402::ngx_module::SettleMode;
+
+        let cases = [
+            ("before_upstream", SettleMode::BeforeUpstream),
+            ("after_success", SettleMode::AfterSuccess),
+            ("off", SettleMode::Off),
+            ("OFF", SettleMode::Off),
+        ];
+        for (value, expected) in cases {
+            let mut config = create_test_config();
+            config.settle_str = ngx_string(value);
+            let parsed = config.parse().unwrap();
+            assert_eq!(parsed.settle, expected, "Unexpected mode for '{value}'");
+        }
+    }
+
+    #[test]
+    fn test_settle_mode_invalid() {
+        let mut config = create_test_config();
+        config.settle_str = ngx_string("sometimes");
+
+        let result = config.parse();
+        assert!(result.is_err(), "Invalid settle mode should be rejected");
+        let error = result.err().map(|e| e.to_string()).unwrap_or_default();
+        assert!(error.contains("settle"), "Unexpected error: {error}");
+    }
+
+    // ============================================================================
+    // Prepaid Session Tests
+    // ============================================================================
+
+    #[test]
+    fn test_sessions_default_off() {
+        let config = create_test_config();
+        let parsed = config.parse().unwrap();
+        assert!(parsed.session.is_none());
+        assert!(parsed.session_purchase.is_none());
+    }
+
+    #[test]
+    fn test_session_purchase_requires_session_zone() {
+        let mut config = create_test_config();
+        config.session_purchase_str = ngx_string("requests=100 duration=1h");
+        // Checked when the configuration is merged, so nginx refuses to start
+        let error = config
+            .validate()
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(error.contains("x402_session"), "Unexpected error: {error}");
+    }
+
+    #[test]
+    fn test_session_purchase_requires_settlement() {
+        let mut config = create_test_config();
+        let session = SessionConfig {
+            table: SharedTable::new(NonNull::dangling()),
+            key: vec![0xab; 32],
+            cookie: "x402_session".to_string(),
+        };
+        config.session = Some(NonNull::from(Box::leak(Box::new(session))));
+        config.session_purchase_str = ngx_string("requests=100 duration=1h");
+        assert!(config.validate().is_ok());
+
+        config.settle_str = ngx_string("off");
+        let error = config
+            .validate()
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(
+            error.contains("x402_settle off"),
+            "Unexpected error: {error}"
+        );
+
+        config.settle_str = ngx_string("before_upstream");
+        config.verify_mode_str = ngx_string("local");
+        config.replay_zone = Some(NonNull::dangling());
+        let error = config
+            .validate()
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(
+            error.contains("x402_verify_mode local"),
+            "Unexpected error: {error}"
+        );
+    }
+
+    // ============================================================================
+    // Access Pass Tests
+    // ============================================================================
+
+    // Settings of `x402_access_pass` in `location /articles/`, merged into a server
+    fn access_pass_settings(value: &str) -> NonNull<AccessPassSettings> {
+        let config = AccessPassConfig::parse(value, Vec::new()).unwrap();
+        let settings = AccessPassSettings::new(config, vec![0xab; 32], "/articles/".to_string());
+        NonNull::from(Box::leak(Box::new(
+            settings.for_server("example.com 0.0.0.0:80"),
+        )))
+    }
+
+    #[test]
+    fn test_access_pass() {
+        let mut config = create_test_config();
+        assert!(config.parse().unwrap().access_pass.is_none());
+
+        config.access_pass = Some(access_pass_settings("duration=24h scope=/articles/"));
+        let parsed = config.parse().unwrap();
+        let access_pass = parsed.access_pass.unwrap();
+        let access_pass = access_pass.get();
+        assert_eq!(access_pass.duration, 86_400);
+        assert_eq!(access_pass.scope, "/articles/");
+        assert_eq!(
+            access_pass.key,
+            location_key(&[0xab; 32], "example.com 0.0.0.0:80", "/articles/")
+        );
+    }
+
+    #[test]
+    fn test_access_pass_requires_settlement() {
+        let mut config = create_test_config();
+        config.access_pass = Some(access_pass_settings("duration=24h"));
+        config.settle_str = ngx_string("off");
+        let error = config
+            .validate()
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(
+            error.contains("x402_settle off"),
+            "Unexpected error: {error}"
+        );
+
+        config.settle_str = ngx_string("after_success");
+        config.verify_mode_str = ngx_string("local");
+        config.replay_zone = Some(NonNull::dangling());
+        let error = config
+            .validate()
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(
+            error.contains("x402_verify_mode local"),
+            "Unexpected error: {error}"
+        );
+    }
+
+    #[test]
+    fn test_access_pass_not_combined_with_upstream_pricing() {
+        let mut config = create_test_config();
+        config.access_pass = Some(access_pass_settings("duration=24h"));
+        config.upstream_pricing_str = ngx_string("on");
+        // Checked when the configuration is merged, so nginx refuses to start
+        let error = config
+            .validate()
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(
+            error.contains("x402_access_pass"),
+            "Unexpected error: {error}"
+        );
+    }
+
+    // ============================================================================
+    // Receipt Tests
+    // ============================================================================
+
+    #[test]
+    fn test_receipt_keys() {
+        use nginx_x402::ngx_module::receipt::{ReceiptConfig, ReceiptKey, ReceiptSigningKey};
+
+        let mut config = create_test_config();
+        let parsed = config.parse().unwrap();
+        assert!(parsed.receipt.is_none());
+        assert!(!parsed.strips_forward_headers());
+
+        // Decoded by x402_receipt_key and allocated from the configuration pool
+        let receipt = ReceiptConfig {
+            keys: vec![
+                ReceiptKey {
+                    kid: "new".to_string(),
+                    key: ReceiptSigningKey::from_raw("EdDSA", &[1; 32]).unwrap(),
+                },
+                ReceiptKey {
+                    kid: "old".to_string(),
+                    key: ReceiptSigningKey::from_raw("ES256", &[2; 32]).unwrap(),
+                },
+            ],
+            ttl: 300,
+        };
+        config.receipt = Some(NonNull::from(Box::leak(Box::new(receipt))));
+        let parsed = config.parse().unwrap();
+        assert!(parsed.strips_forward_headers());
+        let receipt = parsed.receipt.unwrap();
+        assert_eq!(receipt.get().ttl, 300);
+        let kids: Vec<_> = receipt
+            .get()
+            .keys
+            .iter()
+            .map(|key| key.kid.as_str())
+            .collect();
+        assert_eq!(kids, ["new", "old"]);
+    }
+
+    #[test]
+    fn test_receipt_ttl() {
+        let mut config = create_test_config();
+        assert_eq!(config.receipt_ttl().unwrap(), 300);
+
+        config.receipt_ttl_str = ngx_string("10m");
+        assert_eq!(config.receipt_ttl().unwrap(), 600);
+
+        config.receipt_ttl_str = ngx_string("0");
+        assert!(config.receipt_ttl().is_err());
+    }
+
+    #[test]
+    fn test_paywall_assets_url() {
+        let mut config = create_test_config();
+        assert_eq!(config.parse().unwrap().paywall_assets_url, None);
+
+        config.paywall_assets_url_str = ngx_string("/x402/assets/");
+        assert_eq!(
+            config.parse().unwrap().paywall_assets_url.as_deref(),
+            Some("/x402/assets/")
+        );
+
+        config.paywall_assets_url_str = ngx_string("http://cdn.example.com/");
+        assert!(config.parse().is_err());
+    }
+
+    #[test]
+    fn test_response_format() {
+        let mut config = create_test_config();
+        assert_eq!(
+            config.parse().unwrap().response_format,
+            ResponseFormat::Auto
+        );
+
+        for (value, expected) in [
+            ("json", ResponseFormat::Json),
+            ("HTML", ResponseFormat::Html),
+        ] {
+            config.response_format_str = ngx_string(value);
+            assert_eq!(config.parse().unwrap().response_format, expected);
+        }
+
+        config.response_format_str = ngx_string("xml");
+        assert!(config.parse().is_err());
+    }
+
+    // ============================================================================
+    // Forward Headers Tests
+    // ============================================================================
+
+    #[test]
+    fn test_forward_headers_default_off() {
+        let config = create_test_config();
+        let parsed = config.parse().unwrap();
+        assert!(
+            !parsed.forward_headers,
+            "Header forwarding should be off by default"
+        );
+    }
+
+    #[test]
+    fn test_forward_headers_values() {
+        for (value, expected) in [("on", true), ("ON", true), ("off", false)] {
+            let mut config = create_test_config();
+            config.forward_headers_str = ngx_string(value);
+            let parsed = config.parse().unwrap();
+            assert_eq!(
+                parsed.forward_headers, expected,
+                "Unexpected value for '{value}'"
+            );
+        }
+
+        let mut config = create_test_config();
+        config.forward_headers_str = ngx_string("yes");
+        assert!(
+            config.parse().is_err(),
+            "Invalid forward_headers value should be rejected"
+        );
+    }
+
+    // ============================================================================
+    // Upstream Pricing Tests
+    // ============================================================================
+
+    #[test]
+    fn test_upstream_pricing_default_off() {
+        let config = create_test_config();
+        let parsed = config.parse().unwrap();
+        assert!(
+            !parsed.upstream_pricing,
+            "Upstream pricing should be off by default"
+        );
+    }
+
+    #[test]
+    fn test_upstream_pricing_values() {
+        for (value, expected) in [("on", true), ("Off", false)] {
+            let mut config = create_test_config();
+            config.upstream_pricing_str = ngx_string(value);
+            let parsed = config.parse().unwrap();
+            assert_eq!(
+                parsed.upstream_pricing, expected,
+                "Unexpected value for '{value}'"
+            );
+        }
+
+        let mut config = create_test_config();
+        config.upstream_pricing_str = ngx_string("backend");
+        let error = config
+            .parse()
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(
+            error.contains("upstream_pricing"),
+            "Unexpected error: {error}"
+        );
+    }
+
+    // ============================================================================
+    // Verify Mode Tests
+    // ============================================================================
+
+    #[test]
+    fn test_verify_mode_default_facilitator() {
+        use nginx_x402::ngx_module::VerifyMode;
+
+        let config = create_test_config();
+        let parsed = config.parse().unwrap();
+        assert_eq!(parsed.verify_mode, VerifyMode::Facilitator);
+    }
+
+    #[test]
+    fn test_verify_mode_values() {
+        use nginx_x402::ngx_module::VerifyMode;
+
+        let cases = [
+            ("facilitator", VerifyMode::Facilitator),
+            ("local", VerifyMode::Local),
+            ("Hybrid", VerifyMode::Hybrid),
+        ];
+        for (value, expected) in cases {
+            let mut config = create_test_config();
+            config.verify_mode_str = ngx_string(value);
+            config.replay_zone = Some(NonNull::dangling());
+            let parsed = config.parse().unwrap();
+            assert_eq!(
+                parsed.verify_mode, expected,
+                "Unexpected mode for '{value}'"
+            );
+        }
+
+        let mut config = create_test_config();
+        config.verify_mode_str = ngx_string("offline");
+        let error = config
+            .parse()
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(error.contains("verify_mode"), "Unexpected error: {error}");
+    }
+
+    #[test]
+    fn test_verify_mode_without_settlement_requires_replay_zone() {
+        let cases = [
+            ("local", ""),
+            ("local", "before_upstream"),
+            ("hybrid", ""),
+            ("hybrid", "after_success"),
+            ("hybrid", "off"),
+        ];
+        for (mode, settle) in cases {
+            let mut config = create_test_config();
+            config.verify_mode_str = ngx_string(mode);
+            config.settle_str = ngx_string(settle);
+            let error = config
+                .validate()
+                .err()
+                .map(|e| e.to_string())
+                .unwrap_or_default();
+            assert!(
+                error.contains("x402_replay_zone"),
+                "Unexpected result for '{mode}' '{settle}': {error}"
+            );
+
+            config.replay_zone = Some(NonNull::dangling());
+            assert!(config.validate().is_ok(), "'{mode}' '{settle}' rejected");
+        }
+
+        // The facilitator settles before the upstream is reached
+        let mut config = create_test_config();
+        config.verify_mode_str = ngx_string("hybrid");
+        config.settle_str = ngx_string("before_upstream");
+        assert!(config.validate().is_ok());
+    }
+
+    // ============================================================================
+    // Accepted Payment Option Tests
+    // ============================================================================
+
+    #[test]
+    fn test_accept_option_parse() {
+        use nginx_x402::ngx_module::AcceptOption;
+
+        let option = AcceptOption::parse(
+            "network=base asset=0x036CbD53842c5426634e7929541eC2318f3dCF7e decimals=18 amount=0.5 pay_to=0x209693Bc6afc0C5328bA36FaF03C514EF312287C",
+        )
+        .unwrap();
+        assert_eq!(option.network, "base");
+        assert_eq!(option.asset_decimals, Some(18));
+        assert_eq!(
+            option.amount.map(|a| a.to_string()),
+            Some("0.5".to_string())
+        );
+        assert!(option.asset.is_some());
+        assert!(option.pay_to.is_some());
+
+        // Only the network is required
+        let option = AcceptOption::parse("network=base-sepolia").unwrap();
+        assert!(option.amount.is_none());
+        assert!(option.pay_to.is_none());
+    }
+
+    #[test]
+    fn test_accept_option_invalid() {
+        use nginx_x402::ngx_module::AcceptOption;
+
+        for line in [
+            "amount=0.1",                // missing network
+            "network=base price=0.1",    // unknown parameter
+            "network=base network=base", // duplicate parameter
+            "network=base amount",       // not key=value
+            "network=unknown-chain",     // unsupported network
+            "network=base amount=abc",   // invalid amount
+            "network=base decimals=29",  // decimals out of range
+            "network=base pay_to=0x123", // invalid address
+        ] {
+            assert!(
+                AcceptOption::parse(line).is_err(),
+                "'{line}' should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_accepts_create_one_option_per_directive() {
+        use nginx_x402::ngx_module::create_payment_options;
+
+        let mut config = create_test_config();
+        config.amount_str = ngx_string("0.001");
+        config.pay_to_str = ngx_string("0x209693Bc6afc0C5328bA36FaF03C514EF312287C");
+        config.accepts_str = ngx_string("network=base\nnetwork=base-sepolia amount=0.0001");
+        let parsed = config.parse().unwrap();
+        assert_eq!(parsed.accepts.len(), 2);
+
+        let options = create_payment_options(&parsed, "/api", None).unwrap();
+        assert_eq!(options.len(), 2);
+        // Unset amount falls back to x402_amount
+        assert_eq!(options[0].requirements.network, "base");
+        assert_eq!(options[0].requirements.max_amount_required, "1000");
+        assert_eq!(options[1].requirements.network, "base-sepolia");
+        assert_eq!(options[1].requirements.max_amount_required, "100");
+        assert_ne!(
+            options[0].requirements.asset, options[1].requirements.asset,
+            "Each option should use USDC on its own network"
+        );
+    }
+
+    #[test]
+    fn test_no_accepts_creates_single_option() {
+        use nginx_x402::ngx_module::create_payment_options;
+
+        let mut config = create_test_config();
+        config.amount_str = ngx_string("0.001");
+        config.pay_to_str = ngx_string("0x209693Bc6afc0C5328bA36FaF03C514EF312287C");
+        let parsed = config.parse().unwrap();
+        assert!(parsed.accepts.is_empty());
+
+        let options = create_payment_options(&parsed, "/api", None).unwrap();
+        assert_eq!(options.len(), 1);
+    }
+
+    // ============================================================================
+    // Facilitator Tests
+    // ============================================================================
+
+    #[test]
+    fn test_facilitator_endpoint_parse() {
+        use nginx_x402::ngx_module::FacilitatorEndpoint;
+        use std::time::Duration;
+
+        let endpoint = FacilitatorEndpoint::parse("https://x402.org/facilitator").unwrap();
+        assert_eq!(endpoint.url, "https://x402.org/facilitator");
+        assert_eq!(endpoint.weight, 1);
+        assert_eq!(endpoint.priority, 0);
+        assert_eq!(endpoint.max_fails, 3);
+        assert_eq!(endpoint.fail_timeout, Duration::from_secs(30));
+
+        let endpoint = FacilitatorEndpoint::parse(
+            "https://x402.org/facilitator weight=2 priority=1 max_fails=0 fail_timeout=60s",
+        )
+        .unwrap();
+        assert_eq!(endpoint.weight, 2);
+        assert_eq!(endpoint.priority, 1);
+        assert_eq!(endpoint.max_fails, 0);
+        assert_eq!(endpoint.fail_timeout, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_facilitator_endpoint_invalid() {
+        use nginx_x402::ngx_module::FacilitatorEndpoint;
+
+        for line in [
+            "",                                                   // missing URL
+            "x402.org/facilitator",                               // no scheme
+            "https://x402.org/facilitator weight=0",              // zero weight
+            "https://x402.org/facilitator weight=-1",             // negative weight
+            "https://x402.org/facilitator backup",                // not key=value
+            "https://x402.org/facilitator down=1",                // unknown parameter
+            "https://x402.org/facilitator priority=1 priority=2", // duplicate parameter
+            "https://x402.org/facilitator fail_timeout=1m",       // only seconds
+        ] {
+            assert!(
+                FacilitatorEndpoint::parse(line).is_err(),
+                "'{line}' should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_multiple_facilitators() {
+        let mut config = create_test_config();
+        config.facilitator_url_str = ngx_string(
+            "https://facilitator-a.example.com weight=2\nhttps://x402.org/facilitator priority=1",
+        );
+        let parsed = config.parse().unwrap();
+        assert_eq!(parsed.facilitators.len(), 2);
+        assert_eq!(parsed.facilitators[0].weight, 2);
+        assert_eq!(parsed.facilitators[1].priority, 1);
+        // The first facilitator is the primary one
+        assert_eq!(
+            parsed.facilitator_url.as_deref(),
+            Some("https://facilitator-a.example.com")
+        );
+    }
+
+    #[test]
+    fn test_duplicate_facilitator_rejected() {
+        let mut config = create_test_config();
+        config.facilitator_url_str =
+            ngx_string("https://x402.org/facilitator\nhttps://x402.org/facilitator weight=2");
+        let error = config
+            .parse()
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(error.contains("Duplicate"), "Unexpected error: {error}");
+    }
+
+    #[test]
+    fn test_facilitator_fallback_with_arguments() {
+        use nginx_x402::ngx_module::FacilitatorFallback;
+
+        let mut config = create_test_config();
+        config.facilitator_fallback_str = ngx_string("error\n503\n{\"error\": \"try later\"}");
+        assert_eq!(
+            config.parse().unwrap().facilitator_fallback,
+            FacilitatorFallback::Error {
+                status: 503,
+                body: Some("{\"error\": \"try later\"}".to_string())
+            }
+        );
+
+        config.facilitator_fallback_str = ngx_string("unavailable\n10");
+        assert_eq!(
+            config.parse().unwrap().facilitator_fallback,
+            FacilitatorFallback::Unavailable { retry_after: 10 }
+        );
+
+        config.facilitator_fallback_str = ngx_string("retry");
+        assert!(config.parse().is_err());
+    }
+
+    // ============================================================================
+    // Retry and Circuit Breaker Tests
+    // ============================================================================
+
+    #[test]
+    fn test_retry_policy_parse() {
+        use nginx_x402::ngx_module::RetryPolicy;
+        use std::time::Duration;
+
+        let policy = RetryPolicy::parse("2").unwrap();
+        assert_eq!(policy.retries, 2);
+        assert_eq!(policy.backoff, Duration::from_millis(100));
+
+        let policy = RetryPolicy::parse("3 backoff=1s").unwrap();
+        assert_eq!(policy.backoff, Duration::from_secs(1));
+
+        for line in ["", "-1", "two", "11", "2 backoff=soon", "2 delay=1s"] {
+            assert!(
+                RetryPolicy::parse(line).is_err(),
+                "'{line}' should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_retry_delay_backoff_and_jitter() {
+        use nginx_x402::ngx_module::RetryPolicy;
+        use std::time::Duration;
+
+        let policy = RetryPolicy::parse("3 backoff=100ms").unwrap();
+        // Half of the delay is random
+        assert_eq!(policy.delay(1, 0.0), Duration::from_millis(50));
+        assert_eq!(policy.delay(1, 1.0), Duration::from_millis(100));
+        // Doubled for each retry
+        assert_eq!(policy.delay(2, 0.0), Duration::from_millis(100));
+        assert_eq!(policy.delay(3, 1.0), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_circuit_breaker_parse() {
+        use nginx_x402::ngx_module::CircuitBreakerConfig;
+        use std::time::Duration;
+
+        assert_eq!(CircuitBreakerConfig::parse("off").unwrap(), None);
+
+        let breaker = CircuitBreakerConfig::parse("failure_rate=50%")
+            .unwrap()
+            .unwrap();
+        assert_eq!(breaker.failure_rate, 50);
+        assert_eq!(breaker.min_calls, 10);
+        assert_eq!(breaker.window, Duration::from_secs(30));
+        assert_eq!(breaker.open_time, Duration::from_secs(30));
+
+        let breaker =
+            CircuitBreakerConfig::parse("failure_rate=25 min_calls=5 window=1m open_time=500ms")
+                .unwrap()
+                .unwrap();
+        assert_eq!(breaker.failure_rate, 25);
+        assert_eq!(breaker.min_calls, 5);
+        assert_eq!(breaker.window, Duration::from_secs(60));
+        assert_eq!(breaker.open_time, Duration::from_millis(500));
+
+        for line in [
+            "min_calls=5",                   // missing failure_rate
+            "failure_rate=0",                // out of range
+            "failure_rate=101%",             // out of range
+            "failure_rate=50 window=0",      // empty window
+            "failure_rate=50 open_time=abc", // invalid time
+            "failure_rate=50 threshold=5",   // unknown parameter
+            "on",                            // not key=value
+        ] {
+            assert!(
+                CircuitBreakerConfig::parse(line).is_err(),
+                "'{line}' should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_facilitator_policy() {
+        let mut config = create_test_config();
+        config.timeout_str = ngx_string("5");
+        config.retries_str = ngx_string("2 backoff=50ms");
+        config.circuit_breaker_str = ngx_string("failure_rate=50%");
+        let policy = config.parse().unwrap().facilitator_policy();
+        assert_eq!(policy.timeout, Some(std::time::Duration::from_secs(5)));
+        assert_eq!(policy.retry.retries, 2);
+        assert!(policy.circuit_breaker.is_some());
+
+        // Defaults: no retries, no breaker
+        let policy = create_test_config().parse().unwrap().facilitator_policy();
+        assert_eq!(policy.retry.retries, 0);
+        assert!(policy.circuit_breaker.is_none());
+    }
+
+    // ============================================================================
+    // Solana Tests
+    // ============================================================================
+
+    const SOLANA_PAY_TO: &str = "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin";
+    const SOLANA_FEE_PAYER: &str = "2wKupLR9q6wXYppw8Gr2NvWxKBUqm4PPJKkQfoxHDBg4";
+
+    #[test]
+    fn test_solana_requirements() {
+        use nginx_x402::ngx_module::create_requirements;
+
+        let mut config = create_test_config();
+        config.amount_str = ngx_string("0.01");
+        config.pay_to_str = ngx_string(SOLANA_PAY_TO);
+        config.network_str = ngx_string("solana-devnet");
+        config.fee_payer_str = ngx_string(SOLANA_FEE_PAYER);
+        let parsed = config.parse().unwrap();
+
+        let requirements = create_requirements(&parsed, "/api", None).unwrap();
+        assert_eq!(requirements.network, "solana-devnet");
+        assert_eq!(requirements.max_amount_required, "10000");
+        assert_eq!(
+            requirements.asset,
+            "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU"
+        );
+        assert_eq!(
+            requirements.pay_to, SOLANA_PAY_TO,
+            "Solana addresses must keep their case"
+        );
+        assert_eq!(
+            requirements.extra,
+            Some(serde_json::json!({ "feePayer": SOLANA_FEE_PAYER }))
+        );
+    }
+
+    #[test]
+    fn test_solana_requirements_need_fee_payer() {
+        use nginx_x402::ngx_module::create_requirements;
+
+        let mut config = create_test_config();
+        config.amount_str = ngx_string("0.01");
+        config.pay_to_str = ngx_string(SOLANA_PAY_TO);
+        config.network_str = ngx_string("solana");
+        let parsed = config.parse().unwrap();
+
+        let error = create_requirements(&parsed, "/api", None)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("x402_fee_payer"),
+            "Unexpected error: {error}"
+        );
+    }
+
+    #[test]
+    fn test_address_must_match_network() {
+        use nginx_x402::ngx_module::create_requirements;
+
+        // EVM recipient on Solana
+        let mut config = create_test_config();
+        config.amount_str = ngx_string("0.01");
+        config.pay_to_str = ngx_string("0x209693Bc6afc0C5328bA36FaF03C514EF312287C");
+        config.network_str = ngx_string("solana");
+        config.fee_payer_str = ngx_string(SOLANA_FEE_PAYER);
+        let parsed = config.parse().unwrap();
+        assert!(create_requirements(&parsed, "/api", None).is_err());
+
+        // Solana recipient on Base
+        let mut config = create_test_config();
+        config.amount_str = ngx_string("0.01");
+        config.pay_to_str = ngx_string(SOLANA_PAY_TO);
+        config.network_str = ngx_string("base");
+        let parsed = config.parse().unwrap();
+        assert!(create_requirements(&parsed, "/api", None).is_err());
+    }
+
+    #[test]
+    fn test_invalid_fee_payer() {
+        let mut config = create_test_config();
+        config.fee_payer_str = ngx_string("0x209693Bc6afc0C5328bA36FaF03C514EF312287C");
+        assert!(config.parse().is_err());
+    }
+
+    #[test]
+    fn test_accept_option_solana() {
+        use nginx_x402::ngx_module::AcceptOption;
+
+        let option = AcceptOption::parse(&format!(
+            "network=solana pay_to={SOLANA_PAY_TO} fee_payer={SOLANA_FEE_PAYER}"
+        ))
+        .unwrap();
+        assert_eq!(option.pay_to.as_deref(), Some(SOLANA_PAY_TO));
+        assert_eq!(option.fee_payer.as_deref(), Some(SOLANA_FEE_PAYER));
+
+        for line in [
+            "network=solana pay_to=0x209693Bc6afc0C5328bA36FaF03C514EF312287C",
+            &format!("network=base pay_to={SOLANA_PAY_TO}"),
+            "network=solana fee_payer=0x209693Bc6afc0C5328bA36FaF03C514EF312287C",
+        ] {
+            assert!(
+                AcceptOption::parse(line).is_err(),
+                "'{line}' should be rejected"
+            );
+        }
+    }
+
     // ============================================================================
     // Integration Tests: Multiple Validation Failures
     // ============================================================================
@@ -0,0 +1,94 @@
+//! Tests for facilitator fallback modes
+
+use nginx_x402::ngx_module::messages::Messages;
+use nginx_x402::ngx_module::response::{error_body_content_type, render_unavailable_body};
+use nginx_x402::ngx_module::FacilitatorFallback;
+
+#[test]
+fn test_parse_error_mode() {
+    assert_eq!(
+        FacilitatorFallback::parse(&["error"]).unwrap(),
+        FacilitatorFallback::Error {
+            status: 500,
+            body: None
+        }
+    );
+    assert_eq!(
+        FacilitatorFallback::parse(&["error", "502", "Payment service down"]).unwrap(),
+        FacilitatorFallback::Error {
+            status: 502,
+            body: Some("Payment service down".to_string())
+        }
+    );
+    // Legacy alias
+    assert_eq!(
+        FacilitatorFallback::parse(&["500"]).unwrap(),
+        FacilitatorFallback::default()
+    );
+}
+
+#[test]
+fn test_parse_other_modes() {
+    assert_eq!(
+        FacilitatorFallback::parse(&["pass"]).unwrap(),
+        FacilitatorFallback::Pass
+    );
+    assert_eq!(
+        FacilitatorFallback::parse(&["unavailable"]).unwrap(),
+        FacilitatorFallback::Unavailable { retry_after: 30 }
+    );
+    assert_eq!(
+        FacilitatorFallback::parse(&["Unavailable", "120"]).unwrap(),
+        FacilitatorFallback::Unavailable { retry_after: 120 }
+    );
+    assert_eq!(
+        FacilitatorFallback::parse(&["repay"]).unwrap(),
+        FacilitatorFallback::Repay
+    );
+}
+
+#[test]
+fn test_parse_invalid() {
+    for args in [
+        &[][..],
+        &["retry"],
+        &["error", "200"],
+        &["error", "abc"],
+        &["error", "503", "body", "extra"],
+        &["pass", "1"],
+        &["unavailable", "soon"],
+        &["repay", "1"],
+    ] {
+        assert!(
+            FacilitatorFallback::parse(args).is_err(),
+            "{args:?} should be rejected"
+        );
+    }
+}
+
+#[test]
+fn test_unavailable_body() {
+    let body = render_unavailable_body(60, &Messages::default());
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"], "Payment facilitator is unavailable");
+    assert_eq!(body["retryAfter"], 60);
+
+    let body = render_unavailable_body(60, &Messages::negotiate(Some("de"), None));
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body["error"],
+        "Der Zahlungsdienstleister ist nicht erreichbar"
+    );
+}
+
+#[test]
+fn test_error_body_content_type() {
+    assert_eq!(
+        error_body_content_type(r#"{"error": "down"}"#),
+        "application/json; charset=utf-8"
+    );
+    assert_eq!(
+        error_body_content_type("Payment service down"),
+        "text/plain; charset=utf-8"
+    );
+}
@@ -0,0 +1,325 @@
+//! Tests for facilitator failover
+
+use nginx_x402::ngx_module::failover::{
+    attempt_order, call_with_failover, CallError, FacilitatorError, FacilitatorHealth, FailureKind,
+};
+use nginx_x402::ngx_module::{FacilitatorEndpoint, RetryPolicy};
+use reqwest::StatusCode;
+use rust_x402::facilitator::FacilitatorClient;
+use rust_x402::types::{
+    ExactEvmPayload, ExactEvmPayloadAuthorization, FacilitatorConfig, PaymentPayload,
+    PaymentRequirements,
+};
+use rust_x402::X402Error;
+use std::cell::Cell;
+use std::future::Future;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn endpoint(line: &str) -> FacilitatorEndpoint {
+    FacilitatorEndpoint::parse(line).unwrap()
+}
+
+fn run<T>(future: impl Future<Output = T>) -> T {
+    tokio::runtime::Runtime::new().unwrap().block_on(future)
+}
+
+/// Facilitator answering one call with `status`
+fn failing_facilitator(status: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        write!(
+            reader.get_mut(),
+            "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+    });
+    url
+}
+
+fn facilitator_client(url: &str) -> FacilitatorClient {
+    FacilitatorClient::new(FacilitatorConfig::new(url)).unwrap()
+}
+
+fn requirements() -> PaymentRequirements {
+    PaymentRequirements::new(
+        "exact",
+        "base-sepolia",
+        "1000000",
+        "0x036CbD53842c5426634e7929541eC2318f3dCF7e",
+        "0x209693bc6afc0c5328ba36faf03c514ef312287c",
+        "https://example.com/api",
+        "",
+    )
+}
+
+fn payload() -> PaymentPayload {
+    PaymentPayload::new(
+        "exact",
+        "base-sepolia",
+        ExactEvmPayload {
+            signature: "0x".to_string(),
+            authorization: ExactEvmPayloadAuthorization {
+                from: "0x857b06519E91e3A54538791bDbb0E22373e36b66".to_string(),
+                to: "0x209693bc6afc0c5328ba36faf03c514ef312287c".to_string(),
+                value: "1000000".to_string(),
+                valid_after: "0".to_string(),
+                valid_before: "9999999999".to_string(),
+                nonce: "0x01".to_string(),
+            },
+        },
+    )
+}
+
+/// Facilitators tried by a call failing with `kind` on both facilitators of `host`
+fn attempts(host: &str, kind: FailureKind, idempotent: bool) -> usize {
+    let endpoints = [
+        endpoint(&format!("https://a.{host} max_fails=0")),
+        endpoint(&format!("https://b.{host} max_fails=0")),
+    ];
+    let attempts = Cell::new(0);
+    let result: Result<(), CallError> = run(call_with_failover(
+        &endpoints,
+        RetryPolicy::default(),
+        Duration::from_secs(5),
+        idempotent,
+        |_, _| {
+            attempts.set(attempts.get() + 1);
+            async move { Err(CallError::new(kind, "failed")) }
+        },
+    ));
+    assert_eq!(result.err().map(|e| e.kind), Some(kind));
+    attempts.get()
+}
+
+#[test]
+fn test_lower_priority_tried_first() {
+    let endpoints = [
+        endpoint("https://backup.example.com priority=1"),
+        endpoint("https://primary.example.com"),
+    ];
+    for rotation in 0..4 {
+        assert_eq!(attempt_order(&endpoints, &[true, true], rotation), [1, 0]);
+    }
+}
+
+#[test]
+fn test_calls_spread_by_weight() {
+    let endpoints = [
+        endpoint("https://a.example.com weight=3"),
+        endpoint("https://b.example.com"),
+    ];
+    let firsts: Vec<usize> = (0..8)
+        .map(|rotation| attempt_order(&endpoints, &[true, true], rotation)[0])
+        .collect();
+    assert_eq!(firsts.iter().filter(|&&i| i == 0).count(), 6);
+    assert_eq!(firsts.iter().filter(|&&i| i == 1).count(), 2);
+
+    // The others of the priority follow as fallbacks
+    assert_eq!(attempt_order(&endpoints, &[true, true], 3), [1, 0]);
+}
+
+#[test]
+fn test_down_facilitators_skipped() {
+    let endpoints = [
+        endpoint("https://a.example.com"),
+        endpoint("https://b.example.com"),
+        endpoint("https://c.example.com priority=1"),
+    ];
+    assert_eq!(attempt_order(&endpoints, &[false, true, true], 0), [1, 2]);
+    assert_eq!(attempt_order(&endpoints, &[false, false, true], 0), [2]);
+}
+
+#[test]
+fn test_all_down_tries_all() {
+    let endpoints = [
+        endpoint("https://a.example.com priority=1"),
+        endpoint("https://b.example.com"),
+    ];
+    assert_eq!(attempt_order(&endpoints, &[false, false], 0), [1, 0]);
+}
+
+#[test]
+fn test_marked_down_after_max_fails() {
+    let endpoint = endpoint("https://a.example.com max_fails=2 fail_timeout=10");
+    let now = Instant::now();
+    let mut health = FacilitatorHealth::default();
+
+    assert!(!health.record_failure(&endpoint, now));
+    assert!(health.is_up(now));
+    assert!(health.record_failure(&endpoint, now));
+    assert!(!health.is_up(now));
+    assert_eq!(health.consecutive_failures, 2);
+
+    // Up again after fail_timeout, marked down again by the next failure
+    let later = now + Duration::from_secs(10);
+    assert!(health.is_up(later));
+    assert!(health.record_failure(&endpoint, later));
+    assert!(!health.is_up(later));
+
+    health.record_success();
+    assert!(health.is_up(later));
+    assert_eq!(health.consecutive_failures, 0);
+}
+
+#[test]
+fn test_max_fails_zero_never_marks_down() {
+    let endpoint = endpoint("https://a.example.com max_fails=0");
+    let now = Instant::now();
+    let mut health = FacilitatorHealth::default();
+    for _ in 0..10 {
+        assert!(!health.record_failure(&endpoint, now));
+    }
+    assert!(health.is_up(now));
+}
+
+#[test]
+fn test_idempotent_call_fails_over() {
+    assert_eq!(
+        attempts("verify.example.com", FailureKind::NotSent, true),
+        2
+    );
+    assert_eq!(
+        attempts("verify.example.com", FailureKind::NoAnswer, true),
+        2
+    );
+}
+
+#[test]
+fn test_settlement_fails_over_only_if_not_sent() {
+    assert_eq!(
+        attempts("settle.example.com", FailureKind::NotSent, false),
+        2
+    );
+    // The first facilitator may have settled the payment
+    assert_eq!(
+        attempts("settle.example.com", FailureKind::NoAnswer, false),
+        1
+    );
+}
+
+#[test]
+fn test_failure_kind_of_errors() {
+    // Nothing listens on port 1: the connection is refused
+    let refused = run(reqwest::Client::new()
+        .post("http://127.0.0.1:1/settle")
+        .send())
+    .err()
+    .map(X402Error::from)
+    .unwrap();
+    assert_eq!(FailureKind::of(&refused), FailureKind::NotSent);
+
+    let response = X402Error::facilitator_error("Settlement failed with status: 502 Bad Gateway");
+    assert_eq!(FailureKind::of(&response), FailureKind::NoAnswer);
+    let response = X402Error::facilitator_error("verify failed with status: 400 Bad Request");
+    assert_eq!(FailureKind::of(&response), FailureKind::Rejected);
+    let response = X402Error::facilitator_error(
+        "Verification failed with status: 429 Too Many Requests. Response: ",
+    );
+    assert_eq!(FailureKind::of(&response), FailureKind::NoAnswer);
+}
+
+#[test]
+fn test_failure_kind_of_status() {
+    let status = |status| FacilitatorError::Status {
+        endpoint: "verify",
+        status,
+        body: String::new(),
+    };
+    assert_eq!(
+        status(StatusCode::BAD_REQUEST).kind(),
+        FailureKind::Rejected
+    );
+    assert_eq!(
+        status(StatusCode::TOO_MANY_REQUESTS).kind(),
+        FailureKind::NoAnswer
+    );
+    assert_eq!(
+        status(StatusCode::REQUEST_TIMEOUT).kind(),
+        FailureKind::NoAnswer
+    );
+    assert_eq!(
+        status(StatusCode::BAD_GATEWAY).kind(),
+        FailureKind::NoAnswer
+    );
+}
+
+/// rust-x402 only reports the status of a facilitator answer in its error
+/// message: fails if a new version changes the format
+#[test]
+fn test_failure_kind_of_rust_x402_errors() {
+    let (payload, requirements) = (payload(), requirements());
+
+    let client = facilitator_client(&failing_facilitator("400 Bad Request"));
+    let error = run(client.verify(&payload, &requirements)).unwrap_err();
+    assert_eq!(FailureKind::of(&error), FailureKind::Rejected);
+
+    let client = facilitator_client(&failing_facilitator("503 Service Unavailable"));
+    let error = run(client.verify(&payload, &requirements)).unwrap_err();
+    assert_eq!(FailureKind::of(&error), FailureKind::NoAnswer);
+
+    let client = facilitator_client(&failing_facilitator("422 Unprocessable Entity"));
+    let error = run(client.settle(&payload, &requirements)).unwrap_err();
+    assert_eq!(FailureKind::of(&error), FailureKind::Rejected);
+
+    let client = facilitator_client(&failing_facilitator("502 Bad Gateway"));
+    let error = run(client.settle(&payload, &requirements)).unwrap_err();
+    assert_eq!(FailureKind::of(&error), FailureKind::NoAnswer);
+}
+
+#[test]
+fn test_rejected_payment_not_retried() {
+    assert_eq!(
+        attempts("rejected.example.com", FailureKind::Rejected, true),
+        1
+    );
+}
+
+#[test]
+fn test_rejected_payment_keeps_facilitator_up() {
+    let endpoints = [
+        endpoint("https://a.healthy.example.com max_fails=1"),
+        endpoint("https://b.healthy.example.com priority=1"),
+    ];
+    let first_url = |kind: FailureKind| {
+        let urls = std::cell::RefCell::new(Vec::new());
+        let _: Result<(), CallError> = run(call_with_failover(
+            &endpoints,
+            RetryPolicy::default(),
+            Duration::from_secs(5),
+            true,
+            |url, _| {
+                urls.borrow_mut().push(url);
+                async move { Err(CallError::new(kind, "failed")) }
+            },
+        ));
+        urls.into_inner()[0]
+    };
+
+    for _ in 0..3 {
+        assert_eq!(first_url(FailureKind::Rejected), endpoints[0].url);
+    }
+    // A failure marks it down
+    assert_eq!(first_url(FailureKind::NoAnswer), endpoints[0].url);
+    assert_eq!(first_url(FailureKind::NoAnswer), endpoints[1].url);
+}
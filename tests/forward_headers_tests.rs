@@ -0,0 +1,21 @@
+//! Tests for trusted upstream header handling
+
+use nginx_x402::ngx_module::forward::{is_forward_header, FORWARD_HEADER_PREFIX};
+
+#[test]
+fn test_forward_header_prefix_matches_case_insensitively() {
+    assert!(is_forward_header("X-X402-Payer"));
+    assert!(is_forward_header("x-x402-verified"));
+    assert!(is_forward_header("X-x402-Custom"));
+    assert!(is_forward_header(FORWARD_HEADER_PREFIX));
+}
+
+#[test]
+fn test_other_headers_are_kept() {
+    // X-PAYMENT carries the payment itself and must reach the module untouched
+    assert!(!is_forward_header("X-PAYMENT"));
+    assert!(!is_forward_header("X-PAYMENT-RESPONSE"));
+    assert!(!is_forward_header("X-X402"));
+    assert!(!is_forward_header("X-Forwarded-For"));
+    assert!(!is_forward_header(""));
+}
@@ -0,0 +1,289 @@
+//! Tests for local (facilitator-less) payment verification
+
+use k256::ecdsa::{Signature, SigningKey};
+use nginx_x402::ngx_module::local_verify::{
+    address_of, chain_id, domain_separator, keccak256, parse_address, parse_uint256,
+    signed_for_asset, verify_payment_locally, Authorization, TRANSFER_WITH_AUTHORIZATION_TYPE,
+};
+use nginx_x402::ngx_module::select_requirements;
+use rust_x402::types::{
+    ExactEvmPayload, ExactEvmPayloadAuthorization, Network, PaymentPayload, PaymentRequirements,
+};
+
+const NOW: u64 = 1_700_000_000;
+const PAY_TO: &str = "0x209693bc6afc0c5328ba36faf03c514ef312287c";
+const USDC_BASE_SEPOLIA: &str = "0x036CbD53842c5426634e7929541eC2318f3dCF7e";
+
+fn signing_key() -> SigningKey {
+    SigningKey::from_bytes(&[7u8; 32].into()).unwrap()
+}
+
+fn payer() -> String {
+    format!(
+        "0x{}",
+        hex::encode(address_of(signing_key().verifying_key()))
+    )
+}
+
+fn requirements(amount: &str) -> PaymentRequirements {
+    let mut requirements = PaymentRequirements::new(
+        "exact",
+        "base-sepolia",
+        amount,
+        USDC_BASE_SEPOLIA,
+        PAY_TO,
+        "https://example.com/api",
+        "",
+    );
+    requirements.set_usdc_info(Network::Testnet).unwrap();
+    requirements
+}
+
+fn authorization(
+    to: &str,
+    value: &str,
+    valid_after: u64,
+    valid_before: u64,
+) -> ExactEvmPayloadAuthorization {
+    ExactEvmPayloadAuthorization {
+        from: payer(),
+        to: to.to_string(),
+        value: value.to_string(),
+        valid_after: valid_after.to_string(),
+        valid_before: valid_before.to_string(),
+        nonce: format!("0x{}", "ab".repeat(32)),
+    }
+}
+
+/// Sign an authorization for `requirements`, returning `(r || s, v)`
+fn sign(
+    authorization: &ExactEvmPayloadAuthorization,
+    requirements: &PaymentRequirements,
+) -> (Signature, u8) {
+    let extra = requirements.extra.as_ref().unwrap();
+    let domain = domain_separator(
+        extra["name"].as_str().unwrap(),
+        extra["version"].as_str().unwrap(),
+        chain_id(&requirements.network).unwrap(),
+        &parse_address(&requirements.asset).unwrap(),
+    );
+    let unsigned = PaymentPayload::new(
+        "exact",
+        &requirements.network,
+        ExactEvmPayload {
+            signature: String::new(),
+            authorization: authorization.clone(),
+        },
+    );
+    let digest = Authorization::from_payload(&unsigned)
+        .unwrap()
+        .signing_hash(&domain);
+    let (signature, recovery) = signing_key().sign_prehash_recoverable(&digest).unwrap();
+    (signature, recovery.to_byte())
+}
+
+fn signed_payload(
+    authorization: ExactEvmPayloadAuthorization,
+    requirements: &PaymentRequirements,
+) -> PaymentPayload {
+    let (signature, v) = sign(&authorization, requirements);
+    payload_with_signature(authorization, &signature, 27 + v)
+}
+
+fn payload_with_signature(
+    authorization: ExactEvmPayloadAuthorization,
+    signature: &Signature,
+    v: u8,
+) -> PaymentPayload {
+    let mut bytes = signature.to_bytes().to_vec();
+    bytes.push(v);
+    PaymentPayload::new(
+        "exact",
+        "base-sepolia",
+        ExactEvmPayload {
+            signature: format!("0x{}", hex::encode(bytes)),
+            authorization,
+        },
+    )
+}
+
+#[test]
+fn test_transfer_with_authorization_type_hash() {
+    // TRANSFER_WITH_AUTHORIZATION_TYPEHASH of the USDC (FiatToken) contract
+    assert_eq!(
+        hex::encode(keccak256(TRANSFER_WITH_AUTHORIZATION_TYPE.as_bytes())),
+        "7c7c6cdb67a18743f49ec6fa9b35f50d52ed05cbed4cc592e13b44501c1a2267"
+    );
+}
+
+#[test]
+fn test_domain_separator_matches_eip712_example() {
+    // Domain of the "Ether Mail" example in the EIP-712 specification
+    let contract = parse_address("0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC").unwrap();
+    assert_eq!(
+        hex::encode(domain_separator("Ether Mail", "1", 1, &contract)),
+        "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+    );
+}
+
+#[test]
+fn test_parse_uint256() {
+    assert_eq!(parse_uint256("0"), Some([0u8; 32]));
+
+    let mut expected = [0u8; 32];
+    expected[30] = 1;
+    assert_eq!(parse_uint256("256"), Some(expected));
+
+    let max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+    assert_eq!(parse_uint256(max), Some([0xff; 32]));
+    let overflow = "115792089237316195423570985008687907853269984665640564039457584007913129639936";
+    assert_eq!(parse_uint256(overflow), None);
+
+    assert_eq!(parse_uint256(""), None);
+    assert_eq!(parse_uint256("-1"), None);
+    assert_eq!(parse_uint256("0x10"), None);
+}
+
+#[test]
+fn test_valid_payment_accepted() {
+    let requirements = requirements("1000");
+    let payload = signed_payload(authorization(PAY_TO, "1000", 0, NOW + 60), &requirements);
+    assert!(verify_payment_locally(&payload, &requirements, NOW).is_ok());
+
+    // Paying more than required is fine, so is an upper case recipient
+    let upper = PAY_TO.to_uppercase().replacen("0X", "0x", 1);
+    let payload = signed_payload(authorization(&upper, "5000", 0, NOW + 60), &requirements);
+    assert!(verify_payment_locally(&payload, &requirements, NOW).is_ok());
+}
+
+#[test]
+fn test_recovery_id_without_offset_accepted() {
+    let requirements = requirements("1000");
+    let authorization = authorization(PAY_TO, "1000", 0, NOW + 60);
+    let (signature, v) = sign(&authorization, &requirements);
+
+    let payload = payload_with_signature(authorization, &signature, v);
+    assert!(verify_payment_locally(&payload, &requirements, NOW).is_ok());
+}
+
+#[test]
+fn test_high_s_signature_rejected() {
+    let requirements = requirements("1000");
+    let authorization = authorization(PAY_TO, "1000", 0, NOW + 60);
+    let (signature, v) = sign(&authorization, &requirements);
+
+    // (r, n - s) with the other recovery id recovers the same key, but token
+    // contracts refuse it
+    let (r, s) = signature.split_scalars();
+    let high_s = Signature::from_scalars(r.to_bytes(), (-s).to_bytes()).unwrap();
+    let payload = payload_with_signature(authorization, &high_s, 27 + (v ^ 1));
+    let error = verify_payment_locally(&payload, &requirements, NOW).unwrap_err();
+    assert!(error.to_string().contains("'s'"), "{error}");
+}
+
+#[test]
+fn test_insufficient_amount_rejected() {
+    let requirements = requirements("1000");
+    let payload = signed_payload(authorization(PAY_TO, "999", 0, NOW + 60), &requirements);
+    let error = verify_payment_locally(&payload, &requirements, NOW).unwrap_err();
+    assert!(error.to_string().contains("below"), "{error}");
+}
+
+#[test]
+fn test_wrong_recipient_rejected() {
+    let requirements = requirements("1000");
+    let other = "0x1111111111111111111111111111111111111111";
+    let payload = signed_payload(authorization(other, "1000", 0, NOW + 60), &requirements);
+    let error = verify_payment_locally(&payload, &requirements, NOW).unwrap_err();
+    assert!(error.to_string().contains("payTo"), "{error}");
+}
+
+#[test]
+fn test_validity_window_enforced() {
+    let requirements = requirements("1000");
+
+    let expired = signed_payload(authorization(PAY_TO, "1000", 0, NOW), &requirements);
+    let error = verify_payment_locally(&expired, &requirements, NOW).unwrap_err();
+    assert!(error.to_string().contains("expired"), "{error}");
+
+    let early = signed_payload(
+        authorization(PAY_TO, "1000", NOW + 10, NOW + 60),
+        &requirements,
+    );
+    let error = verify_payment_locally(&early, &requirements, NOW).unwrap_err();
+    assert!(error.to_string().contains("not valid yet"), "{error}");
+}
+
+#[test]
+fn test_tampered_authorization_rejected() {
+    let requirements = requirements("1000");
+    let mut payload = signed_payload(authorization(PAY_TO, "1000", 0, NOW + 60), &requirements);
+    // Raising the value after signing changes the signed digest
+    payload.payload.authorization.value = "2000".to_string();
+    let error = verify_payment_locally(&payload, &requirements, NOW).unwrap_err();
+    assert!(error.to_string().contains("payer"), "{error}");
+}
+
+#[test]
+fn test_signature_for_other_asset_rejected() {
+    let requirements = requirements("1000");
+    let mut other_asset = requirements.clone();
+    other_asset.asset = "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913".to_string();
+    let payload = signed_payload(authorization(PAY_TO, "1000", 0, NOW + 60), &other_asset);
+    assert!(verify_payment_locally(&payload, &requirements, NOW).is_err());
+}
+
+#[test]
+fn test_select_requirements_by_signed_asset() {
+    // Same network, recipient and price, different tokens
+    let usdc = requirements("1000");
+    let mut other_asset = usdc.clone();
+    other_asset.asset = "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913".to_string();
+    let options = [usdc.clone(), other_asset.clone()];
+
+    let payload = signed_payload(authorization(PAY_TO, "1000", 0, NOW + 60), &other_asset);
+    assert!(signed_for_asset(&payload, &other_asset));
+    assert!(!signed_for_asset(&payload, &usdc));
+    assert_eq!(select_requirements(&options, &payload), Some(1));
+
+    let payload = signed_payload(authorization(PAY_TO, "1000", 0, NOW + 60), &usdc);
+    assert_eq!(select_requirements(&options, &payload), Some(0));
+
+    // The asset of an invalid signature can't be told
+    let mut payload = payload;
+    payload.payload.signature = "0x1234".to_string();
+    assert_eq!(select_requirements(&options, &payload), None);
+
+    // By elimination, a payment for no known token is for the one without a domain
+    let mut custom = other_asset;
+    custom.extra = None;
+    let options = [usdc, custom];
+    assert_eq!(select_requirements(&options, &payload), Some(1));
+}
+
+#[test]
+fn test_network_mismatch_rejected() {
+    let requirements = requirements("1000");
+    let mut payload = signed_payload(authorization(PAY_TO, "1000", 0, NOW + 60), &requirements);
+    payload.network = "base".to_string();
+    let error = verify_payment_locally(&payload, &requirements, NOW).unwrap_err();
+    assert!(error.to_string().contains("network"), "{error}");
+}
+
+#[test]
+fn test_unknown_domain_rejected() {
+    let mut requirements = requirements("1000");
+    let payload = signed_payload(authorization(PAY_TO, "1000", 0, NOW + 60), &requirements);
+    // Custom assets carry no EIP-712 domain name and version
+    requirements.extra = None;
+    let error = verify_payment_locally(&payload, &requirements, NOW).unwrap_err();
+    assert!(error.to_string().contains("domain"), "{error}");
+}
+
+#[test]
+fn test_malformed_signature_rejected() {
+    let requirements = requirements("1000");
+    let mut payload = signed_payload(authorization(PAY_TO, "1000", 0, NOW + 60), &requirements);
+    payload.payload.signature = "0x1234".to_string();
+    assert!(verify_payment_locally(&payload, &requirements, NOW).is_err());
+}
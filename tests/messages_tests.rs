@@ -0,0 +1,140 @@
+//! Tests for localized messages
+
+use nginx_x402::ngx_module::error::user_errors;
+use nginx_x402::ngx_module::messages::{
+    builtin_languages, is_valid_language_tag, parse_accept_language, CustomCatalog, Message,
+    MessageCatalogs, Messages,
+};
+
+fn catalogs() -> MessageCatalogs {
+    let mut catalogs = MessageCatalogs::default();
+    catalogs
+        .add(CustomCatalog::parse("fr", r#"{"payment_required": "Contenu payant"}"#).unwrap())
+        .unwrap();
+    catalogs
+        .add(CustomCatalog::parse("pt-BR", r#"{"amount": "Preço"}"#).unwrap())
+        .unwrap();
+    catalogs
+        .add(CustomCatalog::parse("nl", r#"{"amount": "Bedrag"}"#).unwrap())
+        .unwrap();
+    catalogs
+}
+
+#[test]
+fn test_parse_accept_language() {
+    assert_eq!(
+        parse_accept_language("fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5"),
+        ["fr-ch", "fr", "en", "de", "*"]
+    );
+    assert_eq!(parse_accept_language("en;q=0.5, ja, de;q=0"), ["ja", "en"]);
+    assert_eq!(parse_accept_language("es;q=abc, fr"), ["fr", "es"]);
+    assert!(parse_accept_language("").is_empty());
+}
+
+#[test]
+fn test_negotiate_builtin() {
+    assert_eq!(Messages::negotiate(None, None).language(), "en");
+    assert!(Messages::negotiate(None, None).is_default());
+    assert_eq!(Messages::negotiate(Some("de-AT"), None).language(), "de");
+    assert_eq!(
+        Messages::negotiate(Some("zh-Hans-CN"), None).language(),
+        "zh"
+    );
+    assert_eq!(
+        Messages::negotiate(Some("sv, ja;q=0.8, es;q=0.9"), None).language(),
+        "es"
+    );
+    assert_eq!(Messages::negotiate(Some("sv, *"), None).language(), "en");
+
+    let messages = Messages::negotiate(Some("ja"), None);
+    assert!(!messages.is_default());
+    assert_eq!(messages.get(Message::PaymentRequired), "お支払いが必要です");
+}
+
+#[test]
+fn test_negotiate_custom() {
+    let catalogs = catalogs();
+
+    // Overrides fall back to the built-in catalog of the language
+    let messages = Messages::negotiate(Some("fr"), Some(&catalogs));
+    assert_eq!(messages.language(), "fr");
+    assert_eq!(messages.get(Message::PaymentRequired), "Contenu payant");
+    assert_eq!(messages.get(Message::Amount), "Montant");
+
+    // Regional catalogs fall back to the primary language, then to English
+    let messages = Messages::negotiate(Some("pt-br"), Some(&catalogs));
+    assert_eq!(messages.language(), "pt-BR");
+    assert_eq!(messages.get(Message::Amount), "Preço");
+    assert_eq!(messages.get(Message::Network), "Rede");
+    assert_eq!(
+        Messages::negotiate(Some("pt-PT"), Some(&catalogs)).language(),
+        "pt"
+    );
+
+    let messages = Messages::negotiate(Some("nl-BE"), Some(&catalogs));
+    assert_eq!(messages.language(), "nl");
+    assert_eq!(messages.get(Message::Amount), "Bedrag");
+    assert_eq!(messages.get(Message::Network), "Network");
+}
+
+#[test]
+fn test_localize() {
+    let messages = Messages::negotiate(Some("es"), None);
+    assert_eq!(
+        messages.localize(user_errors::PAYMENT_ALREADY_USED),
+        "La autorización de pago ya se ha utilizado"
+    );
+    assert_eq!(messages.localize("Premium article"), "Premium article");
+    assert_eq!(
+        Messages::default().localize(user_errors::TIMEOUT),
+        user_errors::TIMEOUT
+    );
+}
+
+#[test]
+fn test_builtin_catalogs() {
+    for message in Message::ALL {
+        assert_eq!(Message::from_key(message.key()), Some(message));
+        assert_eq!(Message::from_english(message.english()), Some(message));
+    }
+    for language in builtin_languages() {
+        let messages = Messages::negotiate(Some(language), None);
+        assert_eq!(messages.language(), language);
+        for message in Message::ALL {
+            assert!(!messages.get(message).is_empty(), "{language} {message:?}");
+        }
+    }
+}
+
+#[test]
+fn test_parse_custom_catalog_rejects_invalid() {
+    for (language, json) in [
+        ("fr", r#"{"unknown_message": "x"}"#),
+        ("fr", r#"{"amount": 1}"#),
+        ("fr", r#"["amount"]"#),
+        ("fr", "{"),
+        ("messages", "{}"),
+        ("f", "{}"),
+        ("fr_FR", "{}"),
+        ("1fr", "{}"),
+    ] {
+        assert!(
+            CustomCatalog::parse(language, json).is_err(),
+            "Accepted {language} {json}"
+        );
+    }
+
+    let mut catalogs = catalogs();
+    let duplicate = CustomCatalog::parse("FR", "{}").unwrap();
+    assert!(catalogs.add(duplicate).is_err());
+}
+
+#[test]
+fn test_language_tags() {
+    for valid in ["en", "pt-BR", "zh-Hant-TW", "es-419"] {
+        assert!(is_valid_language_tag(valid), "Rejected {valid}");
+    }
+    for invalid in ["", "e", "en-", "en_US", "x-toolongsubtag"] {
+        assert!(!is_valid_language_tag(invalid), "Accepted {invalid}");
+    }
+}
@@ -123,3 +123,38 @@ fn test_metrics_registry() {
     // Should have metrics registered
     assert!(!metrics.is_empty());
 }
+
+#[test]
+fn test_settlement_metrics() {
+    let metrics = X402Metrics::get();
+
+    metrics.record_settlement_attempt();
+    metrics.record_settlement_success();
+    metrics.record_settlement_failed();
+    metrics.record_settlement_skipped();
+    metrics.record_settlement_duration(0.5);
+
+    let output = collect_metrics();
+    assert!(output.contains("x402_settlements_total"));
+    assert!(output.contains("x402_settlements_success_total"));
+    assert!(output.contains("x402_settlements_failed_total"));
+    assert!(output.contains("x402_settlements_skipped_total"));
+    assert!(output.contains("x402_settlement_duration_seconds"));
+}
+
+#[test]
+fn test_bypassed_requests_metrics() {
+    let metrics = X402Metrics::get();
+    let counter = metrics
+        .bypassed_requests_total
+        .with_label_values(&["api_key"]);
+    let initial = counter.get();
+
+    metrics.record_bypassed_request("api_key");
+    metrics.record_bypassed_request("cidr");
+
+    assert!(counter.get() > initial);
+    let output = collect_metrics();
+    assert!(output.contains("x402_bypassed_requests_total{reason=\"api_key\"}"));
+    assert!(output.contains("x402_bypassed_requests_total{reason=\"cidr\"}"));
+}
@@ -0,0 +1,114 @@
+//! Tests for the network registry and `x402_network_define` parsing
+
+use nginx_x402::config::{
+    chain_id_to_network, define_network, network_by_chain_id, network_by_name, validate_network,
+    NetworkInfo,
+};
+
+#[test]
+fn test_builtin_networks() {
+    for (name, chain_id) in [
+        ("base", 8453),
+        ("base-sepolia", 84532),
+        ("avalanche", 43114),
+        ("avalanche-fuji", 43113),
+        ("polygon", 137),
+        ("polygon-amoy", 80002),
+        ("iotex", 4689),
+        ("sei", 1329),
+        ("sei-testnet", 1328),
+        ("peaq", 3338),
+    ] {
+        assert!(validate_network(name).is_ok(), "{name} should be supported");
+        assert_eq!(chain_id_to_network(chain_id).unwrap(), name);
+
+        let network = network_by_name(name).unwrap();
+        assert_eq!(network.chain_id, Some(chain_id));
+        assert!(network.usdc.is_some(), "{name} should have USDC");
+        assert_eq!(network.eip712_version.as_deref(), Some("2"));
+    }
+}
+
+#[test]
+fn test_unknown_chain_id() {
+    let error = chain_id_to_network(999_999_001).unwrap_err().to_string();
+    assert!(
+        error.contains("999999001"),
+        "Error should mention the chainId, got: {error}"
+    );
+}
+
+#[test]
+fn test_parse_define() {
+    let network = NetworkInfo::parse_define(&[
+        "unichain",
+        "chain_id=130",
+        "usdc=0x078D782b760474a361dDA0AF3839290b0EF57AD6",
+        "eip712_name=USD Coin",
+    ])
+    .unwrap();
+    assert_eq!(network.name, "unichain");
+    assert_eq!(network.chain_id, Some(130));
+    assert_eq!(
+        network.usdc.as_deref(),
+        Some("0x078D782b760474a361dDA0AF3839290b0EF57AD6")
+    );
+    assert_eq!(network.eip712_name.as_deref(), Some("USD Coin"));
+    assert_eq!(
+        network.eip712_version.as_deref(),
+        Some("2"),
+        "eip712_version should default to 2"
+    );
+
+    let network = NetworkInfo::parse_define(&["my-l2", "chain_id=424242"]).unwrap();
+    assert!(network.usdc.is_none());
+    assert!(network.eip712_name.is_none());
+    assert!(network.eip712_version.is_none());
+}
+
+#[test]
+fn test_parse_define_invalid() {
+    let cases: &[&[&str]] = &[
+        &[],
+        &["my-l2"],
+        &["my-l2", "chain_id=abc"],
+        &["my-l2", "chain_id=1", "chain_id=2"],
+        &["my-l2", "chain_id=1", "usdc=0x1234"],
+        &["my-l2", "chain_id=1", "eip712_version=2"],
+        &["my-l2", "chain_id=1", "symbol=USDC"],
+        &["my-l2", "chain_id"],
+        &["chain_id=1"],
+    ];
+    for args in cases {
+        assert!(
+            NetworkInfo::parse_define(args).is_err(),
+            "{args:?} should be rejected"
+        );
+    }
+}
+
+#[test]
+fn test_define_network() {
+    let network = NetworkInfo::parse_define(&["test-define-l2", "chain_id=990001"]).unwrap();
+    define_network(network.clone()).unwrap();
+
+    assert!(validate_network("test-define-l2").is_ok());
+    assert_eq!(chain_id_to_network(990_001).unwrap(), "test-define-l2");
+    assert_eq!(network_by_chain_id(990_001), Some(network));
+
+    // Redefining a network replaces it
+    let network = NetworkInfo::parse_define(&["test-define-l2", "chain_id=990002"]).unwrap();
+    define_network(network).unwrap();
+    assert!(network_by_chain_id(990_001).is_none());
+    assert_eq!(chain_id_to_network(990_002).unwrap(), "test-define-l2");
+}
+
+#[test]
+fn test_define_network_duplicate_chain_id() {
+    let network = NetworkInfo::parse_define(&["test-define-base", "chain_id=8453"]).unwrap();
+    let error = define_network(network).unwrap_err().to_string();
+    assert!(
+        error.contains("base"),
+        "Error should name the network using the chain ID, got: {error}"
+    );
+}
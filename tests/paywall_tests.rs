@@ -0,0 +1,230 @@
+//! Tests for custom HTML paywall templates and self-hosted paywall assets
+
+use nginx_x402::ngx_module::messages::Messages;
+use nginx_x402::ngx_module::paywall::{
+    asset_symbol, content_security_policy, escape_html, escape_script_json, etag_matches,
+    find_paywall_asset, generate_csp_nonce, is_valid_assets_url, render_hosted_paywall,
+    render_inline_paywall, token_amount, PaywallTemplate, PaywallValues, Placeholder, PAYWALL_CSS,
+    PAYWALL_JS,
+};
+use rust_x402::types::PaymentRequirements;
+
+const USDC_BASE_SEPOLIA: &str = "0x036CbD53842c5426634e7929541eC2318f3dCF7e";
+
+fn requirements() -> PaymentRequirements {
+    PaymentRequirements::new(
+        "exact",
+        "base-sepolia",
+        "10000",
+        USDC_BASE_SEPOLIA,
+        "0x209693bc6afc0c5328ba36faf03c514ef312287c",
+        "https://example.com/articles/1",
+        "Article <1>",
+    )
+}
+
+#[test]
+fn test_parse_placeholders() {
+    let template =
+        PaywallTemplate::parse("<h1>{{description}}</h1><p>{{ amount }} {{asset_symbol}}</p>")
+            .unwrap();
+    assert_eq!(
+        template.placeholders().collect::<Vec<_>>(),
+        [
+            Placeholder::Description,
+            Placeholder::Amount,
+            Placeholder::AssetSymbol
+        ]
+    );
+
+    let template = PaywallTemplate::parse("<p>No placeholders</p>").unwrap();
+    assert_eq!(template.placeholders().count(), 0);
+}
+
+#[test]
+fn test_parse_rejects_invalid() {
+    let error = PaywallTemplate::parse("<p>\n{{price}}</p>")
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("{{price}}"), "Unexpected error: {error}");
+    assert!(error.contains("line 2"), "Unexpected error: {error}");
+
+    let error = PaywallTemplate::parse("<p>{{amount</p>")
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("Unclosed"), "Unexpected error: {error}");
+}
+
+#[test]
+fn test_render() {
+    let template = PaywallTemplate::parse(
+        "<title>{{description}}</title><p>{{amount}} {{asset_symbol}} on {{network}} for {{resource}}</p>{{error}}",
+    )
+    .unwrap();
+    let values = PaywallValues::new(&[requirements()], "Payment required", 6).unwrap();
+    assert_eq!(
+        template.render(&values),
+        "<title>Article &lt;1&gt;</title><p>0.01 USDC on base-sepolia for https://example.com/articles/1</p>Payment required"
+    );
+}
+
+#[test]
+fn test_render_requirements_json() {
+    let template =
+        PaywallTemplate::parse("<script>window.x402 = {{requirements}};</script>").unwrap();
+    let values = PaywallValues::new(&[requirements()], "</script>", 6).unwrap();
+    let html = template.render(&values);
+    let json = html
+        .strip_prefix("<script>window.x402 = ")
+        .and_then(|rest| rest.strip_suffix(";</script>"))
+        .unwrap();
+    assert!(!json.contains('<'), "Unescaped JSON: {json}");
+
+    let response: serde_json::Value = serde_json::from_str(json).unwrap();
+    assert_eq!(response["error"], "</script>");
+    assert_eq!(response["accepts"][0]["maxAmountRequired"], "10000");
+    assert_eq!(response["accepts"][0]["network"], "base-sepolia");
+}
+
+#[test]
+fn test_token_amount() {
+    assert_eq!(token_amount("10000", 6), "0.01");
+    assert_eq!(token_amount("1000000", 6), "1");
+    assert_eq!(token_amount("1500000000000000000", 18), "1.5");
+    assert_eq!(token_amount("not a number", 6), "not a number");
+}
+
+#[test]
+fn test_asset_symbol() {
+    assert_eq!(asset_symbol("base-sepolia", USDC_BASE_SEPOLIA), "USDC");
+    assert_eq!(
+        asset_symbol("base-sepolia", &USDC_BASE_SEPOLIA.to_lowercase()),
+        "USDC"
+    );
+    assert_eq!(asset_symbol("base-sepolia", "0x1234"), "0x1234");
+}
+
+#[test]
+fn test_escaping() {
+    assert_eq!(
+        escape_html(r#"<a href="x">'&'</a>"#),
+        "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+    );
+    assert_eq!(
+        escape_script_json(r#"{"a":"</script>&"}"#),
+        r#"{"a":"\u003c/script\u003e\u0026"}"#
+    );
+}
+
+#[test]
+fn test_find_paywall_asset() {
+    assert_eq!(find_paywall_asset("paywall.js"), Some(PAYWALL_JS));
+    assert_eq!(find_paywall_asset("paywall.css"), Some(PAYWALL_CSS));
+    assert_eq!(find_paywall_asset("paywall.html"), None);
+    assert_eq!(find_paywall_asset(""), None);
+    assert!(PAYWALL_JS
+        .content_type
+        .starts_with("application/javascript"));
+    assert!(PAYWALL_CSS.content_type.starts_with("text/css"));
+}
+
+#[test]
+fn test_asset_etag() {
+    let etag = PAYWALL_JS.etag();
+    assert_eq!(etag, PAYWALL_JS.etag());
+    assert_eq!(etag.len(), 18);
+    assert!(etag.starts_with('"') && etag.ends_with('"'));
+    assert_eq!(etag.trim_matches('"'), PAYWALL_JS.version());
+    assert_ne!(etag, PAYWALL_CSS.etag());
+
+    assert!(etag_matches(&etag, &etag));
+    assert!(etag_matches(&format!("W/{etag}"), &etag));
+    assert!(etag_matches(&format!("\"other\", {etag}"), &etag));
+    assert!(etag_matches("*", &etag));
+    assert!(!etag_matches("\"other\"", &etag));
+    assert!(!etag_matches("", &etag));
+}
+
+#[test]
+fn test_assets_url() {
+    assert!(is_valid_assets_url("/x402/assets/"));
+    assert!(is_valid_assets_url("https://cdn.example.com/x402/"));
+    for invalid in [
+        "",
+        "/x402/assets",
+        "x402/assets/",
+        "http://cdn.example.com/",
+        "/x402\"/",
+        "/x402 assets/",
+    ] {
+        assert!(!is_valid_assets_url(invalid), "Accepted '{invalid}'");
+    }
+}
+
+#[test]
+fn test_csp_nonce() {
+    let nonce = generate_csp_nonce().unwrap();
+    assert_eq!(nonce.len(), 24);
+    assert_ne!(nonce, generate_csp_nonce().unwrap());
+
+    let csp = content_security_policy(&nonce);
+    assert!(csp.starts_with("default-src 'none';"));
+    assert!(csp.contains(&format!("script-src 'nonce-{nonce}';")));
+    assert!(csp.contains(&format!("style-src 'nonce-{nonce}';")));
+    assert!(!csp.contains("unsafe-inline"));
+}
+
+#[test]
+fn test_render_hosted_paywall() {
+    let values = PaywallValues::new(&[requirements()], "</script>", 6).unwrap();
+    let html = render_hosted_paywall(&values, &Messages::default(), "/x402/assets/", "abc123");
+
+    assert!(html.contains(&format!(
+        "href=\"/x402/assets/paywall.css?v={}\" nonce=\"abc123\"",
+        PAYWALL_CSS.version()
+    )));
+    assert!(html.contains(&format!(
+        "src=\"/x402/assets/paywall.js?v={}\" nonce=\"abc123\"",
+        PAYWALL_JS.version()
+    )));
+    assert!(html.contains("<dd>0.01 USDC</dd>"));
+    assert!(html.contains("Article &lt;1&gt;"));
+    assert!(html.contains("<p class=\"error\">&lt;/script&gt;</p>"));
+    // No inline styles or scripts without the nonce
+    assert!(!html.contains("style="));
+    assert_eq!(html.matches("<script").count(), 2);
+    assert_eq!(html.matches("</script>").count(), 2);
+
+    let start = html
+        .find("id=\"x402-requirements\" nonce=\"abc123\">")
+        .unwrap();
+    let json = &html[start..];
+    let json = &json[json.find('>').unwrap() + 1..json.find("</script>").unwrap()];
+    let response: serde_json::Value = serde_json::from_str(json).unwrap();
+    assert_eq!(response["error"], "</script>");
+    assert_eq!(response["accepts"][0]["maxAmountRequired"], "10000");
+
+    // The error element is hidden without an error message
+    let values = PaywallValues::new(&[requirements()], "", 6).unwrap();
+    let html = render_hosted_paywall(&values, &Messages::default(), "/x402/assets/", "abc123");
+    assert!(html.contains("<p class=\"error\" hidden></p>"));
+}
+
+#[test]
+fn test_render_inline_paywall() {
+    let messages = Messages::negotiate(Some("fr-CH, fr;q=0.9, en;q=0.8"), None);
+    let mut values = PaywallValues::new(&[requirements()], "Paiement invalide", 6).unwrap();
+    values.lang = messages.language().to_string();
+    let html = render_inline_paywall(&values, &messages);
+
+    assert!(html.starts_with("<!DOCTYPE html>\n<html lang=\"fr\">"));
+    assert!(html.contains("<h1>Paiement requis</h1>"));
+    assert!(html.contains("<dt>Montant</dt><dd>0.01 USDC</dd>"));
+    assert!(html.contains("<p class=\"error\">Paiement invalide</p>"));
+    assert!(html.contains(std::str::from_utf8(PAYWALL_CSS.body).unwrap()));
+    assert!(html.contains(std::str::from_utf8(PAYWALL_JS.body).unwrap()));
+    assert!(!html.contains("paywall.js"));
+
+    let template = PaywallTemplate::parse("<html lang=\"{{lang}}\">").unwrap();
+    assert_eq!(template.render(&values), "<html lang=\"fr\">");
+}
@@ -0,0 +1,166 @@
+//! Tests for signed payment receipts
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use nginx_x402::ngx_module::receipt::{
+    mint_receipt, render_jwks, ReceiptClaims, ReceiptKey, ReceiptSigningKey, RECEIPT_ISSUER,
+};
+
+const NOW: u64 = 1_700_000_000;
+const PAYER: &str = "0x209693bc6afc0c5328ba36faf03c514ef312287c";
+
+fn ed25519_key() -> ReceiptKey {
+    ReceiptKey {
+        kid: "ed".to_string(),
+        key: ReceiptSigningKey::from_raw("EdDSA", &[1; 32]).unwrap(),
+    }
+}
+
+fn es256_key() -> ReceiptKey {
+    ReceiptKey {
+        kid: "ec".to_string(),
+        key: ReceiptSigningKey::from_raw("ES256", &[2; 32]).unwrap(),
+    }
+}
+
+fn claims() -> ReceiptClaims {
+    ReceiptClaims {
+        iss: RECEIPT_ISSUER,
+        sub: Some(PAYER.to_string()),
+        payer: Some(PAYER.to_string()),
+        amount: Some("0.001".to_string()),
+        asset: Some("0x036CbD53842c5426634e7929541eC2318f3dCF7e".to_string()),
+        network: Some("base-sepolia".to_string()),
+        resource: Some("https://api.example.com/data".to_string()),
+        tx_hash: Some("0xabc".to_string()),
+        iat: NOW,
+        exp: NOW + 300,
+    }
+}
+
+/// Split a JWT into its decoded header, decoded claims, signing input and signature
+fn split_jwt(jwt: &str) -> (serde_json::Value, serde_json::Value, String, Vec<u8>) {
+    let parts: Vec<_> = jwt.split('.').collect();
+    assert_eq!(parts.len(), 3, "Not a JWS compact serialization: {jwt}");
+    let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).unwrap();
+    (
+        serde_json::from_slice(&decode(parts[0])).unwrap(),
+        serde_json::from_slice(&decode(parts[1])).unwrap(),
+        format!("{}.{}", parts[0], parts[1]),
+        decode(parts[2]),
+    )
+}
+
+#[test]
+fn test_ed25519_receipt() {
+    let key = ed25519_key();
+    let jwt = mint_receipt(&key, &claims()).unwrap();
+    let (header, payload, signing_input, signature) = split_jwt(&jwt);
+
+    assert_eq!(
+        header,
+        serde_json::json!({"alg": "EdDSA", "typ": "JWT", "kid": "ed"})
+    );
+    assert_eq!(payload["iss"], "nginx-x402");
+    assert_eq!(payload["sub"], PAYER);
+    assert_eq!(payload["payer"], PAYER);
+    assert_eq!(payload["amount"], "0.001");
+    assert_eq!(payload["network"], "base-sepolia");
+    assert_eq!(payload["txHash"], "0xabc");
+    assert_eq!(payload["exp"], NOW + 300);
+
+    let ReceiptSigningKey::Ed25519(ref signing_key) = key.key else {
+        panic!("Expected an Ed25519 key");
+    };
+    let signature = ed25519_dalek::Signature::from_slice(&signature).unwrap();
+    signing_key
+        .verifying_key()
+        .verify_strict(signing_input.as_bytes(), &signature)
+        .unwrap();
+}
+
+#[test]
+fn test_es256_receipt() {
+    use p256::ecdsa::signature::Verifier;
+
+    let key = es256_key();
+    let jwt = mint_receipt(&key, &claims()).unwrap();
+    let (header, _, signing_input, signature) = split_jwt(&jwt);
+    assert_eq!(header["alg"], "ES256");
+    assert_eq!(header["kid"], "ec");
+
+    let ReceiptSigningKey::Es256(ref signing_key) = key.key else {
+        panic!("Expected a P-256 key");
+    };
+    let signature = p256::ecdsa::Signature::from_slice(&signature).unwrap();
+    signing_key
+        .verifying_key()
+        .verify(signing_input.as_bytes(), &signature)
+        .unwrap();
+}
+
+#[test]
+fn test_unsettled_receipt_has_null_tx_hash() {
+    let mut claims = claims();
+    claims.tx_hash = None;
+    let jwt = mint_receipt(&ed25519_key(), &claims).unwrap();
+    let (_, payload, _, _) = split_jwt(&jwt);
+    assert!(payload["txHash"].is_null());
+}
+
+#[test]
+fn test_from_pem() {
+    use p256::pkcs8::{EncodePrivateKey, LineEnding};
+
+    let ed25519 = ed25519_dalek::SigningKey::from_bytes(&[1; 32]);
+    let pem = ed25519.to_pkcs8_pem(LineEnding::LF).unwrap();
+    let key = ReceiptSigningKey::from_pem(&pem).unwrap();
+    assert_eq!(key.alg(), "EdDSA");
+    assert_eq!(key.to_raw(), vec![1; 32]);
+
+    let es256 = p256::ecdsa::SigningKey::from_slice(&[2; 32]).unwrap();
+    let pem = es256.to_pkcs8_pem(LineEnding::LF).unwrap();
+    let key = ReceiptSigningKey::from_pem(&pem).unwrap();
+    assert_eq!(key.alg(), "ES256");
+    assert_eq!(key.to_raw(), vec![2; 32]);
+
+    assert!(ReceiptSigningKey::from_pem("not a key").is_err());
+}
+
+#[test]
+fn test_default_kid() {
+    let kid = ed25519_key().key.default_kid();
+    assert_eq!(kid.len(), 16);
+    assert_eq!(kid, ed25519_key().key.default_kid());
+    assert_ne!(kid, es256_key().key.default_kid());
+}
+
+#[test]
+fn test_jwks() {
+    let jwks: serde_json::Value =
+        serde_json::from_slice(&render_jwks(&[ed25519_key(), es256_key()])).unwrap();
+    let keys = jwks["keys"].as_array().unwrap();
+    assert_eq!(keys.len(), 2);
+
+    assert_eq!(keys[0]["kty"], "OKP");
+    assert_eq!(keys[0]["crv"], "Ed25519");
+    assert_eq!(keys[0]["kid"], "ed");
+    assert_eq!(keys[0]["alg"], "EdDSA");
+    assert_eq!(keys[0]["use"], "sig");
+    let x = URL_SAFE_NO_PAD
+        .decode(keys[0]["x"].as_str().unwrap())
+        .unwrap();
+    assert_eq!(x.len(), 32);
+
+    assert_eq!(keys[1]["kty"], "EC");
+    assert_eq!(keys[1]["crv"], "P-256");
+    assert_eq!(keys[1]["alg"], "ES256");
+    for coordinate in ["x", "y"] {
+        let value = URL_SAFE_NO_PAD
+            .decode(keys[1][coordinate].as_str().unwrap())
+            .unwrap();
+        assert_eq!(value.len(), 32);
+    }
+    // Public keys only
+    assert!(keys.iter().all(|key| key.get("d").is_none()));
+}
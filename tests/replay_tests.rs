@@ -0,0 +1,196 @@
+//! Tests for replay protection of payment authorizations
+
+use nginx_x402::ngx_module::replay::{
+    in_flight_expiry, replay_expiry, replay_key, DEFAULT_REPLAY_WINDOW, SVM_REPLAY_WINDOW,
+};
+use nginx_x402::ngx_module::runtime::FacilitatorPayload;
+use nginx_x402::ngx_module::shm::{
+    fingerprint, InsertResult, Slot, SlotTable, ZoneSecret, PROBE_WINDOW,
+};
+use nginx_x402::ngx_module::svm::{SvmPaymentPayload, SvmTransaction};
+use rust_x402::types::{ExactEvmPayload, ExactEvmPayloadAuthorization, PaymentPayload};
+
+const NOW: u64 = 1_700_000_000;
+const SECRET: ZoneSecret = [7; 32];
+
+fn payload(from: &str, nonce: &str, valid_before: &str) -> FacilitatorPayload {
+    FacilitatorPayload::Evm(PaymentPayload::new(
+        "exact",
+        "base-sepolia",
+        ExactEvmPayload {
+            signature: "0x".to_string(),
+            authorization: ExactEvmPayloadAuthorization {
+                from: from.to_string(),
+                to: "0x209693bc6afc0c5328ba36faf03c514ef312287c".to_string(),
+                value: "100".to_string(),
+                valid_after: "0".to_string(),
+                valid_before: valid_before.to_string(),
+                nonce: nonce.to_string(),
+            },
+        },
+    ))
+}
+
+fn svm_payload(network: &str, transaction: &str) -> FacilitatorPayload {
+    FacilitatorPayload::Svm(SvmPaymentPayload {
+        x402_version: 1,
+        scheme: "exact".to_string(),
+        network: network.to_string(),
+        payload: SvmTransaction {
+            transaction: transaction.to_string(),
+        },
+    })
+}
+
+#[test]
+fn test_replay_key_ignores_address_case() {
+    let lower = payload("0xabcdef", "0x01ff", "0");
+    let mixed = payload("0xABCdef", "0x01FF", "0");
+    assert_eq!(replay_key(&lower), replay_key(&mixed));
+
+    let other_nonce = payload("0xabcdef", "0x0200", "0");
+    assert_ne!(replay_key(&lower), replay_key(&other_nonce));
+}
+
+#[test]
+fn test_svm_replay_key_hashes_transaction() {
+    let key = replay_key(&svm_payload("solana", "AQID"));
+    assert_eq!(key, replay_key(&svm_payload("solana", "AQID")));
+    assert_ne!(key, replay_key(&svm_payload("solana", "AQIE")));
+    assert_ne!(key, replay_key(&svm_payload("solana-devnet", "AQID")));
+    // The transaction itself isn't kept
+    assert!(!key.contains("AQID"));
+}
+
+#[test]
+fn test_svm_replay_expiry() {
+    let payload = svm_payload("solana", "AQID");
+    assert_eq!(replay_expiry(&payload, NOW), NOW + SVM_REPLAY_WINDOW);
+    assert_eq!(in_flight_expiry(&payload, NOW, 11), NOW + 11);
+}
+
+#[test]
+fn test_replay_expiry_follows_valid_before() {
+    let valid_before = (NOW + 300).to_string();
+    assert_eq!(
+        replay_expiry(&payload("0xa", "0x1", &valid_before), NOW),
+        NOW + 300
+    );
+
+    // Missing or expired authorization windows fall back to the default window
+    for valid_before in ["", "abc", "0", &NOW.to_string()] {
+        assert_eq!(
+            replay_expiry(&payload("0xa", "0x1", valid_before), NOW),
+            NOW + DEFAULT_REPLAY_WINDOW,
+            "Unexpected expiry for validBefore '{valid_before}'"
+        );
+    }
+}
+
+#[test]
+fn test_second_use_is_detected() {
+    let mut slots = vec![Slot::default(); 128];
+    let mut table = SlotTable::new(&mut slots, SECRET);
+    let key = fingerprint(&SECRET, "base-sepolia:0xabc:0x01");
+
+    assert_eq!(table.insert(key, 0, NOW + 60, NOW), InsertResult::Inserted);
+    assert_eq!(
+        table.insert(key, 0, NOW + 60, NOW + 1),
+        InsertResult::Exists
+    );
+    assert!(table.contains(key, NOW));
+}
+
+#[test]
+fn test_entries_expire() {
+    let mut slots = vec![Slot::default(); 128];
+    let mut table = SlotTable::new(&mut slots, SECRET);
+    let key = fingerprint(&SECRET, "base-sepolia:0xabc:0x01");
+
+    assert_eq!(table.insert(key, 0, NOW + 60, NOW), InsertResult::Inserted);
+    assert!(!table.contains(key, NOW + 60));
+    assert_eq!(
+        table.insert(key, 0, NOW + 120, NOW + 60),
+        InsertResult::Inserted
+    );
+}
+
+#[test]
+fn test_removed_entries_can_be_reused() {
+    let mut slots = vec![Slot::default(); 128];
+    let mut table = SlotTable::new(&mut slots, SECRET);
+    let key = fingerprint(&SECRET, "base-sepolia:0xabc:0x01");
+
+    table.insert(key, 0, NOW + 60, NOW);
+    table.remove(key);
+    assert!(!table.contains(key, NOW));
+    assert_eq!(table.insert(key, 0, NOW + 60, NOW), InsertResult::Inserted);
+}
+
+#[test]
+fn test_key_found_behind_removed_entry() {
+    // Every key probes the same window in a table of PROBE_WINDOW slots
+    let mut slots = vec![Slot::default(); PROBE_WINDOW];
+    let mut table = SlotTable::new(&mut slots, SECRET);
+    let keys: Vec<_> = (0..PROBE_WINDOW)
+        .map(|i| fingerprint(&SECRET, &format!("key-{i}")))
+        .collect();
+    for key in &keys {
+        assert_eq!(table.insert(*key, 0, NOW + 60, NOW), InsertResult::Inserted);
+    }
+
+    table.remove(keys[0]);
+    for key in &keys[1..] {
+        assert_eq!(table.insert(*key, 0, NOW + 60, NOW), InsertResult::Exists);
+    }
+}
+
+#[test]
+fn test_full_window_never_evicts_live_entries() {
+    let mut slots = vec![Slot::default(); PROBE_WINDOW];
+    let mut table = SlotTable::new(&mut slots, SECRET);
+    let keys: Vec<_> = (0..PROBE_WINDOW)
+        .map(|i| fingerprint(&SECRET, &format!("key-{i}")))
+        .collect();
+    for (i, key) in keys.iter().enumerate() {
+        table.insert(*key, 0, NOW + 100 + i as u64, NOW);
+    }
+
+    let new_key = fingerprint(&SECRET, "new-key");
+    assert_eq!(table.insert(new_key, 0, NOW + 60, NOW), InsertResult::Full);
+    assert_eq!(table.set(new_key, 0, NOW + 60, NOW), InsertResult::Full);
+    assert!(!table.contains(new_key, NOW));
+    assert!(keys.iter().all(|key| table.contains(*key, NOW)));
+
+    // Room again once an entry expires
+    assert_eq!(
+        table.insert(new_key, 0, NOW + 200, NOW + 100),
+        InsertResult::Inserted
+    );
+}
+
+#[test]
+fn test_fingerprint_is_stable() {
+    assert_eq!(fingerprint(&SECRET, "a"), fingerprint(&SECRET, "a"));
+    assert_ne!(fingerprint(&SECRET, "a"), fingerprint(&SECRET, "b"));
+}
+
+#[test]
+fn test_fingerprint_depends_on_zone_secret() {
+    let other: ZoneSecret = [8; 32];
+    assert_ne!(fingerprint(&SECRET, "a"), fingerprint(&other, "a"));
+
+    let mut slots = vec![Slot::default(); 8];
+    let table = SlotTable::new(&mut slots, other);
+    assert_eq!(table.fingerprint("a"), fingerprint(&other, "a"));
+}
+
+#[test]
+fn test_in_flight_entry_is_short_lived() {
+    let valid_before = (NOW + 300).to_string();
+    let payload = payload("0xa", "0x1", &valid_before);
+    assert_eq!(in_flight_expiry(&payload, NOW, 11), NOW + 11);
+    // Never outlives the authorization
+    assert_eq!(in_flight_expiry(&payload, NOW, 600), NOW + 300);
+    assert_eq!(in_flight_expiry(&payload, NOW, 0), NOW + 1);
+}
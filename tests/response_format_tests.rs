@@ -0,0 +1,159 @@
+//! Tests for response format negotiation and browser detection rules
+
+use nginx_x402::ngx_module::negotiation::{
+    wants_html, BrowserDetection, ClientHints, ResponseFormat,
+};
+
+const CHROME_UA: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+const ELECTRON_UA: &str = "Mozilla/5.0 (Macintosh) AppleWebKit/537.36 (KHTML, like Gecko) MyApp/1.0 Chrome/120.0.0.0 Electron/28.0.0 Safari/537.36";
+const BROWSER_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+
+fn hints(user_agent: &str, accept: &str) -> ClientHints {
+    ClientHints {
+        user_agent: Some(user_agent.to_string()),
+        accept: Some(accept.to_string()),
+        ..ClientHints::default()
+    }
+}
+
+#[test]
+fn test_default_rules() {
+    let rules = BrowserDetection::default();
+    let auto = ResponseFormat::Auto;
+
+    assert!(wants_html(auto, &rules, &hints(CHROME_UA, BROWSER_ACCEPT)));
+    let curl = ClientHints {
+        user_agent: Some("curl/8.5.0".to_string()),
+        ..ClientHints::default()
+    };
+    assert!(!wants_html(auto, &rules, &curl));
+    assert!(!wants_html(
+        auto,
+        &rules,
+        &hints(CHROME_UA, "application/json")
+    ));
+
+    // A JSON body is an API request, whatever the Accept header
+    let mut json_post = hints(CHROME_UA, BROWSER_ACCEPT);
+    json_post.content_type = Some("application/json".to_string());
+    assert!(!wants_html(auto, &rules, &json_post));
+
+    // Browser form submissions get the paywall
+    let form = ClientHints {
+        content_type: Some("application/x-www-form-urlencoded".to_string()),
+        ..ClientHints::default()
+    };
+    assert!(wants_html(auto, &rules, &form));
+    assert!(!wants_html(auto, &rules, &ClientHints::default()));
+}
+
+#[test]
+fn test_forced_format() {
+    let rules = BrowserDetection::default();
+    let browser = hints(CHROME_UA, BROWSER_ACCEPT);
+    let api = hints("python-requests/2.31", "application/json");
+
+    assert!(!wants_html(ResponseFormat::Json, &rules, &browser));
+    assert!(wants_html(ResponseFormat::Html, &rules, &api));
+}
+
+#[test]
+fn test_user_agent_rules() {
+    let rules = BrowserDetection::parse(&[
+        "api_ua=electron/",
+        "browser_ua=^MyKiosk/",
+        "api_ua=Headless",
+    ])
+    .unwrap();
+    let auto = ResponseFormat::Auto;
+
+    // API client rules are case-insensitive and checked before the Accept header
+    assert!(!wants_html(
+        auto,
+        &rules,
+        &hints(ELECTRON_UA, BROWSER_ACCEPT)
+    ));
+    assert!(!wants_html(
+        auto,
+        &rules,
+        &hints("Mozilla/5.0 HeadlessChrome/120.0.0.0", BROWSER_ACCEPT)
+    ));
+    assert!(wants_html(auto, &rules, &hints("mykiosk/2.0", "*/*")));
+    assert!(wants_html(auto, &rules, &hints(CHROME_UA, BROWSER_ACCEPT)));
+
+    // Without rules, Electron apps look like browsers
+    let default_rules = BrowserDetection::default();
+    assert!(wants_html(
+        auto,
+        &default_rules,
+        &hints(ELECTRON_UA, BROWSER_ACCEPT)
+    ));
+}
+
+#[test]
+fn test_accept_thresholds() {
+    let auto = ResponseFormat::Auto;
+    let request = hints("my-client/1.0", "text/html;q=0.7, application/json;q=0.6");
+
+    assert!(wants_html(auto, &BrowserDetection::default(), &request));
+    let strict = BrowserDetection::parse(&["html_q=0.8"]).unwrap();
+    assert!(!wants_html(auto, &strict, &request));
+
+    let request = hints(CHROME_UA, "application/json;q=0.4, text/html;q=0.1");
+    assert!(wants_html(auto, &BrowserDetection::default(), &request));
+    let lenient = BrowserDetection::parse(&["json_q=0.3"]).unwrap();
+    assert!(!wants_html(auto, &lenient, &request));
+}
+
+#[test]
+fn test_format_arg_override() {
+    let rules = BrowserDetection::parse(&["format_arg=format", "api_ua=Electron"]).unwrap();
+    let auto = ResponseFormat::Auto;
+
+    let mut request = hints(CHROME_UA, BROWSER_ACCEPT);
+    request.args = Some("id=1&format=json".to_string());
+    assert!(!wants_html(auto, &rules, &request));
+
+    let mut request = hints(ELECTRON_UA, "application/json");
+    request.args = Some("format=HTML".to_string());
+    assert!(wants_html(auto, &rules, &request));
+
+    // Unknown values and other arguments fall through to detection
+    request.args = Some("format=xml&xformat=html".to_string());
+    assert!(!wants_html(auto, &rules, &request));
+
+    // Ignored unless configured, and by forced formats
+    let mut request = hints(CHROME_UA, BROWSER_ACCEPT);
+    request.args = Some("format=json".to_string());
+    assert!(wants_html(auto, &BrowserDetection::default(), &request));
+    assert!(wants_html(ResponseFormat::Html, &rules, &request));
+}
+
+#[test]
+fn test_parse_rules_rejects_invalid() {
+    for args in [
+        &["browser_ua=("][..],
+        &["api_ua="],
+        &["html_q=1.5"],
+        &["json_q=abc"],
+        &["html_q=0.5", "html_q=0.6"],
+        &["format_arg="],
+        &["format_arg=a&b"],
+        &["format_arg=format", "format_arg=fmt"],
+        &["unknown=1"],
+        &["Electron"],
+    ] {
+        assert!(BrowserDetection::parse(args).is_err(), "Accepted {args:?}");
+    }
+}
+
+#[test]
+fn test_response_format() {
+    assert_eq!(ResponseFormat::parse("auto").unwrap(), ResponseFormat::Auto);
+    assert_eq!(ResponseFormat::parse("JSON").unwrap(), ResponseFormat::Json);
+    assert_eq!(ResponseFormat::parse("html").unwrap(), ResponseFormat::Html);
+    assert!(ResponseFormat::parse("xml").is_err());
+
+    assert!(ResponseFormat::Auto.vary().contains("User-Agent"));
+    assert_eq!(ResponseFormat::Json.vary(), "Accept-Language");
+}
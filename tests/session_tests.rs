@@ -0,0 +1,232 @@
+//! Tests for prepaid session tokens
+
+use nginx_x402::ngx_module::session::{
+    activate_session, cookie_value, parse_duration, render_session_body, reserve_session,
+    session_cookie, SessionGrant, SessionToken, DEFAULT_SESSION_LIFETIME,
+};
+use nginx_x402::ngx_module::shm::{fingerprint, InsertResult, Slot, SlotTable, ZoneSecret};
+
+const NOW: u64 = 1_700_000_000;
+const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+const SECRET: ZoneSecret = [7; 32];
+
+fn token(expires: u64) -> SessionToken {
+    SessionToken {
+        id: "00112233445566778899aabbccddeeff".to_string(),
+        expires,
+    }
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("90"), Some(90));
+    assert_eq!(parse_duration("90s"), Some(90));
+    assert_eq!(parse_duration("15m"), Some(900));
+    assert_eq!(parse_duration("24h"), Some(86_400));
+    assert_eq!(parse_duration("7d"), Some(604_800));
+    for invalid in ["", "h", "1w", "-1", "1.5h", "1 h"] {
+        assert_eq!(parse_duration(invalid), None, "Parsed '{invalid}'");
+    }
+}
+
+#[test]
+fn test_parse_session_grant() {
+    assert_eq!(
+        SessionGrant::parse("requests=1000 duration=24h").unwrap(),
+        SessionGrant {
+            requests: Some(1000),
+            duration: 86_400,
+        }
+    );
+    assert_eq!(
+        SessionGrant::parse("duration=1h").unwrap(),
+        SessionGrant {
+            requests: None,
+            duration: 3600,
+        }
+    );
+    assert_eq!(
+        SessionGrant::parse("requests=10").unwrap(),
+        SessionGrant {
+            requests: Some(10),
+            duration: DEFAULT_SESSION_LIFETIME,
+        }
+    );
+}
+
+#[test]
+fn test_parse_session_grant_rejects_invalid() {
+    for invalid in [
+        "",
+        "requests=0",
+        "requests=abc",
+        "duration=0",
+        "duration=1w",
+        "requests=1 requests=2",
+        "credits=10",
+        "requests",
+    ] {
+        assert!(
+            SessionGrant::parse(invalid).is_err(),
+            "Accepted '{invalid}'"
+        );
+    }
+}
+
+#[test]
+fn test_token_round_trip() {
+    let encoded = token(NOW + 60).encode(KEY);
+    assert_eq!(
+        SessionToken::decode(&encoded, KEY, NOW),
+        Some(token(NOW + 60))
+    );
+}
+
+#[test]
+fn test_token_rejects_tampering_and_expiry() {
+    let encoded = token(NOW + 60).encode(KEY);
+
+    // Wrong key
+    assert_eq!(
+        SessionToken::decode(&encoded, b"another key of at least 32 bytes", NOW),
+        None
+    );
+
+    // Extended expiry
+    let tampered = encoded.replacen(&(NOW + 60).to_string(), &(NOW + 6000).to_string(), 1);
+    assert_eq!(SessionToken::decode(&tampered, KEY, NOW), None);
+
+    // Expired
+    assert_eq!(SessionToken::decode(&encoded, KEY, NOW + 60), None);
+
+    // Malformed
+    for malformed in ["", "abc", "a.b", "a.1.zz", &format!("{encoded}.extra")] {
+        assert_eq!(SessionToken::decode(malformed, KEY, NOW), None);
+    }
+}
+
+#[test]
+fn test_generated_ids_are_unique() {
+    let first = SessionToken::generate(NOW).unwrap();
+    let second = SessionToken::generate(NOW).unwrap();
+    assert_eq!(first.id.len(), 32);
+    assert_ne!(first.id, second.id);
+}
+
+#[test]
+fn test_cookie_value() {
+    let header = "theme=dark; x402_session=abc.1.ff; other=\"x\"";
+    assert_eq!(cookie_value(header, "x402_session"), Some("abc.1.ff"));
+    assert_eq!(cookie_value(header, "other"), Some("x"));
+    assert_eq!(cookie_value(header, "x402"), None);
+}
+
+#[test]
+fn test_session_cookie_and_body() {
+    assert_eq!(
+        session_cookie("x402_session", "abc.1.ff", 3600),
+        "x402_session=abc.1.ff; Path=/; Max-Age=3600; HttpOnly; Secure; SameSite=Lax"
+    );
+
+    let body: serde_json::Value =
+        serde_json::from_slice(&render_session_body("abc.1.ff", NOW, Some(10))).unwrap();
+    assert_eq!(body["token"], "abc.1.ff");
+    assert_eq!(body["expiresAt"], NOW);
+    assert_eq!(body["requests"], 10);
+
+    let body: serde_json::Value =
+        serde_json::from_slice(&render_session_body("abc.1.ff", NOW, None)).unwrap();
+    assert!(body["requests"].is_null());
+}
+
+#[test]
+fn test_balance_is_decremented_until_used_up() {
+    let mut slots = vec![Slot::default(); 64];
+    let mut table = SlotTable::new(&mut slots, SECRET);
+    let key = fingerprint(&SECRET, "00112233445566778899aabbccddeeff");
+    table.set(key, 2, NOW + 60, NOW);
+
+    assert_eq!(table.decrement(key, NOW), Some(1));
+    assert_eq!(table.decrement(key, NOW), Some(0));
+    assert_eq!(table.decrement(key, NOW), None);
+
+    // Unknown and expired sessions have no balance
+    assert_eq!(table.decrement(fingerprint(&SECRET, "unknown"), NOW), None);
+    let expired = fingerprint(&SECRET, "expired");
+    table.set(expired, 5, NOW + 60, NOW);
+    assert_eq!(table.decrement(expired, NOW + 60), None);
+}
+
+#[test]
+fn test_unlimited_balance_is_not_decremented() {
+    let mut slots = vec![Slot::default(); 64];
+    let mut table = SlotTable::new(&mut slots, SECRET);
+    let key = fingerprint(&SECRET, "unlimited");
+    table.set(key, u64::MAX, NOW + 60, NOW);
+
+    assert_eq!(table.decrement(key, NOW), Some(u64::MAX));
+    assert_eq!(table.decrement(key, NOW), Some(u64::MAX));
+}
+
+#[test]
+fn test_reserved_session_is_not_redeemable_until_activated() {
+    let mut slots = vec![Slot::default(); 64];
+    let mut table = SlotTable::new(&mut slots, SECRET);
+    let token = token(NOW + 60);
+    let key = fingerprint(&SECRET, &token.id);
+
+    assert_eq!(
+        reserve_session(&mut table, &token, NOW),
+        InsertResult::Inserted
+    );
+    assert_eq!(table.decrement(key, NOW), None);
+
+    assert_eq!(
+        activate_session(&mut table, &token, Some(2), NOW),
+        InsertResult::Inserted
+    );
+    assert_eq!(table.decrement(key, NOW), Some(1));
+}
+
+#[test]
+fn test_full_zone_never_evicts_live_sessions() {
+    // Every slot is in the probe window of every key
+    let mut slots = vec![Slot::default(); 4];
+    let mut table = SlotTable::new(&mut slots, SECRET);
+    let live: Vec<_> = (0..3)
+        .map(|i| SessionToken {
+            id: format!("live{i}"),
+            expires: NOW + 60,
+        })
+        .collect();
+    for token in &live {
+        activate_session(&mut table, token, Some(5), NOW);
+    }
+
+    let reserved = token(NOW + 60);
+    assert_eq!(
+        reserve_session(&mut table, &reserved, NOW),
+        InsertResult::Inserted
+    );
+    // No slot left for another purchase, it is refused before being settled
+    let refused = SessionToken {
+        id: "refused".to_string(),
+        expires: NOW + 60,
+    };
+    assert_eq!(
+        reserve_session(&mut table, &refused, NOW),
+        InsertResult::Full
+    );
+
+    // The reserved purchase still gets its session
+    assert_eq!(
+        activate_session(&mut table, &reserved, None, NOW),
+        InsertResult::Inserted
+    );
+    for token in &live {
+        assert_eq!(
+            table.decrement(fingerprint(&SECRET, &token.id), NOW),
+            Some(4)
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! Tests for payment settlement with the facilitator
+
+use nginx_x402::ngx_module::{settle_payment, FacilitatorEndpoint, FacilitatorPolicy};
+use rust_x402::types::{
+    ExactEvmPayload, ExactEvmPayloadAuthorization, PaymentPayload, PaymentRequirements,
+};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+/// Facilitator answering one `/settle` call, returning the request body
+fn mock_facilitator() -> (String, thread::JoinHandle<serde_json::Value>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let response = r#"{"success":true,"transaction":"0xabc","network":"base-sepolia"}"#;
+        write!(
+            reader.get_mut(),
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+            response.len()
+        )
+        .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    });
+    (url, handle)
+}
+
+fn requirements(amount: &str) -> PaymentRequirements {
+    PaymentRequirements::new(
+        "exact",
+        "base-sepolia",
+        amount,
+        "0x036CbD53842c5426634e7929541eC2318f3dCF7e",
+        "0x209693bc6afc0c5328ba36faf03c514ef312287c",
+        "https://example.com/api",
+        "",
+    )
+}
+
+fn payload(value: &str) -> PaymentPayload {
+    PaymentPayload::new(
+        "exact",
+        "base-sepolia",
+        ExactEvmPayload {
+            signature: "0x".to_string(),
+            authorization: ExactEvmPayloadAuthorization {
+                from: "0x857b06519E91e3A54538791bDbb0E22373e36b66".to_string(),
+                to: "0x209693bc6afc0c5328ba36faf03c514ef312287c".to_string(),
+                value: value.to_string(),
+                valid_after: "0".to_string(),
+                valid_before: "9999999999".to_string(),
+                nonce: "0x01".to_string(),
+            },
+        },
+    )
+}
+
+#[test]
+fn test_settles_verified_amount() {
+    let (url, facilitator) = mock_facilitator();
+    let endpoints = [FacilitatorEndpoint::parse(&url).unwrap()];
+    let requirements = requirements("500000");
+    let payment = payload("500000").to_base64().unwrap();
+
+    let response = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(settle_payment(
+            &payment,
+            &requirements,
+            &endpoints,
+            FacilitatorPolicy::default(),
+        ))
+        .unwrap();
+    assert!(response.success);
+
+    // An EIP-3009 authorization transfers exactly its value: the facilitator
+    // is asked to settle the amount that was verified, never less
+    let body = facilitator.join().unwrap();
+    assert_eq!(body["paymentRequirements"]["scheme"], "exact");
+    assert_eq!(body["paymentRequirements"]["maxAmountRequired"], "500000");
+    assert_eq!(
+        body["paymentPayload"]["payload"]["authorization"]["value"],
+        "500000"
+    );
+}
@@ -0,0 +1,142 @@
+//! Tests for Solana (SVM) addresses, networks and payment payloads
+
+use nginx_x402::config::{
+    network_by_name, validate_network_address, validate_payment_address, NetworkVm,
+};
+use nginx_x402::ngx_module::svm::{
+    facilitator_endpoint_url, select_svm_requirements, SvmPaymentPayload,
+};
+use nginx_x402::validate_solana_address;
+use rust_x402::types::PaymentRequirements;
+
+const SOLANA_PAY_TO: &str = "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin";
+const EVM_PAY_TO: &str = "0x209693Bc6afc0C5328bA36FaF03C514EF312287C";
+
+fn requirements(network: &str) -> PaymentRequirements {
+    PaymentRequirements::new(
+        "exact",
+        network,
+        "10000",
+        "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+        SOLANA_PAY_TO,
+        "https://example.com/api",
+        "",
+    )
+}
+
+fn encode(json: &serde_json::Value) -> String {
+    use base64::{engine::general_purpose, Engine as _};
+    general_purpose::STANDARD.encode(json.to_string())
+}
+
+#[test]
+fn test_validate_solana_address() {
+    for address in [
+        SOLANA_PAY_TO,
+        "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+        "11111111111111111111111111111111",
+    ] {
+        assert!(
+            validate_solana_address(address).is_ok(),
+            "'{address}' should be valid"
+        );
+    }
+
+    for (address, expected_error) in [
+        ("", "cannot be empty"),
+        ("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrr", "expected 32 bytes"),
+        ("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFi0", "base58"),
+        ("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFinXX", "length"),
+        (EVM_PAY_TO, "base58"),
+    ] {
+        let error = validate_solana_address(address).unwrap_err().to_string();
+        assert!(
+            error.contains(expected_error),
+            "Error for '{address}' should contain '{expected_error}', got: {error}"
+        );
+    }
+}
+
+#[test]
+fn test_validate_address_for_network() {
+    assert!(validate_payment_address(SOLANA_PAY_TO).is_ok());
+    assert!(validate_payment_address(EVM_PAY_TO).is_ok());
+    assert!(validate_payment_address("not-an-address").is_err());
+
+    assert!(validate_network_address(NetworkVm::Svm, SOLANA_PAY_TO).is_ok());
+    assert!(validate_network_address(NetworkVm::Svm, EVM_PAY_TO).is_err());
+    assert!(validate_network_address(NetworkVm::Evm, EVM_PAY_TO).is_ok());
+    assert!(validate_network_address(NetworkVm::Evm, SOLANA_PAY_TO).is_err());
+}
+
+#[test]
+fn test_solana_networks() {
+    for (name, mint) in [
+        ("solana", "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v"),
+        (
+            "solana-devnet",
+            "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU",
+        ),
+    ] {
+        let network = network_by_name(name).unwrap();
+        assert_eq!(network.vm, NetworkVm::Svm);
+        assert_eq!(network.chain_id, None);
+        assert_eq!(network.usdc.as_deref(), Some(mint));
+        assert!(validate_solana_address(mint).is_ok());
+    }
+}
+
+#[test]
+fn test_decode_svm_payload() {
+    let encoded = encode(&serde_json::json!({
+        "x402Version": 1,
+        "scheme": "exact",
+        "network": "solana-devnet",
+        "payload": { "transaction": "AQID" },
+    }));
+    let payload = SvmPaymentPayload::from_base64(&encoded).unwrap();
+    assert_eq!(payload.x402_version, 1);
+    assert_eq!(payload.network, "solana-devnet");
+    assert_eq!(payload.payload.transaction, "AQID");
+
+    // EVM payloads have no transaction
+    let encoded = encode(&serde_json::json!({
+        "x402Version": 1,
+        "scheme": "exact",
+        "network": "base",
+        "payload": { "signature": "0x", "authorization": {} },
+    }));
+    assert!(SvmPaymentPayload::from_base64(&encoded).is_err());
+    assert!(SvmPaymentPayload::from_base64("not base64!").is_err());
+}
+
+#[test]
+fn test_select_svm_requirements() {
+    let options = [requirements("base"), requirements("solana-devnet")];
+    let payload = |network: &str| SvmPaymentPayload {
+        x402_version: 1,
+        scheme: "exact".to_string(),
+        network: network.to_string(),
+        payload: nginx_x402::ngx_module::svm::SvmTransaction {
+            transaction: "AQID".to_string(),
+        },
+    };
+
+    assert_eq!(
+        select_svm_requirements(&options, &payload("solana-devnet")),
+        Some(1)
+    );
+    assert_eq!(select_svm_requirements(&options, &payload("solana")), None);
+}
+
+#[test]
+fn test_facilitator_endpoint_url() {
+    assert_eq!(
+        facilitator_endpoint_url("https://x402.org/facilitator", "verify"),
+        "https://x402.org/facilitator/verify"
+    );
+    assert_eq!(
+        facilitator_endpoint_url("https://x402.org/facilitator/", "settle"),
+        "https://x402.org/facilitator/settle"
+    );
+}
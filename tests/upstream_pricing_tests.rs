@@ -0,0 +1,125 @@
+//! Tests for upstream-driven pricing (price hints in upstream 402 responses)
+
+use nginx_x402::ngx_module::upstream_pricing::{
+    authorized_amount, covers_price, parse_amount, parse_price_hint, PriceHint, Upstream402,
+    MAX_PRICE_HINT_SIZE,
+};
+use rust_decimal::Decimal;
+use rust_x402::types::{ExactEvmPayload, ExactEvmPayloadAuthorization, PaymentPayload};
+use std::str::FromStr;
+
+fn decimal(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap()
+}
+
+fn payment_b64(value: &str) -> String {
+    PaymentPayload::new(
+        "exact",
+        "base-sepolia",
+        ExactEvmPayload {
+            signature: "0x".to_string(),
+            authorization: ExactEvmPayloadAuthorization {
+                from: "0x857b06519E91e3A54538791bDbb0E22373e36b66".to_string(),
+                to: "0x209693bc6afc0c5328ba36faf03c514ef312287c".to_string(),
+                value: value.to_string(),
+                valid_after: "0".to_string(),
+                valid_before: "9999999999".to_string(),
+                nonce: "0x00".to_string(),
+            },
+        },
+    )
+    .to_base64()
+    .unwrap()
+}
+
+#[test]
+fn test_parse_price_hint_amount_and_description() {
+    let hint = parse_price_hint(br#"{"amount": "0.002", "description": "1,520 tokens"}"#);
+    assert_eq!(
+        hint,
+        Some(PriceHint {
+            amount: Some(decimal("0.002")),
+            description: Some("1,520 tokens".to_string()),
+        })
+    );
+}
+
+#[test]
+fn test_parse_price_hint_numeric_amount() {
+    let hint = parse_price_hint(br#"{"amount": 0.5}"#).unwrap();
+    assert_eq!(hint.amount, Some(decimal("0.5")));
+    assert_eq!(hint.description, None);
+}
+
+#[test]
+fn test_parse_price_hint_invalid_amount_is_ignored() {
+    for body in [
+        &br#"{"amount": "-1"}"#[..],
+        br#"{"amount": "abc"}"#,
+        br#"{"amount": true}"#,
+        br#"{"description": "no price"}"#,
+    ] {
+        let hint = parse_price_hint(body).unwrap();
+        assert_eq!(hint.amount, None, "Unexpected amount for {body:?}");
+    }
+}
+
+#[test]
+fn test_parse_price_hint_rejects_non_json_bodies() {
+    assert_eq!(parse_price_hint(b"<html>Payment Required</html>"), None);
+    assert_eq!(parse_price_hint(b"[1, 2]"), None);
+    assert_eq!(parse_price_hint(b""), None);
+}
+
+#[test]
+fn test_parse_amount_header_value() {
+    assert_eq!(parse_amount(" 0.002 "), Some(decimal("0.002")));
+    assert_eq!(parse_amount("0"), Some(Decimal::ZERO));
+    assert_eq!(parse_amount("-0.1"), None);
+    assert_eq!(parse_amount("2000000000"), None);
+    assert_eq!(parse_amount(""), None);
+}
+
+#[test]
+fn test_oversized_body_is_ignored() {
+    let mut state = Upstream402 {
+        parse_body: true,
+        ..Upstream402::default()
+    };
+    state.push_body(b"{\"amount\": ");
+    state.push_body(&vec![b' '; MAX_PRICE_HINT_SIZE]);
+    state.push_body(b"\"0.1\"}");
+
+    assert!(state.truncated);
+    assert!(state.body.is_empty());
+}
+
+#[test]
+fn test_body_is_not_collected_when_disabled() {
+    let mut state = Upstream402::default();
+    state.push_body(br#"{"amount": "0.1"}"#);
+    assert!(state.body.is_empty());
+}
+
+#[test]
+fn test_authorized_amount() {
+    assert_eq!(
+        authorized_amount(&payment_b64("2000")),
+        Some("2000".to_string())
+    );
+    assert_eq!(authorized_amount(&payment_b64("0")), None);
+    assert_eq!(authorized_amount(&payment_b64("1e6")), None);
+    assert_eq!(authorized_amount(&payment_b64("")), None);
+    assert_eq!(authorized_amount("not base64"), None);
+}
+
+#[test]
+fn test_covers_price() {
+    assert!(covers_price("2000", "2000"));
+    assert!(covers_price("2001", "2000"));
+    assert!(covers_price("1", "0"));
+    assert!(!covers_price("1999", "2000"));
+    assert!(!covers_price("999", "1000000000000000000000000"));
+    assert!(!covers_price("2000", "0.002"));
+    assert!(!covers_price("", "0"));
+}
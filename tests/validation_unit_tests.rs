@@ -130,7 +130,7 @@ mod tests {
             ("", "Network name cannot be empty"),
             ("unsupported-network", "Unsupported network"),
             ("ethereum", "Unsupported network"),
-            ("polygon", "Unsupported network"),
+            ("fantom", "Unsupported network"),
         ];
 
         for (network, expected_error) in invalid_cases {
@@ -0,0 +1,29 @@
+//! Tests for values exposed through nginx variables
+
+use nginx_x402::ngx_module::ctx::{PaymentStatus, X402RequestCtx};
+
+#[test]
+fn test_payment_status_values() {
+    assert_eq!(PaymentStatus::None.as_str(), "none");
+    assert_eq!(PaymentStatus::Valid.as_str(), "valid");
+    assert_eq!(PaymentStatus::Invalid.as_str(), "invalid");
+    assert_eq!(
+        PaymentStatus::FacilitatorError.as_str(),
+        "facilitator_error"
+    );
+    assert_eq!(PaymentStatus::Bypassed.as_str(), "bypassed");
+    assert_eq!(PaymentStatus::Replayed.as_str(), "replayed");
+    assert_eq!(PaymentStatus::Session.as_str(), "session");
+    assert_eq!(PaymentStatus::AccessPass.as_str(), "access_pass");
+}
+
+#[test]
+fn test_request_ctx_defaults() {
+    // A fresh context must not report any payment state
+    let ctx = X402RequestCtx::default();
+    assert_eq!(ctx.status, PaymentStatus::None);
+    assert!(ctx.payer.is_none());
+    assert!(ctx.amount.is_none());
+    assert!(ctx.verify_ms.is_none());
+    assert!(ctx.settle_response.is_none());
+}
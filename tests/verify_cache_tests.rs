@@ -0,0 +1,99 @@
+//! Tests for the facilitator verification cache
+
+use nginx_x402::ngx_module::shm::{Slot, SlotTable};
+use nginx_x402::ngx_module::verify_cache::{cache_expiry, caches_result, verify_cache_key};
+use nginx_x402::ngx_module::SettleMode;
+use rust_x402::types::{
+    ExactEvmPayload, ExactEvmPayloadAuthorization, PaymentPayload, PaymentRequirements,
+};
+
+const NOW: u64 = 1_700_000_000;
+
+fn requirements(amount: &str) -> PaymentRequirements {
+    PaymentRequirements::new(
+        "exact",
+        "base-sepolia",
+        amount,
+        "0x036CbD53842c5426634e7929541eC2318f3dCF7e",
+        "0x209693bc6afc0c5328ba36faf03c514ef312287c",
+        "https://example.com/api",
+        "",
+    )
+}
+
+fn payload(valid_before: &str) -> PaymentPayload {
+    PaymentPayload::new(
+        "exact",
+        "base-sepolia",
+        ExactEvmPayload {
+            signature: "0x".to_string(),
+            authorization: ExactEvmPayloadAuthorization {
+                from: "0x857b06519E91e3A54538791bDbb0E22373e36b66".to_string(),
+                to: "0x209693bc6afc0c5328ba36faf03c514ef312287c".to_string(),
+                value: "1000".to_string(),
+                valid_after: "0".to_string(),
+                valid_before: valid_before.to_string(),
+                nonce: "0x01".to_string(),
+            },
+        },
+    )
+}
+
+#[test]
+fn test_cache_key_depends_on_payment_and_requirements() {
+    let key = verify_cache_key("cGF5bWVudA==", &requirements("1000"));
+    assert_eq!(key, verify_cache_key("cGF5bWVudA==", &requirements("1000")));
+    assert_ne!(key, verify_cache_key("b3RoZXI=", &requirements("1000")));
+    assert_ne!(key, verify_cache_key("cGF5bWVudA==", &requirements("2000")));
+}
+
+#[test]
+fn test_valid_result_cached_until_valid_before() {
+    let valid_before = (NOW + 300).to_string();
+    let payload = payload(&valid_before);
+    assert_eq!(cache_expiry(true, Some(&payload), 10, NOW), Some(NOW + 300));
+}
+
+#[test]
+fn test_valid_result_without_usable_valid_before_not_cached() {
+    assert_eq!(cache_expiry(true, Some(&payload("")), 10, NOW), None);
+    assert_eq!(cache_expiry(true, Some(&payload("soon")), 10, NOW), None);
+    // Already expired
+    let past = (NOW - 1).to_string();
+    assert_eq!(cache_expiry(true, Some(&payload(&past)), 10, NOW), None);
+    assert_eq!(cache_expiry(true, None, 10, NOW), None);
+}
+
+#[test]
+fn test_valid_result_only_cached_if_never_settled() {
+    assert!(caches_result(true, SettleMode::Off));
+    assert!(!caches_result(true, SettleMode::AfterSuccess));
+    assert!(!caches_result(true, SettleMode::BeforeUpstream));
+    assert!(caches_result(false, SettleMode::AfterSuccess));
+    assert!(caches_result(false, SettleMode::BeforeUpstream));
+}
+
+#[test]
+fn test_invalid_result_cached_for_negative_ttl() {
+    let valid_before = (NOW + 300).to_string();
+    let payload = payload(&valid_before);
+    assert_eq!(cache_expiry(false, Some(&payload), 10, NOW), Some(NOW + 10));
+    assert_eq!(cache_expiry(false, None, 30, NOW), Some(NOW + 30));
+    // negative_ttl=0 disables caching invalid results
+    assert_eq!(cache_expiry(false, Some(&payload), 0, NOW), None);
+}
+
+#[test]
+fn test_set_replaces_value() {
+    let mut slots = vec![Slot::default(); 64];
+    let mut table = SlotTable::new(&mut slots, [7; 32]);
+    let key = table.fingerprint(&verify_cache_key("cGF5bWVudA==", &requirements("1000")));
+
+    assert_eq!(table.get(key, NOW), None);
+    table.set(key, 2, NOW + 10, NOW);
+    assert_eq!(table.get(key, NOW), Some(2));
+    table.set(key, 1, NOW + 300, NOW);
+    assert_eq!(table.get(key, NOW), Some(1));
+    // Expired entries are not returned
+    assert_eq!(table.get(key, NOW + 300), None);
+}